@@ -181,11 +181,13 @@ fn seed_search_data(storage: &Storage, conversations: usize, turns_per_conversat
             size_bytes: Some(256),
             sha256: Some(format!("{:032x}", idx)),
         };
-        let mut stats = ConversationStats::default();
-        stats.turn_count = turns_per_conversation as i64;
-        stats.questions = vec!["Benchmark".to_string()];
-        stats.search_blob = format!("benchmark conv-{idx:04}");
-        stats.cwd = Some(format!("/tmp/bench/{idx:04}"));
+        let stats = ConversationStats {
+            turn_count: turns_per_conversation as i64,
+            questions: vec!["Benchmark".to_string()],
+            search_blob: format!("benchmark conv-{idx:04}"),
+            cwd: Some(format!("/tmp/bench/{idx:04}")),
+            ..ConversationStats::default()
+        };
         let conversation_id = storage
             .upsert_conversation(
                 format!("bench-conv-{idx:04}.jsonl"),
@@ -200,11 +202,16 @@ fn seed_search_data(storage: &Storage, conversations: usize, turns_per_conversat
             let turn = TurnRecord {
                 index: turn_idx,
                 started_at: None,
+                ended_at: None,
+                duration_seconds: None,
                 context: None,
                 user_inputs: vec![UserInputRecord {
                     raw: json!({"type":"message","role":"user","content":"Benchmark"}),
                     text: Some("Benchmark".into()),
                     images: Vec::new(),
+                    kind: conv_memory::UserInputKind::Prompt,
+                    image_refs: Vec::new(),
+                    files: Vec::new(),
                 }],
                 result: TurnResult {
                     assistant_messages: vec![format!("Answer {idx:04}-{turn_idx:02}")],
@@ -212,6 +219,10 @@ fn seed_search_data(storage: &Storage, conversations: usize, turns_per_conversat
                 },
                 actions: Vec::new(),
                 telemetry: TurnTelemetry::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
             };
             let embedding =
                 generate_embedding(SAMPLE_EMBED_DIM, (idx as u64) << 16 | turn_idx as u64);
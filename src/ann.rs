@@ -0,0 +1,142 @@
+//! Optional KNN index over turn embeddings backed by the `sqlite-vec` extension.
+//!
+//! The `embeddings` table stays the source of truth: the `turn_vectors` vec0 table mirrors the
+//! default embedding space keyed by the `turns` rowid, and search only uses it to pick a
+//! candidate window that is then rescored with the full-precision BLOBs.
+
+use std::ffi::{c_char, c_int};
+use std::sync::Once;
+
+use bytemuck::cast_slice;
+use rusqlite::ffi::{sqlite3, sqlite3_api_routines, sqlite3_auto_extension};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::storage::{decode_embedding, DEFAULT_EMBEDDING_MODEL};
+
+/// Largest `k` accepted by a vec0 KNN query.
+pub(crate) const MAX_K: usize = 4096;
+
+/// Restricts `t.rowid` to the nearest neighbours of the query; binds the query vector and `k`.
+pub(crate) const KNN_FILTER: &str =
+    " AND t.rowid IN (SELECT rowid FROM turn_vectors WHERE embedding MATCH ? AND k = ?)";
+
+type ExtensionInit =
+    unsafe extern "C" fn(*mut sqlite3, *mut *const c_char, *const sqlite3_api_routines) -> c_int;
+
+/// Load the extension into every connection opened after this call.
+pub(crate) fn register() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| unsafe {
+        let init = std::mem::transmute::<*const (), ExtensionInit>(
+            sqlite_vec::sqlite3_vec_init as *const (),
+        );
+        sqlite3_auto_extension(Some(init));
+    });
+}
+
+/// Dimension of the index table, or `None` when it has not been created yet.
+pub(crate) fn dimension(conn: &Connection) -> rusqlite::Result<Option<usize>> {
+    let sql: Option<String> = conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'turn_vectors'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(sql.as_deref().and_then(parse_dimension))
+}
+
+fn parse_dimension(sql: &str) -> Option<usize> {
+    let start = sql.find("float[")? + "float[".len();
+    let len = sql[start..].find(']')?;
+    sql[start..start + len].parse().ok()
+}
+
+fn create(conn: &Connection, dim: usize) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS turn_vectors \
+         USING vec0(embedding float[{dim}] distance_metric=cosine)"
+    ))
+}
+
+/// Mirror the embedding stored for the turn at `rowid`. The table takes the dimension of the
+/// first embedding it sees; vectors of any other dimension are left to the brute-force path.
+pub(crate) fn sync(
+    conn: &Connection,
+    rowid: i64,
+    embedding: Option<&[f32]>,
+) -> rusqlite::Result<()> {
+    let dim = dimension(conn)?;
+    if dim.is_some() {
+        conn.execute("DELETE FROM turn_vectors WHERE rowid = ?1", params![rowid])?;
+    }
+    let Some(embedding) = embedding.filter(|e| !e.is_empty()) else {
+        return Ok(());
+    };
+    match dim {
+        Some(dim) if dim != embedding.len() => return Ok(()),
+        Some(_) => {}
+        None => create(conn, embedding.len())?,
+    }
+    conn.execute(
+        "INSERT INTO turn_vectors(rowid, embedding) VALUES (?1, ?2)",
+        params![rowid, cast_slice::<f32, u8>(embedding)],
+    )?;
+    Ok(())
+}
+
+/// Drop the vectors of the turns of `conversation_id` whose index is `from_index` or higher;
+/// call before deleting the turns.
+pub(crate) fn remove_turns(
+    conn: &Connection,
+    conversation_id: &str,
+    from_index: usize,
+) -> rusqlite::Result<()> {
+    if dimension(conn)?.is_none() {
+        return Ok(());
+    }
+    conn.execute(
+        "DELETE FROM turn_vectors WHERE rowid IN \
+         (SELECT rowid FROM turns WHERE conversation_id = ?1 AND turn_index >= ?2)",
+        params![conversation_id, from_index as i64],
+    )?;
+    Ok(())
+}
+
+/// Recreate the index from the default embedding space, using its most common dimension.
+/// Returns the number of vectors indexed.
+pub(crate) fn rebuild(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute_batch("DROP TABLE IF EXISTS turn_vectors")?;
+    let dim: Option<i64> = conn
+        .query_row(
+            "SELECT dim FROM embeddings WHERE model_name = ?1 AND dim > 0 \
+             GROUP BY dim ORDER BY count(*) DESC, dim LIMIT 1",
+            [DEFAULT_EMBEDDING_MODEL],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(dim) = dim else {
+        return Ok(0);
+    };
+    create(conn, dim as usize)?;
+    // Vectors are decoded in Rust because half-precision rows must be widened for vec0.
+    let mut select = conn.prepare(
+        "SELECT t.rowid, e.vector FROM turns t \
+         JOIN embeddings e ON e.conversation_id = t.conversation_id \
+         AND e.turn_index = t.turn_index \
+         WHERE e.model_name = ?1 AND e.dim = ?2",
+    )?;
+    let mut insert = conn.prepare("INSERT INTO turn_vectors(rowid, embedding) VALUES (?1, ?2)")?;
+    let mut rows = select.query(params![DEFAULT_EMBEDDING_MODEL, dim])?;
+    let mut indexed = 0;
+    while let Some(row) = rows.next()? {
+        let rowid: i64 = row.get(0)?;
+        let blob: Vec<u8> = row.get(1)?;
+        let Some(vector) = decode_embedding(&blob, dim as usize) else {
+            continue;
+        };
+        insert.execute(params![rowid, cast_slice::<f32, u8>(&vector)])?;
+        indexed += 1;
+    }
+    Ok(indexed)
+}
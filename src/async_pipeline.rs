@@ -0,0 +1,291 @@
+//! Async variants of the directory pipeline, behind the `tokio` feature.
+//!
+//! Rollouts are streamed from disk, parsed and embedded on tokio's blocking pool, at most
+//! [`IngestOptions::concurrency`] at a time. `Storage` is not `Sync`, so database work stays on
+//! the calling task; on a multi-threaded runtime it runs inside `block_in_place` so other tasks
+//! keep making progress. The functions take `&mut Storage` so their futures are `Send` and can
+//! be handed to `tokio::spawn`.
+//!
+//! [`IngestOptions::summarizer`] is borrowed rather than shared, so it cannot move to the
+//! blocking pool: the calling task summarizes each conversation just before writing it, which
+//! serializes summaries with the database writes.
+
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::task::{self, JoinSet};
+
+use crate::embedding::EmbeddingModel;
+use crate::extractor::FormatRegistry;
+use crate::pipeline::{
+    check_rollout, discover_rollouts, embed_prepared, file_metadata, fingerprint_matches,
+    finish_check, finish_ingest, parse_rollout_file, record_ingest_setup, remove_deleted_rollouts,
+    report, summarize_prepared, write_unless_duplicate, ChangeDetection, DirState, FileOutcome,
+    IngestOptions, PipelineConfig, PipelineError, PreparedRollout, ProgressEvent, UpdateStats,
+};
+use crate::redact::Redactor;
+use crate::storage::Storage;
+
+/// Async [`process_rollout_dir_with`](crate::process_rollout_dir_with). The embedder is shared
+/// with the blocking pool, hence the `Arc`; [`ProgressEvent::TurnsEmbedded`] is not reported.
+pub async fn process_rollout_dir_async(
+    dir: impl AsRef<Path>,
+    storage: &mut Storage,
+    embedder: Option<Arc<EmbeddingModel>>,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    let rollouts = discover(dir.as_ref(), options).await?;
+    report(
+        options.progress,
+        ProgressEvent::Discovered {
+            total: rollouts.len(),
+        },
+    );
+    let mut stats = UpdateStats::default();
+    ingest_files(&rollouts, storage, embedder, options, &mut stats).await?;
+    Ok(stats)
+}
+
+/// Async [`update_rollout_dir_with`](crate::update_rollout_dir_with). Files whose stored
+/// modification time and size still match are skipped after an async `stat`; the others are
+/// checked, and grown rollouts appended to, on the calling task.
+pub async fn update_rollout_dir_async(
+    dir: impl AsRef<Path>,
+    storage: &mut Storage,
+    embedder: Option<Arc<EmbeddingModel>>,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    let rollouts = discover(dir.as_ref(), options).await?;
+    report(
+        options.progress,
+        ProgressEvent::Discovered {
+            total: rollouts.len(),
+        },
+    );
+    let mut stats = UpdateStats::default();
+    let mut pending = Vec::new();
+    let mut state = DirState::default();
+
+    for path in rollouts {
+        let checked = match tokio::fs::metadata(&path).await {
+            Ok(metadata) if unchanged_metadata(&path, &metadata, storage, options)? => {
+                Ok(Some(FileOutcome::Skipped))
+            }
+            Ok(_) => {
+                blocking(|| check_rollout(&path, storage, embedder.as_deref(), options, &mut state))
+            }
+            Err(err) => Err(PipelineError::io(&path, err)),
+        };
+        finish_check(path, checked, options, &mut stats, &mut pending)?;
+    }
+
+    stats.duplicates = std::mem::take(&mut state.duplicates);
+    stats.report.files = std::mem::take(&mut state.reports);
+    if options.remove_deleted {
+        blocking(|| {
+            remove_deleted_rollouts(
+                &[dir.as_ref()],
+                storage,
+                options,
+                &state.moved_from,
+                &mut stats,
+            )
+        })?;
+    }
+    ingest_files(&pending, storage, embedder, options, &mut stats).await?;
+    Ok(stats)
+}
+
+/// Discover rollouts on the blocking pool.
+async fn discover(dir: &Path, options: &IngestOptions<'_>) -> Result<Vec<PathBuf>, PipelineError> {
+    let dir = dir.to_path_buf();
+    let discovery = IngestOptions {
+        exclude: options.exclude.clone(),
+        max_depth: options.max_depth,
+        follow_symlinks: options.follow_symlinks,
+        since: options.since,
+        until: options.until,
+        ..IngestOptions::default()
+    };
+    join(task::spawn_blocking(move || discover_rollouts(&dir, &discovery)).await)
+}
+
+/// Whether `path` can be skipped on its metadata alone, without hashing or parsing it.
+fn unchanged_metadata(
+    path: &Path,
+    metadata: &Metadata,
+    storage: &Storage,
+    options: &IngestOptions<'_>,
+) -> Result<bool, PipelineError> {
+    if options.change_detection == ChangeDetection::Hash {
+        return Ok(false);
+    }
+    let stored = storage
+        .get_rollout_fingerprint(path)
+        .map_err(|err| PipelineError::storage(path, err))?;
+    let (modified_at, size_bytes) = file_metadata(metadata);
+    Ok(stored.is_some_and(|stored| fingerprint_matches(&stored, modified_at, size_bytes)))
+}
+
+/// Read, parse and embed `paths` with bounded concurrency and write them as they complete.
+async fn ingest_files(
+    paths: &[PathBuf],
+    storage: &mut Storage,
+    embedder: Option<Arc<EmbeddingModel>>,
+    options: &IngestOptions<'_>,
+    stats: &mut UpdateStats,
+) -> Result<(), PipelineError> {
+    if options.dry_run {
+        for path in paths {
+            report(
+                options.progress,
+                ProgressEvent::FileFinished {
+                    path,
+                    outcome: FileOutcome::Ingested,
+                },
+            );
+        }
+        stats.processed += paths.len();
+        return Ok(());
+    }
+    if let Some(first) = paths.first() {
+        blocking(|| record_ingest_setup(storage, embedder.as_deref(), options.redactor, first))?;
+    }
+
+    let mut tasks = JoinSet::new();
+    let mut queued = paths.iter();
+    let mut written = HashMap::new();
+    loop {
+        while tasks.len() < options.concurrency.max(1) {
+            let Some(path) = queued.next() else {
+                break;
+            };
+            report(options.progress, ProgressEvent::FileStarted { path });
+            tasks.spawn(prepare(
+                path.clone(),
+                embedder.clone(),
+                options.config,
+                options.formats.cloned(),
+                options.redactor.cloned(),
+            ));
+        }
+        let Some(joined) = tasks.join_next().await else {
+            break;
+        };
+        let (path, prepared) = join(joined);
+        let result = prepared.and_then(|mut prepared| {
+            blocking(|| {
+                summarize_prepared(&mut prepared, options.summarizer, &options.config)?;
+                write_unless_duplicate(storage, prepared, &mut written, options.post_ingest)
+            })
+        });
+        finish_ingest(&path, result, options, stats)?;
+    }
+    Ok(())
+}
+
+/// Stream the rollout at `path` through decompression, parsing and embedding on the blocking
+/// pool, reading the file as the parser consumes it rather than loading it whole.
+async fn prepare(
+    path: PathBuf,
+    embedder: Option<Arc<EmbeddingModel>>,
+    config: PipelineConfig,
+    formats: Option<FormatRegistry>,
+    redactor: Option<Redactor>,
+) -> (PathBuf, Result<PreparedRollout, PipelineError>) {
+    join(
+        task::spawn_blocking(move || {
+            let prepared = parse_rollout_file(&path, &config, formats.as_ref(), redactor.as_ref())
+                .and_then(|mut prepared| {
+                    embed_prepared(&mut prepared, embedder.as_deref(), None, &config)?;
+                    Ok(prepared)
+                });
+            (path, prepared)
+        })
+        .await,
+    )
+}
+
+/// Run database work from async code without stalling the other tasks of a multi-threaded
+/// runtime.
+fn blocking<R>(work: impl FnOnce() -> R) -> R {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            task::block_in_place(work)
+        }
+        _ => work(),
+    }
+}
+
+/// Unwrap a finished task, resuming its panic on the caller.
+fn join<T>(joined: Result<T, task::JoinError>) -> T {
+    joined.unwrap_or_else(|err| panic::resume_unwind(err.into_panic()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn sample_rollout(id: &str) -> String {
+        format!(
+            r#"{{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{{"id":"{id}","cwd":"/tmp"}}}}
+{{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{{"type":"message","role":"user","content":[{{"type":"input_text","text":"hello"}}]}}}}
+"#
+        )
+    }
+
+    async fn import_then_update(storage: &mut Storage, dir: &Path) {
+        let options = IngestOptions {
+            concurrency: 2,
+            ..IngestOptions::default()
+        };
+        let stats = process_rollout_dir_async(dir, storage, None, &options)
+            .await
+            .unwrap();
+        assert_eq!(stats.processed, 3);
+
+        std::fs::write(
+            dir.join("rollout-2025-01-02T00-00-00-d.jsonl"),
+            sample_rollout("urn:uuid:d"),
+        )
+        .unwrap();
+        let stats = update_rollout_dir_async(dir, storage, None, &options)
+            .await
+            .unwrap();
+        assert_eq!((stats.processed, stats.skipped), (1, 3));
+    }
+
+    fn rollout_dir() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(
+                dir.path()
+                    .join(format!("rollout-2025-01-01T00-00-00-{name}.jsonl")),
+                sample_rollout(&format!("urn:uuid:{name}")),
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    #[tokio::test]
+    async fn imports_and_updates_on_a_current_thread_runtime() {
+        let dir = rollout_dir();
+        let mut storage = Storage::open_in_memory().unwrap();
+        import_then_update(&mut storage, dir.path()).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn imports_and_updates_from_a_spawned_task() {
+        let dir = rollout_dir();
+        let mut storage = Storage::open_in_memory().unwrap();
+        tokio::spawn(async move { import_then_update(&mut storage, dir.path()).await })
+            .await
+            .unwrap();
+    }
+}
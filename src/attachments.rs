@@ -0,0 +1,111 @@
+use serde_json::Value;
+
+use crate::images::{decode_base64, decode_data_url};
+use crate::types::FileAttachment;
+
+/// Characters of an attached file's text kept on the user input.
+pub(crate) const ATTACHMENT_TEXT_CHARS: usize = 20_000;
+
+/// A file attached to a user message: a Codex / Responses `input_file` item, a Chat Completions
+/// `file` item or an Anthropic `document` block. Text is kept when the item carries it inline or
+/// as a UTF-8 `data:` URL; binary files (PDFs, archives, ...) only keep their name and type.
+pub(crate) fn file_attachment(item: &Value) -> Option<FileAttachment> {
+    let file = item
+        .get("file")
+        .filter(|file| file.is_object())
+        .unwrap_or(item);
+    let source = item.get("source");
+    let filename = ["filename", "name", "title"]
+        .iter()
+        .find_map(|key| file.get(*key).and_then(Value::as_str))
+        .map(str::to_string);
+    let mut mime_type = ["mime_type", "media_type"]
+        .iter()
+        .find_map(|key| {
+            file.get(*key)
+                .or_else(|| source.and_then(|source| source.get(*key)))
+                .and_then(Value::as_str)
+        })
+        .map(str::to_string);
+
+    let text = if let Some(text) = ["text", "content"]
+        .iter()
+        .find_map(|key| file.get(*key).and_then(Value::as_str))
+    {
+        Some(text.to_string())
+    } else if let Some(url) = file.get("file_data").and_then(Value::as_str) {
+        decode_data_url(url).and_then(|(data_mime, bytes)| {
+            mime_type = mime_type.take().or(data_mime);
+            utf8_text(bytes)
+        })
+    } else if let Some(source) = source {
+        let data = source.get("data").and_then(Value::as_str);
+        match source.get("type").and_then(Value::as_str) {
+            Some("text") => data.map(str::to_string),
+            Some("base64") => data.and_then(decode_base64).and_then(utf8_text),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    if filename.is_none() && text.is_none() {
+        return None;
+    }
+    let (text, truncated) = match text {
+        Some(text) if text.chars().count() > ATTACHMENT_TEXT_CHARS => (
+            Some(text.chars().take(ATTACHMENT_TEXT_CHARS).collect()),
+            true,
+        ),
+        text => (text, false),
+    };
+    Some(FileAttachment {
+        filename,
+        mime_type,
+        text,
+        truncated,
+    })
+}
+
+fn utf8_text(bytes: Vec<u8>) -> Option<String> {
+    String::from_utf8(bytes)
+        .ok()
+        .filter(|text| !text.contains('\0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn attachments_keep_their_name_and_text() {
+        let inline = file_attachment(&json!({
+            "type": "input_file",
+            "filename": "notes.md",
+            "file_data": "data:text/markdown;base64,IyBUb2Rv",
+        }))
+        .unwrap();
+        assert_eq!(inline.filename.as_deref(), Some("notes.md"));
+        assert_eq!(inline.mime_type.as_deref(), Some("text/markdown"));
+        assert_eq!(inline.text.as_deref(), Some("# Todo"));
+
+        let document = file_attachment(&json!({
+            "type": "document",
+            "title": "spec.txt",
+            "source": {"type": "text", "media_type": "text/plain", "data": "x".repeat(ATTACHMENT_TEXT_CHARS + 1)},
+        }))
+        .unwrap();
+        assert_eq!(document.text.unwrap().len(), ATTACHMENT_TEXT_CHARS);
+        assert!(document.truncated);
+
+        let pdf = file_attachment(&json!({
+            "type": "file",
+            "file": {"filename": "report.pdf", "file_data": "data:application/pdf;base64,/w=="},
+        }))
+        .unwrap();
+        assert_eq!(pdf.filename.as_deref(), Some("report.pdf"));
+        assert_eq!(pdf.text, None);
+        assert_eq!(file_attachment(&json!({"type": "input_file"})), None);
+    }
+}
@@ -1,12 +1,19 @@
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
 use clap::{Parser, ValueHint};
 use conv_memory::{
-    process_rollout_dir, process_rollout_file, EmbeddingModel, EmbeddingModelConfig, Storage,
+    embed_missing, process_chatgpt_export, process_rollout_dirs_with, process_rollout_file_with,
+    process_rollout_reader_with, reembed_all, EmbeddingModel, EmbeddingModelConfig, IngestOptions,
+    ParseOptions, PipelineConfig, ProgressEvent, ProgressSink, Redactor, RolloutFormat, Storage,
+    TurnSegmentation,
 };
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
 /// Import Codex rollout transcripts into the ConvMemory SQLite store.
 #[derive(Debug, Parser)]
@@ -16,13 +23,30 @@ use conv_memory::{
     about = "Batch ingest Codex rollouts into the ConvMemory knowledge base"
 )]
 struct Cli {
-    /// Path to a rollout file or directory tree (defaults to ./codex/sessions).
+    /// Path to a rollout file or directory tree (defaults to ./codex/sessions), or `-` to read
+    /// one uncompressed rollout from stdin. Several directory trees are imported in one run.
     #[arg(
         value_name = "SOURCE",
         default_value = "codex/sessions",
         value_hint = ValueHint::AnyPath
     )]
-    source: PathBuf,
+    sources: Vec<PathBuf>,
+
+    /// Transcript format: `codex` rollouts, `claude-code` sessions (every `.jsonl` file under a
+    /// directory), `chat-messages` logs of `{role, content}` messages (every `.json` and `.jsonl`
+    /// file), or `auto` to detect it per file and pick up both `rollout-*.jsonl` and Claude Code
+    /// `<uuid>.jsonl` files.
+    #[arg(long, value_name = "FORMAT", default_value_t = RolloutFormat::Auto)]
+    format: RolloutFormat,
+
+    /// Import the conversations of a ChatGPT data export's `conversations.json` instead of
+    /// SOURCE; conversations unchanged since the last import of the export are skipped.
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    chatgpt_export: Option<PathBuf>,
+
+    /// Rollout path recorded for a rollout read from stdin.
+    #[arg(long, value_name = "LABEL", default_value = "stdin")]
+    stdin_label: String,
 
     /// SQLite database to create or update.
     #[arg(
@@ -34,6 +58,78 @@ struct Cli {
     )]
     database: PathBuf,
 
+    /// Store rollout paths relative to this directory, so the database keeps matching its
+    /// rollouts after being moved to another machine or home directory.
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    rollout_root: Option<PathBuf>,
+
+    /// Worker threads in each of the parsing and embedding stages when importing a directory.
+    /// Each embedding worker runs inference with `--embed-threads` threads.
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// After a directory import, list the N rollouts that took longest with their parse, embed
+    /// and write times.
+    #[arg(long, value_name = "N")]
+    slowest: Option<usize>,
+
+    /// Skip rollouts that fail to import and list them at the end instead of aborting.
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Skip malformed lines, records of unknown type and bad timestamps inside a transcript
+    /// instead of failing the whole file, and report how many lines were skipped.
+    #[arg(long)]
+    lenient: bool,
+
+    /// Where Codex rollouts are split into turns: at `turn-context` records, at every
+    /// `user-message`, or `auto` (user messages until the first `turn_context`, for archives
+    /// with rollouts from older Codex versions).
+    #[arg(long, value_name = "MODE", default_value_t = TurnSegmentation::TurnContext)]
+    segment_turns: TurnSegmentation,
+
+    /// Skip files and directories whose path below a SOURCE matches this glob (repeatable),
+    /// e.g. `--exclude '*/trash/*'`.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Directory levels below each SOURCE to search for rollouts.
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Follow symlinked directories and rollouts.
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Only import rollouts started on or after this date (`YYYY-MM-DD`, UTC) or RFC 3339 time.
+    #[arg(long, value_name = "DATE", value_parser = parse_time)]
+    since: Option<OffsetDateTime>,
+
+    /// Only import rollouts started before this date (`YYYY-MM-DD`, UTC) or RFC 3339 time.
+    #[arg(long, value_name = "DATE", value_parser = parse_time)]
+    until: Option<OffsetDateTime>,
+
+    /// Replace API keys, tokens, private keys and secret `NAME=value` assignments with
+    /// `[REDACTED]` before rollouts are stored and embedded.
+    #[arg(long)]
+    redact_secrets: bool,
+
+    /// Redact matches of a regular expression before storage (repeatable), given as
+    /// `NAME=REGEX=>REPLACEMENT`, e.g. `--redact-rule 'email=[\w.+-]+@[\w.-]+=>[EMAIL]'`.
+    /// Applied after `--redact-secrets`, in order, and recorded in the database.
+    #[arg(long, value_name = "RULE", value_parser = parse_redaction_rule)]
+    redact_rule: Vec<(String, String, String)>,
+
+    /// Embed every turn already in the database again with `--embed-model` instead of
+    /// importing, replacing the previous model's vectors once all are done.
+    #[arg(long, requires = "embed_model")]
+    reembed: bool,
+
+    /// Embed only the turns in the database that have no embedding yet (e.g. imported without
+    /// a model) with `--embed-model`, instead of importing.
+    #[arg(long, requires = "embed_model", conflicts_with = "reembed")]
+    embed_missing: bool,
+
     /// Optional GGUF embedding model for vectorising turn summaries.
     #[arg(long, value_name = "MODEL", value_hint = ValueHint::FilePath)]
     embed_model: Option<PathBuf>,
@@ -51,6 +147,32 @@ struct Cli {
     embed_threads_batch: Option<u32>,
 }
 
+/// Parse a `YYYY-MM-DD` date (midnight UTC) or an RFC 3339 timestamp.
+fn parse_time(value: &str) -> Result<OffsetDateTime, String> {
+    let rfc3339 = if value.len() == 10 {
+        format!("{value}T00:00:00Z")
+    } else {
+        value.to_string()
+    };
+    OffsetDateTime::parse(&rfc3339, &Rfc3339)
+        .map_err(|err| format!("expected YYYY-MM-DD or an RFC 3339 time: {err}"))
+}
+
+/// Split a `NAME=REGEX=>REPLACEMENT` redaction rule; the regex is compiled later.
+fn parse_redaction_rule(value: &str) -> Result<(String, String, String), String> {
+    let (name, rule) = value
+        .split_once('=')
+        .ok_or("expected NAME=REGEX=>REPLACEMENT")?;
+    let (pattern, replacement) = rule
+        .rsplit_once("=>")
+        .ok_or("expected NAME=REGEX=>REPLACEMENT")?;
+    Ok((
+        name.to_string(),
+        pattern.to_string(),
+        replacement.to_string(),
+    ))
+}
+
 fn main() {
     if let Err(err) = run() {
         eprintln!("error: {err}");
@@ -70,6 +192,9 @@ fn run() -> Result<(), Box<dyn Error>> {
     }
 
     let storage = Storage::open(&cli.database)?;
+    if let Some(root) = &cli.rollout_root {
+        storage.set_rollout_root(Some(root))?;
+    }
 
     let embedder = if let Some(model_path) = &cli.embed_model {
         let config = EmbeddingModelConfig {
@@ -83,39 +208,186 @@ fn run() -> Result<(), Box<dyn Error>> {
         None
     };
 
-    let mut source = cli.source.clone();
-    if !source.exists() && source == PathBuf::from("codex/sessions") {
-        let fallback = PathBuf::from("../sessions");
-        if fallback.exists() {
-            source = fallback;
+    let total = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let progress = |event: ProgressEvent<'_>| match event {
+        ProgressEvent::Discovered { total: found } => total.store(found, Ordering::Relaxed),
+        ProgressEvent::FileFinished { .. } | ProgressEvent::FileFailed { .. } => {
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            eprint!("\r{done}/{} rollouts", total.load(Ordering::Relaxed));
+        }
+        _ => {}
+    };
+    let show_progress = io::stderr().is_terminal();
+    let mut redactor = None;
+    if cli.redact_secrets || !cli.redact_rule.is_empty() {
+        let mut rules = if cli.redact_secrets {
+            Redactor::secrets()
+        } else {
+            Redactor::default()
+        };
+        for (name, pattern, replacement) in &cli.redact_rule {
+            rules
+                .add_rule(name, pattern, replacement)
+                .map_err(|err| format!("invalid --redact-rule {name}: {err}"))?;
         }
+        redactor = Some(rules);
     }
-
-    let metadata = fs::metadata(&source)
-        .map_err(|err| format!("failed to read source {}: {err}", source.to_string_lossy()))?;
-
+    let options = IngestOptions {
+        concurrency: cli.jobs,
+        progress: show_progress.then_some(&progress as &dyn ProgressSink),
+        skip_failures: cli.keep_going,
+        exclude: cli.exclude.clone(),
+        max_depth: cli.max_depth,
+        follow_symlinks: cli.follow_symlinks,
+        since: cli.since,
+        until: cli.until,
+        redactor: redactor.as_ref(),
+        config: PipelineConfig {
+            format: cli.format,
+            parse: ParseOptions {
+                lenient: cli.lenient,
+                segmentation: cli.segment_turns,
+            },
+            ..PipelineConfig::default()
+        },
+        ..IngestOptions::default()
+    };
     let start = Instant::now();
 
-    if metadata.is_file() {
-        process_rollout_file(&source, &storage, embedder.as_ref(), None)?;
+    if cli.reembed || cli.embed_missing {
+        let embedder = embedder
+            .as_ref()
+            .ok_or("embedding requires --embed-model")?;
+        let result = if cli.reembed {
+            reembed_all(&storage, embedder, &options)
+        } else {
+            embed_missing(&storage, embedder, &options)
+        };
+        if show_progress {
+            eprintln!();
+        }
+        let stats = result?;
+        for failure in &stats.failed {
+            eprintln!("skipped: {}", failure.error);
+        }
         println!(
-            "Imported rollout {} in {:.2?}",
-            source.display(),
+            "Embedded turns of {} conversation(s) in {:.2?}",
+            stats.processed,
             start.elapsed()
         );
-    } else if metadata.is_dir() {
-        let count = process_rollout_dir(&source, &storage, embedder.as_ref())?;
+        return Ok(());
+    }
+
+    if let Some(export) = &cli.chatgpt_export {
+        let result = process_chatgpt_export(export, &storage, embedder.as_ref(), &options);
+        if show_progress {
+            eprintln!();
+        }
+        let stats = result?;
+        for failure in &stats.failed {
+            eprintln!("skipped: {}", failure.error);
+        }
         println!(
-            "Imported {count} rollout(s) from {} in {:.2?}",
-            source.display(),
-            start.elapsed()
+            "Imported {} conversation(s) from {} in {:.2?} ({} unchanged)",
+            stats.processed,
+            export.display(),
+            start.elapsed(),
+            stats.skipped
         );
-    } else {
-        return Err(format!(
-            "source {} is neither a file nor a directory",
-            source.display()
-        )
-        .into());
+        return Ok(());
+    }
+
+    if let [source] = cli.sources.as_slice() {
+        if source == Path::new("-") {
+            process_rollout_reader_with(
+                io::stdin().lock(),
+                &storage,
+                embedder.as_ref(),
+                &cli.stdin_label,
+                &options,
+            )?;
+            println!(
+                "Imported rollout {} from stdin in {:.2?}",
+                cli.stdin_label,
+                start.elapsed()
+            );
+            return Ok(());
+        }
+    }
+
+    let mut sources = cli.sources.clone();
+    if let [source] = sources.as_mut_slice() {
+        if !source.exists() && source == Path::new("codex/sessions") {
+            let fallback = PathBuf::from("../sessions");
+            if fallback.exists() {
+                *source = fallback;
+            }
+        }
+    }
+
+    for source in &sources {
+        let metadata = fs::metadata(source)
+            .map_err(|err| format!("failed to read source {}: {err}", source.to_string_lossy()))?;
+        if metadata.is_file() && sources.len() == 1 {
+            process_rollout_file_with(source, &storage, embedder.as_ref(), None, &options)?;
+            println!(
+                "Imported rollout {} in {:.2?}",
+                source.display(),
+                start.elapsed()
+            );
+            return Ok(());
+        }
+        if !metadata.is_dir() {
+            return Err(format!(
+                "source {} is not a directory; only a single SOURCE may be a file",
+                source.display()
+            )
+            .into());
+        }
+    }
+
+    let result = process_rollout_dirs_with(&sources, &storage, embedder.as_ref(), &options);
+    if show_progress {
+        eprintln!();
+    }
+    let stats = result?;
+    for failure in &stats.failed {
+        eprintln!("skipped: {}", failure.error);
+    }
+    let roots: Vec<_> = sources
+        .iter()
+        .map(|source| source.display().to_string())
+        .collect();
+    println!(
+        "Imported {} rollout(s) from {} in {:.2?}",
+        stats.processed,
+        roots.join(", "),
+        start.elapsed()
+    );
+    if !stats.duplicates.is_empty() {
+        println!("{} duplicate rollout(s) skipped", stats.duplicates.len());
+    }
+    if !stats.failed.is_empty() {
+        println!("{} rollout(s) failed", stats.failed.len());
+    }
+    let warnings: usize = stats.report.files.iter().map(|file| file.warnings).sum();
+    if warnings > 0 {
+        println!("{warnings} malformed line(s) skipped");
+    }
+    if let Some(n) = cli.slowest {
+        for file in stats.report.slowest(n) {
+            println!(
+                "{:>10.2?}  parse {:.2?}, embed {:.2?}, write {:.2?}, {} turn(s), {} bytes  {}",
+                file.total_time(),
+                file.parse_time,
+                file.embed_time,
+                file.write_time,
+                file.turns,
+                file.bytes,
+                file.path.display()
+            );
+        }
     }
 
     Ok(())
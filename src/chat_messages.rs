@@ -0,0 +1,350 @@
+//! Lenient parser for plain chat logs: a JSON array of `{role, content, timestamp?}` messages,
+//! the same wrapped as `{"messages": [...]}`, or one message per JSONL line. Meant for logs of
+//! tools without a parser of their own.
+
+use std::io::BufRead;
+
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::extractor::{snippet, ParseError, ParseOptions};
+use crate::types::*;
+
+/// Fields read as a message's time, in order of preference.
+const TIME_FIELDS: [&str; 4] = ["timestamp", "created_at", "create_time", "time"];
+
+/// Parse a chat log in any of the layouts above. `user` (or `human`) messages open turns and
+/// `assistant` (`ai`, `model`, `bot`) messages answer them; OpenAI-style `tool_calls` become
+/// actions whose output is the `tool` message with the matching `tool_call_id`. Content may be a
+/// string or a list of text / image parts. Times may be RFC 3339 strings or Unix seconds or
+/// milliseconds; a message without one inherits the previous message's, and a log without any
+/// yields a record without times. Messages with other roles, and fields not listed here, are
+/// ignored; only malformed JSON fails the parse.
+pub fn parse_chat_messages<R: BufRead>(reader: R) -> Result<ConversationRecord, ParseError> {
+    parse_chat_messages_from(reader, ParseOptions::default(), None)
+}
+
+/// Like [`parse_chat_messages`], feeding every line read to `hasher`.
+pub(crate) fn parse_chat_messages_from<R: BufRead>(
+    mut reader: R,
+    options: ParseOptions,
+    mut hasher: Option<&mut Sha256>,
+) -> Result<ConversationRecord, ParseError> {
+    let mut text = String::new();
+    let mut lines: Vec<(usize, usize)> = Vec::new();
+    loop {
+        let start = text.len();
+        let read = reader
+            .read_line(&mut text)
+            .map_err(|source| ParseError::Io {
+                path: None,
+                line: lines.len() + 1,
+                source,
+            })?;
+        if read == 0 {
+            break;
+        }
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(&text.as_bytes()[start..]);
+        }
+        lines.push((start, text.len()));
+    }
+
+    let mut meta = Map::new();
+    let mut warnings = Vec::new();
+    // Line of each JSONL message; JSON documents leave it empty.
+    let mut message_lines = Vec::new();
+    let messages = match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Array(messages)) => messages,
+        Ok(Value::Object(mut object)) => match object.remove("messages") {
+            Some(Value::Array(messages)) => {
+                for (from, to) in [("id", "id"), ("conversation_id", "id"), ("title", "title")] {
+                    if let Some(value) = object.get(from).and_then(Value::as_str) {
+                        meta.entry(to).or_insert_with(|| value.into());
+                    }
+                }
+                messages
+            }
+            _ => vec![Value::Object(object)],
+        },
+        // Anything but a single JSON document is read as JSONL.
+        _ => {
+            let mut messages = Vec::new();
+            for (idx, &(start, end)) in lines.iter().enumerate() {
+                let line = text[start..end].trim();
+                if line.is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(line) {
+                    Ok(message) => {
+                        messages.push(message);
+                        message_lines.push(idx + 1);
+                    }
+                    Err(source) => {
+                        let error = ParseError::Json {
+                            path: None,
+                            line: idx + 1,
+                            snippet: snippet(line),
+                            source,
+                        };
+                        options.recover(error, &mut warnings)?;
+                    }
+                }
+            }
+            messages
+        }
+    };
+
+    let mut builder = ConversationBuilder {
+        warnings,
+        ..ConversationBuilder::default()
+    };
+    // Messages before the first timed one take its time; untimed logs get a placeholder that
+    // is dropped again below.
+    let first_time = messages.iter().find_map(message_time);
+    for (idx, message) in messages.iter().enumerate() {
+        builder.line = message_lines.get(idx).copied().unwrap_or(0);
+        let timestamp = match message_time(message) {
+            Some(timestamp) => {
+                builder.observe_timestamp(timestamp);
+                timestamp
+            }
+            None => builder
+                .last_timestamp
+                .or(first_time)
+                .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+        };
+        let role = message
+            .get("role")
+            .or_else(|| message.get("author"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match role.as_str() {
+            "user" | "human" => handle_user_message(&mut builder, timestamp, message),
+            "assistant" | "ai" | "model" | "bot" => {
+                handle_assistant_message(&mut builder, timestamp, message)
+            }
+            "tool" | "function" => handle_tool_message(&mut builder, timestamp, message),
+            _ => {}
+        }
+    }
+
+    if !meta.is_empty() {
+        builder.session_meta = Some(Value::Object(meta));
+    }
+    let mut record = builder.finalize();
+    if first_time.is_none() {
+        for turn in &mut record.turns {
+            turn.started_at = None;
+            turn.ended_at = None;
+            turn.duration_seconds = None;
+        }
+    }
+    Ok(record)
+}
+
+/// Time of a message from the first of [`TIME_FIELDS`] that parses.
+fn message_time(message: &Value) -> Option<OffsetDateTime> {
+    TIME_FIELDS
+        .iter()
+        .find_map(|field| match message.get(field)? {
+            Value::String(text) => OffsetDateTime::parse(text, &Rfc3339).ok(),
+            Value::Number(number) => {
+                let seconds = number.as_f64()?;
+                // Unix times in milliseconds passed 1e11 in 1973.
+                let nanos = if seconds.abs() >= 1e11 {
+                    seconds * 1e6
+                } else {
+                    seconds * 1e9
+                };
+                OffsetDateTime::from_unix_timestamp_nanos(nanos as i128).ok()
+            }
+            _ => None,
+        })
+}
+
+/// Text and image URLs of a message's `content`: a string, a part, or a list of parts.
+fn content_parts(content: Option<&Value>) -> (Option<String>, Vec<String>) {
+    let mut texts = Vec::new();
+    let mut images = Vec::new();
+    let parts = match content {
+        Some(Value::Array(parts)) => parts.iter().collect(),
+        Some(part) => vec![part],
+        None => Vec::new(),
+    };
+    for part in parts {
+        match part {
+            Value::String(text) => texts.push(text.as_str()),
+            Value::Object(part) => {
+                if let Some(text) = part.get("text").and_then(Value::as_str) {
+                    texts.push(text);
+                }
+                let image = part.get("image_url").and_then(|image| {
+                    image
+                        .as_str()
+                        .or_else(|| image.get("url").and_then(Value::as_str))
+                });
+                if let Some(image) = image {
+                    images.push(image.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    let text = texts.join("\n");
+    let text = text.trim();
+    ((!text.is_empty()).then(|| text.to_string()), images)
+}
+
+fn handle_user_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    message: &Value,
+) {
+    let (text, images) = content_parts(message.get("content"));
+    if text.is_none() && images.is_empty() {
+        return;
+    }
+    let context = TurnContextInfo {
+        raw: json!({}),
+        cwd: None,
+        approval_policy: None,
+        sandbox_mode: None,
+        sandbox_network_access: None,
+        model: None,
+        effort: None,
+        summary_style: None,
+    };
+    let turn = builder.start_new_turn(context, timestamp);
+    turn.push_user_input(UserInputRecord {
+        raw: message.clone(),
+        text,
+        images,
+        kind: UserInputKind::Prompt,
+        image_refs: Vec::new(),
+        files: Vec::new(),
+    });
+}
+
+fn handle_assistant_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    message: &Value,
+) {
+    let turn = builder.ensure_turn(timestamp);
+    turn.ensure_started_at(timestamp);
+    if let Some(model) = message.get("model").and_then(Value::as_str) {
+        let context = turn.context.get_or_insert_with(|| TurnContextInfo {
+            raw: json!({}),
+            cwd: None,
+            approval_policy: None,
+            sandbox_mode: None,
+            sandbox_network_access: None,
+            model: None,
+            effort: None,
+            summary_style: None,
+        });
+        context.model.get_or_insert_with(|| model.to_string());
+    }
+    if let (Some(text), _) = content_parts(message.get("content")) {
+        turn.push_assistant_message(text);
+    }
+    let calls = message
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for call in calls {
+        let function = call.get("function").unwrap_or(call);
+        let name = function
+            .get("name")
+            .and_then(Value::as_str)
+            .map(String::from);
+        // OpenAI sends arguments as a JSON string; keep it parsed when it is one.
+        let arguments = function.get("arguments").map(|arguments| match arguments {
+            Value::String(text) => serde_json::from_str(text).unwrap_or_else(|_| arguments.clone()),
+            other => other.clone(),
+        });
+        let action = turn.action_builder_mut(call.get("id").and_then(Value::as_str));
+        action.set_kind(ActionKind::FunctionCall { name });
+        action.set_arguments(arguments);
+        action.push_event(timestamp, "tool_call".into(), call.clone());
+    }
+}
+
+fn handle_tool_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    message: &Value,
+) {
+    let turn = builder.ensure_turn(timestamp);
+    let content = content_parts(message.get("content")).0.unwrap_or_default();
+    if let Some(call_id) = message.get("tool_call_id").and_then(Value::as_str) {
+        turn.action_builder_mut(Some(call_id))
+            .set_output(ActionOutput {
+                content: Some(content.clone()),
+                success: None,
+                raw: message.clone(),
+            });
+    }
+    if !content.is_empty() {
+        turn.record_tool_output_text(content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_messages_from_arrays_and_jsonl() {
+        let array = r#"{"id": "log-1", "title": "Retry logic", "messages": [
+            {"role": "system", "content": "You are terse."},
+            {"role": "user", "content": "Add retries", "timestamp": "2025-03-01T09:00:00Z"},
+            {"role": "assistant", "model": "llama-3", "content": null, "tool_calls": [
+                {"id": "c1", "function": {"name": "read_file", "arguments": "{\"path\": \"net.rs\"}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "c1", "content": "fn fetch() {}"},
+            {"role": "assistant", "content": [{"type": "text", "text": "Wrapped fetch in a loop."}],
+             "timestamp": 1740819605000},
+            {"role": "user", "content": "Thanks"}
+        ]}"#;
+        let record = parse_chat_messages(array.as_bytes()).unwrap();
+        let meta = record.session_meta.as_ref().unwrap();
+        assert_eq!(
+            (&meta["id"], &meta["title"]),
+            (&json!("log-1"), &json!("Retry logic"))
+        );
+        assert_eq!(record.duration_seconds, Some(5));
+        assert_eq!(record.turns.len(), 2);
+        let turn = &record.turns[0];
+        assert_eq!(turn.user_inputs[0].text.as_deref(), Some("Add retries"));
+        assert_eq!(
+            turn.context.as_ref().unwrap().model.as_deref(),
+            Some("llama-3")
+        );
+        assert_eq!(turn.result.assistant_messages, ["Wrapped fetch in a loop."]);
+        let action = &turn.actions[0];
+        assert_eq!(action.arguments.as_ref().unwrap()["path"], "net.rs");
+        assert_eq!(
+            action.output.as_ref().unwrap().content.as_deref(),
+            Some("fn fetch() {}")
+        );
+        assert_eq!(record.turns[1].started_at, record.ended_at);
+
+        let jsonl = "{\"role\": \"human\", \"content\": \"Hi\"}\n\n{\"role\": \"ai\", \"content\": \"Hello\"}\n";
+        let record = parse_chat_messages(jsonl.as_bytes()).unwrap();
+        assert_eq!(record.turns.len(), 1);
+        assert_eq!(record.turns[0].result.assistant_messages, ["Hello"]);
+        assert_eq!(
+            (record.started_at, record.turns[0].started_at),
+            (None, None)
+        );
+
+        let err = parse_chat_messages("{\"role\": \"user\"}\nnot json\n".as_bytes()).unwrap_err();
+        assert_eq!(err.line(), 2);
+    }
+}
@@ -0,0 +1,478 @@
+//! Parser for the `conversations.json` file of a ChatGPT data export, whose conversations are
+//! trees of messages keyed by id, mapped onto the same [`ConversationRecord`] model as Codex
+//! rollouts.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use serde_json::{json, Map, Value};
+use time::OffsetDateTime;
+
+use crate::extractor::{snippet, ParseError};
+use crate::types::*;
+
+/// `originator` recorded in the session metadata of imported ChatGPT conversations.
+pub(crate) const CHATGPT_ORIGINATOR: &str = "chatgpt";
+
+/// Parse a ChatGPT `conversations.json` export (or a single exported conversation object) into
+/// one record per conversation, in file order. Only the branch leading to each conversation's
+/// `current_node` is kept, so regenerated answers and edited prompts that were abandoned do not
+/// show up. Every user message opens a turn; assistant messages addressed to a tool (`python`,
+/// `browser`, ...) become actions whose output is the following tool message, and `thoughts`
+/// become reasoning summaries. Errors about a single conversation report its 1-based position in
+/// the export as the line.
+pub fn parse_chatgpt_export<R: Read>(reader: R) -> Result<Vec<ConversationRecord>, ParseError> {
+    read_chatgpt_export(reader)?
+        .iter()
+        .enumerate()
+        .map(|(idx, conversation)| parse_chatgpt_conversation(conversation, idx + 1))
+        .collect()
+}
+
+/// The raw conversation objects of an export, so callers can fingerprint them one by one.
+pub(crate) fn read_chatgpt_export<R: Read>(mut reader: R) -> Result<Vec<Value>, ParseError> {
+    let mut text = String::new();
+    reader
+        .read_to_string(&mut text)
+        .map_err(|source| ParseError::Io {
+            path: None,
+            line: 1,
+            source,
+        })?;
+    let value: Value = serde_json::from_str(&text).map_err(|source| ParseError::Json {
+        path: None,
+        line: source.line(),
+        snippet: snippet(
+            text.lines()
+                .nth(source.line().saturating_sub(1))
+                .unwrap_or(""),
+        ),
+        source,
+    })?;
+    match value {
+        Value::Array(conversations) => Ok(conversations),
+        conversation @ Value::Object(_) => Ok(vec![conversation]),
+        other => Err(ParseError::MissingField {
+            path: None,
+            line: 1,
+            field: "mapping",
+            snippet: snippet(&other.to_string()),
+        }),
+    }
+}
+
+/// Parse one exported conversation; `position` is reported as the line of errors.
+pub(crate) fn parse_chatgpt_conversation(
+    conversation: &Value,
+    position: usize,
+) -> Result<ConversationRecord, ParseError> {
+    let title = conversation.get("title").and_then(Value::as_str);
+    let missing = |field| ParseError::MissingField {
+        path: None,
+        line: position,
+        field,
+        snippet: snippet(title.unwrap_or_default()),
+    };
+    let mapping = conversation
+        .get("mapping")
+        .and_then(Value::as_object)
+        .ok_or_else(|| missing("mapping"))?;
+    let default_model = conversation
+        .get("default_model_slug")
+        .and_then(Value::as_str);
+
+    let mut builder = ConversationBuilder::default();
+    if let Some(created) = conversation.get("create_time").and_then(unix_time) {
+        builder.observe_timestamp(created);
+    }
+    // Tool name -> id of the assistant message that called it and awaits its output.
+    let mut pending_calls: HashMap<String, String> = HashMap::new();
+    for message in current_thread(mapping, conversation) {
+        let timestamp = match message.get("create_time").and_then(unix_time) {
+            Some(timestamp) => {
+                builder.observe_timestamp(timestamp);
+                timestamp
+            }
+            None => builder
+                .last_timestamp
+                .ok_or_else(|| missing("create_time"))?,
+        };
+        let metadata = message.get("metadata");
+        if metadata
+            .and_then(|metadata| metadata.get("is_visually_hidden_from_conversation"))
+            .and_then(Value::as_bool)
+            == Some(true)
+        {
+            continue;
+        }
+        let author = message.get("author");
+        let role = author
+            .and_then(|author| author.get("role"))
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        match role {
+            "user" => handle_user_message(&mut builder, timestamp, message),
+            "assistant" => {
+                let model = metadata
+                    .and_then(|metadata| metadata.get("model_slug"))
+                    .and_then(Value::as_str)
+                    .or(default_model);
+                handle_assistant_message(
+                    &mut builder,
+                    timestamp,
+                    message,
+                    model,
+                    &mut pending_calls,
+                );
+            }
+            "tool" => {
+                let name = author
+                    .and_then(|author| author.get("name"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                handle_tool_message(&mut builder, timestamp, message, name, &mut pending_calls);
+            }
+            _ => {}
+        }
+    }
+
+    let mut meta = Map::new();
+    let id = conversation
+        .get("conversation_id")
+        .or_else(|| conversation.get("id"))
+        .and_then(Value::as_str);
+    if let Some(id) = id {
+        meta.insert("id".into(), id.into());
+    }
+    if let Some(title) = title {
+        meta.insert("title".into(), title.into());
+    }
+    if let Some(model) = default_model {
+        meta.insert("model".into(), model.into());
+    }
+    meta.insert("originator".into(), CHATGPT_ORIGINATOR.into());
+    builder.session_meta = Some(Value::Object(meta));
+    Ok(builder.finalize())
+}
+
+/// Messages from the root of the tree down to `current_node`, oldest first. Exports without a
+/// current node fall back to following the last child of every node.
+fn current_thread<'a>(mapping: &'a Map<String, Value>, conversation: &Value) -> Vec<&'a Value> {
+    let mut ids: Vec<&str> = Vec::new();
+    if let Some(current) = conversation.get("current_node").and_then(Value::as_str) {
+        let mut next = Some(current);
+        while let Some(id) = next {
+            // The parent chain of a well-formed export never revisits a node.
+            if ids.len() > mapping.len() {
+                break;
+            }
+            ids.push(id);
+            next = mapping
+                .get(id)
+                .and_then(|node| node.get("parent"))
+                .and_then(Value::as_str);
+        }
+        ids.reverse();
+    } else {
+        let root = mapping.iter().find(|(_, node)| {
+            node.get("parent")
+                .and_then(Value::as_str)
+                .is_none_or(|parent| !mapping.contains_key(parent))
+        });
+        let mut next = root.map(|(id, _)| id.as_str());
+        while let Some(id) = next {
+            if ids.len() > mapping.len() {
+                break;
+            }
+            ids.push(id);
+            next = mapping
+                .get(id)
+                .and_then(|node| node.get("children"))
+                .and_then(Value::as_array)
+                .and_then(|children| children.last())
+                .and_then(Value::as_str);
+        }
+    }
+    ids.into_iter()
+        .filter_map(|id| mapping.get(id)?.get("message"))
+        .filter(|message| !message.is_null())
+        .collect()
+}
+
+/// Seconds since the epoch, as exports store them, to a timestamp.
+fn unix_time(value: &Value) -> Option<OffsetDateTime> {
+    let seconds = value.as_f64()?;
+    OffsetDateTime::from_unix_timestamp_nanos((seconds * 1e9) as i128).ok()
+}
+
+/// Text of a message: its string `parts` or its `text`, trimmed; `None` when empty.
+fn message_text(content: &Value) -> Option<String> {
+    let text = match content.get("parts").and_then(Value::as_array) {
+        Some(parts) => parts
+            .iter()
+            .filter_map(Value::as_str)
+            .filter(|part| !part.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => content
+            .get("text")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+    };
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn handle_user_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    message: &Value,
+) {
+    let content = message.get("content").unwrap_or(&Value::Null);
+    if content.get("content_type").and_then(Value::as_str) == Some("user_editable_context") {
+        return;
+    }
+    let text = message_text(content);
+    let images: Vec<String> = content
+        .get("parts")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|part| part.get("asset_pointer").and_then(Value::as_str))
+        .map(String::from)
+        .collect();
+    if text.is_none() && images.is_empty() {
+        return;
+    }
+    let context = TurnContextInfo {
+        raw: json!({}),
+        cwd: None,
+        approval_policy: None,
+        sandbox_mode: None,
+        sandbox_network_access: None,
+        model: None,
+        effort: None,
+        summary_style: None,
+    };
+    let turn = builder.start_new_turn(context, timestamp);
+    turn.push_user_input(UserInputRecord {
+        raw: message.clone(),
+        text,
+        images,
+        kind: UserInputKind::Prompt,
+        image_refs: Vec::new(),
+        files: Vec::new(),
+    });
+}
+
+fn handle_assistant_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    message: &Value,
+    model: Option<&str>,
+    pending_calls: &mut HashMap<String, String>,
+) {
+    let turn = builder.ensure_turn(timestamp);
+    turn.ensure_started_at(timestamp);
+    if let Some(model) = model {
+        let context = turn.context.get_or_insert_with(|| TurnContextInfo {
+            raw: json!({}),
+            cwd: None,
+            approval_policy: None,
+            sandbox_mode: None,
+            sandbox_network_access: None,
+            model: None,
+            effort: None,
+            summary_style: None,
+        });
+        context.model.get_or_insert_with(|| model.to_string());
+    }
+
+    let content = message.get("content").unwrap_or(&Value::Null);
+    let content_type = content
+        .get("content_type")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let recipient = message
+        .get("recipient")
+        .and_then(Value::as_str)
+        .unwrap_or("all");
+    if recipient != "all" {
+        let call_id = message
+            .get("id")
+            .and_then(Value::as_str)
+            .unwrap_or(recipient);
+        let action = turn.action_builder_mut(Some(call_id));
+        action.set_kind(ActionKind::FunctionCall {
+            name: Some(recipient.to_string()),
+        });
+        action.set_arguments(
+            message_text(content)
+                .map(|text| serde_json::from_str(&text).unwrap_or(Value::String(text))),
+        );
+        action.push_event(timestamp, "tool_call".into(), message.clone());
+        pending_calls.insert(recipient.to_string(), call_id.to_string());
+        return;
+    }
+    match content_type {
+        "thoughts" => {
+            let thoughts = content
+                .get("thoughts")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for thought in thoughts {
+                let text = thought
+                    .get("content")
+                    .or_else(|| thought.get("summary"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                if !text.trim().is_empty() {
+                    turn.push_reasoning_summary(text.to_string());
+                }
+            }
+        }
+        "reasoning_recap" | "model_editable_context" => {}
+        _ => {
+            if let Some(text) = message_text(content) {
+                turn.push_assistant_message(text);
+            }
+        }
+    }
+}
+
+fn handle_tool_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    message: &Value,
+    name: &str,
+    pending_calls: &mut HashMap<String, String>,
+) {
+    let turn = builder.ensure_turn(timestamp);
+    let Some(content) = message.get("content").and_then(message_text) else {
+        return;
+    };
+    // Tools are named like their recipient, possibly with a method suffix (`browser.search`).
+    let tool = name.split('.').next().unwrap_or(name);
+    let call_id = pending_calls
+        .remove(name)
+        .or_else(|| pending_calls.remove(tool));
+    if let Some(call_id) = call_id {
+        let success = message
+            .get("status")
+            .and_then(Value::as_str)
+            .is_none_or(|status| status == "finished_successfully");
+        turn.action_builder_mut(Some(&call_id))
+            .set_output(ActionOutput {
+                content: Some(content.clone()),
+                success: Some(success),
+                raw: message.clone(),
+            });
+    }
+    turn.record_tool_output_text(content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, parent: Option<&str>, message: Value) -> (String, Value) {
+        (
+            id.to_string(),
+            json!({ "id": id, "parent": parent, "children": [], "message": message }),
+        )
+    }
+
+    #[test]
+    fn follows_the_current_branch_and_maps_tool_calls() {
+        let mapping: Map<String, Value> = [
+            node("root", None, Value::Null),
+            node(
+                "q1",
+                Some("root"),
+                json!({
+                    "id": "q1", "author": {"role": "user"}, "create_time": 1_735_689_600.0,
+                    "content": {"content_type": "text", "parts": ["Plot sin(x) please"]}
+                }),
+            ),
+            node(
+                "old",
+                Some("q1"),
+                json!({
+                    "id": "old", "author": {"role": "assistant"}, "create_time": 1_735_689_601.0,
+                    "content": {"content_type": "text", "parts": ["An abandoned answer"]}
+                }),
+            ),
+            node(
+                "call",
+                Some("q1"),
+                json!({
+                    "id": "call", "author": {"role": "assistant"}, "recipient": "python",
+                    "create_time": 1_735_689_602.0,
+                    "metadata": {"model_slug": "gpt-4o"},
+                    "content": {"content_type": "code", "text": "plot(sin)"}
+                }),
+            ),
+            node(
+                "out",
+                Some("call"),
+                json!({
+                    "id": "out", "author": {"role": "tool", "name": "python"},
+                    "create_time": 1_735_689_603.0, "status": "finished_successfully",
+                    "content": {"content_type": "execution_output", "text": "<figure>"}
+                }),
+            ),
+            node(
+                "answer",
+                Some("out"),
+                json!({
+                    "id": "answer", "author": {"role": "assistant"}, "recipient": "all",
+                    "content": {"content_type": "text", "parts": ["Here is the plot."]}
+                }),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let export = json!([{
+            "title": "Plotting sine",
+            "create_time": 1_735_689_600.0,
+            "conversation_id": "c-1",
+            "default_model_slug": "gpt-4o-mini",
+            "current_node": "answer",
+            "mapping": mapping,
+        }]);
+
+        let records = parse_chatgpt_export(export.to_string().as_bytes()).unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        let meta = record.session_meta.as_ref().unwrap();
+        assert_eq!(meta["id"], "c-1");
+        assert_eq!(meta["title"], "Plotting sine");
+        assert_eq!(meta["originator"], CHATGPT_ORIGINATOR);
+
+        assert_eq!(record.turns.len(), 1);
+        let turn = &record.turns[0];
+        assert_eq!(
+            turn.user_inputs[0].text.as_deref(),
+            Some("Plot sin(x) please")
+        );
+        assert_eq!(turn.result.assistant_messages, ["Here is the plot."]);
+        assert_eq!(
+            turn.context.as_ref().unwrap().model.as_deref(),
+            Some("gpt-4o")
+        );
+        let action = &turn.actions[0];
+        assert_eq!(action.call_id.as_deref(), Some("call"));
+        assert!(
+            matches!(&action.kind, ActionKind::FunctionCall { name } if name.as_deref() == Some("python"))
+        );
+        assert_eq!(
+            action.output.as_ref().unwrap().content.as_deref(),
+            Some("<figure>")
+        );
+        // The answer without a time of its own inherits the tool output's.
+        assert_eq!(record.ended_at.unwrap().unix_timestamp(), 1_735_689_603);
+    }
+}
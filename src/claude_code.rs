@@ -0,0 +1,465 @@
+//! Parser for Claude Code session transcripts (`~/.claude/projects/<project>/<session>.jsonl`),
+//! mapped onto the same [`ConversationRecord`] model as Codex rollouts.
+
+use std::collections::HashSet;
+use std::io::BufRead;
+
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::attachments::file_attachment;
+use crate::extractor::{snippet, split_mcp_tool_name, ParseError, ParseOptions};
+use crate::types::*;
+
+/// `originator` recorded in the session metadata of Claude Code sessions, next to Codex's own
+/// originators, so the two can be told apart with `Filter::meta_eq("originator", ...)`.
+pub(crate) const CLAUDE_CODE_ORIGINATOR: &str = "claude_code";
+
+/// Parse a Claude Code session JSONL stream. Every user prompt opens a turn; assistant text,
+/// thinking and `tool_use` blocks fill it, and `tool_result` blocks become the output of the
+/// action with the matching id. The session id, working directory, git branch and CLI version of
+/// the entries are collected into a synthesized `session_meta`, and token usage is summed over
+/// the distinct assistant messages. Meta and sidechain (subagent) entries are kept as telemetry
+/// of the current turn rather than opening turns of their own.
+pub fn parse_claude_code_session<R: BufRead>(reader: R) -> Result<ConversationRecord, ParseError> {
+    parse_claude_code_from(reader, ParseOptions::default(), None)
+}
+
+/// Like [`parse_claude_code_session`], feeding every line read to `hasher`.
+pub(crate) fn parse_claude_code_from<R: BufRead>(
+    mut reader: R,
+    options: ParseOptions,
+    mut hasher: Option<&mut Sha256>,
+) -> Result<ConversationRecord, ParseError> {
+    let mut builder = ConversationBuilder::default();
+    let mut meta = Map::new();
+    let mut total: Option<TokenUsageBreakdown> = None;
+    let mut counted_messages: HashSet<String> = HashSet::new();
+    let mut line_no = 0;
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        let read = reader
+            .read_line(&mut buf)
+            .map_err(|source| ParseError::Io {
+                path: None,
+                line: line_no + 1,
+                source,
+            })?;
+        if read == 0 {
+            break;
+        }
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(buf.as_bytes());
+        }
+        line_no += 1;
+        builder.line = line_no;
+        let line = buf.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: Value = match serde_json::from_str(line) {
+            Ok(value) => value,
+            Err(source) => {
+                let error = ParseError::Json {
+                    path: None,
+                    line: line_no,
+                    snippet: snippet(line),
+                    source,
+                };
+                options.recover(error, &mut builder.warnings)?;
+                continue;
+            }
+        };
+        let entry_type = value
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if entry_type == "summary" {
+            if let Some(summary) = value.get("summary").and_then(Value::as_str) {
+                meta.insert("summary".into(), summary.into());
+            }
+            continue;
+        }
+        if !matches!(entry_type, "user" | "assistant" | "system") {
+            continue;
+        }
+
+        let timestamp = match value.get("timestamp").and_then(Value::as_str) {
+            Some(timestamp_str) => match OffsetDateTime::parse(timestamp_str, &Rfc3339) {
+                Ok(parsed) => {
+                    builder.observe_timestamp(parsed);
+                    parsed
+                }
+                Err(source) => {
+                    let error = ParseError::Timestamp {
+                        path: None,
+                        line: line_no,
+                        value: timestamp_str.to_string(),
+                        source,
+                    };
+                    options.recover(error, &mut builder.warnings)?;
+                    continue;
+                }
+            },
+            None => match builder.last_timestamp {
+                Some(last) => last,
+                None => {
+                    let error = ParseError::MissingField {
+                        path: None,
+                        line: line_no,
+                        field: "timestamp",
+                        snippet: snippet(line),
+                    };
+                    options.recover(error, &mut builder.warnings)?;
+                    continue;
+                }
+            },
+        };
+        record_session_meta(&mut meta, &value);
+
+        let side_entry = entry_type == "system"
+            || value.get("isMeta").and_then(Value::as_bool) == Some(true)
+            || value.get("isSidechain").and_then(Value::as_bool) == Some(true);
+        if side_entry {
+            let turn = builder.ensure_turn(timestamp);
+            turn.telemetry.misc_events.push(Timed {
+                timestamp,
+                data: value,
+            });
+            continue;
+        }
+
+        let Some(message) = value.get("message") else {
+            continue;
+        };
+        if entry_type == "user" {
+            handle_user_message(&mut builder, timestamp, &value, message);
+        } else {
+            let message_id = message.get("id").and_then(Value::as_str);
+            let usage = message.get("usage").map(usage_breakdown);
+            handle_assistant_message(&mut builder, timestamp, message);
+            // Claude Code writes one entry per content block, each repeating the message usage.
+            if let Some(usage) = usage {
+                if message_id.is_none_or(|id| counted_messages.insert(id.to_string())) {
+                    add_usage(total.get_or_insert_with(Default::default), &usage);
+                    builder.token_usage.last = Some(usage);
+                }
+            }
+        }
+    }
+
+    if !meta.is_empty() {
+        meta.insert("originator".into(), CLAUDE_CODE_ORIGINATOR.into());
+        builder.session_meta = Some(Value::Object(meta));
+    }
+    builder.token_usage.total = total;
+    Ok(builder.finalize())
+}
+
+/// Keep the first session id, working directory, git branch and CLI version seen.
+fn record_session_meta(meta: &mut Map<String, Value>, entry: &Value) {
+    for (from, to) in [
+        ("sessionId", "id"),
+        ("cwd", "cwd"),
+        ("version", "cli_version"),
+    ] {
+        if let Some(value) = entry.get(from).and_then(Value::as_str) {
+            meta.entry(to).or_insert_with(|| value.into());
+        }
+    }
+    if let Some(branch) = entry.get("gitBranch").and_then(Value::as_str) {
+        if !branch.is_empty() {
+            meta.entry("git")
+                .or_insert_with(|| json!({ "branch": branch }));
+        }
+    }
+}
+
+fn handle_user_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    entry: &Value,
+    message: &Value,
+) {
+    let mut text_parts = Vec::new();
+    let mut images = Vec::new();
+    let mut files = Vec::new();
+    match message.get("content") {
+        Some(Value::String(text)) => text_parts.push(text.clone()),
+        Some(Value::Array(blocks)) => {
+            for block in blocks {
+                match block
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                {
+                    "text" => {
+                        if let Some(text) = block.get("text").and_then(Value::as_str) {
+                            text_parts.push(text.to_string());
+                        }
+                    }
+                    "image" => {
+                        if let Some(image) = block.get("source").and_then(image_url) {
+                            images.push(image);
+                        }
+                    }
+                    "document" => files.extend(file_attachment(block)),
+                    "tool_result" => {
+                        let turn = builder.ensure_turn(timestamp);
+                        handle_tool_result(turn, block);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    if text_parts.is_empty() && images.is_empty() && files.is_empty() {
+        return;
+    }
+
+    let cwd = entry.get("cwd").and_then(Value::as_str).map(String::from);
+    let permission_mode = entry
+        .get("permissionMode")
+        .and_then(Value::as_str)
+        .map(String::from);
+    let context = TurnContextInfo {
+        raw: json!({
+            "cwd": cwd,
+            "git_branch": entry.get("gitBranch"),
+            "permission_mode": permission_mode,
+        }),
+        cwd,
+        approval_policy: permission_mode,
+        sandbox_mode: None,
+        sandbox_network_access: None,
+        model: None,
+        effort: None,
+        summary_style: None,
+    };
+    let turn = builder.start_new_turn(context, timestamp);
+    turn.push_user_input(UserInputRecord {
+        raw: message.clone(),
+        text: (!text_parts.is_empty()).then(|| text_parts.join("\n")),
+        images,
+        kind: UserInputKind::Prompt,
+        image_refs: Vec::new(),
+        files,
+    });
+}
+
+fn handle_assistant_message(
+    builder: &mut ConversationBuilder,
+    timestamp: OffsetDateTime,
+    message: &Value,
+) {
+    let turn = builder.ensure_turn(timestamp);
+    turn.ensure_started_at(timestamp);
+    let model = message
+        .get("model")
+        .and_then(Value::as_str)
+        .filter(|model| !model.starts_with('<'));
+    if let Some(model) = model {
+        let context = turn.context.get_or_insert_with(|| TurnContextInfo {
+            raw: json!({}),
+            cwd: None,
+            approval_policy: None,
+            sandbox_mode: None,
+            sandbox_network_access: None,
+            model: None,
+            effort: None,
+            summary_style: None,
+        });
+        context.model.get_or_insert_with(|| model.to_string());
+    }
+
+    let blocks = message
+        .get("content")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for block in blocks {
+        match block
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "text" => {
+                if let Some(text) = block.get("text").and_then(Value::as_str) {
+                    if !text.trim().is_empty() {
+                        turn.push_assistant_message(text.to_string());
+                    }
+                }
+            }
+            "thinking" => {
+                if let Some(text) = block.get("thinking").and_then(Value::as_str) {
+                    if !text.trim().is_empty() {
+                        turn.push_reasoning_summary(text.to_string());
+                    }
+                }
+            }
+            "redacted_thinking" => turn.mark_reasoning_encrypted(),
+            "tool_use" => {
+                let call_id = block.get("id").and_then(Value::as_str);
+                let name = block.get("name").and_then(Value::as_str).map(String::from);
+                let action = turn.action_builder_mut(call_id);
+                // Claude Code exposes MCP tools as `mcp__<server>__<tool>`.
+                match name
+                    .as_deref()
+                    .filter(|name| name.starts_with("mcp__"))
+                    .and_then(split_mcp_tool_name)
+                {
+                    Some((server, tool)) => action.set_kind(ActionKind::McpToolCall {
+                        server: Some(server),
+                        tool: Some(tool),
+                    }),
+                    None => action.set_kind(ActionKind::FunctionCall { name }),
+                }
+                action.set_arguments(block.get("input").cloned());
+                action.push_event(timestamp, "tool_use".into(), block.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn handle_tool_result(turn: &mut TurnBuilder, block: &Value) {
+    let call_id = block.get("tool_use_id").and_then(Value::as_str);
+    let content = match block.get("content") {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    };
+    let is_error = block.get("is_error").and_then(Value::as_bool);
+    turn.action_builder_mut(call_id).set_output(ActionOutput {
+        content: Some(content.clone()),
+        success: Some(!is_error.unwrap_or(false)),
+        raw: block.clone(),
+    });
+    turn.record_tool_output_text(content);
+}
+
+/// A `data:` URL for a base64 image block, or the URL of a linked one.
+fn image_url(source: &Value) -> Option<String> {
+    match source.get("type").and_then(Value::as_str)? {
+        "base64" => Some(format!(
+            "data:{};base64,{}",
+            source.get("media_type").and_then(Value::as_str)?,
+            source.get("data").and_then(Value::as_str)?
+        )),
+        "url" => source.get("url").and_then(Value::as_str).map(String::from),
+        _ => None,
+    }
+}
+
+/// Map an Anthropic `usage` object onto the Codex breakdown: input counts fresh, cache-written
+/// and cache-read tokens, with the cache reads also reported as cached input.
+fn usage_breakdown(usage: &Value) -> TokenUsageBreakdown {
+    let get = |key: &str| usage.get(key).and_then(Value::as_u64);
+    let cache_read = get("cache_read_input_tokens");
+    let input = [
+        get("input_tokens"),
+        get("cache_creation_input_tokens"),
+        cache_read,
+    ]
+    .into_iter()
+    .flatten()
+    .reduce(|sum, tokens| sum + tokens);
+    let output = get("output_tokens");
+    TokenUsageBreakdown {
+        input_tokens: input,
+        cached_input_tokens: cache_read,
+        output_tokens: output,
+        reasoning_output_tokens: None,
+        total_tokens: match (input, output) {
+            (None, None) => None,
+            (input, output) => Some(input.unwrap_or(0) + output.unwrap_or(0)),
+        },
+    }
+}
+
+fn add_usage(total: &mut TokenUsageBreakdown, usage: &TokenUsageBreakdown) {
+    for (sum, tokens) in [
+        (&mut total.input_tokens, usage.input_tokens),
+        (&mut total.cached_input_tokens, usage.cached_input_tokens),
+        (&mut total.output_tokens, usage.output_tokens),
+        (&mut total.total_tokens, usage.total_tokens),
+    ] {
+        if let Some(tokens) = tokens {
+            *sum = Some(sum.unwrap_or(0) + tokens);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_prompts_tool_calls_and_usage_onto_turns() {
+        let data = r#"{"type":"summary","summary":"Fix the flaky test","leafUuid":"u4"}
+{"type":"user","sessionId":"s-1","cwd":"/work/repo","gitBranch":"main","version":"1.0.80","uuid":"u1","timestamp":"2025-06-01T10:00:00.000Z","message":{"role":"user","content":"Why does storage_test flake?"}}
+{"type":"assistant","sessionId":"s-1","uuid":"u2","timestamp":"2025-06-01T10:00:05.000Z","message":{"id":"msg_1","model":"claude-sonnet-4-5","role":"assistant","content":[{"type":"thinking","thinking":"Check the test first."}],"usage":{"input_tokens":10,"cache_read_input_tokens":90,"output_tokens":5}}}
+{"type":"assistant","sessionId":"s-1","uuid":"u3","timestamp":"2025-06-01T10:00:06.000Z","message":{"id":"msg_1","model":"claude-sonnet-4-5","role":"assistant","content":[{"type":"tool_use","id":"toolu_1","name":"Bash","input":{"command":"cargo test storage"}}],"usage":{"input_tokens":10,"cache_read_input_tokens":90,"output_tokens":5}}}
+{"type":"user","sessionId":"s-1","uuid":"u4","timestamp":"2025-06-01T10:00:09.000Z","message":{"role":"user","content":[{"type":"tool_result","tool_use_id":"toolu_1","content":"1 failed","is_error":true}]}}
+{"type":"user","isMeta":true,"sessionId":"s-1","timestamp":"2025-06-01T10:00:10.000Z","message":{"role":"user","content":"Caveat: local command output follows."}}
+{"type":"assistant","sessionId":"s-1","timestamp":"2025-06-01T10:00:12.000Z","message":{"id":"msg_2","model":"claude-sonnet-4-5","role":"assistant","content":[{"type":"text","text":"The test shares a temp dir."}],"usage":{"input_tokens":20,"output_tokens":7}}}
+{"type":"user","sessionId":"s-1","timestamp":"2025-06-01T10:01:00.000Z","message":{"role":"user","content":[{"type":"text","text":"Fix it"}]}}
+"#;
+        let record = parse_claude_code_session(std::io::Cursor::new(data)).unwrap();
+        let meta = record.session_meta.as_ref().unwrap();
+        assert_eq!(meta["id"], "s-1");
+        assert_eq!(meta["cwd"], "/work/repo");
+        assert_eq!(meta["git"]["branch"], "main");
+        assert_eq!(meta["summary"], "Fix the flaky test");
+        assert_eq!(meta["originator"], CLAUDE_CODE_ORIGINATOR);
+        assert_eq!(record.duration_seconds, Some(60));
+
+        assert_eq!(record.turns.len(), 2);
+        let turn = &record.turns[0];
+        assert_eq!(
+            turn.user_inputs[0].text.as_deref(),
+            Some("Why does storage_test flake?")
+        );
+        let context = turn.context.as_ref().unwrap();
+        assert_eq!(context.model.as_deref(), Some("claude-sonnet-4-5"));
+        assert_eq!(context.cwd.as_deref(), Some("/work/repo"));
+        assert_eq!(turn.result.reasoning_summaries, ["Check the test first."]);
+        assert_eq!(
+            turn.result.assistant_messages,
+            ["The test shares a temp dir."]
+        );
+        let action = &turn.actions[0];
+        assert!(
+            matches!(&action.kind, ActionKind::FunctionCall { name } if name.as_deref() == Some("Bash"))
+        );
+        assert_eq!(
+            action.arguments.as_ref().unwrap()["command"],
+            "cargo test storage"
+        );
+        let output = action.output.as_ref().unwrap();
+        assert_eq!(
+            (output.content.as_deref(), output.success),
+            (Some("1 failed"), Some(false))
+        );
+        assert_eq!(turn.telemetry.misc_events.len(), 1);
+        assert_eq!(
+            record.turns[1].user_inputs[0].text.as_deref(),
+            Some("Fix it")
+        );
+
+        // msg_1 is counted once although two entries repeat its usage.
+        let total = record.token_usage.total.as_ref().unwrap();
+        assert_eq!(total.input_tokens, Some(120));
+        assert_eq!(total.cached_input_tokens, Some(90));
+        assert_eq!(total.output_tokens, Some(12));
+        assert_eq!(total.total_tokens, Some(132));
+    }
+}
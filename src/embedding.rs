@@ -1,5 +1,10 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 #[cfg(feature = "embedding-runtime")]
@@ -32,6 +37,42 @@ impl EmbeddingModelConfig {
     }
 }
 
+/// Identity of an embedding model, recorded in the database so vectors from different models
+/// are never compared with each other.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmbeddingModelInfo {
+    /// Model file name without extension (e.g. `nomic-embed-text-v1.5.Q4_K_M`).
+    pub name: String,
+    pub dim: usize,
+    /// Hex SHA-256 of the model file.
+    pub file_hash: String,
+}
+
+impl EmbeddingModelInfo {
+    /// Describe the model stored at `path`, hashing the file.
+    pub fn for_model_file(path: impl AsRef<Path>, dim: usize) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Self {
+            name,
+            dim,
+            file_hash: format!("{:x}", hasher.finalize()),
+        })
+    }
+}
+
+impl fmt::Display for EmbeddingModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = self.file_hash.get(..12).unwrap_or(&self.file_hash);
+        write!(f, "{} ({} dims, sha256 {hash})", self.name, self.dim)
+    }
+}
+
 /// Errors produced by the embedding runtime.
 #[derive(Error, Debug)]
 pub enum EmbeddingError {
@@ -41,6 +82,8 @@ pub enum EmbeddingError {
     #[cfg(feature = "embedding-runtime")]
     #[error("embedding inference failed: {0}")]
     Inference(#[from] llama_cpp::LlamaContextError),
+    #[error("failed to read model file: {0}")]
+    Io(#[from] io::Error),
     #[error("embedding output missing")]
     MissingOutput,
     #[error("embedding runtime not available in this build; recompile with the `embedding-runtime` feature")]
@@ -52,6 +95,7 @@ pub struct EmbeddingModel {
     model: LlamaModel,
     threads: u32,
     threads_batch: u32,
+    info: EmbeddingModelInfo,
 }
 
 #[cfg(feature = "embedding-runtime")]
@@ -65,7 +109,8 @@ impl EmbeddingModel {
         params.use_mmap = true;
         params.use_mlock = false;
 
-        let model = LlamaModel::load_from_file(config.model_path, params)?;
+        let model = LlamaModel::load_from_file(&config.model_path, params)?;
+        let info = EmbeddingModelInfo::for_model_file(&config.model_path, model.embed_len())?;
         let threads = config
             .threads
             .unwrap_or_else(|| (num_cpus::get_physical().saturating_sub(1)).max(1) as u32);
@@ -75,6 +120,7 @@ impl EmbeddingModel {
             model,
             threads,
             threads_batch,
+            info,
         })
     }
 
@@ -109,10 +155,17 @@ impl EmbeddingModel {
     pub fn embedding_dim(&self) -> usize {
         self.model.embed_len()
     }
+
+    /// Name, dimension and file hash of the loaded model.
+    pub fn info(&self) -> &EmbeddingModelInfo {
+        &self.info
+    }
 }
 
 #[cfg(not(feature = "embedding-runtime"))]
-pub struct EmbeddingModel;
+pub struct EmbeddingModel {
+    info: EmbeddingModelInfo,
+}
 
 #[cfg(not(feature = "embedding-runtime"))]
 impl EmbeddingModel {
@@ -134,6 +187,10 @@ impl EmbeddingModel {
     pub fn embedding_dim(&self) -> usize {
         0
     }
+
+    pub fn info(&self) -> &EmbeddingModelInfo {
+        &self.info
+    }
 }
 
 #[cfg(all(test, feature = "embedding-runtime"))]
@@ -1,59 +1,732 @@
-use std::io::BufRead;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
+use crate::attachments::file_attachment;
+use crate::chat_messages::parse_chat_messages_from;
+use crate::claude_code::parse_claude_code_from;
 use crate::types::*;
 
+/// File name endings of rollouts: plain JSONL, or compressed with gzip or zstd.
+const ROLLOUT_SUFFIXES: [&str; 3] = [".jsonl", ".jsonl.gz", ".jsonl.zst"];
+
+/// Maximum number of characters of the offending record kept in a [`ParseError`].
+const SNIPPET_CHARS: usize = 160;
+
+/// Errors raised while parsing a rollout. Every variant records the 1-based line number of the
+/// offending record, plus the rollout path once the pipeline attaches it via
+/// [`ParseError::with_path`].
 #[derive(Debug, Error)]
 pub enum ParseError {
-    #[error("io error: {0}")]
-    Io(#[from] std::io::Error),
-    #[error("json error: {0}")]
-    Json(#[from] serde_json::Error),
-    #[error("missing field '{0}'")]
-    MissingField(&'static str),
-    #[error("invalid timestamp '{0}': {1}")]
-    Timestamp(String, time::error::Parse),
+    #[error("{}: io error: {source}", location(.path, *.line))]
+    Io {
+        path: Option<PathBuf>,
+        line: usize,
+        source: std::io::Error,
+    },
+    #[error("{}: json error: {source} in `{snippet}`", location(.path, *.line))]
+    Json {
+        path: Option<PathBuf>,
+        line: usize,
+        snippet: String,
+        source: serde_json::Error,
+    },
+    #[error("{}: missing field '{field}' in `{snippet}`", location(.path, *.line))]
+    MissingField {
+        path: Option<PathBuf>,
+        line: usize,
+        field: &'static str,
+        snippet: String,
+    },
+    #[error("{}: invalid timestamp '{value}': {source}", location(.path, *.line))]
+    Timestamp {
+        path: Option<PathBuf>,
+        line: usize,
+        value: String,
+        source: time::error::Parse,
+    },
+}
+
+impl ParseError {
+    /// Attach the rollout path the failing stream was read from.
+    pub fn with_path(mut self, rollout_path: impl AsRef<Path>) -> Self {
+        let slot = match &mut self {
+            ParseError::Io { path, .. }
+            | ParseError::Json { path, .. }
+            | ParseError::MissingField { path, .. }
+            | ParseError::Timestamp { path, .. } => path,
+        };
+        *slot = Some(rollout_path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Rollout path the error originated from, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ParseError::Io { path, .. }
+            | ParseError::Json { path, .. }
+            | ParseError::MissingField { path, .. }
+            | ParseError::Timestamp { path, .. } => path.as_deref(),
+        }
+    }
+
+    /// 1-based line number of the offending record.
+    pub fn line(&self) -> usize {
+        match self {
+            ParseError::Io { line, .. }
+            | ParseError::Json { line, .. }
+            | ParseError::MissingField { line, .. }
+            | ParseError::Timestamp { line, .. } => *line,
+        }
+    }
+}
+
+fn location(path: &Option<PathBuf>, line: usize) -> String {
+    match path {
+        Some(path) => format!("{}:{line}", path.display()),
+        None => format!("line {line}"),
+    }
+}
+
+/// How strictly transcripts are read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Skip malformed JSON lines, records of unknown type and records with a missing or
+    /// invalid timestamp, listing them in [`ConversationRecord::warnings`], instead of failing
+    /// on the first one. I/O errors still fail the parse.
+    pub lenient: bool,
+    /// Where Codex rollouts are cut into turns.
+    pub segmentation: TurnSegmentation,
+}
+
+/// How a Codex rollout is split into turns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TurnSegmentation {
+    /// A new turn starts at each `turn_context` record. Rollouts from Codex versions that did
+    /// not write them become a single turn.
+    #[default]
+    TurnContext,
+    /// A new turn also starts at each user prompt after the current turn's first one, so
+    /// legacy rollouts get one turn per request. Injected context messages never open a turn.
+    UserMessage,
+    /// [`TurnSegmentation::UserMessage`] until the first `turn_context` record, then
+    /// [`TurnSegmentation::TurnContext`]: suits archives mixing old and new rollouts.
+    Auto,
+}
+
+impl TurnSegmentation {
+    fn name(self) -> &'static str {
+        match self {
+            TurnSegmentation::TurnContext => "turn-context",
+            TurnSegmentation::UserMessage => "user-message",
+            TurnSegmentation::Auto => "auto",
+        }
+    }
+}
+
+impl FromStr for TurnSegmentation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "turn-context" => Ok(TurnSegmentation::TurnContext),
+            "user-message" => Ok(TurnSegmentation::UserMessage),
+            "auto" => Ok(TurnSegmentation::Auto),
+            other => Err(format!(
+                "unknown turn segmentation '{other}', expected turn-context, user-message or auto"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TurnSegmentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl ParseOptions {
+    /// In lenient mode, record `error` in `warnings` so the caller can skip its line;
+    /// otherwise hand it back.
+    pub(crate) fn recover(
+        &self,
+        error: ParseError,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<(), ParseError> {
+        let kind = match &error {
+            _ if !self.lenient => return Err(error),
+            ParseError::Io { .. } => return Err(error),
+            ParseError::Json { .. } => ParseWarningKind::MalformedJson,
+            ParseError::MissingField { .. } => ParseWarningKind::MissingField,
+            ParseError::Timestamp { .. } => ParseWarningKind::InvalidTimestamp,
+        };
+        warnings.push(ParseWarning {
+            line: error.line(),
+            kind,
+            message: error.to_string(),
+        });
+        Ok(())
+    }
+
+    /// In lenient mode, note that the record on `line` has a type the parser ignores.
+    pub(crate) fn note_unknown_type(
+        &self,
+        line: usize,
+        record_type: &str,
+        warnings: &mut Vec<ParseWarning>,
+    ) {
+        if self.lenient {
+            warnings.push(ParseWarning {
+                line,
+                kind: ParseWarningKind::UnknownType,
+                message: format!("line {line}: unknown record type '{record_type}'"),
+            });
+        }
+    }
+}
+
+pub(crate) fn snippet(line: &str) -> String {
+    let trimmed = line.trim();
+    if trimmed.chars().count() <= SNIPPET_CHARS {
+        trimmed.to_string()
+    } else {
+        let mut cut: String = trimmed.chars().take(SNIPPET_CHARS).collect();
+        cut.push('…');
+        cut
+    }
+}
+
+/// Where a later parse of a growing rollout can pick up: the start of the line that opened the
+/// last turn, plus the conversation-level parser state as it was just before that line.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct ResumeState {
+    /// Byte offset of the resume line in the (uncompressed) rollout.
+    pub offset: u64,
+    /// Number of lines before the resume line.
+    pub line: usize,
+    /// Index the turn opened by the resume line receives.
+    pub turn_index: usize,
+    pub session_meta: Option<Value>,
+    pub first_timestamp: Option<OffsetDateTime>,
+    pub last_timestamp: Option<OffsetDateTime>,
+    pub token_usage: TokenUsageSummary,
+    /// SHA-256 of the bytes before `offset`, when the parse was given a hasher.
+    pub prefix_sha256: Option<String>,
+    /// Layout detected from the rollout's first record.
+    #[serde(default)]
+    pub rollout_version: Option<RolloutVersion>,
+    /// Turn of the most recent history compaction before the resume line.
+    #[serde(default)]
+    pub last_compaction_turn: Option<usize>,
+}
+
+fn hex_digest(hasher: &Sha256) -> String {
+    format!("{:x}", hasher.clone().finalize())
+}
+
+/// Transcript format of a session file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RolloutFormat {
+    /// Decide from the first record: Claude Code entries carry a `sessionId` or a `user`,
+    /// `assistant`, `summary` or `system` type, chat logs start with a JSON document spread over
+    /// several lines or a `role` without a `type`; anything else is read as a Codex rollout.
+    #[default]
+    Auto,
+    /// Codex CLI rollout (`rollout-*.jsonl`).
+    Codex,
+    /// Claude Code session transcript (see [`parse_claude_code_session`]).
+    ///
+    /// [`parse_claude_code_session`]: crate::parse_claude_code_session
+    ClaudeCode,
+    /// Plain chat log of `{role, content}` messages (see [`parse_chat_messages`]).
+    ///
+    /// [`parse_chat_messages`]: crate::parse_chat_messages
+    ChatMessages,
+}
+
+impl FromStr for RolloutFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(RolloutFormat::Auto),
+            "codex" => Ok(RolloutFormat::Codex),
+            "claude-code" => Ok(RolloutFormat::ClaudeCode),
+            "chat-messages" => Ok(RolloutFormat::ChatMessages),
+            other => Err(format!(
+                "unknown format '{other}', expected auto, codex, claude-code or chat-messages"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RolloutFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Format of a transcript whose first non-empty line is `line`.
+fn detect_format(line: &str) -> RolloutFormat {
+    // Codex and Claude Code write one compact record per line.
+    if line.starts_with('[') || line == "{" {
+        return RolloutFormat::ChatMessages;
+    }
+    let Ok(value) = serde_json::from_str::<Value>(line) else {
+        return RolloutFormat::Codex;
+    };
+    let claude_type = matches!(
+        value.get("type").and_then(Value::as_str),
+        Some("user" | "assistant" | "summary" | "system")
+    );
+    if claude_type || value.get("sessionId").is_some() {
+        RolloutFormat::ClaudeCode
+    } else if value.get("type").is_none()
+        && (value.get("role").is_some() || value.get("messages").is_some())
+    {
+        RolloutFormat::ChatMessages
+    } else {
+        RolloutFormat::Codex
+    }
+}
+
+/// A transcript format the pipeline can read. The built-in formats are the variants of
+/// [`RolloutFormat`]; others are added to a [`FormatRegistry`] and handed to the pipeline through
+/// [`IngestOptions::formats`](crate::IngestOptions::formats), so logs of further tools can be
+/// imported without forking the parser.
+pub trait TranscriptFormat: Send + Sync {
+    /// Short name of the format, e.g. `codex`.
+    fn name(&self) -> &str;
+
+    /// Whether a transcript whose first non-empty line is `head` is in this format.
+    fn detect(&self, head: &[u8]) -> bool;
+
+    /// Parse a whole transcript.
+    fn parse(&self, reader: &mut dyn BufRead) -> Result<ConversationRecord, ParseError>;
+
+    /// Whether directory walks in [`RolloutFormat::Auto`] pick up a file called `name`. None by
+    /// default, so the format is only read from files imported one by one.
+    fn matches_file_name(&self, name: &str) -> bool {
+        let _ = name;
+        false
+    }
+}
+
+impl TranscriptFormat for RolloutFormat {
+    fn name(&self) -> &str {
+        match self {
+            RolloutFormat::Auto => "auto",
+            RolloutFormat::Codex => "codex",
+            RolloutFormat::ClaudeCode => "claude-code",
+            RolloutFormat::ChatMessages => "chat-messages",
+        }
+    }
+
+    fn detect(&self, head: &[u8]) -> bool {
+        let head = String::from_utf8_lossy(head);
+        *self == RolloutFormat::Auto || detect_format(head.trim()) == *self
+    }
+
+    fn parse(&self, reader: &mut dyn BufRead) -> Result<ConversationRecord, ParseError> {
+        parse_transcript(reader, *self)
+    }
+
+    /// A Codex `rollout-*.jsonl` or a Claude Code `<session uuid>.jsonl`, possibly compressed.
+    /// Claude Code sessions and chat logs are only told apart by content, so those formats take
+    /// every `.jsonl` (and chat logs every `.json`) file.
+    fn matches_file_name(&self, name: &str) -> bool {
+        let Some(stem) = ROLLOUT_SUFFIXES
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext))
+        else {
+            return *self == RolloutFormat::ChatMessages && name.ends_with(".json");
+        };
+        let codex = stem.starts_with("rollout-");
+        match self {
+            RolloutFormat::Codex => codex,
+            RolloutFormat::ClaudeCode | RolloutFormat::ChatMessages => true,
+            RolloutFormat::Auto => codex || is_uuid(stem),
+        }
+    }
+}
+
+/// Whether `text` is a hyphenated UUID, as Claude Code names its session files.
+fn is_uuid(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(idx, c)| match idx {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Built-in formats in the order they are tried; Codex accepts anything and comes last.
+static BUILTIN_FORMATS: [RolloutFormat; 3] = [
+    RolloutFormat::ClaudeCode,
+    RolloutFormat::ChatMessages,
+    RolloutFormat::Codex,
+];
+
+/// The transcript formats [`RolloutFormat::Auto`] chooses from: registered formats, in the order
+/// they were registered, then the built-in ones. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct FormatRegistry {
+    custom: Vec<Arc<dyn TranscriptFormat>>,
 }
 
-/// Parse a rollout JSONL stream into a structured representation.
+impl FormatRegistry {
+    /// A registry of the built-in formats only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `format`, tried before the built-in formats and those registered after it.
+    pub fn register(&mut self, format: impl TranscriptFormat + 'static) -> &mut Self {
+        self.custom.push(Arc::new(format));
+        self
+    }
+
+    /// Every format, in detection order.
+    pub fn formats(&self) -> impl Iterator<Item = &dyn TranscriptFormat> + '_ {
+        let custom = self.custom.iter().map(|format| format.as_ref());
+        custom.chain(
+            BUILTIN_FORMATS
+                .iter()
+                .map(|format| format as &dyn TranscriptFormat),
+        )
+    }
+
+    /// The format with `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&dyn TranscriptFormat> {
+        self.formats().find(|format| format.name() == name)
+    }
+
+    /// The first format recognising a transcript whose first non-empty line is `head`; Codex
+    /// when no other does.
+    pub fn detect(&self, head: &[u8]) -> &dyn TranscriptFormat {
+        self.detect_custom(head).unwrap_or_else(|| {
+            BUILTIN_FORMATS
+                .iter()
+                .find(|format| format.detect(head))
+                .unwrap()
+        })
+    }
+
+    /// Parse a transcript in `format`, detecting it among all formats for
+    /// [`RolloutFormat::Auto`].
+    pub fn parse<R: BufRead>(
+        &self,
+        reader: R,
+        format: RolloutFormat,
+    ) -> Result<ConversationRecord, ParseError> {
+        parse_transcript_from(reader, format, Some(self), ParseOptions::default(), None)
+            .map(|(sessions, _)| first_session(sessions))
+    }
+
+    /// Whether a directory walk in `format` picks up a file called `name`; registered formats
+    /// only take part in [`RolloutFormat::Auto`].
+    pub(crate) fn matches_file_name(&self, name: &str, format: RolloutFormat) -> bool {
+        format.matches_file_name(name)
+            || format == RolloutFormat::Auto
+                && self
+                    .custom
+                    .iter()
+                    .any(|custom| custom.matches_file_name(name))
+    }
+
+    fn detect_custom(&self, head: &[u8]) -> Option<&dyn TranscriptFormat> {
+        self.custom
+            .iter()
+            .map(|format| format.as_ref())
+            .find(|format| format.detect(head))
+    }
+}
+
+impl fmt::Debug for FormatRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.formats().map(|format| format.name()))
+            .finish()
+    }
+}
+
+/// Parse a session transcript in `format`, detecting it from the first record for
+/// [`RolloutFormat::Auto`]. Only built-in formats are considered; see
+/// [`FormatRegistry::parse`] for registered ones.
+pub fn parse_transcript<R: BufRead>(
+    reader: R,
+    format: RolloutFormat,
+) -> Result<ConversationRecord, ParseError> {
+    parse_transcript_with(reader, format, ParseOptions::default())
+}
+
+/// Like [`parse_transcript`], with `options`; see [`ParseOptions::lenient`].
+pub fn parse_transcript_with<R: BufRead>(
+    reader: R,
+    format: RolloutFormat,
+    options: ParseOptions,
+) -> Result<ConversationRecord, ParseError> {
+    parse_transcript_from(reader, format, None, options, None)
+        .map(|(sessions, _)| first_session(sessions))
+}
+
+/// Like [`parse_transcript`], also detecting the registered `formats`, and feeding every line
+/// read to `hasher`. Codex rollouts come back split into their sessions; other transcripts hold
+/// one. Only Codex rollouts can be resumed, so other transcripts come back without a
+/// [`ResumeState`].
+pub(crate) fn parse_transcript_from<R: BufRead>(
+    mut reader: R,
+    format: RolloutFormat,
+    formats: Option<&FormatRegistry>,
+    options: ParseOptions,
+    hasher: Option<&mut Sha256>,
+) -> Result<(Vec<ConversationRecord>, Option<ResumeState>), ParseError> {
+    let mut head = String::new();
+    let mut custom = None;
+    let format = match format {
+        RolloutFormat::Auto => loop {
+            let start = head.len();
+            let read = reader
+                .read_line(&mut head)
+                .map_err(|source| ParseError::Io {
+                    path: None,
+                    line: head.matches('\n').count() + 1,
+                    source,
+                })?;
+            if read == 0 || !head[start..].trim().is_empty() {
+                let line = head[start..].trim();
+                custom = formats.and_then(|formats| formats.detect_custom(line.as_bytes()));
+                break detect_format(line);
+            }
+        },
+        format => format,
+    };
+    // Replay the lines read for detection, so they are hashed and numbered as usual.
+    let reader = Cursor::new(head).chain(reader);
+    if let Some(custom) = custom {
+        return parse_custom(custom, reader, hasher).map(|record| (vec![record], None));
+    }
+    match format {
+        RolloutFormat::ClaudeCode => {
+            parse_claude_code_from(reader, options, hasher).map(|record| (vec![record], None))
+        }
+        RolloutFormat::ChatMessages => {
+            parse_chat_messages_from(reader, options, hasher).map(|record| (vec![record], None))
+        }
+        _ => parse_rollout_from(reader, &ResumeState::default(), options, hasher)
+            .map(|(sessions, resume)| (sessions, Some(resume))),
+    }
+}
+
+/// The first session of a parse, which always yields at least one.
+fn first_session(sessions: Vec<ConversationRecord>) -> ConversationRecord {
+    sessions.into_iter().next().unwrap_or_default()
+}
+
+/// Parse with a registered `format`, feeding every byte of the transcript to `hasher`,
+/// including any the format leaves unread.
+fn parse_custom(
+    format: &dyn TranscriptFormat,
+    reader: impl Read,
+    hasher: Option<&mut Sha256>,
+) -> Result<ConversationRecord, ParseError> {
+    let mut reader = BufReader::new(HashingReader {
+        inner: reader,
+        hasher,
+    });
+    let record = format.parse(&mut reader)?;
+    if reader.get_ref().hasher.is_some() {
+        io::copy(&mut reader, &mut io::sink()).map_err(|source| ParseError::Io {
+            path: None,
+            line: 0,
+            source,
+        })?;
+    }
+    Ok(record)
+}
+
+/// Reader that feeds the bytes read through it to a hasher.
+struct HashingReader<'h, R> {
+    inner: R,
+    hasher: Option<&'h mut Sha256>,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(hasher) = self.hasher.as_deref_mut() {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// Parse a rollout JSONL stream into a structured representation. Only the first session of a
+/// rollout holding several is returned; see [`parse_rollout_sessions`].
 pub fn parse_rollout<R: BufRead>(reader: R) -> Result<ConversationRecord, ParseError> {
-    let mut builder = ConversationBuilder::default();
-    for line in reader.lines() {
-        let line = line?;
+    parse_rollout_sessions(reader).map(first_session)
+}
+
+/// Parse a rollout into one record per session. A resumed session can be appended to the file
+/// of an earlier one; every `session_meta` record with an id other than the current session's
+/// starts a new record. The result holds at least one record.
+pub fn parse_rollout_sessions<R: BufRead>(
+    reader: R,
+) -> Result<Vec<ConversationRecord>, ParseError> {
+    parse_rollout_from(
+        reader,
+        &ResumeState::default(),
+        ParseOptions::default(),
+        None,
+    )
+    .map(|(sessions, _)| sessions)
+}
+
+/// Parse a rollout from `resume`, with `reader` positioned at `resume.offset`, into its
+/// sessions (see [`parse_rollout_sessions`]). The first record holds only the turns from
+/// `resume.turn_index` on; the returned state is where the next parse can resume.
+///
+/// `hasher`, when given, must cover the bytes before `resume.offset`. Every line read is fed to
+/// it, so it ends up hashing the whole rollout, and the returned state records the hash of the
+/// bytes before its offset.
+pub(crate) fn parse_rollout_from<R: BufRead>(
+    mut reader: R,
+    resume: &ResumeState,
+    options: ParseOptions,
+    mut hasher: Option<&mut Sha256>,
+) -> Result<(Vec<ConversationRecord>, ResumeState), ParseError> {
+    let mut builder = ConversationBuilder {
+        session_meta: resume.session_meta.clone(),
+        next_index: resume.turn_index,
+        first_timestamp: resume.first_timestamp,
+        last_timestamp: resume.last_timestamp,
+        token_usage: resume.token_usage.clone(),
+        rollout_version: resume.rollout_version,
+        last_compaction_turn: resume.last_compaction_turn,
+        ..ConversationBuilder::default()
+    };
+    let mut next_resume = ResumeState {
+        prefix_sha256: hasher.as_deref().map(hex_digest),
+        ..resume.clone()
+    };
+    let mut offset = resume.offset;
+    let mut line_no = resume.line;
+    let mut seen_turn_context = false;
+    let mut sessions = Vec::new();
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        let read = reader
+            .read_line(&mut buf)
+            .map_err(|source| ParseError::Io {
+                path: None,
+                line: line_no + 1,
+                source,
+            })?;
+        if read == 0 {
+            break;
+        }
+        // The hash of the bytes before this line, in case it becomes the resume point.
+        let line_start_hasher = hasher.as_deref().cloned();
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(buf.as_bytes());
+        }
+        let line_start = offset;
+        offset += read as u64;
+        line_no += 1;
+        builder.line = line_no;
+        let line = buf.strip_suffix('\n').unwrap_or(&buf);
+        let line = line.strip_suffix('\r').unwrap_or(line);
         if line.trim().is_empty() {
             continue;
         }
-        let value: Value = serde_json::from_str(&line)?;
+        let value: Value = match serde_json::from_str(line) {
+            Ok(value) => value,
+            Err(source) => {
+                let error = ParseError::Json {
+                    path: None,
+                    line: line_no,
+                    snippet: snippet(line),
+                    source,
+                };
+                options.recover(error, &mut builder.warnings)?;
+                continue;
+            }
+        };
         if let Some(record_type) = value.get("record_type").and_then(Value::as_str) {
             if record_type == "state" {
                 continue;
             }
         }
+        let version = *builder
+            .rollout_version
+            .get_or_insert_with(|| record_version(&value));
+        let value = match version {
+            RolloutVersion::Envelope => value,
+            RolloutVersion::Legacy => adapt_legacy_record(value),
+        };
+        if starts_new_session(&builder, &value) {
+            let next = ConversationBuilder {
+                rollout_version: builder.rollout_version,
+                line: builder.line,
+                ..ConversationBuilder::default()
+            };
+            sessions.push(std::mem::replace(&mut builder, next).finalize());
+            seen_turn_context = false;
+        }
 
         let timestamp = if let Some(timestamp_str) = value.get("timestamp").and_then(Value::as_str)
         {
-            let parsed = OffsetDateTime::parse(timestamp_str, &Rfc3339)
-                .map_err(|err| ParseError::Timestamp(timestamp_str.to_string(), err))?;
-            builder.observe_timestamp(parsed);
-            parsed
+            match OffsetDateTime::parse(timestamp_str, &Rfc3339) {
+                Ok(parsed) => {
+                    builder.observe_timestamp(parsed);
+                    parsed
+                }
+                Err(source) => {
+                    let error = ParseError::Timestamp {
+                        path: None,
+                        line: line_no,
+                        value: timestamp_str.to_string(),
+                        source,
+                    };
+                    options.recover(error, &mut builder.warnings)?;
+                    continue;
+                }
+            }
         } else if let Some(last) = builder.last_timestamp {
             last
         } else if let Some(first) = builder.first_timestamp {
             first
         } else {
-            return Err(ParseError::MissingField("timestamp"));
+            let error = ParseError::MissingField {
+                path: None,
+                line: line_no,
+                field: "timestamp",
+                snippet: snippet(line),
+            };
+            options.recover(error, &mut builder.warnings)?;
+            continue;
         };
         let item_type = match value.get("type").and_then(Value::as_str) {
             Some(kind) => kind,
-            None if is_legacy_session_meta(&value) => {
-                builder.session_meta = Some(value);
+            None => {
+                let error = ParseError::MissingField {
+                    path: None,
+                    line: line_no,
+                    field: "type",
+                    snippet: snippet(line),
+                };
+                options.recover(error, &mut builder.warnings)?;
                 continue;
             }
-            None => return Err(ParseError::MissingField("type")),
         };
 
         match item_type {
@@ -65,12 +738,25 @@ pub fn parse_rollout<R: BufRead>(reader: R) -> Result<ConversationRecord, ParseE
             }
             "turn_context" => {
                 if let Some(payload) = value.get("payload") {
+                    seen_turn_context = true;
+                    next_resume =
+                        resume_point(&builder, line_start, line_no, line_start_hasher.as_ref());
                     let context = parse_turn_context(payload.clone());
                     builder.start_new_turn(context, timestamp);
                 }
             }
             "response_item" => {
                 if let Some(payload) = value.get("payload") {
+                    let split_on_prompts = match options.segmentation {
+                        TurnSegmentation::TurnContext => false,
+                        TurnSegmentation::UserMessage => true,
+                        TurnSegmentation::Auto => !seen_turn_context,
+                    };
+                    if split_on_prompts && is_user_prompt(payload) && builder.has_prompt() {
+                        next_resume =
+                            resume_point(&builder, line_start, line_no, line_start_hasher.as_ref());
+                        builder.split_turn(timestamp);
+                    }
                     handle_response_item(&mut builder, timestamp, payload.clone());
                 }
             }
@@ -84,41 +770,62 @@ pub fn parse_rollout<R: BufRead>(reader: R) -> Result<ConversationRecord, ParseE
                     handle_compacted(&mut builder, timestamp, payload.clone());
                 }
             }
-            _ => {}
+            other => options.note_unknown_type(line_no, other, &mut builder.warnings),
         }
     }
-    Ok(builder.finalize())
+    sessions.push(builder.finalize());
+    Ok((sessions, next_resume))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn parses_basic_rollout() {
-        let data = r#"
-{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"urn:uuid:test","cwd":"/tmp"}}
-{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"hello"}]}}
-{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"reasoning","summary":[{"type":"summary_text","text":"thinking"}]}}
-{"timestamp":"2025-01-01T00:00:03.000Z","type":"response_item","payload":{"type":"function_call","name":"shell","call_id":"call-1","arguments":"{\"command\":[\"ls\"]}"}}
-{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"function_call_output","call_id":"call-1","output":"{\"content\":\"done\"}"}}
-{"timestamp":"2025-01-01T00:00:05.000Z","type":"event_msg","payload":{"type":"token_count","rate_limits":{"primary":{"used_percent":1,"window_minutes":1,"resets_at":0}}}}
-        "#;
+/// Whether `value` is the `session_meta` record of a session other than the one being parsed.
+fn starts_new_session(builder: &ConversationBuilder, value: &Value) -> bool {
+    let session_id = |meta: &Value| meta.get("id").and_then(Value::as_str).map(str::to_string);
+    if value.get("type").and_then(Value::as_str) != Some("session_meta") {
+        return false;
+    }
+    let current = builder.session_meta.as_ref().and_then(session_id);
+    let next = value.get("payload").and_then(session_id);
+    matches!((current, next), (Some(current), Some(next)) if current != next)
+}
 
-        let cursor = std::io::Cursor::new(data.as_bytes());
-        let record = parse_rollout(cursor).expect("parse");
-        assert_eq!(record.turns.len(), 1);
-        assert_eq!(record.duration_seconds, Some(5));
-        assert!(record.token_usage.total.is_none());
-        let turn = &record.turns[0];
-        assert_eq!(turn.user_inputs.len(), 1);
-        assert_eq!(turn.result.reasoning_summaries.len(), 1);
-        assert_eq!(turn.actions.len(), 1);
-        assert_eq!(turn.actions[0].call_id.as_deref(), Some("call-1"));
-        assert_eq!(turn.telemetry.token_counts.len(), 1);
+/// Resume state for a turn opened by line `line_no`, which starts at byte `offset`; `hasher`
+/// covers the bytes before it.
+fn resume_point(
+    builder: &ConversationBuilder,
+    offset: u64,
+    line_no: usize,
+    hasher: Option<&Sha256>,
+) -> ResumeState {
+    ResumeState {
+        offset,
+        line: line_no - 1,
+        turn_index: builder.next_index,
+        session_meta: builder.session_meta.clone(),
+        first_timestamp: builder.first_timestamp,
+        last_timestamp: builder.last_timestamp,
+        token_usage: builder.token_usage.clone(),
+        prefix_sha256: hasher.map(hex_digest),
+        rollout_version: builder.rollout_version,
+        last_compaction_turn: builder.last_compaction_turn,
     }
 }
 
+/// Whether a response item is a user message typed by the user, as opposed to context Codex
+/// injects in their name.
+fn is_user_prompt(payload: &Value) -> bool {
+    payload.get("type").and_then(Value::as_str) == Some("message")
+        && payload.get("role").and_then(Value::as_str) == Some("user")
+        && payload
+            .get("content")
+            .and_then(Value::as_array)
+            .and_then(|content| {
+                content
+                    .iter()
+                    .find_map(|item| item.get("text").and_then(Value::as_str))
+            })
+            .is_none_or(|text| UserInputKind::classify(text) == UserInputKind::Prompt)
+}
+
 fn parse_turn_context(raw: Value) -> TurnContextInfo {
     let cwd = raw
         .get("cwd")
@@ -184,6 +891,7 @@ fn handle_response_item(
         "custom_tool_call_output" => handle_custom_tool_output(turn, &payload),
         "local_shell_call" => handle_local_shell_call(turn, &payload),
         "web_search_call" => handle_web_search_call(turn, &payload),
+        "mcp_tool_call" | "mcp_call" => handle_mcp_call(turn, timestamp, &payload),
         _ => {}
     }
 }
@@ -202,6 +910,7 @@ fn handle_message(turn: &mut TurnBuilder, payload: Value) {
     if role == "user" {
         let mut text_parts = Vec::new();
         let mut images = Vec::new();
+        let mut files = Vec::new();
         for item in &content {
             match item.get("type").and_then(Value::as_str).unwrap_or_default() {
                 "input_text" => {
@@ -214,17 +923,21 @@ fn handle_message(turn: &mut TurnBuilder, payload: Value) {
                         images.push(url.to_string());
                     }
                 }
+                "input_file" | "file" | "document" => files.extend(file_attachment(item)),
                 _ => {}
             }
         }
+        let text = (!text_parts.is_empty()).then(|| text_parts.join(""));
         let record = UserInputRecord {
             raw: payload,
-            text: if text_parts.is_empty() {
-                None
-            } else {
-                Some(text_parts.join(""))
-            },
+            kind: text
+                .as_deref()
+                .map(UserInputKind::classify)
+                .unwrap_or_default(),
+            text,
             images,
+            image_refs: Vec::new(),
+            files,
         };
         turn.push_user_input(record);
     } else if role == "assistant" {
@@ -237,6 +950,7 @@ fn handle_message(turn: &mut TurnBuilder, payload: Value) {
             }
         }
         if !text_parts.is_empty() {
+            turn.discard_deltas("agent_message", payload.get("id").and_then(Value::as_str));
             turn.push_assistant_message(text_parts.join(""));
         }
     }
@@ -249,6 +963,9 @@ fn handle_reasoning(turn: &mut TurnBuilder, payload: &Value) {
                 turn.push_reasoning_summary(text.to_string());
             }
         }
+        if !summary_items.is_empty() {
+            turn.discard_deltas("agent_reasoning", payload.get("id").and_then(Value::as_str));
+        }
     }
     if payload.get("content").is_some() {
         turn.mark_reasoning_encrypted();
@@ -307,6 +1024,11 @@ fn handle_function_call(turn: &mut TurnBuilder, timestamp: OffsetDateTime, paylo
                 timeout_ms,
                 escalated,
             });
+        } else if let Some((server, tool)) = split_mcp_tool_name(name_str) {
+            builder.set_kind(ActionKind::McpToolCall {
+                server: Some(server),
+                tool: Some(tool),
+            });
         } else {
             builder.set_kind(ActionKind::FunctionCall {
                 name: Some(name_str.clone()),
@@ -316,6 +1038,14 @@ fn handle_function_call(turn: &mut TurnBuilder, timestamp: OffsetDateTime, paylo
         builder.set_kind(ActionKind::FunctionCall { name: None });
     }
 
+    // `apply_patch` is called directly, or through the shell with the patch as an argument.
+    let patch = arguments
+        .as_ref()
+        .and_then(find_patch)
+        .or_else(|| arguments_str.contains(PATCH_BEGIN).then_some(arguments_str));
+    if let Some(patch) = patch {
+        builder.set_file_changes(parse_apply_patch(patch));
+    }
     builder.set_arguments(arguments);
     builder.push_event(timestamp, "function_call".into(), payload.clone());
 }
@@ -337,12 +1067,20 @@ fn handle_function_output(turn: &mut TurnBuilder, payload: &Value) {
         .unwrap_or(output_str)
         .to_string();
 
+    // Shell outputs carry `metadata: {exit_code, duration_seconds}`.
+    let metadata = raw_output.get("metadata");
+    let exit_code = metadata.and_then(|metadata| metadata.get("exit_code"));
+    let duration = metadata
+        .and_then(|metadata| metadata.get("duration_seconds"))
+        .and_then(Value::as_f64)
+        .map(|seconds| (seconds * 1000.0).round() as u64);
+
     let builder = turn.action_builder_mut(call_id);
+    builder.record_exit(exit_code.and_then(as_exit_code), duration);
     builder.set_output(ActionOutput {
         content: Some(content_text.clone()),
         success: raw_output.get("success").and_then(Value::as_bool),
         raw: raw_output,
-        ..Default::default()
     });
     turn.record_tool_output_text(content_text);
 }
@@ -361,6 +1099,9 @@ fn handle_custom_tool_call(turn: &mut TurnBuilder, payload: &Value) {
     let parsed_input = serde_json::from_str::<Value>(input).ok();
 
     let builder = turn.action_builder_mut(call_id);
+    if input.contains(PATCH_BEGIN) {
+        builder.set_file_changes(parse_apply_patch(input));
+    }
     builder.set_kind(ActionKind::CustomToolCall { name });
     builder.set_arguments(parsed_input);
     builder.update_status_text(status);
@@ -418,6 +1159,9 @@ fn handle_local_shell_call(turn: &mut TurnBuilder, payload: &Value) {
     });
     builder.update_status_text(status.clone());
     builder.update_local_status(status);
+    if let Some(patch) = find_patch(&action) {
+        builder.set_file_changes(parse_apply_patch(patch));
+    }
     builder.set_arguments(Some(action));
 }
 
@@ -440,6 +1184,216 @@ fn handle_web_search_call(turn: &mut TurnBuilder, payload: &Value) {
     builder.update_status_text(status);
 }
 
+/// First line of every `apply_patch` patch.
+const PATCH_BEGIN: &str = "*** Begin Patch";
+
+/// A string holding an `apply_patch` patch anywhere in `value`: the `patch` / `input`
+/// argument, or one word of a shell command.
+fn find_patch(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(text) => text.contains(PATCH_BEGIN).then_some(text.as_str()),
+        Value::Array(items) => items.iter().find_map(find_patch),
+        Value::Object(map) => map.values().find_map(find_patch),
+        _ => None,
+    }
+}
+
+/// Files of an `apply_patch` patch with their added and removed line counts. Text before
+/// `*** Begin Patch` (e.g. a shell heredoc) and after `*** End Patch` is ignored; a file that
+/// is only moved counts as an update of its original path.
+pub(crate) fn parse_apply_patch(patch: &str) -> Vec<FileChange> {
+    let mut changes: Vec<FileChange> = Vec::new();
+    let Some(start) = patch.find(PATCH_BEGIN) else {
+        return changes;
+    };
+    for line in patch[start..].lines().skip(1) {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with("*** End Patch") {
+            break;
+        }
+        let header = [
+            ("*** Add File: ", FileChangeKind::Add),
+            ("*** Update File: ", FileChangeKind::Update),
+            ("*** Delete File: ", FileChangeKind::Delete),
+        ]
+        .into_iter()
+        .find_map(|(prefix, kind)| Some((line.strip_prefix(prefix)?, kind)));
+        if let Some((path, kind)) = header {
+            changes.push(FileChange {
+                path: path.trim().to_string(),
+                kind,
+                hunks_added: 0,
+                hunks_removed: 0,
+            });
+            continue;
+        }
+        let Some(change) = changes.last_mut() else {
+            continue;
+        };
+        if line.starts_with('+') {
+            change.hunks_added += 1;
+        } else if line.starts_with('-') {
+            change.hunks_removed += 1;
+        }
+    }
+    changes
+}
+
+/// `mcp__<server>__<tool>` or `<server>__<tool>`, the names MCP tools are exposed under.
+pub(crate) fn split_mcp_tool_name(name: &str) -> Option<(String, String)> {
+    let name = name.strip_prefix("mcp__").unwrap_or(name);
+    let (server, tool) = name.split_once("__")?;
+    (!server.is_empty() && !tool.is_empty()).then(|| (server.to_string(), tool.to_string()))
+}
+
+/// An MCP call recorded as a single response item, with its result inline.
+fn handle_mcp_call(turn: &mut TurnBuilder, timestamp: OffsetDateTime, payload: &Value) {
+    let call_id = payload
+        .get("call_id")
+        .or_else(|| payload.get("id"))
+        .and_then(Value::as_str);
+    let server = payload
+        .get("server")
+        .or_else(|| payload.get("server_label"))
+        .and_then(Value::as_str)
+        .map(String::from);
+    let tool = payload
+        .get("tool")
+        .or_else(|| payload.get("name"))
+        .and_then(Value::as_str)
+        .map(String::from);
+    let status = payload
+        .get("status")
+        .and_then(Value::as_str)
+        .map(String::from);
+    let output = match (payload.get("error"), payload.get("output")) {
+        (Some(error), _) if !error.is_null() => Some(mcp_error_output(error)),
+        (_, Some(output)) if !output.is_null() => Some(mcp_result_output(output)),
+        _ => None,
+    };
+
+    let builder = turn.action_builder_mut(call_id);
+    builder.set_kind(ActionKind::McpToolCall { server, tool });
+    builder.set_arguments(payload.get("arguments").map(parse_mcp_arguments));
+    builder.update_status_text(status);
+    builder.push_event(timestamp, "mcp_tool_call".into(), payload.clone());
+    if let Some(output) = output {
+        let text = output.content.clone().unwrap_or_default();
+        builder.set_output(output);
+        if !text.is_empty() {
+            turn.record_tool_output_text(text);
+        }
+    }
+}
+
+/// `mcp_tool_call_begin` / `mcp_tool_call_end`: both carry the `invocation`; the end event
+/// also carries the `result`, used as the output unless a `function_call_output` gave one.
+fn handle_mcp_event(
+    turn: &mut TurnBuilder,
+    timestamp: OffsetDateTime,
+    event_type: String,
+    payload: &Value,
+) {
+    let call_id = extract_call_id(payload);
+    let invocation = payload.get("invocation").unwrap_or(payload);
+    let server = invocation
+        .get("server")
+        .and_then(Value::as_str)
+        .map(String::from);
+    let tool = invocation
+        .get("tool")
+        .and_then(Value::as_str)
+        .map(String::from);
+
+    let builder = turn.action_builder_mut(call_id.as_deref());
+    builder.set_kind(ActionKind::McpToolCall { server, tool });
+    if let Some(arguments) = invocation.get("arguments").filter(|args| !args.is_null()) {
+        builder.set_arguments(Some(parse_mcp_arguments(arguments)));
+    }
+    let output =
+        payload
+            .get("result")
+            .filter(|_| !builder.has_output())
+            .map(|result| match result {
+                Value::Object(map) if map.contains_key("Err") => mcp_error_output(&map["Err"]),
+                Value::Object(map) if map.contains_key("Ok") => mcp_result_output(&map["Ok"]),
+                other => mcp_result_output(other),
+            });
+    builder.push_event(timestamp, event_type, payload.clone());
+    if let Some(output) = output {
+        let text = output.content.clone().unwrap_or_default();
+        builder.set_output(output);
+        if !text.is_empty() {
+            turn.record_tool_output_text(text);
+        }
+    }
+}
+
+/// MCP arguments arrive either as an object or as a JSON-encoded string.
+fn parse_mcp_arguments(arguments: &Value) -> Value {
+    match arguments {
+        Value::String(text) => serde_json::from_str(text).unwrap_or_else(|_| arguments.clone()),
+        other => other.clone(),
+    }
+}
+
+/// A `CallToolResult` (`content` parts plus `isError`) or a plain string output. Text parts are
+/// joined; a result without any falls back to its `structuredContent`.
+fn mcp_result_output(result: &Value) -> ActionOutput {
+    if let Value::String(text) = result {
+        return ActionOutput {
+            content: Some(text.clone()),
+            success: None,
+            raw: result.clone(),
+        };
+    }
+    let text = result
+        .get("content")
+        .and_then(Value::as_array)
+        .map(|parts| {
+            parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .filter(|text| !text.is_empty())
+        .or_else(|| {
+            result
+                .get("structuredContent")
+                .or_else(|| result.get("structured_content"))
+                .map(Value::to_string)
+        });
+    let is_error = result
+        .get("isError")
+        .or_else(|| result.get("is_error"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    ActionOutput {
+        content: text,
+        success: Some(!is_error),
+        raw: result.clone(),
+    }
+}
+
+fn mcp_error_output(error: &Value) -> ActionOutput {
+    let text = error
+        .as_str()
+        .map(String::from)
+        .or_else(|| {
+            error
+                .get("message")
+                .and_then(Value::as_str)
+                .map(String::from)
+        })
+        .unwrap_or_else(|| error.to_string());
+    ActionOutput {
+        content: Some(text),
+        success: Some(false),
+        raw: error.clone(),
+    }
+}
+
 fn handle_event(builder: &mut ConversationBuilder, timestamp: OffsetDateTime, payload: Value) {
     let event_type = payload
         .get("type")
@@ -460,6 +1414,7 @@ fn handle_event(builder: &mut ConversationBuilder, timestamp: OffsetDateTime, pa
         match event_type.as_str() {
             "agent_message" => {
                 if let Some(message) = payload.get("message").and_then(Value::as_str) {
+                    turn.discard_deltas("agent_message", stream_item_id(&payload));
                     turn.record_event_agent_message(message.to_string());
                 }
                 turn.telemetry.misc_events.push(Timed {
@@ -469,6 +1424,7 @@ fn handle_event(builder: &mut ConversationBuilder, timestamp: OffsetDateTime, pa
             }
             "agent_reasoning" | "agent_reasoning_raw_content" => {
                 if let Some(text) = payload.get("text").and_then(Value::as_str) {
+                    turn.discard_deltas(&event_type, stream_item_id(&payload));
                     turn.record_event_agent_message(text.to_string());
                 }
                 turn.telemetry.misc_events.push(Timed {
@@ -494,16 +1450,51 @@ fn handle_event(builder: &mut ConversationBuilder, timestamp: OffsetDateTime, pa
                     data: payload.clone(),
                 });
             }
-            "exec_command_begin"
-            | "exec_command_end"
-            | "mcp_tool_call_begin"
-            | "mcp_tool_call_end"
-            | "web_search_begin"
-            | "web_search_end" => {
+            "mcp_tool_call_begin" | "mcp_tool_call_end" => {
+                handle_mcp_event(turn, timestamp, event_type, &payload);
+            }
+            "exec_command_end" => {
+                let call_id = extract_call_id(&payload);
+                let builder = turn.action_builder_mut(call_id.as_deref());
+                builder.record_exit(
+                    payload.get("exit_code").and_then(as_exit_code),
+                    payload.get("duration").and_then(duration_ms),
+                );
+                builder.push_event(timestamp, event_type, payload.clone());
+            }
+            "error" | "stream_error" | "turn_aborted" => {
+                let kind = match event_type.as_str() {
+                    "error" => TurnErrorKind::Error,
+                    "stream_error" => TurnErrorKind::StreamError,
+                    _ => TurnErrorKind::Aborted,
+                };
+                let message = payload
+                    .get("message")
+                    .or_else(|| payload.get("reason"))
+                    .and_then(Value::as_str)
+                    .map(String::from);
+                turn.errors.push(TurnError {
+                    timestamp,
+                    kind,
+                    message,
+                });
+            }
+            "exec_command_begin" | "web_search_begin" | "web_search_end" => {
                 let call_id = extract_call_id(&payload);
                 let builder = turn.action_builder_mut(call_id.as_deref());
                 builder.push_event(timestamp, event_type, payload.clone());
             }
+            kind if kind.ends_with("_delta") && payload.get("delta").is_some() => {
+                let delta = payload
+                    .get("delta")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                turn.push_delta(
+                    kind.trim_end_matches("_delta"),
+                    stream_item_id(&payload),
+                    delta,
+                );
+            }
             _ => {
                 turn.telemetry.misc_events.push(Timed {
                     timestamp,
@@ -519,11 +1510,27 @@ fn handle_event(builder: &mut ConversationBuilder, timestamp: OffsetDateTime, pa
 }
 
 fn handle_compacted(builder: &mut ConversationBuilder, timestamp: OffsetDateTime, payload: Value) {
-    let turn = builder.ensure_turn(timestamp);
-    turn.ensure_started_at(timestamp);
-    if let Some(message) = payload.get("message").and_then(Value::as_str) {
-        turn.push_assistant_message(message.to_string());
-        turn.record_tool_output_text(message.to_string());
+    let summary = payload
+        .get("message")
+        .and_then(Value::as_str)
+        .filter(|message| !message.trim().is_empty())
+        .map(str::to_string);
+    builder.push_compaction(timestamp, summary);
+}
+
+fn as_exit_code(value: &Value) -> Option<i32> {
+    value.as_i64().and_then(|code| i32::try_from(code).ok())
+}
+
+/// A duration as serialized by Codex (`{"secs": 1, "nanos": 5000000}`), or plain milliseconds.
+fn duration_ms(value: &Value) -> Option<u64> {
+    match value {
+        Value::Object(map) => {
+            let secs = map.get("secs").and_then(Value::as_u64)?;
+            let nanos = map.get("nanos").and_then(Value::as_u64).unwrap_or(0);
+            Some(secs * 1000 + nanos / 1_000_000)
+        }
+        other => other.as_u64(),
     }
 }
 
@@ -535,9 +1542,508 @@ fn extract_call_id(payload: &Value) -> Option<String> {
         .map(String::from)
 }
 
+/// Id of the item a streamed event belongs to, when the producer records one.
+fn stream_item_id(payload: &Value) -> Option<&str> {
+    payload
+        .get("item_id")
+        .or_else(|| payload.get("itemId"))
+        .or_else(|| payload.get("id"))
+        .and_then(Value::as_str)
+}
+
 fn is_legacy_session_meta(value: &Value) -> bool {
     value.get("type").is_none()
         && value.get("record_type").is_none()
         && value.get("id").is_some()
         && value.get("timestamp").is_some()
 }
+
+/// Record types of the [`RolloutVersion::Envelope`] layout.
+const ENVELOPE_TYPES: &[&str] = &[
+    "session_meta",
+    "turn_context",
+    "response_item",
+    "event_msg",
+    "compacted",
+];
+
+/// Layout of a rollout, judged from its first record. Records matching neither layout count as
+/// [`RolloutVersion::Envelope`], so they surface as unknown record types.
+fn record_version(value: &Value) -> RolloutVersion {
+    if value.get("payload").is_some() {
+        return RolloutVersion::Envelope;
+    }
+    let bare_item = value
+        .get("type")
+        .and_then(Value::as_str)
+        .is_some_and(|kind| !ENVELOPE_TYPES.contains(&kind));
+    if bare_item || is_legacy_session_meta(value) {
+        RolloutVersion::Legacy
+    } else {
+        RolloutVersion::Envelope
+    }
+}
+
+/// Wrap a [`RolloutVersion::Legacy`] record in the envelope the current parser reads: the bare
+/// session metadata becomes `session_meta`, anything else a `response_item`. A timestamp the
+/// record carries is kept; otherwise the previous record's applies.
+fn adapt_legacy_record(value: Value) -> Value {
+    let kind = if is_legacy_session_meta(&value) {
+        "session_meta"
+    } else {
+        "response_item"
+    };
+    let mut envelope = serde_json::Map::new();
+    if let Some(timestamp) = value.get("timestamp") {
+        envelope.insert("timestamp".into(), timestamp.clone());
+    }
+    envelope.insert("type".into(), kind.into());
+    envelope.insert("payload".into(), value);
+    Value::Object(envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_transcript_formats_from_the_first_record() {
+        let cases = [
+            (
+                r#"{"timestamp":"2025-01-01T00:00:00Z","type":"session_meta","payload":{}}"#,
+                RolloutFormat::Codex,
+            ),
+            (
+                r#"{"type":"user","sessionId":"s-1"}"#,
+                RolloutFormat::ClaudeCode,
+            ),
+            (
+                r#"{"role":"user","content":"hi"}"#,
+                RolloutFormat::ChatMessages,
+            ),
+            (
+                r#"[{"role":"user","content":"hi"}]"#,
+                RolloutFormat::ChatMessages,
+            ),
+            ("{", RolloutFormat::ChatMessages),
+        ];
+        for (line, format) in cases {
+            assert_eq!(detect_format(line), format, "{line}");
+        }
+        let log = "[\n  {\"role\": \"user\", \"content\": \"hi\"}\n]\n";
+        let record = parse_transcript(log.as_bytes(), RolloutFormat::Auto).unwrap();
+        assert_eq!(record.turns[0].user_inputs[0].text.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn parses_basic_rollout() {
+        let data = r#"
+{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"urn:uuid:test","cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"hello"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"reasoning","summary":[{"type":"summary_text","text":"thinking"}]}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"response_item","payload":{"type":"function_call","name":"shell","call_id":"call-1","arguments":"{\"command\":[\"ls\"]}"}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"function_call_output","call_id":"call-1","output":"{\"content\":\"done\"}"}}
+{"timestamp":"2025-01-01T00:00:05.000Z","type":"event_msg","payload":{"type":"token_count","rate_limits":{"primary":{"used_percent":1,"window_minutes":1,"resets_at":0}}}}
+        "#;
+
+        let cursor = std::io::Cursor::new(data.as_bytes());
+        let record = parse_rollout(cursor).expect("parse");
+        assert_eq!(record.turns.len(), 1);
+        assert_eq!(record.duration_seconds, Some(5));
+        assert!(record.token_usage.total.is_none());
+        let turn = &record.turns[0];
+        assert_eq!(turn.user_inputs.len(), 1);
+        assert_eq!(turn.result.reasoning_summaries.len(), 1);
+        assert_eq!(turn.actions.len(), 1);
+        assert_eq!(turn.actions[0].call_id.as_deref(), Some("call-1"));
+        assert_eq!(turn.telemetry.token_counts.len(), 1);
+        assert_eq!(turn.ended_at, record.ended_at);
+        assert_eq!(turn.duration_seconds, Some(4));
+    }
+
+    #[test]
+    fn parses_mcp_tool_calls_into_structured_actions() {
+        let data = r#"
+{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"mcp","cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"find the ticket"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"function_call","name":"linear__search","call_id":"c1","arguments":"{\"query\":\"login bug\"}"}}
+{"timestamp":"2025-01-01T00:00:02.100Z","type":"event_msg","payload":{"type":"mcp_tool_call_begin","call_id":"c1","invocation":{"server":"linear","tool":"search","arguments":{"query":"login bug"}}}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"event_msg","payload":{"type":"mcp_tool_call_end","call_id":"c1","invocation":{"server":"linear","tool":"search","arguments":{"query":"login bug"}},"duration":{"secs":0,"nanos":900},"result":{"Ok":{"content":[{"type":"text","text":"ENG-42 Login fails"}],"isError":false}}}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"event_msg","payload":{"type":"mcp_tool_call_end","call_id":"c2","invocation":{"server":"github","tool":"get_issue","arguments":null},"result":{"Err":"timed out"}}}
+{"timestamp":"2025-01-01T00:00:05.000Z","type":"response_item","payload":{"type":"mcp_call","id":"c3","server_label":"docs","name":"lookup","arguments":"{\"topic\":\"auth\"}","output":"See auth.md"}}
+        "#;
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        let actions = &record.turns[0].actions;
+        assert_eq!(actions.len(), 3);
+        let kinds: Vec<_> = actions
+            .iter()
+            .map(|action| match &action.kind {
+                ActionKind::McpToolCall { server, tool } => {
+                    format!(
+                        "{}.{}",
+                        server.as_deref().unwrap(),
+                        tool.as_deref().unwrap()
+                    )
+                }
+                other => panic!("unexpected kind {other:?}"),
+            })
+            .collect();
+        assert_eq!(kinds, ["linear.search", "github.get_issue", "docs.lookup"]);
+
+        let search = &actions[0];
+        assert_eq!(search.arguments.as_ref().unwrap()["query"], "login bug");
+        assert_eq!(search.events.len(), 3);
+        let output = search.output.as_ref().unwrap();
+        assert_eq!(
+            (output.content.as_deref(), output.success),
+            (Some("ENG-42 Login fails"), Some(true))
+        );
+        let failed = actions[1].output.as_ref().unwrap();
+        assert_eq!(
+            (failed.content.as_deref(), failed.success),
+            (Some("timed out"), Some(false))
+        );
+        assert_eq!(actions[2].arguments.as_ref().unwrap()["topic"], "auth");
+        assert_eq!(
+            actions[2].output.as_ref().unwrap().content.as_deref(),
+            Some("See auth.md")
+        );
+    }
+
+    #[test]
+    fn reconstructs_streamed_deltas_into_messages() {
+        let data = r#"
+{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"deltas","cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"status?"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"event_msg","payload":{"type":"agent_reasoning_delta","delta":"Checking "}}
+{"timestamp":"2025-01-01T00:00:02.100Z","type":"event_msg","payload":{"type":"agent_reasoning_delta","delta":"the build."}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"event_msg","payload":{"type":"agent_message_delta","item_id":"m1","delta":"All "}}
+{"timestamp":"2025-01-01T00:00:03.100Z","type":"event_msg","payload":{"type":"agent_message_delta","item_id":"m2","delta":"Also "}}
+{"timestamp":"2025-01-01T00:00:03.200Z","type":"event_msg","payload":{"type":"agent_message_delta","item_id":"m1","delta":"green."}}
+{"timestamp":"2025-01-01T00:00:03.300Z","type":"event_msg","payload":{"type":"agent_message_delta","item_id":"m2","delta":"no warnings."}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"message","id":"m2","role":"assistant","content":[{"type":"output_text","text":"Also no warnings."}]}}
+        "#;
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        let turn = &record.turns[0];
+        assert_eq!(
+            turn.result.assistant_messages,
+            ["Also no warnings.", "All green."]
+        );
+        assert_eq!(turn.result.reasoning_summaries, ["Checking the build."]);
+        assert!(turn.telemetry.misc_events.is_empty());
+    }
+
+    #[test]
+    fn records_exit_codes_and_durations_of_commands() {
+        let data = r#"
+{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"exec","cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"function_call","name":"shell","call_id":"c1","arguments":"{\"command\":[\"cargo\",\"test\"]}"}}
+{"timestamp":"2025-01-01T00:00:05.000Z","type":"event_msg","payload":{"type":"exec_command_end","call_id":"c1","exit_code":101,"duration":{"secs":4,"nanos":250000000}}}
+{"timestamp":"2025-01-01T00:00:06.000Z","type":"response_item","payload":{"type":"function_call","name":"shell","call_id":"c2","arguments":"{\"command\":[\"ls\"]}"}}
+{"timestamp":"2025-01-01T00:00:07.000Z","type":"response_item","payload":{"type":"function_call_output","call_id":"c2","output":"{\"output\":\"src\",\"metadata\":{\"exit_code\":0,\"duration_seconds\":0.1}}"}}
+        "#;
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        let statuses: Vec<_> = record.turns[0]
+            .actions
+            .iter()
+            .map(|action| {
+                let status = &action.status;
+                (status.exit_code, status.duration_ms, status.success)
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (Some(101), Some(4250), Some(false)),
+                (Some(0), Some(100), Some(true))
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_report_line_and_record_snippet() {
+        let data = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"x"}}
+
+{"timestamp":"yesterday","type":"event_msg","payload":{"type":"agent_message"}}
+"#;
+        let err = parse_rollout(std::io::Cursor::new(data.as_bytes())).unwrap_err();
+        match &err {
+            ParseError::Timestamp { line, value, .. } => {
+                assert_eq!(*line, 3);
+                assert_eq!(value, "yesterday");
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+        let err = err.with_path("sessions/rollout-a.jsonl");
+        assert_eq!(err.path(), Some(Path::new("sessions/rollout-a.jsonl")));
+        assert!(err
+            .to_string()
+            .starts_with("sessions/rollout-a.jsonl:3: invalid timestamp"));
+
+        let data =
+            "{\"timestamp\":\"2025-01-01T00:00:00.000Z\",\"type\":\"session_meta\"}\n{not json";
+        let err = parse_rollout(std::io::Cursor::new(data.as_bytes())).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::Json { line: 2, snippet, .. } if snippet == "{not json")
+        );
+    }
+
+    #[test]
+    fn lenient_parse_skips_bad_lines_with_warnings() {
+        let data = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"x"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payl
+{"timestamp":"yesterday","type":"response_item","payload":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"lost"}]}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"hologram","payload":{}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"hello"}]}}
+"#;
+        let err = parse_rollout(data.as_bytes()).unwrap_err();
+        assert_eq!(err.line(), 3);
+
+        let options = ParseOptions {
+            lenient: true,
+            ..ParseOptions::default()
+        };
+        let record = parse_transcript_with(data.as_bytes(), RolloutFormat::Auto, options).unwrap();
+        assert_eq!(record.turns[0].result.assistant_messages, ["hello"]);
+        let warnings: Vec<_> = record
+            .warnings
+            .iter()
+            .map(|warning| (warning.line, warning.kind))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (3, ParseWarningKind::MalformedJson),
+                (4, ParseWarningKind::InvalidTimestamp),
+                (5, ParseWarningKind::UnknownType),
+            ]
+        );
+        assert!(record.warnings[1].message.contains("'yesterday'"));
+    }
+
+    #[test]
+    fn turns_and_actions_keep_their_source_lines() {
+        let data = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"x"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"run it"}]}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"response_item","payload":{"type":"function_call","name":"shell","arguments":"{}","call_id":"c1"}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"running"}]}}
+{"timestamp":"2025-01-01T00:00:05.000Z","type":"response_item","payload":{"type":"function_call_output","call_id":"c1","output":"ok"}}
+
+{"timestamp":"2025-01-01T00:00:06.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:07.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"again"}]}}
+"#;
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        let turns: Vec<_> = record.turns.iter().map(|turn| turn.source_lines).collect();
+        assert_eq!(turns, [Some((2, 6)), Some((8, 9))]);
+        assert_eq!(record.turns[0].actions[0].source_lines, Some((4, 6)));
+    }
+
+    #[test]
+    fn turns_get_token_deltas_from_cumulative_snapshots() {
+        let data = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"x"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"one"}]}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"event_msg","payload":{"type":"token_count","info":{"total_token_usage":{"input_tokens":100,"cached_input_tokens":0,"output_tokens":10,"total_tokens":110}}}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"event_msg","payload":{"type":"token_count","info":{"total_token_usage":{"input_tokens":250,"cached_input_tokens":80,"output_tokens":30,"total_tokens":280}}}}
+{"timestamp":"2025-01-01T00:00:05.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:06.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"two"}]}}
+{"timestamp":"2025-01-01T00:00:07.000Z","type":"event_msg","payload":{"type":"token_count","info":{"total_token_usage":{"input_tokens":400,"cached_input_tokens":200,"output_tokens":45,"total_tokens":445}}}}
+{"timestamp":"2025-01-01T00:00:08.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:09.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"three"}]}}
+"#;
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        let first = record.turns[0].token_usage.as_ref().unwrap();
+        assert_eq!(
+            (
+                first.input_tokens,
+                first.cached_input_tokens,
+                first.output_tokens
+            ),
+            (Some(250), Some(80), Some(30))
+        );
+        assert_eq!(first.reasoning_output_tokens, None);
+        let second = record.turns[1].token_usage.as_ref().unwrap();
+        assert_eq!(
+            (
+                second.input_tokens,
+                second.cached_input_tokens,
+                second.output_tokens
+            ),
+            (Some(150), Some(120), Some(15))
+        );
+        assert_eq!(second.total_tokens, Some(165));
+        assert!(record.turns[2].token_usage.is_none());
+    }
+
+    #[test]
+    fn compacted_records_link_to_the_turns_they_replaced() {
+        let turn = |second: u32, text: &str| {
+            format!(
+                r#"{{"timestamp":"2025-01-01T00:00:{second:02}.000Z","type":"turn_context","payload":{{"cwd":"/tmp"}}}}
+{{"timestamp":"2025-01-01T00:00:{second:02}.500Z","type":"response_item","payload":{{"type":"message","role":"user","content":[{{"type":"input_text","text":"{text}"}}]}}}}
+"#
+            )
+        };
+        let compacted = |second: u32, summary: &str| {
+            format!(
+                r#"{{"timestamp":"2025-01-01T00:00:{second:02}.900Z","type":"compacted","payload":{{"message":"{summary}"}}}}
+"#
+            )
+        };
+        let data = [
+            turn(1, "one"),
+            turn(2, "two"),
+            compacted(2, "did one and two"),
+            turn(3, "three"),
+            turn(4, "four"),
+            compacted(4, "did three and four"),
+        ]
+        .concat();
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        let compactions: Vec<_> = record.compactions().collect();
+        assert_eq!(compactions.len(), 2);
+        assert_eq!(compactions[0].summary.as_deref(), Some("did one and two"));
+        assert_eq!(compactions[0].replaced_turns, (0, 1));
+        assert_eq!(compactions[1].replaced_turns, (1, 3));
+        assert_eq!(compactions[1].replaced_turn_count(), 3);
+        assert!(record.turns[1].result.assistant_messages.is_empty());
+        assert_eq!(record.turns[1].compactions, [compactions[0].clone()]);
+    }
+
+    #[test]
+    fn attached_files_are_kept_on_the_user_input() {
+        let data = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"x"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"summarize this"},{"type":"input_file","filename":"notes.txt","file_data":"data:text/plain;base64,aGVsbG8="}]}}
+"#;
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        let input = &record.turns[0].user_inputs[0];
+        assert_eq!(input.text.as_deref(), Some("summarize this"));
+        assert_eq!(input.files[0].filename.as_deref(), Some("notes.txt"));
+        assert_eq!(input.files[0].text.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn a_new_session_meta_starts_a_new_session() {
+        let data = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"first"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"one"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"session_meta","payload":{"id":"first"}}
+{"timestamp":"2025-01-02T00:00:00.000Z","type":"session_meta","payload":{"id":"second"}}
+{"timestamp":"2025-01-02T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"two"}]}}
+"#;
+        let sessions = parse_rollout_sessions(data.as_bytes()).unwrap();
+        assert_eq!(sessions.len(), 2);
+        let ids: Vec<_> = sessions
+            .iter()
+            .map(|session| session.session_meta.as_ref().unwrap()["id"].clone())
+            .collect();
+        assert_eq!(ids, ["first", "second"]);
+        assert!(sessions[0].ended_at < sessions[1].started_at);
+        assert_eq!(sessions[1].turns[0].index, 0);
+        assert_eq!(
+            sessions[1].turns[0].user_inputs[0].text.as_deref(),
+            Some("two")
+        );
+        assert_eq!(parse_rollout(data.as_bytes()).unwrap().turns.len(), 1);
+    }
+
+    #[test]
+    fn legacy_rollout_records_are_adapted_and_the_version_recorded() {
+        let data = r#"{"id":"legacy-1","timestamp":"2025-05-01T10:00:00.000Z","instructions":null}
+{"record_type":"state"}
+{"type":"message","role":"user","content":[{"type":"input_text","text":"list files"}]}
+{"type":"function_call","name":"shell","arguments":"{\"command\":[\"ls\"]}","call_id":"c1"}
+{"type":"function_call_output","call_id":"c1","output":"README.md"}
+{"type":"message","role":"assistant","content":[{"type":"output_text","text":"Just a README."}]}
+"#;
+        let record = parse_rollout(data.as_bytes()).unwrap();
+        assert_eq!(record.rollout_version, Some(RolloutVersion::Legacy));
+        assert_eq!(record.session_meta.as_ref().unwrap()["id"], "legacy-1");
+        let turn = &record.turns[0];
+        assert_eq!(turn.user_inputs[0].text.as_deref(), Some("list files"));
+        assert_eq!(turn.result.assistant_messages, ["Just a README."]);
+        assert_eq!(turn.actions[0].call_id.as_deref(), Some("c1"));
+        assert!(turn.actions[0].output.is_some());
+
+        let current = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"x"}}"#;
+        let record = parse_rollout(current.as_bytes()).unwrap();
+        assert_eq!(record.rollout_version, Some(RolloutVersion::Envelope));
+    }
+
+    #[test]
+    fn legacy_rollouts_can_be_split_on_user_prompts() {
+        let data = r#"{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"x"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"<environment_context><cwd>/w</cwd></environment_context>"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"first"}]}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"response_item","payload":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"one"}]}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"second"}]}}
+{"timestamp":"2025-01-01T00:00:05.000Z","type":"response_item","payload":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"two"}]}}
+"#;
+        let parse = |segmentation| {
+            let options = ParseOptions {
+                segmentation,
+                ..ParseOptions::default()
+            };
+            parse_rollout_from(data.as_bytes(), &ResumeState::default(), options, None).unwrap()
+        };
+        let (sessions, _) = parse(TurnSegmentation::TurnContext);
+        assert_eq!(sessions[0].turns.len(), 1);
+
+        for segmentation in [TurnSegmentation::UserMessage, TurnSegmentation::Auto] {
+            let (sessions, resume) = parse(segmentation);
+            let turns: Vec<_> = sessions[0]
+                .turns
+                .iter()
+                .map(|turn| {
+                    (
+                        turn.user_inputs.len(),
+                        turn.result.assistant_messages.clone(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                turns,
+                [(2, vec!["one".to_string()]), (1, vec!["two".to_string()])]
+            );
+            assert_eq!(resume.turn_index, 1);
+            assert!(data[resume.offset as usize..].contains("\"second\""));
+            assert!(!data[resume.offset as usize..].contains("\"one\""));
+        }
+        assert_eq!(
+            "user-message".parse::<TurnSegmentation>(),
+            Ok(TurnSegmentation::UserMessage)
+        );
+    }
+
+    #[test]
+    fn hashes_the_stream_and_the_prefix_before_the_resume_point() {
+        let data = concat!(
+            "{\"timestamp\":\"2025-01-01T00:00:00.000Z\",\"type\":\"session_meta\",\"payload\":{\"id\":\"x\"}}\n",
+            "{\"timestamp\":\"2025-01-01T00:00:01.000Z\",\"type\":\"turn_context\",\"payload\":{\"cwd\":\"/tmp\"}}\n",
+            "{\"timestamp\":\"2025-01-01T00:00:02.000Z\",\"type\":\"turn_context\",\"payload\":{\"cwd\":\"/tmp\"}}\n",
+            "{\"timestamp\":\"2025-01-01T00:00:03.000Z\",\"type\":\"event_msg\",\"payload\":{\"type\":\"agent_message\"}}",
+        );
+        let mut hasher = Sha256::new();
+        let (_, resume) = parse_rollout_from(
+            std::io::Cursor::new(data.as_bytes()),
+            &ResumeState::default(),
+            ParseOptions::default(),
+            Some(&mut hasher),
+        )
+        .unwrap();
+        let offset = resume.offset as usize;
+        assert_eq!(
+            &data[offset..offset + 40],
+            &data.lines().nth(2).unwrap()[..40]
+        );
+        assert_eq!(
+            resume.prefix_sha256,
+            Some(format!("{:x}", Sha256::digest(&data.as_bytes()[..offset])))
+        );
+        assert_eq!(
+            format!("{:x}", hasher.finalize()),
+            format!("{:x}", Sha256::digest(data.as_bytes()))
+        );
+    }
+}
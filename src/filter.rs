@@ -0,0 +1,378 @@
+//! Typed filter expressions compiled into parameterised SQL over the `conversations` table.
+
+use std::ops::{RangeFrom, RangeInclusive, RangeToInclusive};
+
+use rusqlite::types::Value as SqlValue;
+
+use crate::search::SearchError;
+
+/// Conversation columns that can be filtered on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Id,
+    RolloutPath,
+    Model,
+    Cwd,
+    GitRepo,
+    GitBranch,
+    Preview,
+    Title,
+    AutoTitle,
+    StartedAt,
+    EndedAt,
+    DurationSeconds,
+    TurnCount,
+    TokenInput,
+    TokenOutput,
+    TokenTotal,
+    HasLiveEvents,
+    ApprovalCount,
+    FailedCommandCount,
+    HadErrors,
+    CompactionCount,
+    RolloutVersion,
+    Pinned,
+    HitCount,
+}
+
+impl Column {
+    pub(crate) fn sql(self) -> &'static str {
+        match self {
+            Column::Id => "c.id",
+            Column::RolloutPath => "c.rollout_path",
+            Column::Model => "c.model",
+            Column::Cwd => "c.cwd",
+            Column::GitRepo => "c.git_repo",
+            Column::GitBranch => "c.git_branch",
+            Column::Preview => "c.preview",
+            Column::Title => "c.title",
+            Column::AutoTitle => "c.auto_title",
+            Column::StartedAt => "c.started_at",
+            Column::EndedAt => "c.ended_at",
+            Column::DurationSeconds => "c.duration_seconds",
+            Column::TurnCount => "c.turn_count",
+            Column::TokenInput => "c.token_input",
+            Column::TokenOutput => "c.token_output",
+            Column::TokenTotal => "c.token_total",
+            Column::HasLiveEvents => "c.has_live_events",
+            Column::ApprovalCount => "c.approval_count",
+            Column::FailedCommandCount => "c.failed_command_count",
+            Column::HadErrors => "c.had_errors",
+            Column::CompactionCount => "c.compaction_count",
+            Column::RolloutVersion => "c.rollout_version",
+            Column::Pinned => "c.pinned",
+            Column::HitCount => "c.hit_count",
+        }
+    }
+}
+
+/// What a filter compares: a real column, or a dotted key inside the session metadata JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field<'a> {
+    Column(Column),
+    /// Dotted path into `meta_json` (e.g. `git.branch`); segments may only contain ASCII
+    /// alphanumerics, `_` and `-`.
+    Meta(&'a str),
+}
+
+impl From<Column> for Field<'_> {
+    fn from(column: Column) -> Self {
+        Field::Column(column)
+    }
+}
+
+/// Literal compared against a [`Field`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterValue<'a> {
+    Text(&'a str),
+    Integer(i64),
+    Real(f64),
+    Bool(bool),
+}
+
+impl<'a> From<&'a str> for FilterValue<'a> {
+    fn from(value: &'a str) -> Self {
+        FilterValue::Text(value)
+    }
+}
+
+impl From<i64> for FilterValue<'_> {
+    fn from(value: i64) -> Self {
+        FilterValue::Integer(value)
+    }
+}
+
+impl From<f64> for FilterValue<'_> {
+    fn from(value: f64) -> Self {
+        FilterValue::Real(value)
+    }
+}
+
+impl From<bool> for FilterValue<'_> {
+    fn from(value: bool) -> Self {
+        FilterValue::Bool(value)
+    }
+}
+
+impl FilterValue<'_> {
+    fn to_sql(self) -> SqlValue {
+        match self {
+            FilterValue::Text(text) => SqlValue::Text(text.to_string()),
+            FilterValue::Integer(value) => SqlValue::Integer(value),
+            FilterValue::Real(value) => SqlValue::Real(value),
+            FilterValue::Bool(value) => SqlValue::Integer(value as i64),
+        }
+    }
+}
+
+/// Inclusive integer range with optional bounds, e.g. `(20..).into()` for "at least 20".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IntRange {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl IntRange {
+    /// Filter matching values of `field` within this range.
+    pub fn to_filter<'a>(self, field: impl Into<Field<'a>>) -> Filter<'a> {
+        Filter::range(field, self.min.map(Into::into), self.max.map(Into::into))
+    }
+}
+
+impl From<RangeInclusive<i64>> for IntRange {
+    fn from(range: RangeInclusive<i64>) -> Self {
+        IntRange {
+            min: Some(*range.start()),
+            max: Some(*range.end()),
+        }
+    }
+}
+
+impl From<RangeFrom<i64>> for IntRange {
+    fn from(range: RangeFrom<i64>) -> Self {
+        IntRange {
+            min: Some(range.start),
+            max: None,
+        }
+    }
+}
+
+impl From<RangeToInclusive<i64>> for IntRange {
+    fn from(range: RangeToInclusive<i64>) -> Self {
+        IntRange {
+            min: None,
+            max: Some(range.end),
+        }
+    }
+}
+
+/// Boolean filter expression over conversation columns and metadata keys.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter<'a> {
+    Eq(Field<'a>, FilterValue<'a>),
+    /// Text field starts with the given prefix.
+    Prefix(Field<'a>, &'a str),
+    In(Field<'a>, Vec<FilterValue<'a>>),
+    /// Inclusive range; either bound may be open.
+    Range {
+        field: Field<'a>,
+        min: Option<FilterValue<'a>>,
+        max: Option<FilterValue<'a>>,
+    },
+    And(Vec<Filter<'a>>),
+    Or(Vec<Filter<'a>>),
+}
+
+impl<'a> Filter<'a> {
+    pub fn eq(field: impl Into<Field<'a>>, value: impl Into<FilterValue<'a>>) -> Self {
+        Filter::Eq(field.into(), value.into())
+    }
+
+    /// Shorthand for equality on a metadata key.
+    pub fn meta_eq(key: &'a str, value: impl Into<FilterValue<'a>>) -> Self {
+        Filter::Eq(Field::Meta(key), value.into())
+    }
+
+    pub fn prefix(field: impl Into<Field<'a>>, prefix: &'a str) -> Self {
+        Filter::Prefix(field.into(), prefix)
+    }
+
+    pub fn range(
+        field: impl Into<Field<'a>>,
+        min: Option<FilterValue<'a>>,
+        max: Option<FilterValue<'a>>,
+    ) -> Self {
+        Filter::Range {
+            field: field.into(),
+            min,
+            max,
+        }
+    }
+
+    /// Append this expression to `sql`, pushing every literal onto `values`.
+    pub(crate) fn compile(
+        &self,
+        sql: &mut String,
+        values: &mut Vec<SqlValue>,
+    ) -> Result<(), SearchError> {
+        match self {
+            Filter::Eq(field, value) => {
+                push_field(sql, values, field)?;
+                sql.push_str(" = ?");
+                values.push(value.to_sql());
+            }
+            Filter::Prefix(field, prefix) => {
+                sql.push_str("substr(");
+                push_field(sql, values, field)?;
+                sql.push_str(", 1, ?) = ?");
+                values.push(SqlValue::Integer(prefix.chars().count() as i64));
+                values.push(SqlValue::Text(prefix.to_string()));
+            }
+            Filter::In(field, options) => {
+                if options.is_empty() {
+                    sql.push('0');
+                    return Ok(());
+                }
+                push_field(sql, values, field)?;
+                sql.push_str(" IN (");
+                for (idx, option) in options.iter().enumerate() {
+                    if idx > 0 {
+                        sql.push_str(", ");
+                    }
+                    sql.push('?');
+                    values.push(option.to_sql());
+                }
+                sql.push(')');
+            }
+            Filter::Range { field, min, max } => {
+                let bounds: Vec<(&str, &FilterValue<'_>)> = [(" >= ?", min), (" <= ?", max)]
+                    .into_iter()
+                    .filter_map(|(op, bound)| bound.as_ref().map(|bound| (op, bound)))
+                    .collect();
+                if bounds.is_empty() {
+                    sql.push('1');
+                    return Ok(());
+                }
+                sql.push('(');
+                for (idx, (op, bound)) in bounds.into_iter().enumerate() {
+                    if idx > 0 {
+                        sql.push_str(" AND ");
+                    }
+                    push_field(sql, values, field)?;
+                    sql.push_str(op);
+                    values.push(bound.to_sql());
+                }
+                sql.push(')');
+            }
+            Filter::And(filters) => compile_group(sql, values, filters, " AND ", '1')?,
+            Filter::Or(filters) => compile_group(sql, values, filters, " OR ", '0')?,
+        }
+        Ok(())
+    }
+}
+
+fn compile_group(
+    sql: &mut String,
+    values: &mut Vec<SqlValue>,
+    filters: &[Filter<'_>],
+    joiner: &str,
+    empty: char,
+) -> Result<(), SearchError> {
+    if filters.is_empty() {
+        sql.push(empty);
+        return Ok(());
+    }
+    sql.push('(');
+    for (idx, filter) in filters.iter().enumerate() {
+        if idx > 0 {
+            sql.push_str(joiner);
+        }
+        filter.compile(sql, values)?;
+    }
+    sql.push(')');
+    Ok(())
+}
+
+fn push_field(
+    sql: &mut String,
+    values: &mut Vec<SqlValue>,
+    field: &Field<'_>,
+) -> Result<(), SearchError> {
+    match field {
+        Field::Column(column) => sql.push_str(column.sql()),
+        Field::Meta(key) => {
+            ensure_valid_meta_key(key)?;
+            sql.push_str("json_extract(c.meta_json, ?)");
+            values.push(SqlValue::Text(format!("$.{key}")));
+        }
+    }
+    Ok(())
+}
+
+fn ensure_valid_meta_key(key: &str) -> Result<(), SearchError> {
+    if key.is_empty() {
+        return Err(SearchError::InvalidMetaKey(key.to_string()));
+    }
+    for segment in key.split('.') {
+        if segment.is_empty()
+            || !segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(SearchError::InvalidMetaKey(key.to_string()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(filter: &Filter<'_>) -> (String, Vec<SqlValue>) {
+        let mut sql = String::new();
+        let mut values = Vec::new();
+        filter.compile(&mut sql, &mut values).unwrap();
+        (sql, values)
+    }
+
+    #[test]
+    fn compiles_nested_expressions_with_bound_values() {
+        let filter = Filter::And(vec![
+            Filter::meta_eq("git.branch", "main"),
+            Filter::Or(vec![
+                Filter::prefix(Column::Cwd, "/src"),
+                Filter::In(Field::Column(Column::Model), vec!["a".into(), "b".into()]),
+            ]),
+            Filter::range(Column::TurnCount, Some(20.into()), None),
+            Filter::Or(Vec::new()),
+        ]);
+        let (sql, values) = compile(&filter);
+        assert_eq!(
+            sql,
+            "(json_extract(c.meta_json, ?) = ? AND (substr(c.cwd, 1, ?) = ? OR c.model IN (?, ?)) \
+             AND (c.turn_count >= ?) AND 0)"
+        );
+        assert_eq!(
+            values,
+            vec![
+                SqlValue::Text("$.git.branch".into()),
+                SqlValue::Text("main".into()),
+                SqlValue::Integer(4),
+                SqlValue::Text("/src".into()),
+                SqlValue::Text("a".into()),
+                SqlValue::Text("b".into()),
+                SqlValue::Integer(20),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unsafe_meta_keys() {
+        let mut sql = String::new();
+        let err = Filter::meta_eq("a'); DROP TABLE turns; --", "x")
+            .compile(&mut sql, &mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(err, SearchError::InvalidMetaKey(_)));
+    }
+}
@@ -0,0 +1,62 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::types::{ConversationRecord, ImageRef};
+
+/// Media type and bytes of a base64 `data:` URL. Linked images (`https://…`) and other
+/// encodings yield `None`.
+pub(crate) fn decode_data_url(url: &str) -> Option<(Option<String>, Vec<u8>)> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime_type = header.strip_suffix(";base64")?;
+    let bytes = decode_base64(data)?;
+    let mime_type = (!mime_type.is_empty()).then(|| mime_type.to_string());
+    Some((mime_type, bytes))
+}
+
+/// Bytes of standard base64 text, ignoring surrounding whitespace.
+pub(crate) fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(data.trim()).ok()
+}
+
+/// Reference to an image by the SHA-256 of its bytes.
+pub(crate) fn image_ref(mime_type: Option<String>, bytes: &[u8]) -> ImageRef {
+    ImageRef {
+        sha256: format!("{:x}", Sha256::digest(bytes)),
+        mime_type,
+        size_bytes: bytes.len() as u64,
+    }
+}
+
+/// Fill [`UserInputRecord::image_refs`](crate::UserInputRecord::image_refs) for every inline
+/// image of `record`.
+pub(crate) fn hash_images(record: &mut ConversationRecord) {
+    for turn in &mut record.turns {
+        for input in &mut turn.user_inputs {
+            input.image_refs = input
+                .images
+                .iter()
+                .filter_map(|url| decode_data_url(url))
+                .map(|(mime_type, bytes)| image_ref(mime_type, &bytes))
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_urls_decode_and_hash_by_content() {
+        let (mime_type, bytes) = decode_data_url("data:image/png;base64,aGVsbG8=").unwrap();
+        assert_eq!(mime_type.as_deref(), Some("image/png"));
+        assert_eq!(bytes, b"hello");
+        assert_eq!(
+            image_ref(mime_type, &bytes).sha256,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(decode_data_url("https://example.com/shot.png"), None);
+        assert_eq!(decode_data_url("data:text/plain,hello"), None);
+    }
+}
@@ -1,15 +1,62 @@
+#[cfg(feature = "sqlite-vec")]
+mod ann;
+#[cfg(feature = "tokio")]
+mod async_pipeline;
+mod attachments;
+mod chat_messages;
+mod chatgpt;
+mod claude_code;
 mod embedding;
 mod extractor;
+mod filter;
+mod images;
+mod migrations;
+mod multi;
 mod pipeline;
+mod redact;
 mod search;
+mod stats;
 mod storage;
 mod types;
+mod vector_index;
 
-pub use embedding::{EmbeddingError, EmbeddingModel, EmbeddingModelConfig};
-pub use extractor::{parse_rollout, ParseError};
+#[cfg(feature = "tokio")]
+pub use async_pipeline::{process_rollout_dir_async, update_rollout_dir_async};
+pub use chat_messages::parse_chat_messages;
+pub use chatgpt::parse_chatgpt_export;
+pub use claude_code::parse_claude_code_session;
+pub use embedding::{EmbeddingError, EmbeddingModel, EmbeddingModelConfig, EmbeddingModelInfo};
+pub use extractor::{
+    parse_rollout, parse_rollout_sessions, parse_transcript, parse_transcript_with, FormatRegistry,
+    ParseError, ParseOptions, RolloutFormat, TranscriptFormat, TurnSegmentation,
+};
+pub use filter::{Column, Field, Filter, FilterValue, IntRange};
+pub use migrations::SCHEMA_VERSION;
+pub use multi::{FederatedResult, MultiStorage};
 pub use pipeline::{
-    process_rollout_dir, process_rollout_file, update_rollout_dir, PipelineError, UpdateStats,
+    embed_missing, process_chatgpt_export, process_rollout_dir, process_rollout_dir_with,
+    process_rollout_dirs_with, process_rollout_file, process_rollout_file_with,
+    process_rollout_reader, process_rollout_reader_with, reembed_all, update_rollout_dir,
+    update_rollout_dir_with, update_rollout_dirs_with, ChangeDetection, DuplicateRollout,
+    FileFailure, FileOutcome, FileReport, ImportReport, IngestOptions, PipelineConfig,
+    PipelineError, PostIngestHook, ProgressEvent, ProgressSink, StoredConversation, SummarizeError,
+    Summarizer, UpdateStats,
+};
+pub use redact::{RedactionRuleInfo, Redactor, REDACTED};
+pub use search::{
+    find_similar_conversations, search_iter, search_like_turn, search_with_facets,
+    search_with_text, search_with_vector, write_json_lines, FacetCount, FacetedResults,
+    FieldWeights, RerankError, Reranker, SearchError, SearchFacets, SearchIter, SearchParams,
+    SearchResult, SearchResultDetails, SimilarConversation, DEFAULT_PREFETCH,
+};
+pub use stats::DerivationConfig;
+#[cfg(feature = "encryption")]
+pub use storage::EncryptionKey;
+pub use storage::{
+    AccessOrder, AccessStats, Annotation, ConversationStats, ConversationSummary, DailyUsage,
+    EmbeddingPrecision, EmbeddingSpace, IngestTransaction, IntegrityFinding, JournalMode,
+    ListParams, RebuildReport, RolloutFingerprint, Storage, StorageError, StorageOptions,
+    StoredImage, StoredTurn, Synchronous, UsageCount, DEFAULT_EMBEDDING_MODEL,
 };
-pub use search::{search_with_text, search_with_vector, SearchError, SearchParams, SearchResult};
-pub use storage::{ConversationStats, RolloutFingerprint, Storage, StorageError};
 pub use types::*;
+pub use vector_index::{VectorHit, VectorIndex, VectorIndexConfig};
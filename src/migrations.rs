@@ -0,0 +1,711 @@
+//! Numbered schema migrations tracked with SQLite's `PRAGMA user_version`.
+//!
+//! Every schema change is appended to [`MIGRATIONS`] with the next version number; a database
+//! records the last version applied and only newer migrations run on open. Databases written by
+//! a newer library are rejected instead of being modified.
+//!
+//! Migrations only change the schema: columns they add stay empty on existing rows until a
+//! re-import fills them in. [`Storage::rebuild_derived`](crate::Storage::rebuild_derived)
+//! recomputes the conversation columns derived from stored turns, but only for conversations
+//! whose turns were stored at version 25 or later.
+
+use rusqlite::{Connection, Transaction};
+
+use crate::storage::{StorageError, DEFAULT_EMBEDDING_MODEL};
+
+/// A single schema step, applied inside its own transaction.
+struct Migration {
+    version: i64,
+    apply: fn(&Transaction<'_>) -> rusqlite::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        apply: baseline,
+    },
+    Migration {
+        version: 2,
+        apply: embeddings_table,
+    },
+    Migration {
+        version: 3,
+        apply: meta_table,
+    },
+    Migration {
+        version: 4,
+        apply: annotations_table,
+    },
+    Migration {
+        version: 5,
+        apply: pinned_column,
+    },
+    Migration {
+        version: 6,
+        apply: secondary_indexes,
+    },
+    Migration {
+        version: 7,
+        apply: title_column,
+    },
+    Migration {
+        version: 8,
+        apply: command_and_file_tables,
+    },
+    Migration {
+        version: 9,
+        apply: rollout_resume_column,
+    },
+    Migration {
+        version: 10,
+        apply: auto_title_column,
+    },
+    Migration {
+        version: 11,
+        apply: git_columns,
+    },
+    Migration {
+        version: 12,
+        apply: usage_daily_table,
+    },
+    Migration {
+        version: 13,
+        apply: summary_column,
+    },
+    Migration {
+        version: 14,
+        apply: file_changes_column,
+    },
+    Migration {
+        version: 15,
+        apply: failed_command_count_column,
+    },
+    Migration {
+        version: 16,
+        apply: turn_end_columns,
+    },
+    Migration {
+        version: 17,
+        apply: urls_column,
+    },
+    Migration {
+        version: 18,
+        apply: image_store,
+    },
+    Migration {
+        version: 19,
+        apply: error_columns,
+    },
+    Migration {
+        version: 20,
+        apply: rollout_version_column,
+    },
+    Migration {
+        version: 21,
+        apply: turn_source_line_columns,
+    },
+    Migration {
+        version: 22,
+        apply: turn_token_columns,
+    },
+    Migration {
+        version: 23,
+        apply: compaction_columns,
+    },
+    Migration {
+        version: 24,
+        apply: last_plan_column,
+    },
+    Migration {
+        version: 25,
+        apply: turn_inputs_column,
+    },
+];
+
+/// Indexes backing the common listing and filter queries, as `(name, table, columns)`.
+const SECONDARY_INDEXES: [(&str, &str, &str); 5] = [
+    (
+        "idx_conversations_started_at",
+        "conversations",
+        "started_at",
+    ),
+    ("idx_conversations_model", "conversations", "model"),
+    ("idx_conversations_cwd", "conversations", "cwd"),
+    (
+        "idx_conversations_rollout_path",
+        "conversations",
+        "rollout_path",
+    ),
+    (
+        "idx_turns_started_at",
+        "turns",
+        "conversation_id, started_at",
+    ),
+];
+
+/// Schema version written by this library.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Version recorded in the database (`0` for a new or pre-versioning database).
+pub(crate) fn current_version(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Apply every pending migration and return how many ran.
+pub(crate) fn migrate(conn: &Connection) -> Result<usize, StorageError> {
+    let found = current_version(conn)?;
+    if found > SCHEMA_VERSION {
+        return Err(StorageError::SchemaTooNew {
+            found,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    let mut applied = 0;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > found) {
+        let tx = conn.unchecked_transaction()?;
+        (migration.apply)(&tx)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+/// Columns added to existing tables before migrations were versioned; databases created by
+/// those releases may lack any of them.
+const LEGACY_COLUMNS: &[(&str, &str, &str)] = &[
+    ("conversations", "rollout_modified_at", "TEXT"),
+    ("conversations", "rollout_size_bytes", "INTEGER"),
+    ("conversations", "rollout_hash", "TEXT"),
+    ("conversations", "preview", "TEXT"),
+    ("conversations", "first_question", "TEXT"),
+    ("conversations", "last_question", "TEXT"),
+    ("conversations", "last_user_message", "TEXT"),
+    ("conversations", "model", "TEXT"),
+    ("conversations", "turn_count", "INTEGER"),
+    ("conversations", "has_live_events", "INTEGER"),
+    ("conversations", "commands_json", "TEXT"),
+    ("conversations", "files_json", "TEXT"),
+    ("conversations", "questions_json", "TEXT"),
+    ("conversations", "search_blob", "TEXT"),
+    ("conversations", "cwd", "TEXT"),
+    ("conversations", "hit_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("conversations", "last_accessed_at", "TEXT"),
+    ("conversations", "approval_count", "INTEGER"),
+    ("turns", "hit_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("turns", "last_accessed_at", "TEXT"),
+    ("turns", "reasoning_json", "TEXT"),
+    ("turns", "context_json", "TEXT"),
+    ("turns", "embedding_norm", "REAL"),
+    ("turns", "embedding_bits", "BLOB"),
+];
+
+/// Version 1: the schema as of the introduction of versioning. Creates it from scratch, or
+/// brings an unversioned database up to it by adding any missing legacy columns.
+fn baseline(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS conversations (
+            id TEXT PRIMARY KEY,
+            rollout_path TEXT NOT NULL,
+            started_at TEXT,
+            ended_at TEXT,
+            duration_seconds INTEGER,
+            token_input INTEGER,
+            token_cached INTEGER,
+            token_output INTEGER,
+            token_reasoning INTEGER,
+            token_total INTEGER,
+            token_model_context INTEGER,
+            embedding_dim INTEGER,
+            meta_json TEXT,
+            rollout_modified_at TEXT,
+            rollout_size_bytes INTEGER,
+            rollout_hash TEXT,
+            preview TEXT,
+            first_question TEXT,
+            last_question TEXT,
+            last_user_message TEXT,
+            model TEXT,
+            turn_count INTEGER,
+            has_live_events INTEGER,
+            commands_json TEXT,
+            files_json TEXT,
+            questions_json TEXT,
+            search_blob TEXT,
+            cwd TEXT,
+            hit_count INTEGER NOT NULL DEFAULT 0,
+            last_accessed_at TEXT,
+            approval_count INTEGER
+        );
+
+        CREATE TABLE IF NOT EXISTS turns (
+            conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+            turn_index INTEGER NOT NULL,
+            started_at TEXT,
+            user_text TEXT,
+            assistant_text TEXT,
+            fallback_text TEXT,
+            actions_json TEXT,
+            telemetry_json TEXT,
+            embedding BLOB,
+            hit_count INTEGER NOT NULL DEFAULT 0,
+            last_accessed_at TEXT,
+            reasoning_json TEXT,
+            context_json TEXT,
+            embedding_norm REAL,
+            embedding_bits BLOB,
+            PRIMARY KEY (conversation_id, turn_index)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_turns_conversation ON turns(conversation_id);
+        "#,
+    )?;
+    for (table, column, ty) in LEGACY_COLUMNS {
+        add_column_if_missing(tx, table, column, ty)?;
+    }
+    Ok(())
+}
+
+/// Version 2: move turn embeddings into an `embeddings` table keyed by model name, so several
+/// embedding spaces can coexist. Existing vectors land in the default space.
+fn embeddings_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE embeddings (
+            conversation_id TEXT NOT NULL,
+            turn_index INTEGER NOT NULL,
+            model_name TEXT NOT NULL,
+            dim INTEGER NOT NULL,
+            vector BLOB NOT NULL,
+            norm REAL,
+            bits BLOB,
+            PRIMARY KEY (conversation_id, turn_index, model_name),
+            FOREIGN KEY (conversation_id, turn_index)
+                REFERENCES turns(conversation_id, turn_index) ON DELETE CASCADE
+        );
+        "#,
+    )?;
+    tx.execute(
+        "INSERT INTO embeddings
+         (conversation_id, turn_index, model_name, dim, vector, norm, bits)
+         SELECT conversation_id, turn_index, ?1, length(embedding) / 4, embedding,
+                embedding_norm, embedding_bits
+         FROM turns
+         WHERE length(embedding) > 0 AND length(embedding) % 4 = 0",
+        [DEFAULT_EMBEDDING_MODEL],
+    )?;
+    tx.execute_batch(
+        r#"
+        ALTER TABLE turns DROP COLUMN embedding;
+        ALTER TABLE turns DROP COLUMN embedding_norm;
+        ALTER TABLE turns DROP COLUMN embedding_bits;
+        "#,
+    )
+}
+
+/// Version 3: key/value table for database-wide metadata such as the embedding model.
+fn meta_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);")
+}
+
+/// Version 4: free-text notes attached to a conversation or one of its turns.
+fn annotations_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE annotations (
+            id INTEGER PRIMARY KEY,
+            conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+            turn_index INTEGER,
+            note TEXT NOT NULL,
+            author TEXT,
+            created_at TEXT NOT NULL
+        );
+        CREATE INDEX annotations_by_turn ON annotations(conversation_id, turn_index);
+        "#,
+    )
+}
+
+/// Version 5: user-set `pinned` flag on conversations, kept across re-imports.
+fn pinned_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;")
+}
+
+/// Version 6: indexes for listing by start time and filtering by model, cwd or rollout path.
+fn secondary_indexes(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    for (name, table, columns) in SECONDARY_INDEXES {
+        tx.execute(
+            &format!("CREATE INDEX IF NOT EXISTS {name} ON {table}({columns})"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Version 7: optional user-set `title`, kept across re-imports.
+fn title_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN title TEXT;")
+}
+
+/// Version 8: one row per command run and file touched by a conversation, backfilled from
+/// `commands_json` / `files_json` and indexed for lookups from the command or file side.
+fn command_and_file_tables(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE conversation_commands (
+            conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+            command TEXT NOT NULL,
+            PRIMARY KEY (conversation_id, command)
+        ) WITHOUT ROWID;
+        CREATE INDEX idx_conversation_commands_command ON conversation_commands(command);
+        CREATE TABLE conversation_files (
+            conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+            path TEXT NOT NULL,
+            PRIMARY KEY (conversation_id, path)
+        ) WITHOUT ROWID;
+        CREATE INDEX idx_conversation_files_path ON conversation_files(path);
+
+        INSERT OR IGNORE INTO conversation_commands (conversation_id, command)
+            SELECT c.id, j.value FROM conversations c, json_each(c.commands_json) j
+            WHERE json_valid(c.commands_json) AND j.type = 'text';
+        INSERT OR IGNORE INTO conversation_files (conversation_id, path)
+            SELECT c.id, j.value FROM conversations c, json_each(c.files_json) j
+            WHERE json_valid(c.files_json) AND j.type = 'text';
+        "#,
+    )
+}
+
+/// Drop and recreate the secondary indexes, restoring any that were dropped by hand.
+pub(crate) fn rebuild_indexes(conn: &Connection) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for (name, _, _) in SECONDARY_INDEXES {
+        tx.execute(&format!("DROP INDEX IF EXISTS {name}"), [])?;
+    }
+    secondary_indexes(&tx)?;
+    tx.commit()
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    ty: &str,
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {ty}"), [])?;
+    }
+    Ok(())
+}
+
+/// Version 9: where an update can resume parsing a rollout that has only grown since its last
+/// import.
+fn rollout_resume_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN rollout_resume_json TEXT;")
+}
+
+/// Version 10: `auto_title` derived at import, next to the user-set `title`.
+fn auto_title_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN auto_title TEXT;")
+}
+
+/// Version 11: repository and branch a conversation worked in, indexed for scoping search to
+/// one repository.
+fn git_columns(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        ALTER TABLE conversations ADD COLUMN git_repo TEXT;
+        ALTER TABLE conversations ADD COLUMN git_branch TEXT;
+        CREATE INDEX idx_conversations_git_repo ON conversations(git_repo, git_branch);
+        "#,
+    )
+}
+
+/// Version 12: token usage per start day and model, kept in step with `conversations` by
+/// triggers so every insert, re-import and delete adjusts the rollup, and backfilled from the
+/// existing rows. Conversations without a start time are left out; an unknown model is `''`.
+fn usage_daily_table(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE usage_daily (
+            date TEXT NOT NULL,
+            model TEXT NOT NULL,
+            input_tokens INTEGER NOT NULL,
+            output_tokens INTEGER NOT NULL,
+            conversations INTEGER NOT NULL,
+            PRIMARY KEY (date, model)
+        ) WITHOUT ROWID;
+
+        INSERT INTO usage_daily (date, model, input_tokens, output_tokens, conversations)
+            SELECT substr(started_at, 1, 10), COALESCE(model, ''),
+                   SUM(COALESCE(token_input, 0)), SUM(COALESCE(token_output, 0)), COUNT(*)
+            FROM conversations WHERE started_at IS NOT NULL
+            GROUP BY substr(started_at, 1, 10), COALESCE(model, '');
+
+        CREATE TRIGGER usage_daily_insert AFTER INSERT ON conversations
+        WHEN NEW.started_at IS NOT NULL
+        BEGIN
+            INSERT INTO usage_daily (date, model, input_tokens, output_tokens, conversations)
+                VALUES (substr(NEW.started_at, 1, 10), COALESCE(NEW.model, ''),
+                        COALESCE(NEW.token_input, 0), COALESCE(NEW.token_output, 0), 1)
+                ON CONFLICT (date, model) DO UPDATE SET
+                    input_tokens = input_tokens + excluded.input_tokens,
+                    output_tokens = output_tokens + excluded.output_tokens,
+                    conversations = conversations + 1;
+        END;
+
+        CREATE TRIGGER usage_daily_delete AFTER DELETE ON conversations
+        WHEN OLD.started_at IS NOT NULL
+        BEGIN
+            UPDATE usage_daily SET
+                input_tokens = input_tokens - COALESCE(OLD.token_input, 0),
+                output_tokens = output_tokens - COALESCE(OLD.token_output, 0),
+                conversations = conversations - 1
+            WHERE date = substr(OLD.started_at, 1, 10) AND model = COALESCE(OLD.model, '');
+            DELETE FROM usage_daily
+            WHERE date = substr(OLD.started_at, 1, 10) AND model = COALESCE(OLD.model, '')
+                AND conversations <= 0;
+        END;
+
+        CREATE TRIGGER usage_daily_update
+        AFTER UPDATE OF started_at, model, token_input, token_output ON conversations
+        BEGIN
+            UPDATE usage_daily SET
+                input_tokens = input_tokens - COALESCE(OLD.token_input, 0),
+                output_tokens = output_tokens - COALESCE(OLD.token_output, 0),
+                conversations = conversations - 1
+            WHERE OLD.started_at IS NOT NULL
+                AND date = substr(OLD.started_at, 1, 10) AND model = COALESCE(OLD.model, '');
+            DELETE FROM usage_daily
+            WHERE OLD.started_at IS NOT NULL
+                AND date = substr(OLD.started_at, 1, 10) AND model = COALESCE(OLD.model, '')
+                AND conversations <= 0;
+            INSERT INTO usage_daily (date, model, input_tokens, output_tokens, conversations)
+                SELECT substr(NEW.started_at, 1, 10), COALESCE(NEW.model, ''),
+                       COALESCE(NEW.token_input, 0), COALESCE(NEW.token_output, 0), 1
+                WHERE NEW.started_at IS NOT NULL
+                ON CONFLICT (date, model) DO UPDATE SET
+                    input_tokens = input_tokens + excluded.input_tokens,
+                    output_tokens = output_tokens + excluded.output_tokens,
+                    conversations = conversations + 1;
+        END;
+        "#,
+    )
+}
+
+/// Version 13: abstract produced by a [`Summarizer`](crate::Summarizer) during ingest.
+fn summary_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN summary TEXT;")
+}
+
+/// Version 14: per-file `apply_patch` diffstat of a conversation.
+fn file_changes_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN file_changes_json TEXT;")
+}
+
+/// Version 15: commands that exited non-zero, for finding sessions with failures.
+fn failed_command_count_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN failed_command_count INTEGER;")
+}
+
+/// Version 16: when each turn's last record was written and how long the turn took.
+fn turn_end_columns(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        ALTER TABLE turns ADD COLUMN ended_at TEXT;
+        ALTER TABLE turns ADD COLUMN duration_seconds INTEGER;
+        "#,
+    )
+}
+
+/// Version 17: URLs linked by the assistant or opened by web searches.
+fn urls_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN urls_json TEXT;")
+}
+
+/// Version 18: content-addressed image attachments and each turn's references to them.
+fn image_store(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS images (
+            sha256 TEXT PRIMARY KEY,
+            mime_type TEXT,
+            size_bytes INTEGER NOT NULL,
+            data BLOB NOT NULL
+        );
+        ALTER TABLE turns ADD COLUMN images_json TEXT;
+        "#,
+    )
+}
+
+/// Version 19: error and abort events of each turn, and whether a conversation had any.
+fn error_columns(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        ALTER TABLE turns ADD COLUMN errors_json TEXT;
+        ALTER TABLE conversations ADD COLUMN had_errors INTEGER;
+        "#,
+    )
+}
+
+/// Version 20: record layout a Codex rollout was written in.
+fn rollout_version_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN rollout_version TEXT;")
+}
+
+/// Version 21: range of transcript lines each turn was parsed from.
+fn turn_source_line_columns(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        ALTER TABLE turns ADD COLUMN source_line_start INTEGER;
+        ALTER TABLE turns ADD COLUMN source_line_end INTEGER;
+        "#,
+    )
+}
+
+/// Version 22: tokens spent per turn, from consecutive `token_count` snapshots.
+fn turn_token_columns(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        ALTER TABLE turns ADD COLUMN input_tokens INTEGER;
+        ALTER TABLE turns ADD COLUMN cached_input_tokens INTEGER;
+        ALTER TABLE turns ADD COLUMN output_tokens INTEGER;
+        ALTER TABLE turns ADD COLUMN reasoning_output_tokens INTEGER;
+        ALTER TABLE turns ADD COLUMN total_tokens INTEGER;
+        "#,
+    )
+}
+
+/// Version 23: history compactions per turn, and their count per conversation.
+fn compaction_columns(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        ALTER TABLE turns ADD COLUMN compactions_json TEXT;
+        ALTER TABLE conversations ADD COLUMN compaction_count INTEGER;
+        "#,
+    )
+}
+
+/// Version 24: the agent's last plan in each conversation.
+fn last_plan_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE conversations ADD COLUMN last_plan_json TEXT;")
+}
+
+/// Version 25: each turn's user inputs with the kind they were classified as at import, so
+/// derived columns can be recomputed without re-splitting the joined `user_text`. Turns without
+/// it mark conversations that `rebuild_derived` leaves alone.
+fn turn_inputs_column(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE turns ADD COLUMN inputs_json TEXT;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_unversioned_databases_and_rejects_newer_ones() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE conversations (id TEXT PRIMARY KEY, rollout_path TEXT NOT NULL,
+                                         started_at TEXT, token_input INTEGER,
+                                         token_output INTEGER);
+             CREATE TABLE turns (conversation_id TEXT NOT NULL, turn_index INTEGER NOT NULL,
+                                 started_at TEXT, embedding BLOB,
+                                 PRIMARY KEY (conversation_id, turn_index));",
+        )
+        .unwrap();
+        assert_eq!(migrate(&conn).unwrap(), MIGRATIONS.len());
+        assert_eq!(current_version(&conn).unwrap(), SCHEMA_VERSION);
+        conn.execute(
+            "INSERT INTO conversations (id, rollout_path, approval_count) VALUES ('a', 'a', 1)",
+            [],
+        )
+        .unwrap();
+        assert_eq!(migrate(&conn).unwrap(), 0);
+
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(matches!(
+            migrate(&conn),
+            Err(StorageError::SchemaTooNew { found, supported })
+                if found == SCHEMA_VERSION + 1 && supported == SCHEMA_VERSION
+        ));
+    }
+
+    #[test]
+    fn rebuilds_missing_secondary_indexes() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        let indexes = |conn: &Connection| -> i64 {
+            conn.query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'index' AND name LIKE 'idx_%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let created = indexes(&conn);
+        conn.execute_batch("DROP INDEX idx_conversations_model")
+            .unwrap();
+        assert_eq!(indexes(&conn), created - 1);
+        rebuild_indexes(&conn).unwrap();
+        assert_eq!(indexes(&conn), created);
+    }
+
+    #[test]
+    fn backfills_command_and_file_tables_from_json_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"CREATE TABLE conversations (id TEXT PRIMARY KEY, rollout_path TEXT NOT NULL,
+                                          started_at TEXT, token_input INTEGER,
+                                          token_output INTEGER, commands_json TEXT,
+                                          files_json TEXT);
+               CREATE TABLE turns (conversation_id TEXT NOT NULL, turn_index INTEGER NOT NULL,
+                                   started_at TEXT, embedding BLOB,
+                                   PRIMARY KEY (conversation_id, turn_index));
+               INSERT INTO conversations (id, rollout_path, commands_json, files_json)
+                   VALUES ('a', 'a', '["cargo","git"]', '["src/lib.rs"]'),
+                          ('b', 'b', '["cargo"]', NULL),
+                          ('c', 'c', 'not json', '[]');"#,
+        )
+        .unwrap();
+        migrate(&conn).unwrap();
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(
+            count("SELECT COUNT(*) FROM conversation_commands WHERE command = 'cargo'"),
+            2
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM conversation_commands"), 3);
+        assert_eq!(count("SELECT COUNT(*) FROM conversation_files"), 1);
+    }
+
+    #[test]
+    fn moves_turn_embeddings_into_the_default_space() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE conversations (id TEXT PRIMARY KEY, rollout_path TEXT NOT NULL,
+                                         started_at TEXT, token_input INTEGER,
+                                         token_output INTEGER);
+             CREATE TABLE turns (conversation_id TEXT NOT NULL, turn_index INTEGER NOT NULL,
+                                 started_at TEXT, embedding BLOB,
+                                 PRIMARY KEY (conversation_id, turn_index));
+             INSERT INTO conversations (id, rollout_path) VALUES ('a', 'a.jsonl');
+             INSERT INTO turns (conversation_id, turn_index, embedding)
+                 VALUES ('a', 0, zeroblob(8)), ('a', 1, NULL);",
+        )
+        .unwrap();
+        migrate(&conn).unwrap();
+        let (model, dim): (String, i64) = conn
+            .query_row("SELECT model_name, dim FROM embeddings", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((model.as_str(), dim), (DEFAULT_EMBEDDING_MODEL, 2));
+        assert!(conn.prepare("SELECT embedding FROM turns").is_err());
+    }
+}
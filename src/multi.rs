@@ -0,0 +1,187 @@
+//! Federated search over several ConvMemory databases (e.g. one per machine or project).
+
+use std::cmp::Ordering;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::search::{search_with_vector, SearchError, SearchParams, SearchResult};
+use crate::storage::{Storage, StorageError};
+
+/// A set of labelled databases searched together.
+#[derive(Default)]
+pub struct MultiStorage {
+    sources: Vec<(String, Storage)>,
+}
+
+/// Search hit tagged with the label of the database it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedResult {
+    pub source: String,
+    pub result: SearchResult,
+}
+
+impl MultiStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an already opened database under `label`.
+    pub fn add(&mut self, label: impl Into<String>, storage: Storage) {
+        self.sources.push((label.into(), storage));
+    }
+
+    /// Open the database at `path` and add it under `label`.
+    pub fn open(
+        &mut self,
+        label: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Result<(), StorageError> {
+        self.add(label, Storage::open(path)?);
+        Ok(())
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = (&str, &Storage)> {
+        self.sources
+            .iter()
+            .map(|(label, storage)| (label.as_str(), storage))
+    }
+
+    /// Run the search against every database and merge the hits by score (ties broken by
+    /// source label, conversation id and turn index). Filters, prefetch and any reranker apply
+    /// per database; `offset`/`limit` and `record_access` apply to the merged list.
+    /// `vector_index` is ignored since an index only describes one database.
+    pub fn search_with_vector(
+        &self,
+        query_vector: &[f32],
+        params: &SearchParams<'_>,
+    ) -> Result<Vec<FederatedResult>, SearchError> {
+        let per_source = SearchParams {
+            offset: 0,
+            limit: params.offset.saturating_add(params.limit),
+            record_access: false,
+            vector_index: None,
+            ..params.clone()
+        };
+
+        let mut merged = Vec::new();
+        for (label, storage) in &self.sources {
+            for result in search_with_vector(storage, query_vector, &per_source)? {
+                merged.push(FederatedResult {
+                    source: label.clone(),
+                    result,
+                });
+            }
+        }
+        merged.sort_by(compare_federated);
+        let merged: Vec<FederatedResult> = merged
+            .into_iter()
+            .skip(params.offset)
+            .take(params.limit)
+            .collect();
+
+        if params.record_access {
+            for (label, storage) in &self.sources {
+                let hits: Vec<(&str, usize)> = merged
+                    .iter()
+                    .filter(|hit| &hit.source == label)
+                    .map(|hit| (hit.result.conversation_id.as_str(), hit.result.turn_index))
+                    .collect();
+                if !hits.is_empty() {
+                    storage.record_access(&hits)?;
+                }
+            }
+        }
+        Ok(merged)
+    }
+}
+
+fn compare_federated(a: &FederatedResult, b: &FederatedResult) -> Ordering {
+    b.result
+        .score
+        .total_cmp(&a.result.score)
+        .then_with(|| a.source.cmp(&b.source))
+        .then_with(|| a.result.conversation_id.cmp(&b.result.conversation_id))
+        .then_with(|| a.result.turn_index.cmp(&b.result.turn_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ConversationStats, RolloutFingerprint};
+    use crate::types::{ConversationRecord, TurnRecord, TurnResult, TurnTelemetry};
+
+    fn storage_with(conversations: &[(&str, [f32; 2])]) -> Storage {
+        let storage = Storage::open_in_memory().unwrap();
+        for (id, embedding) in conversations {
+            storage
+                .upsert_conversation(
+                    format!("{id}.jsonl"),
+                    &ConversationRecord::default(),
+                    &RolloutFingerprint::default(),
+                    &ConversationStats::default(),
+                    Some(id),
+                )
+                .unwrap();
+            let turn = TurnRecord {
+                index: 0,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: TurnTelemetry::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage.insert_turn(id, &turn, Some(embedding)).unwrap();
+        }
+        storage
+    }
+
+    #[test]
+    fn merges_ranked_results_across_databases() {
+        let mut multi = MultiStorage::new();
+        multi.add(
+            "laptop",
+            storage_with(&[("a", [1.0, 0.0]), ("b", [0.0, 1.0])]),
+        );
+        multi.add(
+            "desktop",
+            storage_with(&[("c", [0.9, 0.1]), ("d", [0.5, 0.5])]),
+        );
+
+        let params = SearchParams {
+            record_access: true,
+            ..SearchParams::new(3)
+        };
+        let hits = multi.search_with_vector(&[1.0, 0.0], &params).unwrap();
+        let keys: Vec<(&str, &str)> = hits
+            .iter()
+            .map(|hit| (hit.source.as_str(), hit.result.conversation_id.as_str()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![("laptop", "a"), ("desktop", "c"), ("desktop", "d")]
+        );
+
+        let page = SearchParams {
+            offset: 3,
+            ..SearchParams::new(3)
+        };
+        let rest = multi.search_with_vector(&[1.0, 0.0], &page).unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].result.conversation_id, "b");
+
+        let (_, laptop) = multi.sources().next().unwrap();
+        let hits: i64 = laptop
+            .connection()
+            .query_row("SELECT sum(hit_count) FROM turns", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(hits, 1);
+    }
+}
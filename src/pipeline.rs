@@ -1,32 +1,102 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, Metadata};
-use std::io::Cursor;
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use flate2::read::MultiGzDecoder;
+use globset::{Glob, GlobSetBuilder};
 use serde_json::Value;
 use sha2::{Digest, Sha256};
 use thiserror::Error;
+use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 use walkdir::WalkDir;
 
+use crate::chatgpt::{parse_chatgpt_conversation, read_chatgpt_export};
 use crate::embedding::{EmbeddingError, EmbeddingModel};
-use crate::extractor::{parse_rollout, ParseError};
-use crate::storage::{ConversationStats, RolloutFingerprint, Storage, StorageError};
-use crate::types::{ActionKind, ActionRecord, ConversationRecord, TurnRecord, TurnTelemetry};
-
-/// Errors surfaced when processing and persisting rollout files.
+use crate::extractor::{
+    parse_rollout_from, parse_transcript_from, FormatRegistry, ParseError, ParseOptions,
+    ResumeState, RolloutFormat, TranscriptFormat,
+};
+use crate::images::hash_images;
+use crate::redact::Redactor;
+use crate::stats::{compute_conversation_stats, DerivationConfig};
+use crate::storage::{
+    extract_conversation_id, render_attachment, ConversationStats, RolloutFingerprint, Storage,
+    StorageError, DEFAULT_EMBEDDING_MODEL,
+};
+use crate::types::{ConversationRecord, TurnRecord, UserInputKind};
+
+/// Errors surfaced when processing and persisting rollout files. Per-file failures carry the
+/// rollout path so batch runs can point at the offending file.
 #[derive(Error, Debug)]
 pub enum PipelineError {
     #[error("parse error: {0}")]
     Parse(#[from] ParseError),
-    #[error("embedding error: {0}")]
-    Embedding(#[from] EmbeddingError),
+    #[error("{}: embedding error: {source}", .path.display())]
+    Embedding {
+        path: PathBuf,
+        source: EmbeddingError,
+    },
+    #[error("{}: summarizer error: {source}", .path.display())]
+    Summarize {
+        path: PathBuf,
+        source: SummarizeError,
+    },
+    #[error("{}: storage error: {source}", .path.display())]
+    Storage { path: PathBuf, source: StorageError },
+    /// A storage failure not tied to a single rollout.
     #[error("storage error: {0}")]
-    Storage(#[from] StorageError),
-    #[error("io error: {0}")]
-    Io(#[from] std::io::Error),
+    Database(StorageError),
+    #[error("{}: io error: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
     #[error("walkdir error: {0}")]
     WalkDir(#[from] walkdir::Error),
+    #[error("invalid exclude pattern: {0}")]
+    Pattern(#[from] globset::Error),
+}
+
+impl PipelineError {
+    /// Rollout (or directory) path the failure relates to, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            PipelineError::Parse(err) => err.path(),
+            PipelineError::Embedding { path, .. }
+            | PipelineError::Summarize { path, .. }
+            | PipelineError::Storage { path, .. }
+            | PipelineError::Io { path, .. } => Some(path),
+            PipelineError::WalkDir(err) => err.path(),
+            PipelineError::Database(_) | PipelineError::Pattern(_) => None,
+        }
+    }
+
+    pub(crate) fn io(path: &Path, source: std::io::Error) -> Self {
+        PipelineError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub(crate) fn storage(path: &Path, source: StorageError) -> Self {
+        PipelineError::Storage {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    fn embedding(path: &Path, source: EmbeddingError) -> Self {
+        PipelineError::Embedding {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
 }
 
 /// Process a single rollout file, generating embeddings (when an embedder is provided) and
@@ -37,263 +107,2069 @@ pub fn process_rollout_file(
     embedder: Option<&EmbeddingModel>,
     conversation_id_override: Option<&str>,
 ) -> Result<(), PipelineError> {
-    let rollout_path = rollout_path.as_ref();
-    let (bytes, fingerprint) = load_rollout_data(rollout_path, None)?;
-    ingest_rollout_bytes(
+    process_rollout_file_with(
         rollout_path,
-        &bytes,
-        &fingerprint,
         storage,
         embedder,
         conversation_id_override,
+        &IngestOptions::default(),
+    )
+}
+
+/// Like [`process_rollout_file`], honouring the `config`, `redactor`, `summarizer` and
+/// `post_ingest` of `options`; the options that steer directory walks and batches do not apply.
+pub fn process_rollout_file_with(
+    rollout_path: impl AsRef<Path>,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    conversation_id_override: Option<&str>,
+    options: &IngestOptions<'_>,
+) -> Result<(), PipelineError> {
+    let rollout_path = rollout_path.as_ref();
+    record_ingest_setup(storage, embedder, options.redactor, rollout_path)?;
+    let config = &options.config;
+    let mut prepared = parse_rollout_file(rollout_path, config, options.formats, options.redactor)?;
+    embed_prepared(&mut prepared, embedder, None, config)?;
+    summarize_prepared(&mut prepared, options.summarizer, config)?;
+    write_rollout(
+        storage,
+        &prepared,
+        conversation_id_override,
+        options.post_ingest,
+    )
+}
+
+/// Process a rollout read from `reader` instead of a file on disk, e.g. piped from stdin, fetched
+/// from an API or decrypted on the fly. The contents must already be decompressed.
+/// `source_label` stands in for the rollout path: it is stored as the conversation's
+/// `rollout_path` (and derives its id when the rollout has no session id). No file time or size
+/// is recorded, so directory updates never mistake the conversation for an unchanged file.
+pub fn process_rollout_reader(
+    reader: impl Read,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    source_label: &str,
+) -> Result<(), PipelineError> {
+    process_rollout_reader_with(
+        reader,
+        storage,
+        embedder,
+        source_label,
+        &IngestOptions::default(),
     )
 }
 
+/// Like [`process_rollout_reader`], honouring the `config`, `redactor`, `summarizer` and
+/// `post_ingest` of `options`.
+pub fn process_rollout_reader_with(
+    reader: impl Read,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    source_label: &str,
+    options: &IngestOptions<'_>,
+) -> Result<(), PipelineError> {
+    let path = Path::new(source_label);
+    record_ingest_setup(storage, embedder, options.redactor, path)?;
+    let config = &options.config;
+    let reader = BufReader::new(reader);
+    let mut prepared = parse_rollout_stream(
+        path,
+        reader,
+        None,
+        config,
+        options.formats,
+        options.redactor,
+    )?;
+    embed_prepared(&mut prepared, embedder, None, config)?;
+    summarize_prepared(&mut prepared, options.summarizer, config)?;
+    write_rollout(storage, &prepared, None, options.post_ingest)
+}
+
+/// Ingest every conversation of a ChatGPT data export's `conversations.json` at `export_path`
+/// (see [`crate::parse_chatgpt_export`]). Each conversation is stored under the rollout path
+/// `<export_path>#<conversation id>` and fingerprinted by the hash of its own JSON, so importing
+/// a newer export again only rewrites the conversations that changed (counted as `skipped`
+/// otherwise). Honours the `config`, `redactor`, `summarizer`, `post_ingest`, `progress`,
+/// `since`, `until` and `skip_failures` of `options`.
+pub fn process_chatgpt_export(
+    export_path: impl AsRef<Path>,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    let export_path = export_path.as_ref();
+    record_ingest_setup(storage, embedder, options.redactor, export_path)?;
+    let file = fs::File::open(export_path).map_err(|err| PipelineError::io(export_path, err))?;
+    let conversations =
+        read_chatgpt_export(BufReader::new(file)).map_err(|err| err.with_path(export_path))?;
+    report(
+        options.progress,
+        ProgressEvent::Discovered {
+            total: conversations.len(),
+        },
+    );
+    let mut stats = UpdateStats::default();
+    for (idx, conversation) in conversations.iter().enumerate() {
+        let id = conversation
+            .get("conversation_id")
+            .or_else(|| conversation.get("id"))
+            .and_then(Value::as_str)
+            .map_or_else(|| (idx + 1).to_string(), String::from);
+        let path = PathBuf::from(format!("{}#{id}", export_path.display()));
+        report(options.progress, ProgressEvent::FileStarted { path: &path });
+        let result =
+            import_chatgpt_conversation(conversation, idx + 1, &path, storage, embedder, options);
+        match result {
+            Ok(Some(outcome)) => {
+                match outcome {
+                    FileOutcome::Ingested => stats.processed += 1,
+                    _ => stats.skipped += 1,
+                }
+                report(
+                    options.progress,
+                    ProgressEvent::FileFinished {
+                        path: &path,
+                        outcome,
+                    },
+                );
+            }
+            Ok(None) => {}
+            Err(error) => {
+                report(
+                    options.progress,
+                    ProgressEvent::FileFailed {
+                        path: &path,
+                        error: &error,
+                    },
+                );
+                if !options.skip_failures {
+                    return Err(error);
+                }
+                stats.failed.push(FileFailure { path, error });
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Import one exported conversation unless it is unchanged or outside `since`/`until` (`None`).
+fn import_chatgpt_conversation(
+    conversation: &Value,
+    position: usize,
+    path: &Path,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+) -> Result<Option<FileOutcome>, PipelineError> {
+    let started = Instant::now();
+    let config = &options.config;
+    let sha256 = format!("{:x}", Sha256::digest(conversation.to_string().as_bytes()));
+    let stored = storage
+        .get_rollout_fingerprint(path)
+        .map_err(|err| PipelineError::storage(path, err))?;
+    if stored.is_some_and(|stored| stored.sha256.as_deref() == Some(sha256.as_str())) {
+        return Ok(Some(FileOutcome::Skipped));
+    }
+    let mut record =
+        parse_chatgpt_conversation(conversation, position).map_err(|err| err.with_path(path))?;
+    if let Some(started) = record.started_at {
+        if options.since.is_some_and(|since| started < since)
+            || options.until.is_some_and(|until| started >= until)
+        {
+            return Ok(None);
+        }
+    }
+    if let Some(redactor) = options.redactor {
+        redactor.redact_record(&mut record);
+    }
+    if config.store_images {
+        hash_images(&mut record);
+    }
+    let stats = compute_conversation_stats(&record, &config.derivation());
+    let mut prepared = PreparedRollout {
+        path: path.to_path_buf(),
+        fingerprint: RolloutFingerprint {
+            modified_at: None,
+            size_bytes: None,
+            sha256: Some(sha256),
+        },
+        record,
+        stats,
+        first_new_turn: 0,
+        embeddings: None,
+        resume: None,
+        bytes: 0,
+        parse_time: started.elapsed(),
+        embed_time: Duration::ZERO,
+        later_sessions: Vec::new(),
+    };
+    embed_prepared(&mut prepared, embedder, options.progress, config)?;
+    summarize_prepared(&mut prepared, options.summarizer, config)?;
+    write_rollout(storage, &prepared, None, options.post_ingest)?;
+    Ok(Some(FileOutcome::Ingested))
+}
+
 /// Process every rollout file under `dir`, returning the number of files that were ingested.
 pub fn process_rollout_dir(
     dir: impl AsRef<Path>,
     storage: &Storage,
     embedder: Option<&EmbeddingModel>,
 ) -> Result<usize, PipelineError> {
-    let rollouts = discover_rollouts(dir.as_ref())?;
-    let mut processed = 0usize;
-    for path in rollouts {
-        process_rollout_file(&path, storage, embedder, None)?;
-        processed += 1;
-    }
-    Ok(processed)
+    let stats = process_rollout_dir_with(dir, storage, embedder, &IngestOptions::default())?;
+    Ok(stats.processed)
 }
 
-/// Incrementally process rollout files under `dir`, skipping those whose metadata has not changed.
-pub fn update_rollout_dir(
+/// Like [`process_rollout_dir`], tuned by `options`. Ingestion runs as a staged pipeline:
+/// rollouts are parsed on [`IngestOptions::concurrency`] threads and embedded on as many others
+/// while SQLite writes stay serialized on the calling thread, so reading the next file, embedding
+/// the current one and writing the previous one overlap. Files are written in completion order.
+/// Only `processed`, `duplicates` and `failed` of the returned stats are filled.
+pub fn process_rollout_dir_with(
     dir: impl AsRef<Path>,
     storage: &Storage,
     embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
 ) -> Result<UpdateStats, PipelineError> {
-    let rollouts = discover_rollouts(dir.as_ref())?;
+    process_rollout_dirs_with(&[dir], storage, embedder, options)
+}
+
+/// Like [`process_rollout_dir_with`] over several root directories at once (e.g.
+/// `~/.codex/sessions` plus an archive drive), with one set of combined stats. A rollout reached
+/// through more than one root is ingested once.
+pub fn process_rollout_dirs_with<P: AsRef<Path>>(
+    dirs: &[P],
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    let rollouts = discover_roots(dirs, options)?;
+    report(
+        options.progress,
+        ProgressEvent::Discovered {
+            total: rollouts.len(),
+        },
+    );
     let mut stats = UpdateStats::default();
+    ingest_files(&rollouts, storage, embedder, options, &mut stats)?;
+    Ok(stats)
+}
 
-    for path in rollouts {
-        let metadata = fs::metadata(&path)?;
-        let (modified_at, size_bytes) = file_metadata(&metadata);
+/// Tunables of how rollouts are turned into stored rows and embeddings. The defaults suit a
+/// general-purpose embedding model with a context of a few thousand tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    /// Turn summaries sent to the embedding model per batch.
+    pub embed_batch_size: usize,
+    /// Most recent user messages kept in `conversations.questions_json`.
+    pub max_stored_questions: usize,
+    /// Characters of each action's output included in the embedded turn summary.
+    pub action_output_chars: usize,
+    /// Transcript format of the files read. [`RolloutFormat::Auto`] (the default) detects it
+    /// per file, and directory walks then pick up Codex `rollout-*.jsonl` files as well as
+    /// Claude Code sessions named `<uuid>.jsonl`; with [`RolloutFormat::ClaudeCode`] every
+    /// `.jsonl` file is read.
+    pub format: RolloutFormat,
+    /// How strictly transcripts are parsed. With [`ParseOptions::lenient`], bad lines are
+    /// skipped and counted in [`FileReport::warnings`] instead of failing the file.
+    pub parse: ParseOptions,
+    /// Keep injected context messages ([`UserInputKind::EnvironmentContext`] and
+    /// [`UserInputKind::UserInstructions`]) in previews, stored questions, the search blob and
+    /// embedded turn summaries. Off by default; they stay in the turn's `user_inputs` either way.
+    pub include_context_inputs: bool,
+    /// Hash inline (`data:` URL) image attachments of user messages, keep one copy of each in
+    /// the database's image store and record them in
+    /// [`UserInputRecord::image_refs`](crate::UserInputRecord::image_refs). Off by
+    /// default, since screenshots can be large.
+    pub store_images: bool,
+}
 
-        if let Some(existing) = storage.get_rollout_fingerprint(&path)? {
-            if fingerprint_matches(&existing, modified_at, size_bytes) {
-                stats.skipped += 1;
-                continue;
-            }
+impl PipelineConfig {
+    /// The subset of the settings that [`Storage::rebuild_derived`] needs to reproduce the
+    /// derived conversation columns written at import.
+    pub fn derivation(&self) -> DerivationConfig {
+        DerivationConfig {
+            max_stored_questions: self.max_stored_questions,
+            include_context_inputs: self.include_context_inputs,
         }
+    }
+}
 
-        let (bytes, fingerprint) = load_rollout_data(&path, Some(&metadata))?;
-        ingest_rollout_bytes(&path, &bytes, &fingerprint, storage, embedder, None)?;
-        stats.processed += 1;
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            embed_batch_size: 32,
+            max_stored_questions: 5,
+            action_output_chars: 200,
+            format: RolloutFormat::Auto,
+            parse: ParseOptions::default(),
+            include_context_inputs: false,
+            store_images: false,
+        }
     }
+}
 
-    Ok(stats)
+/// How [`update_rollout_dir_with`] decides that an already imported rollout is unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChangeDetection {
+    /// Compare the stored modification time and size only. Cheapest, but a `touch`-ed file is
+    /// re-imported and a different file with the same time and size is skipped.
+    #[default]
+    Metadata,
+    /// Like [`ChangeDetection::Metadata`], but when the time or size differ, hash the file and
+    /// skip it if the content still matches the stored SHA-256, recording the new metadata.
+    HashOnMismatch,
+    /// Always hash the file and skip it only if the content matches the stored SHA-256, e.g.
+    /// for rollouts restored from a backup with their modification times preserved.
+    Hash,
 }
 
-/// Summary of incremental update work.
-#[derive(Debug, Default)]
-pub struct UpdateStats {
-    pub processed: usize,
-    pub skipped: usize,
+/// Knobs for directory ingestion.
+#[derive(Clone)]
+pub struct IngestOptions<'a> {
+    /// Worker threads in each of the parsing and embedding stages of ingestion. Even with `1` (the
+    /// default), parsing, embedding and writing of consecutive rollouts overlap and files are
+    /// written in order. [`std::thread::available_parallelism`] is a sensible value for large
+    /// imports.
+    pub concurrency: usize,
+    /// Receives a [`ProgressEvent`] as each file is started, embedded and finished.
+    pub progress: Option<&'a dyn ProgressSink>,
+    /// Record rollouts that fail to read, parse, embed or write in [`UpdateStats::failed`] and
+    /// carry on with the rest, instead of stopping at the first failure.
+    pub skip_failures: bool,
+    /// Decide what would happen to each rollout without writing anything: the returned stats and
+    /// the [`ProgressEvent::FileFinished`] outcomes describe the run as if it had been real,
+    /// but no file is parsed or embedded and the database is left untouched.
+    pub dry_run: bool,
+    /// Glob patterns matched against paths relative to the directory being walked; matching
+    /// files and directories are skipped, e.g. `*/trash/*` or `**/*.bak.jsonl`.
+    pub exclude: Vec<String>,
+    /// Directory levels below the walked directory to descend into; `Some(0)` only considers
+    /// rollouts directly inside it. Unlimited by default.
+    pub max_depth: Option<usize>,
+    /// Descend into symlinked directories and read symlinked rollouts. Off by default;
+    /// symlink loops are skipped rather than followed.
+    pub follow_symlinks: bool,
+    /// Only consider rollouts started at or after this time: the timestamp in a
+    /// `rollout-YYYY-MM-DDTHH-MM-SS-*` file name (read as UTC), or else the file's modification
+    /// time.
+    pub since: Option<OffsetDateTime>,
+    /// Only consider rollouts started before this time, judged like [`IngestOptions::since`].
+    pub until: Option<OffsetDateTime>,
+    /// Make [`update_rollout_dir_with`] delete the conversations whose rollout under the
+    /// directory no longer exists, so the database mirrors the disk. Rollouts merely left out
+    /// by the discovery filters above are kept.
+    pub remove_deleted: bool,
+    /// When an already imported rollout counts as unchanged and is skipped.
+    pub change_detection: ChangeDetection,
+    /// Batch size, stored question count and summary lengths used for every rollout.
+    pub config: PipelineConfig,
+    /// Called on the writing thread after each conversation is stored, e.g. to maintain a
+    /// derived index or queue a notification without querying the database again.
+    pub post_ingest: Option<&'a dyn PostIngestHook>,
+    /// Scrubs secrets and other sensitive text from every rollout before it is stored and
+    /// embedded, e.g. [`Redactor::secrets`] plus rules of your own. Its rules are recorded in
+    /// the database ([`Storage::redaction_rules`]). Off by default.
+    pub redactor: Option<&'a Redactor>,
+    /// Writes an abstract of each ingested conversation to `conversations.summary`, e.g. with a
+    /// local LLM. Directory imports call it on the embedding workers, next to the embedder; the
+    /// async variants call it on the writing task, one conversation at a time.
+    pub summarizer: Option<&'a dyn Summarizer>,
+    /// Transcript formats of other tools, tried before the built-in ones when
+    /// [`PipelineConfig::format`] is [`RolloutFormat::Auto`]. Directory walks also pick up the
+    /// files they claim by name ([`TranscriptFormat::matches_file_name`]).
+    pub formats: Option<&'a FormatRegistry>,
 }
 
-fn discover_rollouts(dir: &Path) -> Result<Vec<PathBuf>, PipelineError> {
-    let mut rollouts: Vec<PathBuf> = Vec::new();
-    if !dir.exists() {
-        return Ok(rollouts);
-    }
-    for entry in WalkDir::new(dir) {
-        let entry = entry?;
-        if !entry.file_type().is_file() {
-            continue;
-        }
-        let name = entry.file_name().to_string_lossy();
-        if name.starts_with("rollout-") && name.ends_with(".jsonl") {
-            rollouts.push(entry.into_path());
+impl Default for IngestOptions<'_> {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            progress: None,
+            skip_failures: false,
+            dry_run: false,
+            exclude: Vec::new(),
+            max_depth: None,
+            follow_symlinks: false,
+            since: None,
+            until: None,
+            remove_deleted: false,
+            change_detection: ChangeDetection::default(),
+            config: PipelineConfig::default(),
+            post_ingest: None,
+            redactor: None,
+            summarizer: None,
+            formats: None,
         }
     }
-    rollouts.sort();
-    Ok(rollouts)
 }
 
-fn load_rollout_data(
-    path: &Path,
-    metadata: Option<&Metadata>,
-) -> Result<(Vec<u8>, RolloutFingerprint), PipelineError> {
-    let owned_meta;
-    let meta = match metadata {
-        Some(m) => m,
-        None => {
-            owned_meta = fs::metadata(path)?;
-            &owned_meta
-        }
-    };
+/// Observer of directory ingestion, e.g. to drive a progress bar. Events for different files
+/// may arrive from worker threads and interleave when [`IngestOptions::concurrency`] is above
+/// one. Closures taking a [`ProgressEvent`] implement it.
+pub trait ProgressSink: Sync {
+    fn report(&self, event: ProgressEvent<'_>);
+}
 
-    let bytes = fs::read(path)?;
-    let (modified_at, size_bytes) = file_metadata(meta);
-    let sha256 = Some(format!("{:x}", Sha256::digest(&bytes)));
+impl<F> ProgressSink for F
+where
+    F: Fn(ProgressEvent<'_>) + Sync,
+{
+    fn report(&self, event: ProgressEvent<'_>) {
+        self(event)
+    }
+}
 
-    Ok((
-        bytes,
-        RolloutFingerprint {
-            modified_at,
-            size_bytes,
-            sha256,
-        },
-    ))
+/// Callback run after each conversation of a directory ingestion is committed: imported, or
+/// brought up to date from a grown rollout. Moved, duplicate and unchanged rollouts and dry runs
+/// store nothing and do not call it. Closures taking a [`StoredConversation`] implement it.
+pub trait PostIngestHook: Sync {
+    fn conversation_stored(&self, stored: StoredConversation<'_>);
 }
 
-fn ingest_rollout_bytes(
-    rollout_path: &Path,
-    bytes: &[u8],
-    fingerprint: &RolloutFingerprint,
-    storage: &Storage,
-    embedder: Option<&EmbeddingModel>,
-    conversation_id_override: Option<&str>,
-) -> Result<(), PipelineError> {
-    let cursor = Cursor::new(bytes);
-    let record = parse_rollout(cursor)?;
+impl<F> PostIngestHook for F
+where
+    F: Fn(StoredConversation<'_>) + Sync,
+{
+    fn conversation_stored(&self, stored: StoredConversation<'_>) {
+        self(stored)
+    }
+}
 
-    let stats = compute_conversation_stats(&record);
-    let conversation_id = storage.upsert_conversation(
-        rollout_path,
-        &record,
-        fingerprint,
-        &stats,
-        conversation_id_override,
-    )?;
+/// Error type returned by [`Summarizer`] implementations.
+pub type SummarizeError = Box<dyn std::error::Error + Send + Sync>;
 
-    let embeddings = if let Some(embedder) = embedder {
-        let summaries: Vec<String> = record.turns.iter().map(render_turn_summary).collect();
-        let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(record.turns.len());
-        for chunk in summaries.chunks(EMBED_BATCH_SIZE) {
-            if chunk.is_empty() {
-                continue;
-            }
-            let refs: Vec<&str> = chunk.iter().map(|s| s.as_str()).collect();
-            let chunk_vectors = embedder.embed_batch(&refs)?;
-            if chunk_vectors.len() != refs.len() {
-                for item in chunk {
-                    let vector = embedder.embed(item)?;
-                    vectors.push(vector);
-                }
-                continue;
-            }
-            vectors.extend(chunk_vectors);
-        }
-        if vectors.len() != record.turns.len() {
-            return Err(PipelineError::Embedding(EmbeddingError::MissingOutput));
-        }
-        Some(vectors)
-    } else {
-        None
-    };
+/// Produces the abstract stored as a conversation's `summary` (see [`IngestOptions::summarizer`]).
+/// It receives every turn of the conversation rendered as text, as they are embedded, oldest
+/// first; a grown rollout is summarized again as a whole. Returning `None` keeps the summary
+/// stored by an earlier import, if any. Closures taking the rendered turns implement it.
+pub trait Summarizer: Sync {
+    fn summarize(&self, turns: &[String]) -> Result<Option<String>, SummarizeError>;
+}
 
-    for (idx, turn) in record.turns.iter().enumerate() {
-        let embedding_slice = embeddings.as_ref().map(|vecs| vecs[idx].as_slice());
-        storage.insert_turn(&conversation_id, turn, embedding_slice)?;
+impl<F> Summarizer for F
+where
+    F: Fn(&[String]) -> Result<Option<String>, SummarizeError> + Sync,
+{
+    fn summarize(&self, turns: &[String]) -> Result<Option<String>, SummarizeError> {
+        self(turns)
     }
+}
 
-    Ok(())
+/// A conversation just written to the database, as passed to a [`PostIngestHook`].
+#[derive(Debug, Clone, Copy)]
+pub struct StoredConversation<'a> {
+    /// Id of the conversation row.
+    pub conversation_id: &'a str,
+    pub rollout_path: &'a Path,
+    /// The whole conversation, including turns stored by earlier imports.
+    pub record: &'a ConversationRecord,
+    /// The derived columns written with it.
+    pub stats: &'a ConversationStats,
+    /// Position in `record.turns` of the first turn written by this import; non-zero when only
+    /// the appended lines of a grown rollout were parsed.
+    pub first_new_turn: usize,
 }
 
-fn fingerprint_matches(
-    existing: &RolloutFingerprint,
-    modified_at: Option<OffsetDateTime>,
-    size_bytes: Option<u64>,
-) -> bool {
-    match (existing.modified_at, modified_at) {
-        (Some(stored), Some(current)) if stored == current => {}
-        _ => return false,
-    }
+/// Step of a directory ingestion reported to a [`ProgressSink`].
+#[derive(Debug, Clone, Copy)]
+pub enum ProgressEvent<'a> {
+    /// Rollout files found under the directory; sent once, before any other event.
+    Discovered { total: usize },
+    /// A rollout is about to be read and parsed.
+    FileStarted { path: &'a Path },
+    /// Another batch of `turns` turns of the rollout was embedded.
+    TurnsEmbedded { path: &'a Path, turns: usize },
+    /// The rollout needs no further work.
+    FileFinished {
+        path: &'a Path,
+        outcome: FileOutcome,
+    },
+    /// The rollout failed; the run stops with this error unless
+    /// [`IngestOptions::skip_failures`] is set.
+    FileFailed {
+        path: &'a Path,
+        error: &'a PipelineError,
+    },
+}
 
-    match (existing.size_bytes, size_bytes) {
-        (Some(stored), Some(current)) if stored == current => {}
-        _ => return false,
+/// How a rollout reported by [`ProgressEvent::FileFinished`] was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    /// Parsed, embedded and written.
+    Ingested,
+    /// Unchanged since the last update.
+    Skipped,
+    /// Recognised as a moved rollout and re-pointed.
+    Moved,
+    /// Grown since the last update; only the appended lines were parsed and embedded.
+    Appended,
+    /// Only its conversation's turn embeddings were written, by [`reembed_all`] or
+    /// [`embed_missing`].
+    Embedded,
+    /// Gone from disk; its conversation was deleted ([`IngestOptions::remove_deleted`]).
+    Removed,
+    /// Same content as a rollout imported from another path; not imported again
+    /// ([`UpdateStats::duplicates`]).
+    Duplicate,
+}
+
+pub(crate) fn report(progress: Option<&dyn ProgressSink>, event: ProgressEvent<'_>) {
+    if let Some(progress) = progress {
+        progress.report(event);
     }
+}
 
-    true
+/// Incrementally process rollout files under `dir`, skipping those whose metadata has not changed.
+///
+/// A changed, uncompressed rollout whose bytes up to the start of its last imported turn are
+/// unchanged (a live session that has only been appended to) is re-parsed from that turn on:
+/// earlier turns and their embeddings are kept and only the new tail is embedded.
+///
+/// A file seen for the first time whose SHA-256 matches a stored rollout that no longer exists
+/// on disk is treated as that rollout moved or renamed: its conversation is re-pointed at the
+/// new path instead of being imported a second time.
+pub fn update_rollout_dir(
+    dir: impl AsRef<Path>,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+) -> Result<UpdateStats, PipelineError> {
+    update_rollout_dir_with(dir, storage, embedder, &IngestOptions::default())
 }
 
-fn file_metadata(meta: &Metadata) -> (Option<OffsetDateTime>, Option<u64>) {
-    let modified_at = meta.modified().ok().map(OffsetDateTime::from);
-    let size_bytes = Some(meta.len());
-    (modified_at, size_bytes)
+/// Like [`update_rollout_dir`], tuned by `options` as [`process_rollout_dir_with`] is. Appended
+/// rollouts are brought up to date on the calling thread while checking; other changed and new
+/// rollouts are ingested after every file has been checked.
+pub fn update_rollout_dir_with(
+    dir: impl AsRef<Path>,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    update_rollout_dirs_with(&[dir], storage, embedder, options)
 }
 
-fn render_turn_summary(turn: &TurnRecord) -> String {
-    let mut sections = Vec::new();
+/// Like [`update_rollout_dir_with`] over several root directories at once, with one set of
+/// combined stats. Moves and copies are recognised across roots, and
+/// [`IngestOptions::remove_deleted`] prunes conversations whose rollout was under any of them.
+pub fn update_rollout_dirs_with<P: AsRef<Path>>(
+    dirs: &[P],
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    let rollouts = discover_roots(dirs, options)?;
+    report(
+        options.progress,
+        ProgressEvent::Discovered {
+            total: rollouts.len(),
+        },
+    );
+    let mut stats = UpdateStats::default();
+    let mut pending = Vec::new();
+    let mut state = DirState::default();
 
-    if !turn.user_inputs.is_empty() {
-        let mut rendered_inputs = Vec::new();
-        for (idx, input) in turn.user_inputs.iter().enumerate() {
-            let mut fragment = String::new();
-            if let Some(text) = &input.text {
-                fragment.push_str(text);
-            }
-            if !input.images.is_empty() {
-                if !fragment.is_empty() {
-                    fragment.push('\n');
-                }
-                fragment.push_str(&format!("[{} image(s)]", input.images.len()));
+    for path in rollouts {
+        let checked = check_rollout(&path, storage, embedder, options, &mut state);
+        finish_check(path, checked, options, &mut stats, &mut pending)?;
+    }
+
+    stats.duplicates = state.duplicates;
+    stats.report.files = state.reports;
+    if options.remove_deleted {
+        remove_deleted_rollouts(dirs, storage, options, &state.moved_from, &mut stats)?;
+    }
+    ingest_files(&pending, storage, embedder, options, &mut stats)?;
+    Ok(stats)
+}
+
+/// Rollouts under each of `dirs` in turn, each root sorted, without repeating files reached
+/// through overlapping roots.
+pub(crate) fn discover_roots<P: AsRef<Path>>(
+    dirs: &[P],
+    options: &IngestOptions<'_>,
+) -> Result<Vec<PathBuf>, PipelineError> {
+    let mut seen = HashSet::new();
+    let mut rollouts = Vec::new();
+    for dir in dirs {
+        for path in discover_rollouts(dir.as_ref(), options)? {
+            if seen.insert(path.clone()) {
+                rollouts.push(path);
             }
-            if !fragment.is_empty() {
-                rendered_inputs.push(format!("#{} {}", idx + 1, fragment.trim()));
+        }
+    }
+    Ok(rollouts)
+}
+
+/// Count and report the result of [`check_rollout`] for `path`, queueing it in `pending` when
+/// it has to be ingested.
+pub(crate) fn finish_check(
+    path: PathBuf,
+    checked: Result<Option<FileOutcome>, PipelineError>,
+    options: &IngestOptions<'_>,
+    stats: &mut UpdateStats,
+    pending: &mut Vec<PathBuf>,
+) -> Result<(), PipelineError> {
+    match checked {
+        Ok(Some(outcome)) => {
+            match outcome {
+                FileOutcome::Skipped => stats.skipped += 1,
+                FileOutcome::Moved => stats.moved += 1,
+                FileOutcome::Appended => stats.appended += 1,
+                FileOutcome::Ingested
+                | FileOutcome::Embedded
+                | FileOutcome::Removed
+                | FileOutcome::Duplicate => {}
             }
+            report(
+                options.progress,
+                ProgressEvent::FileFinished {
+                    path: &path,
+                    outcome,
+                },
+            );
         }
-        if !rendered_inputs.is_empty() {
-            sections.push(format!("User:\n{}", rendered_inputs.join("\n\n")));
+        Ok(None) => pending.push(path),
+        Err(error) => {
+            report(
+                options.progress,
+                ProgressEvent::FileFailed {
+                    path: &path,
+                    error: &error,
+                },
+            );
+            stats.record_failure(path, error, options)?;
         }
     }
+    Ok(())
+}
 
-    let mut result_texts = Vec::new();
-    if !turn.result.assistant_messages.is_empty() {
-        result_texts.push(turn.result.assistant_messages.join("\n\n"));
-    }
-    if let Some(fallback) = &turn.result.fallback {
-        result_texts.push(format!(
-            "[fallback {:?}] {}",
-            fallback.source, fallback.text
-        ));
-    }
-    if !result_texts.is_empty() {
-        sections.push(format!("Assistant:\n{}", result_texts.join("\n\n")));
+/// What [`update_rollout_dir_with`] learns about the directory while checking its files.
+#[derive(Default)]
+pub(crate) struct DirState {
+    /// Previous paths of the rollouts recognised as moved.
+    pub(crate) moved_from: HashSet<PathBuf>,
+    /// Content hashes of the new rollouts queued for ingestion, with their paths.
+    queued: HashMap<String, PathBuf>,
+    pub(crate) duplicates: Vec<DuplicateRollout>,
+    /// Timings of the grown rollouts whose tail was appended.
+    pub(crate) reports: Vec<FileReport>,
+}
+
+/// Whether `path` names a conversation of an export file that still exists, as recorded by
+/// [`process_chatgpt_export`].
+fn in_export(path: &Path) -> bool {
+    path.to_str()
+        .and_then(|path| path.rsplit_once('#'))
+        .is_some_and(|(export, _)| Path::new(export).is_file())
+}
+
+/// Delete the conversations whose rollout, recorded under one of `dirs`, no longer exists on disk.
+/// Rollouts just recognised as moved (`moved_from`) are kept for a dry run, which does not
+/// re-point them.
+pub(crate) fn remove_deleted_rollouts<P: AsRef<Path>>(
+    dirs: &[P],
+    storage: &Storage,
+    options: &IngestOptions<'_>,
+    moved_from: &HashSet<PathBuf>,
+    stats: &mut UpdateStats,
+) -> Result<(), PipelineError> {
+    let conversations = storage
+        .conversation_rollouts()
+        .map_err(PipelineError::Database)?;
+    for (conversation_id, path) in conversations {
+        let under_dirs = dirs.iter().any(|dir| path.starts_with(dir));
+        if !under_dirs || path.exists() || moved_from.contains(&path) || in_export(&path) {
+            continue;
+        }
+        let removed = if options.dry_run {
+            Ok(true)
+        } else {
+            storage
+                .delete_conversation(&conversation_id)
+                .map_err(|err| PipelineError::storage(&path, err))
+        };
+        match removed {
+            Ok(removed) => {
+                stats.removed += usize::from(removed);
+                report(
+                    options.progress,
+                    ProgressEvent::FileFinished {
+                        path: &path,
+                        outcome: FileOutcome::Removed,
+                    },
+                );
+            }
+            Err(error) => {
+                report(
+                    options.progress,
+                    ProgressEvent::FileFailed {
+                        path: &path,
+                        error: &error,
+                    },
+                );
+                stats.record_failure(path, error, options)?;
+            }
+        }
     }
+    Ok(())
+}
 
-    if !turn.actions.is_empty() {
-        let mut action_summaries = Vec::new();
-        for action in &turn.actions {
-            let summary = match &action.kind {
-                crate::types::ActionKind::FunctionCall { name } => format!(
-                    "function_call {}",
-                    name.clone().unwrap_or_else(|| "(unknown)".into())
-                ),
-                crate::types::ActionKind::CustomToolCall { name } => format!(
-                    "custom_tool {}",
-                    name.clone().unwrap_or_else(|| "(unknown)".into())
-                ),
-                crate::types::ActionKind::LocalShellExec {
-                    command, workdir, ..
-                } => {
-                    let joined = command.join(" ");
-                    if let Some(dir) = workdir {
-                        format!("shell `{}` (cwd: {})", joined, dir)
-                    } else {
-                        format!("shell `{}`", joined)
+/// Settle `path` without a full ingest when it is unchanged ([`FileOutcome::Skipped`]), a moved
+/// rollout ([`FileOutcome::Moved`], re-pointed here), a copy of another imported or queued
+/// rollout ([`FileOutcome::Duplicate`]) or has only grown ([`FileOutcome::Appended`], its tail
+/// ingested here); `None` when it must be ingested.
+pub(crate) fn check_rollout(
+    path: &Path,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+    state: &mut DirState,
+) -> Result<Option<FileOutcome>, PipelineError> {
+    let metadata = fs::metadata(path).map_err(|err| PipelineError::io(path, err))?;
+    let existing = storage
+        .get_rollout_fingerprint(path)
+        .map_err(|err| PipelineError::storage(path, err))?;
+    match &existing {
+        Some(existing) if is_unchanged(path, &metadata, storage, existing, options)? => {
+            Ok(Some(FileOutcome::Skipped))
+        }
+        Some(_) => {
+            let appended = append_rollout(
+                path,
+                &metadata,
+                storage,
+                embedder,
+                options,
+                &mut state.reports,
+            )?;
+            Ok(appended.then_some(FileOutcome::Appended))
+        }
+        None => {
+            let fingerprint = hash_rollout(path, &metadata)?;
+            let Some(sha256) = fingerprint.sha256.clone() else {
+                return Ok(None);
+            };
+            match find_same_content(storage, path, &sha256)? {
+                Some(SameContent::MovedFrom(previous)) => {
+                    if !options.dry_run {
+                        storage
+                            .move_rollout(&previous, path, &fingerprint)
+                            .map_err(|err| PipelineError::storage(path, err))?;
                     }
+                    state.moved_from.insert(previous);
+                    Ok(Some(FileOutcome::Moved))
                 }
-                crate::types::ActionKind::WebSearch { query } => format!(
-                    "web_search {}",
-                    query.clone().unwrap_or_else(|| "(query missing)".into())
-                ),
-                crate::types::ActionKind::Other { kind } => {
-                    format!("{}", kind.clone().unwrap_or_else(|| "other".into()))
+                Some(SameContent::CopyOf(original)) => {
+                    state.duplicates.push(DuplicateRollout {
+                        path: path.to_path_buf(),
+                        original,
+                    });
+                    Ok(Some(FileOutcome::Duplicate))
                 }
-            };
+                None => match state.queued.get(&sha256) {
+                    Some(original) => {
+                        state.duplicates.push(DuplicateRollout {
+                            path: path.to_path_buf(),
+                            original: original.clone(),
+                        });
+                        Ok(Some(FileOutcome::Duplicate))
+                    }
+                    None => {
+                        state.queued.insert(sha256, path.to_path_buf());
+                        Ok(None)
+                    }
+                },
+            }
+        }
+    }
+}
 
-            let status = action
+/// Whether the imported rollout at `path` is unchanged under [`IngestOptions::change_detection`].
+/// When only its metadata moved on, the new time and size are stored so the next update can
+/// skip it without hashing again.
+fn is_unchanged(
+    path: &Path,
+    metadata: &Metadata,
+    storage: &Storage,
+    existing: &RolloutFingerprint,
+    options: &IngestOptions<'_>,
+) -> Result<bool, PipelineError> {
+    let (modified_at, size_bytes) = file_metadata(metadata);
+    let metadata_matches = fingerprint_matches(existing, modified_at, size_bytes);
+    match options.change_detection {
+        ChangeDetection::Metadata => return Ok(metadata_matches),
+        ChangeDetection::HashOnMismatch if metadata_matches => return Ok(true),
+        _ => {}
+    }
+    let Some(stored_sha256) = existing.sha256.as_deref() else {
+        return Ok(false);
+    };
+    let fingerprint = hash_rollout(path, metadata)?;
+    if fingerprint.sha256.as_deref() != Some(stored_sha256) {
+        return Ok(false);
+    }
+    if !metadata_matches && !options.dry_run {
+        storage
+            .move_rollout(path, path, &fingerprint)
+            .map_err(|err| PipelineError::storage(path, err))?;
+    }
+    Ok(true)
+}
+
+/// Bring the conversation of the grown rollout at `path` up to date by parsing it from its stored
+/// resume point, provided the bytes before that point are unchanged. Returns `false` when the
+/// rollout has to be ingested in full instead. A dry run stops after checking the prefix; a real
+/// one adds the timings of the tail to `reports`.
+fn append_rollout(
+    path: &Path,
+    metadata: &Metadata,
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+    reports: &mut Vec<FileReport>,
+) -> Result<bool, PipelineError> {
+    let progress = options.progress;
+    if is_compressed(path) {
+        return Ok(false);
+    }
+    let Some((conversation_id, resume)) = storage
+        .rollout_resume(path)
+        .map_err(|err| PipelineError::storage(path, err))?
+    else {
+        return Ok(false);
+    };
+    let Some(prefix_sha256) = resume.prefix_sha256.as_deref() else {
+        return Ok(false);
+    };
+    if resume.offset == 0 || metadata.len() < resume.offset {
+        return Ok(false);
+    }
+
+    let mut file = fs::File::open(path).map_err(|err| PipelineError::io(path, err))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut (&mut file).take(resume.offset), &mut hasher)
+        .map_err(|err| PipelineError::io(path, err))?;
+    if format!("{:x}", hasher.clone().finalize()) != prefix_sha256 {
+        return Ok(false);
+    }
+    if options.dry_run {
+        return Ok(true);
+    }
+    report(progress, ProgressEvent::FileStarted { path });
+    let parse_started = Instant::now();
+    let (mut sessions, next_resume) = parse_rollout_from(
+        BufReader::new(file),
+        &resume,
+        options.config.parse,
+        Some(&mut hasher),
+    )
+    .map_err(|err| err.with_path(path))?;
+    // A new session was appended: import the whole rollout again to split it.
+    if sessions.len() > 1 {
+        return Ok(false);
+    }
+    let mut tail_record = sessions.remove(0);
+    if let Some(redactor) = options.redactor {
+        redactor.redact_record(&mut tail_record);
+    }
+    if options.config.store_images {
+        hash_images(&mut tail_record);
+    }
+    let (modified_at, size_bytes) = file_metadata(metadata);
+    let fingerprint = RolloutFingerprint {
+        modified_at,
+        size_bytes,
+        sha256: Some(format!("{:x}", hasher.finalize())),
+    };
+
+    let mut turns: Vec<TurnRecord> = storage
+        .load_turn_records(&conversation_id)
+        .map_err(|err| PipelineError::storage(path, err))?
+        .into_iter()
+        .filter(|turn| turn.index < resume.turn_index)
+        .collect();
+    let first_new_turn = turns.len();
+    turns.extend(tail_record.turns);
+    let record = ConversationRecord {
+        turns,
+        ..tail_record
+    };
+
+    let stats = compute_conversation_stats(&record, &options.config.derivation());
+    let mut prepared = PreparedRollout {
+        path: path.to_path_buf(),
+        fingerprint,
+        record,
+        stats,
+        first_new_turn,
+        embeddings: None,
+        resume: Some(next_resume),
+        bytes: metadata.len() - resume.offset,
+        parse_time: parse_started.elapsed(),
+        embed_time: Duration::ZERO,
+        later_sessions: Vec::new(),
+    };
+
+    record_ingest_setup(storage, embedder, options.redactor, path)?;
+    embed_prepared(&mut prepared, embedder, progress, &options.config)?;
+    summarize_prepared(&mut prepared, options.summarizer, &options.config)?;
+    reports.push(write_timed(
+        storage,
+        &prepared,
+        Some(&conversation_id),
+        options.post_ingest,
+    )?);
+    Ok(true)
+}
+
+/// Summary of incremental update work.
+#[derive(Debug, Default)]
+pub struct UpdateStats {
+    pub processed: usize,
+    pub skipped: usize,
+    /// Rollouts recognised by content hash at a new path and re-pointed without re-importing.
+    pub moved: usize,
+    /// Grown rollouts updated by parsing only their appended lines; not counted in `processed`.
+    pub appended: usize,
+    /// Conversations deleted because their rollout disappeared, with
+    /// [`IngestOptions::remove_deleted`].
+    pub removed: usize,
+    /// Rollouts not imported because a rollout with the same content was already imported from,
+    /// or earlier in the run queued at, another path.
+    pub duplicates: Vec<DuplicateRollout>,
+    /// Rollouts passed over because of an error, when [`IngestOptions::skip_failures`] is set.
+    pub failed: Vec<FileFailure>,
+    /// Timings of every rollout parsed and written, in the order they were written.
+    pub report: ImportReport,
+}
+
+/// Per-file timings of an import, to find the rollouts that slow a run down.
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub files: Vec<FileReport>,
+}
+
+impl ImportReport {
+    /// The `n` files that took longest to parse, embed and write, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&FileReport> {
+        let mut files: Vec<&FileReport> = self.files.iter().collect();
+        files.sort_by_key(|file| std::cmp::Reverse(file.total_time()));
+        files.truncate(n);
+        files
+    }
+}
+
+/// How long one rollout took to import. An appended rollout only counts its new tail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Reading, decompressing, hashing and parsing the rollout and deriving its stats.
+    pub parse_time: Duration,
+    /// Embedding its turn summaries; zero without an embedder.
+    pub embed_time: Duration,
+    /// Writing its conversation and turns, including the [`IngestOptions::post_ingest`] hook.
+    pub write_time: Duration,
+    /// Turns written.
+    pub turns: usize,
+    /// Size of the rollout file on disk (compressed, for `.gz` / `.zst`), or of the appended
+    /// part of a grown rollout.
+    pub bytes: u64,
+    /// Lines skipped by a lenient parse; see [`PipelineConfig::parse`].
+    pub warnings: usize,
+}
+
+impl FileReport {
+    pub fn total_time(&self) -> Duration {
+        self.parse_time + self.embed_time + self.write_time
+    }
+}
+
+impl UpdateStats {
+    /// Keep `error` for `path` when failures are skipped, otherwise hand it back.
+    pub(crate) fn record_failure(
+        &mut self,
+        path: impl Into<PathBuf>,
+        error: PipelineError,
+        options: &IngestOptions<'_>,
+    ) -> Result<(), PipelineError> {
+        if !options.skip_failures {
+            return Err(error);
+        }
+        self.failed.push(FileFailure {
+            path: path.into(),
+            error,
+        });
+        Ok(())
+    }
+}
+
+/// A copy of another rollout, reported in [`UpdateStats::duplicates`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateRollout {
+    pub path: PathBuf,
+    /// The rollout whose conversation holds the content.
+    pub original: PathBuf,
+}
+
+/// A rollout that could not be ingested, reported in [`UpdateStats::failed`].
+#[derive(Debug)]
+pub struct FileFailure {
+    pub path: PathBuf,
+    pub error: PipelineError,
+}
+
+/// A stored rollout with the same content as the one at a new path.
+enum SameContent {
+    /// Its file is gone, so the new file is that rollout moved.
+    MovedFrom(PathBuf),
+    /// Its file is still there, so the new file is a copy.
+    CopyOf(PathBuf),
+}
+
+/// Stored rollout other than `path` whose content hash is `sha256`, preferring one whose file is
+/// gone so moves win over copies.
+fn find_same_content(
+    storage: &Storage,
+    path: &Path,
+    sha256: &str,
+) -> Result<Option<SameContent>, PipelineError> {
+    let candidates: Vec<PathBuf> = storage
+        .rollout_paths_with_hash(sha256)
+        .map_err(|err| PipelineError::storage(path, err))?
+        .into_iter()
+        .filter(|candidate| candidate != path)
+        .collect();
+    Ok(match candidates.iter().position(|c| !c.exists()) {
+        Some(idx) => Some(SameContent::MovedFrom(candidates[idx].clone())),
+        None => candidates.into_iter().next().map(SameContent::CopyOf),
+    })
+}
+
+/// Fingerprint of the rollout at `path`, hashing the file without keeping it in memory.
+fn hash_rollout(path: &Path, metadata: &Metadata) -> Result<RolloutFingerprint, PipelineError> {
+    let mut hasher = Sha256::new();
+    open_rollout(path)
+        .and_then(|mut reader| io::copy(&mut reader, &mut hasher))
+        .map_err(|err| PipelineError::io(path, err))?;
+    let (modified_at, size_bytes) = file_metadata(metadata);
+    Ok(RolloutFingerprint {
+        modified_at,
+        size_bytes,
+        sha256: Some(format!("{:x}", hasher.finalize())),
+    })
+}
+
+/// Rollout files under `dir`, sorted, honouring the discovery settings of `options`.
+pub(crate) fn discover_rollouts(
+    dir: &Path,
+    options: &IngestOptions<'_>,
+) -> Result<Vec<PathBuf>, PipelineError> {
+    let mut rollouts: Vec<PathBuf> = Vec::new();
+    if !dir.exists() {
+        return Ok(rollouts);
+    }
+    let mut exclude = GlobSetBuilder::new();
+    for pattern in &options.exclude {
+        exclude.add(Glob::new(pattern)?);
+    }
+    let exclude = exclude.build()?;
+
+    let mut walker = WalkDir::new(dir).follow_links(options.follow_symlinks);
+    if let Some(depth) = options.max_depth {
+        walker = walker.max_depth(depth + 1);
+    }
+    let entries = walker.into_iter().filter_entry(|entry| {
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        entry.depth() == 0 || !exclude.is_match(relative)
+    });
+    for entry in entries {
+        let entry = match entry {
+            Err(err) if err.loop_ancestor().is_some() => continue,
+            entry => entry?,
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy();
+        let transcript = match options.formats {
+            Some(formats) => formats.matches_file_name(&name, options.config.format),
+            None => options.config.format.matches_file_name(&name),
+        };
+        if !transcript {
+            continue;
+        }
+        if options.since.is_some() || options.until.is_some() {
+            let started = match rollout_name_timestamp(&name) {
+                Some(started) => started,
+                None => OffsetDateTime::from(
+                    entry
+                        .metadata()?
+                        .modified()
+                        .map_err(|err| PipelineError::io(entry.path(), err))?,
+                ),
+            };
+            if options.since.is_some_and(|since| started < since)
+                || options.until.is_some_and(|until| started >= until)
+            {
+                continue;
+            }
+        }
+        rollouts.push(entry.into_path());
+    }
+    rollouts.sort();
+    Ok(rollouts)
+}
+
+/// Start time encoded in a Codex rollout file name (`rollout-2025-10-01T09-30-00-<id>.jsonl`),
+/// taken as UTC.
+fn rollout_name_timestamp(name: &str) -> Option<OffsetDateTime> {
+    let stamp = name.strip_prefix("rollout-")?.get(..19)?;
+    if stamp.as_bytes()[13] != b'-' || stamp.as_bytes()[16] != b'-' {
+        return None;
+    }
+    let rfc3339 = format!(
+        "{}:{}:{}Z",
+        stamp.get(..13)?,
+        stamp.get(14..16)?,
+        stamp.get(17..)?
+    );
+    OffsetDateTime::parse(&rfc3339, &Rfc3339).ok()
+}
+
+/// Whether the rollout at `path` is stored gzip- or zstd-compressed.
+fn is_compressed(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("gz" | "zst")
+    )
+}
+
+/// Reader over the decompressed contents of the rollout at `path`, chosen by its extension.
+fn open_rollout(path: &Path) -> io::Result<Box<dyn Read>> {
+    decode_rollout(path, fs::File::open(path)?)
+}
+
+/// Decompress `reader`, the raw contents of the rollout at `path`, by the path's extension.
+fn decode_rollout<'a>(path: &Path, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(BufReader::new(reader))),
+        Some("zst") => Box::new(zstd::Decoder::new(reader)?),
+        _ => Box::new(reader),
+    })
+}
+
+/// A parsed (and optionally embedded) rollout, ready to be written.
+pub(crate) struct PreparedRollout {
+    path: PathBuf,
+    fingerprint: RolloutFingerprint,
+    record: ConversationRecord,
+    stats: ConversationStats,
+    /// Position in `record.turns` of the first turn to write; earlier turns are already stored.
+    first_new_turn: usize,
+    /// Embeddings of the turns from `first_new_turn` on.
+    embeddings: Option<Vec<Vec<f32>>>,
+    /// Where the next update can resume parsing, for uncompressed rollouts.
+    resume: Option<ResumeState>,
+    /// Size of the rollout file, or of the part of it parsed; see [`FileReport::bytes`].
+    bytes: u64,
+    parse_time: Duration,
+    embed_time: Duration,
+    /// Sessions appended to the same rollout after this one (see
+    /// [`parse_rollout_sessions`](crate::parse_rollout_sessions)), each written as a
+    /// conversation of its own.
+    later_sessions: Vec<PreparedRollout>,
+}
+
+/// Parse the rollout at `path`, decompressing `.gz` / `.zst` files, and derive its stats
+/// without touching the database, so it can run on a worker thread. The rollout has no
+/// embeddings yet; see [`embed_prepared`].
+pub(crate) fn parse_rollout_file(
+    path: &Path,
+    config: &PipelineConfig,
+    formats: Option<&FormatRegistry>,
+    redactor: Option<&Redactor>,
+) -> Result<PreparedRollout, PipelineError> {
+    let metadata = fs::metadata(path).map_err(|err| PipelineError::io(path, err))?;
+    let reader = open_rollout(path).map_err(|err| PipelineError::io(path, err))?;
+    parse_rollout_stream(
+        path,
+        BufReader::new(reader),
+        Some(&metadata),
+        config,
+        formats,
+        redactor,
+    )
+}
+
+/// Parse the decompressed contents of the rollout at `path` as they stream in, hashing them on
+/// the way so the file is never held in memory whole. The fingerprint's hash covers the
+/// decompressed contents, so compressing an archived rollout is recognised as a move.
+/// `metadata` is that of the rollout's file, if it has one; only files can be resumed. The
+/// registered `formats` take part in detecting the format. Secrets are scrubbed with `redactor`
+/// before the stats are derived; the hash is of the original.
+pub(crate) fn parse_rollout_stream(
+    path: &Path,
+    reader: impl BufRead,
+    metadata: Option<&Metadata>,
+    config: &PipelineConfig,
+    formats: Option<&FormatRegistry>,
+    redactor: Option<&Redactor>,
+) -> Result<PreparedRollout, PipelineError> {
+    let started = Instant::now();
+    let mut hasher = Sha256::new();
+    let (sessions, resume) = parse_transcript_from(
+        reader,
+        config.format,
+        formats,
+        config.parse,
+        Some(&mut hasher),
+    )
+    .map_err(|err| err.with_path(path))?;
+    let (modified_at, size_bytes) = metadata.map(file_metadata).unwrap_or_default();
+    let fingerprint = RolloutFingerprint {
+        modified_at,
+        size_bytes,
+        sha256: Some(format!("{:x}", hasher.finalize())),
+    };
+    let mut sessions: Vec<PreparedRollout> = sessions
+        .into_iter()
+        .map(|mut record| {
+            if let Some(redactor) = redactor {
+                redactor.redact_record(&mut record);
+            }
+            if config.store_images {
+                hash_images(&mut record);
+            }
+            let stats = compute_conversation_stats(&record, &config.derivation());
+            PreparedRollout {
+                path: path.to_path_buf(),
+                fingerprint: fingerprint.clone(),
+                record,
+                stats,
+                first_new_turn: 0,
+                embeddings: None,
+                resume: None,
+                bytes: 0,
+                parse_time: Duration::ZERO,
+                embed_time: Duration::ZERO,
+                later_sessions: Vec::new(),
+            }
+        })
+        .collect();
+    let mut prepared = sessions.remove(0);
+    prepared.later_sessions = sessions;
+    // Only a rollout holding a single session is appended to in place.
+    prepared.resume = resume.filter(|_| {
+        metadata.is_some() && !is_compressed(path) && prepared.later_sessions.is_empty()
+    });
+    prepared.bytes = metadata.map_or(0, Metadata::len);
+    prepared.parse_time = started.elapsed();
+    Ok(prepared)
+}
+
+/// Embed the turns of `prepared` that are to be written.
+pub(crate) fn embed_prepared(
+    prepared: &mut PreparedRollout,
+    embedder: Option<&EmbeddingModel>,
+    progress: Option<&dyn ProgressSink>,
+    config: &PipelineConfig,
+) -> Result<(), PipelineError> {
+    let started = Instant::now();
+    prepared.embeddings = embed_turns(
+        &prepared.path,
+        &prepared.record.turns[prepared.first_new_turn..],
+        embedder,
+        progress,
+        config,
+    )?;
+    for session in &mut prepared.later_sessions {
+        embed_prepared(session, embedder, progress, config)?;
+    }
+    if embedder.is_some() {
+        prepared.embed_time = started.elapsed();
+    }
+    Ok(())
+}
+
+/// Ask `summarizer` for an abstract of the whole prepared conversation and keep it in its stats.
+pub(crate) fn summarize_prepared(
+    prepared: &mut PreparedRollout,
+    summarizer: Option<&dyn Summarizer>,
+    config: &PipelineConfig,
+) -> Result<(), PipelineError> {
+    let Some(summarizer) = summarizer else {
+        return Ok(());
+    };
+    for session in &mut prepared.later_sessions {
+        summarize_prepared(session, Some(summarizer), config)?;
+    }
+    let turns: Vec<String> = prepared
+        .record
+        .turns
+        .iter()
+        .map(|turn| render_turn_summary(turn, config))
+        .collect();
+    let summary = summarizer
+        .summarize(&turns)
+        .map_err(|source| PipelineError::Summarize {
+            path: prepared.path.clone(),
+            source,
+        })?;
+    prepared.stats.summary = summary
+        .map(|summary| summary.trim().to_string())
+        .filter(|summary| !summary.is_empty());
+    Ok(())
+}
+
+/// Embed the summaries of `turns` in batches, reporting each batch to `progress`.
+fn embed_turns(
+    rollout_path: &Path,
+    turns: &[TurnRecord],
+    embedder: Option<&EmbeddingModel>,
+    progress: Option<&dyn ProgressSink>,
+    config: &PipelineConfig,
+) -> Result<Option<Vec<Vec<f32>>>, PipelineError> {
+    let Some(embedder) = embedder else {
+        return Ok(None);
+    };
+    let summaries: Vec<String> = turns
+        .iter()
+        .map(|turn| render_turn_summary(turn, config))
+        .collect();
+    let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(turns.len());
+    for chunk in summaries.chunks(config.embed_batch_size.max(1)) {
+        if chunk.is_empty() {
+            continue;
+        }
+        let refs: Vec<&str> = chunk.iter().map(|s| s.as_str()).collect();
+        let chunk_vectors = embedder
+            .embed_batch(&refs)
+            .map_err(|err| PipelineError::embedding(rollout_path, err))?;
+        if chunk_vectors.len() != refs.len() {
+            for item in chunk {
+                let vector = embedder
+                    .embed(item)
+                    .map_err(|err| PipelineError::embedding(rollout_path, err))?;
+                vectors.push(vector);
+            }
+            report(
+                progress,
+                ProgressEvent::TurnsEmbedded {
+                    path: rollout_path,
+                    turns: chunk.len(),
+                },
+            );
+            continue;
+        }
+        vectors.extend(chunk_vectors);
+        report(
+            progress,
+            ProgressEvent::TurnsEmbedded {
+                path: rollout_path,
+                turns: chunk.len(),
+            },
+        );
+    }
+    if vectors.len() != turns.len() {
+        return Err(PipelineError::embedding(
+            rollout_path,
+            EmbeddingError::MissingOutput,
+        ));
+    }
+    Ok(Some(vectors))
+}
+
+/// Record the embedding model and redaction rules an ingest is about to store rollouts with,
+/// before the first of them (`path`) is written.
+pub(crate) fn record_ingest_setup(
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    redactor: Option<&Redactor>,
+    path: &Path,
+) -> Result<(), PipelineError> {
+    if let Some(embedder) = embedder {
+        storage
+            .record_embedding_model(embedder.info())
+            .map_err(|err| PipelineError::storage(path, err))?;
+    }
+    if let Some(redactor) = redactor {
+        storage
+            .record_redaction_rules(&redactor.rules())
+            .map_err(|err| PipelineError::storage(path, err))?;
+    }
+    Ok(())
+}
+
+/// Write a prepared rollout's conversation and its new turns, together with the sessions
+/// appended after it, in one transaction, then hand each to `post_ingest`. A crash part-way
+/// therefore never leaves the file's fingerprint stored with some of its sessions missing.
+/// Embedding happens beforehand so the write lock is held only briefly.
+fn write_rollout(
+    storage: &Storage,
+    prepared: &PreparedRollout,
+    conversation_id_override: Option<&str>,
+    post_ingest: Option<&dyn PostIngestHook>,
+) -> Result<(), PipelineError> {
+    let sessions: Vec<&PreparedRollout> = std::iter::once(prepared)
+        .chain(&prepared.later_sessions)
+        .collect();
+    let write = || -> Result<Vec<String>, StorageError> {
+        let tx = storage.begin_ingest()?;
+        let mut conversation_ids = Vec::with_capacity(sessions.len());
+        for (idx, session) in sessions.iter().enumerate() {
+            let id_override = if idx == 0 {
+                conversation_id_override
+            } else {
+                None
+            };
+            conversation_ids.push(write_session(storage, session, id_override)?);
+        }
+        tx.commit()?;
+        Ok(conversation_ids)
+    };
+    let conversation_ids = write().map_err(|err| PipelineError::storage(&prepared.path, err))?;
+    if let Some(post_ingest) = post_ingest {
+        for (session, conversation_id) in sessions.iter().zip(&conversation_ids) {
+            post_ingest.conversation_stored(StoredConversation {
+                conversation_id,
+                rollout_path: &session.path,
+                record: &session.record,
+                stats: &session.stats,
+                first_new_turn: session.first_new_turn,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Write one session of a prepared rollout inside the caller's ingest transaction and return
+/// its conversation id.
+fn write_session(
+    storage: &Storage,
+    session: &PreparedRollout,
+    conversation_id_override: Option<&str>,
+) -> Result<String, StorageError> {
+    let PreparedRollout {
+        path,
+        fingerprint,
+        record,
+        stats,
+        first_new_turn,
+        embeddings,
+        resume,
+        ..
+    } = session;
+    let conversation_id =
+        storage.upsert_conversation(path, record, fingerprint, stats, conversation_id_override)?;
+    for (idx, turn) in record.turns[*first_new_turn..].iter().enumerate() {
+        let embedding_slice = embeddings.as_ref().map(|vecs| vecs[idx].as_slice());
+        storage.insert_turn(&conversation_id, turn, embedding_slice)?;
+    }
+    let turn_count = record.turns.iter().map(|turn| turn.index + 1).max();
+    storage.truncate_turns(&conversation_id, turn_count.unwrap_or(0))?;
+    storage.set_rollout_resume(&conversation_id, resume.as_ref())?;
+    Ok(conversation_id)
+}
+
+/// Ingest `paths`, counting them in `stats` and reporting to `options.progress`. With a
+/// concurrency above one they are parsed and embedded on that many scoped worker threads while
+/// the calling thread, which owns the single SQLite connection, writes each finished rollout as
+/// it arrives. Unless failures are skipped, stops at the first one; rollouts already written
+/// stay written.
+fn ingest_files(
+    paths: &[PathBuf],
+    storage: &Storage,
+    embedder: Option<&EmbeddingModel>,
+    options: &IngestOptions<'_>,
+    stats: &mut UpdateStats,
+) -> Result<(), PipelineError> {
+    if options.dry_run {
+        for path in paths {
+            report(
+                options.progress,
+                ProgressEvent::FileFinished {
+                    path,
+                    outcome: FileOutcome::Ingested,
+                },
+            );
+        }
+        stats.processed += paths.len();
+        return Ok(());
+    }
+    if let Some(first) = paths.first() {
+        record_ingest_setup(storage, embedder, options.redactor, first)?;
+    }
+    let progress = options.progress;
+    let parse = |path: &PathBuf| -> Result<PreparedRollout, PipelineError> {
+        report(progress, ProgressEvent::FileStarted { path });
+        parse_rollout_file(path, &options.config, options.formats, options.redactor)
+    };
+    let embed = |_: &PathBuf, parsed: Result<PreparedRollout, PipelineError>| {
+        let mut prepared = parsed?;
+        embed_prepared(&mut prepared, embedder, progress, &options.config)?;
+        summarize_prepared(&mut prepared, options.summarizer, &options.config)?;
+        Ok(prepared)
+    };
+    let mut written = HashMap::new();
+    run_stages(
+        paths,
+        options.concurrency,
+        parse,
+        embed,
+        |path, prepared| {
+            let result = prepared.and_then(|prepared| {
+                write_unless_duplicate(storage, prepared, &mut written, options.post_ingest)
+            });
+            finish_ingest(path, result, options, stats)
+        },
+    )
+}
+
+/// Pass each of `items` through `first` and then `second`, each stage running on `concurrency`
+/// (at least one) scoped worker threads linked by bounded channels, and hand every result to
+/// `consume` on the calling thread as it completes. The stages overlap: while one item is in
+/// `second`, later ones are in `first` and earlier ones in `consume`. Workers stop picking up
+/// items once `consume` fails, and its error is returned.
+fn run_stages<T, M, R>(
+    items: &[T],
+    concurrency: usize,
+    first: impl Fn(&T) -> M + Sync,
+    second: impl Fn(&T, M) -> R + Sync,
+    mut consume: impl FnMut(&T, R) -> Result<(), PipelineError>,
+) -> Result<(), PipelineError>
+where
+    T: Sync,
+    M: Send,
+    R: Send,
+{
+    let workers = concurrency.max(1).min(items.len());
+    let next = AtomicUsize::new(0);
+    let (first_sender, first_receiver) = mpsc::sync_channel::<(&T, M)>(workers);
+    let first_receiver = Arc::new(Mutex::new(first_receiver));
+    let (second_sender, second_receiver) = mpsc::sync_channel::<(&T, R)>(workers);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = first_sender.clone();
+            let next = &next;
+            let first = &first;
+            scope.spawn(move || {
+                while let Some(item) = items.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    // The receiver is gone once every `second` worker has stopped.
+                    if sender.send((item, first(item))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        for _ in 0..workers {
+            let receiver = Arc::clone(&first_receiver);
+            let sender = second_sender.clone();
+            let second = &second;
+            scope.spawn(move || loop {
+                let Ok(Ok((item, value))) = receiver.lock().map(|receiver| receiver.recv()) else {
+                    break;
+                };
+                // The receiver is gone once the consumer has failed.
+                if sender.send((item, second(item, value))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop((first_sender, first_receiver, second_sender));
+
+        for (item, result) in second_receiver {
+            if let Err(error) = consume(item, result) {
+                next.store(items.len(), Ordering::Relaxed);
+                return Err(error);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// What [`write_unless_duplicate`] did with a rollout.
+pub(crate) enum Written {
+    Stored(FileReport),
+    /// Not written; the path of the rollout it duplicates.
+    Duplicate(PathBuf),
+}
+
+/// Write `prepared` unless a rollout with the same content was imported from another path that
+/// still exists, or is in `written` (the content hashes written so far in this run).
+pub(crate) fn write_unless_duplicate(
+    storage: &Storage,
+    prepared: PreparedRollout,
+    written: &mut HashMap<String, PathBuf>,
+    post_ingest: Option<&dyn PostIngestHook>,
+) -> Result<Written, PipelineError> {
+    if let Some(sha256) = prepared.fingerprint.sha256.as_deref() {
+        if let Some(original) = written.get(sha256) {
+            return Ok(Written::Duplicate(original.clone()));
+        }
+        if let Some(SameContent::CopyOf(original)) =
+            find_same_content(storage, &prepared.path, sha256)?
+        {
+            return Ok(Written::Duplicate(original));
+        }
+    }
+    let report = write_timed(storage, &prepared, None, post_ingest)?;
+    if let Some(sha256) = prepared.fingerprint.sha256 {
+        written.insert(sha256, prepared.path);
+    }
+    Ok(Written::Stored(report))
+}
+
+/// [`write_rollout`], timed and summarised for the [`ImportReport`].
+fn write_timed(
+    storage: &Storage,
+    prepared: &PreparedRollout,
+    conversation_id_override: Option<&str>,
+    post_ingest: Option<&dyn PostIngestHook>,
+) -> Result<FileReport, PipelineError> {
+    let started = Instant::now();
+    write_rollout(storage, prepared, conversation_id_override, post_ingest)?;
+    Ok(FileReport {
+        path: prepared.path.clone(),
+        parse_time: prepared.parse_time,
+        embed_time: prepared.embed_time,
+        write_time: started.elapsed(),
+        turns: prepared.record.turns.len() - prepared.first_new_turn
+            + prepared
+                .later_sessions
+                .iter()
+                .map(|session| session.record.turns.len())
+                .sum::<usize>(),
+        bytes: prepared.bytes,
+        warnings: prepared.record.warnings.len()
+            + prepared
+                .later_sessions
+                .iter()
+                .map(|session| session.record.warnings.len())
+                .sum::<usize>(),
+    })
+}
+
+/// Count and report the result of [`write_unless_duplicate`] for `path`.
+pub(crate) fn finish_ingest(
+    path: &Path,
+    result: Result<Written, PipelineError>,
+    options: &IngestOptions<'_>,
+    stats: &mut UpdateStats,
+) -> Result<(), PipelineError> {
+    match result {
+        Ok(written) => {
+            let outcome = match written {
+                Written::Duplicate(original) => {
+                    stats.duplicates.push(DuplicateRollout {
+                        path: path.to_path_buf(),
+                        original,
+                    });
+                    FileOutcome::Duplicate
+                }
+                Written::Stored(file) => {
+                    stats.processed += 1;
+                    stats.report.files.push(file);
+                    FileOutcome::Ingested
+                }
+            };
+            report(
+                options.progress,
+                ProgressEvent::FileFinished { path, outcome },
+            );
+            Ok(())
+        }
+        Err(error) => {
+            report(
+                options.progress,
+                ProgressEvent::FileFailed {
+                    path,
+                    error: &error,
+                },
+            );
+            stats.record_failure(path, error, options)
+        }
+    }
+}
+
+/// Run `work` on each of `items`, on `concurrency` scoped worker threads when it is above one,
+/// and hand every result to `consume` on the calling thread as it completes. Workers stop
+/// picking up items once `consume` fails, and its error is returned.
+fn run_pool<T, R>(
+    items: &[T],
+    concurrency: usize,
+    work: impl Fn(&T) -> R + Sync,
+    mut consume: impl FnMut(&T, R) -> Result<(), PipelineError>,
+) -> Result<(), PipelineError>
+where
+    T: Sync,
+    R: Send,
+{
+    if concurrency <= 1 {
+        for item in items {
+            consume(item, work(item))?;
+        }
+        return Ok(());
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::sync_channel::<(&T, R)>(concurrency);
+    thread::scope(|scope| {
+        for _ in 0..concurrency.min(items.len()) {
+            let sender = sender.clone();
+            let next = &next;
+            let work = &work;
+            scope.spawn(move || {
+                while let Some(item) = items.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    // The receiver is gone once the consumer has failed.
+                    if sender.send((item, work(item))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (item, result) in receiver {
+            if let Err(error) = consume(item, result) {
+                next.store(items.len(), Ordering::Relaxed);
+                return Err(error);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Embedding space [`reembed_all`] stages new vectors in until they replace the default space.
+const REEMBED_SPACE: &str = "reembed-staging";
+
+/// Conversations whose turns are loaded into memory at a time when embedding stored turns.
+const EMBED_STORED_CHUNK: usize = 64;
+
+/// Embed every stored turn again with `embedder`, e.g. to switch embedding models without
+/// deleting the database and re-importing everything. Turn summaries are rendered from the
+/// rollout when it is unchanged on disk and from the stored turns otherwise.
+///
+/// New vectors are staged in a separate embedding space and replace the default space, with
+/// `embedder` recorded as its model, only after every conversation was embedded: search keeps
+/// working on the old vectors meanwhile and an interrupted run changes nothing. `processed`
+/// counts re-embedded conversations; ones that fail under [`IngestOptions::skip_failures`] are
+/// left without embeddings. Progress events are reported per conversation rollout.
+pub fn reembed_all(
+    storage: &Storage,
+    embedder: &EmbeddingModel,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    let targets: Vec<EmbedTarget> = storage
+        .conversation_rollouts()
+        .map_err(PipelineError::Database)?
+        .into_iter()
+        .map(|(conversation_id, path)| EmbedTarget {
+            conversation_id,
+            path,
+            turns: None,
+        })
+        .collect();
+    storage
+        .delete_embedding_space(REEMBED_SPACE)
+        .map_err(PipelineError::Database)?;
+    let stats = embed_stored_turns(storage, embedder, options, &targets, REEMBED_SPACE)?;
+    storage
+        .promote_embedding_space(REEMBED_SPACE, embedder.info())
+        .map_err(PipelineError::Database)?;
+    Ok(stats)
+}
+
+/// Embed only the stored turns that have no vector in the default embedding space, e.g. ones
+/// imported before an embedding model was available, leaving parsed data and conversation rows
+/// untouched. Fails with a [`StorageError::EmbeddingModelMismatch`] when the database was
+/// embedded with another model. `processed` counts the conversations that gained embeddings.
+pub fn embed_missing(
+    storage: &Storage,
+    embedder: &EmbeddingModel,
+    options: &IngestOptions<'_>,
+) -> Result<UpdateStats, PipelineError> {
+    let targets: Vec<EmbedTarget> = storage
+        .turns_missing_embeddings()
+        .map_err(PipelineError::Database)?
+        .into_iter()
+        .map(|(conversation_id, path, turns)| EmbedTarget {
+            conversation_id,
+            path,
+            turns: Some(turns),
+        })
+        .collect();
+    if !targets.is_empty() {
+        storage
+            .record_embedding_model(embedder.info())
+            .map_err(PipelineError::Database)?;
+    }
+    embed_stored_turns(
+        storage,
+        embedder,
+        options,
+        &targets,
+        DEFAULT_EMBEDDING_MODEL,
+    )
+}
+
+/// A stored conversation whose turns [`embed_stored_turns`] embeds.
+struct EmbedTarget {
+    conversation_id: String,
+    path: PathBuf,
+    /// Indices of the turns to embed; `None` embeds all of them.
+    turns: Option<Vec<usize>>,
+}
+
+/// Embed the turns of `targets` into the `model_name` space, a chunk of conversations at a
+/// time, reporting each conversation's rollout to `options.progress`.
+fn embed_stored_turns(
+    storage: &Storage,
+    embedder: &EmbeddingModel,
+    options: &IngestOptions<'_>,
+    targets: &[EmbedTarget],
+    model_name: &str,
+) -> Result<UpdateStats, PipelineError> {
+    let progress = options.progress;
+    report(
+        progress,
+        ProgressEvent::Discovered {
+            total: targets.len(),
+        },
+    );
+
+    let mut stats = UpdateStats::default();
+    for chunk in targets.chunks(EMBED_STORED_CHUNK) {
+        let mut loaded = Vec::with_capacity(chunk.len());
+        for target in chunk {
+            let path = target.path.as_path();
+            report(progress, ProgressEvent::FileStarted { path });
+            match load_turns_for_embedding(storage, &target.conversation_id, path, options) {
+                Ok(mut turns) => {
+                    if let Some(wanted) = &target.turns {
+                        turns.retain(|turn| wanted.contains(&turn.index));
+                    }
+                    loaded.push((target, turns));
+                }
+                Err(error) => {
+                    report(
+                        progress,
+                        ProgressEvent::FileFailed {
+                            path,
+                            error: &error,
+                        },
+                    );
+                    stats.record_failure(path, error, options)?;
+                }
+            }
+        }
+
+        let embed = |(target, turns): &(&EmbedTarget, Vec<TurnRecord>)| {
+            embed_turns(
+                &target.path,
+                turns,
+                Some(embedder),
+                progress,
+                &options.config,
+            )
+        };
+        run_pool(
+            &loaded,
+            options.concurrency,
+            embed,
+            |(target, turns), vectors| {
+                let path = target.path.as_path();
+                let result = vectors.and_then(|vectors| {
+                    let write = || -> Result<(), StorageError> {
+                        let tx = storage.begin_ingest()?;
+                        for (turn, vector) in turns.iter().zip(vectors.unwrap_or_default()) {
+                            storage.insert_embedding(
+                                &target.conversation_id,
+                                turn.index,
+                                model_name,
+                                &vector,
+                            )?;
+                        }
+                        tx.commit()
+                    };
+                    write().map_err(|err| PipelineError::storage(path, err))
+                });
+                match result {
+                    Ok(()) => {
+                        report(
+                            progress,
+                            ProgressEvent::FileFinished {
+                                path,
+                                outcome: FileOutcome::Embedded,
+                            },
+                        );
+                        stats.processed += 1;
+                        Ok(())
+                    }
+                    Err(error) => {
+                        report(
+                            progress,
+                            ProgressEvent::FileFailed {
+                                path,
+                                error: &error,
+                            },
+                        );
+                        stats.record_failure(path, error, options)
+                    }
+                }
+            },
+        )?;
+    }
+    Ok(stats)
+}
+
+/// Turns of a stored conversation to embed: parsed from its rollout when the file is unchanged
+/// since import (so summaries match a fresh import exactly, scrubbed by `options.redactor`),
+/// else the stored turns.
+fn load_turns_for_embedding(
+    storage: &Storage,
+    conversation_id: &str,
+    path: &Path,
+    options: &IngestOptions<'_>,
+) -> Result<Vec<TurnRecord>, PipelineError> {
+    let stored = storage
+        .get_rollout_fingerprint(path)
+        .map_err(|err| PipelineError::storage(path, err))?;
+    let unchanged = match (stored, fs::metadata(path)) {
+        (Some(stored), Ok(metadata)) => {
+            let (modified_at, size_bytes) = file_metadata(&metadata);
+            fingerprint_matches(&stored, modified_at, size_bytes)
+        }
+        _ => false,
+    };
+    if unchanged {
+        let reader = open_rollout(path).map_err(|err| PipelineError::io(path, err))?;
+        let PipelineConfig { format, parse, .. } = options.config;
+        let (sessions, _) =
+            parse_transcript_from(BufReader::new(reader), format, options.formats, parse, None)
+                .map_err(|err| err.with_path(path))?;
+        let single = sessions.len() == 1;
+        let mut record = sessions
+            .into_iter()
+            .find(|record| single || extract_conversation_id(record, path) == conversation_id)
+            .unwrap_or_default();
+        if let Some(redactor) = options.redactor {
+            redactor.redact_record(&mut record);
+        }
+        return Ok(record.turns);
+    }
+    storage
+        .load_turn_records(conversation_id)
+        .map_err(|err| PipelineError::storage(path, err))
+}
+
+pub(crate) fn fingerprint_matches(
+    existing: &RolloutFingerprint,
+    modified_at: Option<OffsetDateTime>,
+    size_bytes: Option<u64>,
+) -> bool {
+    match (existing.modified_at, modified_at) {
+        (Some(stored), Some(current)) if stored == current => {}
+        _ => return false,
+    }
+
+    match (existing.size_bytes, size_bytes) {
+        (Some(stored), Some(current)) if stored == current => {}
+        _ => return false,
+    }
+
+    true
+}
+
+pub(crate) fn file_metadata(meta: &Metadata) -> (Option<OffsetDateTime>, Option<u64>) {
+    let modified_at = meta.modified().ok().map(OffsetDateTime::from);
+    let size_bytes = Some(meta.len());
+    (modified_at, size_bytes)
+}
+
+fn render_turn_summary(turn: &TurnRecord, config: &PipelineConfig) -> String {
+    let mut sections = Vec::new();
+
+    let mut rendered_inputs = Vec::new();
+    let inputs = turn
+        .user_inputs
+        .iter()
+        .filter(|input| config.include_context_inputs || input.kind == UserInputKind::Prompt);
+    for (idx, input) in inputs.enumerate() {
+        let mut fragment = String::new();
+        if let Some(text) = &input.text {
+            fragment.push_str(text);
+        }
+        if !input.images.is_empty() {
+            if !fragment.is_empty() {
+                fragment.push('\n');
+            }
+            fragment.push_str(&format!("[{} image(s)]", input.images.len()));
+        }
+        for file in &input.files {
+            if !fragment.is_empty() {
+                fragment.push('\n');
+            }
+            fragment.push_str(&render_attachment(file));
+        }
+        if !fragment.is_empty() {
+            rendered_inputs.push(format!("#{} {}", idx + 1, fragment.trim()));
+        }
+    }
+    if !rendered_inputs.is_empty() {
+        sections.push(format!("User:\n{}", rendered_inputs.join("\n\n")));
+    }
+
+    let mut result_texts = Vec::new();
+    if !turn.result.assistant_messages.is_empty() {
+        result_texts.push(turn.result.assistant_messages.join("\n\n"));
+    }
+    if let Some(fallback) = &turn.result.fallback {
+        result_texts.push(format!(
+            "[fallback {:?}] {}",
+            fallback.source, fallback.text
+        ));
+    }
+    if !result_texts.is_empty() {
+        sections.push(format!("Assistant:\n{}", result_texts.join("\n\n")));
+    }
+
+    if !turn.actions.is_empty() {
+        let mut action_summaries = Vec::new();
+        for action in &turn.actions {
+            let summary = match &action.kind {
+                crate::types::ActionKind::FunctionCall { name } => format!(
+                    "function_call {}",
+                    name.clone().unwrap_or_else(|| "(unknown)".into())
+                ),
+                crate::types::ActionKind::CustomToolCall { name } => format!(
+                    "custom_tool {}",
+                    name.clone().unwrap_or_else(|| "(unknown)".into())
+                ),
+                crate::types::ActionKind::LocalShellExec {
+                    command, workdir, ..
+                } => {
+                    let joined = command.join(" ");
+                    if let Some(dir) = workdir {
+                        format!("shell `{}` (cwd: {})", joined, dir)
+                    } else {
+                        format!("shell `{}`", joined)
+                    }
+                }
+                crate::types::ActionKind::WebSearch { query } => format!(
+                    "web_search {}",
+                    query.clone().unwrap_or_else(|| "(query missing)".into())
+                ),
+                crate::types::ActionKind::McpToolCall { server, tool } => format!(
+                    "mcp {}.{}",
+                    server.as_deref().unwrap_or("(unknown)"),
+                    tool.as_deref().unwrap_or("(unknown)")
+                ),
+                crate::types::ActionKind::Other { kind } => {
+                    kind.clone().unwrap_or_else(|| "other".into())
+                }
+            };
+
+            let status = action
                 .status
                 .status_text
                 .clone()
@@ -306,11 +2182,17 @@ fn render_turn_summary(turn: &TurnRecord) -> String {
             if let Some(status) = status {
                 rendered.push_str(&format!(" [status: {}]", status));
             }
+            if let (Some(false), Some(code)) = (action.status.success, action.status.exit_code) {
+                rendered.push_str(&format!(" [failed: exit {}]", code));
+            }
             if let Some(output) = &action.output {
                 if let Some(content) = &output.content {
                     let snippet = content.trim();
                     if !snippet.is_empty() {
-                        let shortened = snippet.chars().take(200).collect::<String>();
+                        let shortened = snippet
+                            .chars()
+                            .take(config.action_output_chars)
+                            .collect::<String>();
                         rendered.push_str(&format!(" -> {}", shortened));
                     }
                 }
@@ -322,336 +2204,1333 @@ fn render_turn_summary(turn: &TurnRecord) -> String {
         }
     }
 
-    if sections.is_empty() {
-        "No transcript recorded for this turn.".to_string()
-    } else {
-        sections.join("\n\n")
-    }
-}
+    if sections.is_empty() {
+        "No transcript recorded for this turn.".to_string()
+    } else {
+        sections.join("\n\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ListParams, Storage};
+    use std::io::Write;
+    use std::time::Duration;
+    use tempfile::{tempdir, NamedTempFile};
+
+    fn sample_rollout() -> String {
+        sample_rollout_with_assistant("hi there")
+    }
+
+    fn sample_rollout_with_assistant(text: &str) -> String {
+        format!(
+            r#"
+{{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{{"id":"urn:uuid:test","cwd":"/tmp"}}}}
+{{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{{"type":"message","role":"user","content":[{{"type":"input_text","text":"hello"}}]}}}}
+{{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{{"type":"message","role":"assistant","content":[{{"type":"output_text","text":"{text}"}}]}}}}
+"#,
+            text = text
+        )
+    }
+
+    #[test]
+    fn pipeline_stores_rollout_without_embeddings() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        let contents = sample_rollout();
+        tmp.write_all(contents.as_bytes()).unwrap();
+        tmp.flush().unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_file(tmp.path(), &storage, None, None).unwrap();
+
+        let count: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM turns", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn pipeline_stores_rollout_read_from_a_reader() {
+        let storage = Storage::open_in_memory().unwrap();
+        for _ in 0..2 {
+            let rollout = sample_rollout();
+            process_rollout_reader(rollout.as_bytes(), &storage, None, "stdin").unwrap();
+        }
+
+        let rows: (i64, String, Option<i64>) = storage
+            .connection()
+            .query_row(
+                "SELECT COUNT(*), rollout_path, rollout_size_bytes FROM conversations",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(rows, (1, "stdin".to_string(), None));
+    }
+
+    #[test]
+    fn reingesting_a_shrunken_rollout_drops_stale_turns() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("rollout.jsonl");
+        let second_turn = r#"{"timestamp":"2025-01-01T00:00:03.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"again"}]}}"#;
+        std::fs::write(&file_path, format!("{}{second_turn}\n", sample_rollout())).unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let turns = || -> i64 {
+            storage
+                .connection()
+                .query_row("SELECT COUNT(*) FROM turns", [], |row| row.get(0))
+                .unwrap()
+        };
+        process_rollout_file(&file_path, &storage, None, None).unwrap();
+        assert_eq!(turns(), 2);
+
+        std::fs::write(&file_path, sample_rollout()).unwrap();
+        process_rollout_file(&file_path, &storage, None, None).unwrap();
+        assert_eq!(turns(), 1);
+    }
+
+    #[test]
+    fn pipeline_processes_directory() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("2025/10/01");
+        std::fs::create_dir_all(&nested).unwrap();
+        let file_path = nested.join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        std::fs::write(&file_path, sample_rollout()).unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let processed = process_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(processed, 1);
+
+        let count: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM turns", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn parallel_directory_ingest_writes_every_rollout() {
+        let dir = tempdir().unwrap();
+        for idx in 0..6 {
+            let rollout = sample_rollout().replace("urn:uuid:test", &format!("urn:uuid:{idx}"));
+            let name = format!("rollout-2025-10-01T00-00-0{idx}-abc.jsonl");
+            std::fs::write(dir.path().join(name), rollout).unwrap();
+        }
+
+        let storage = Storage::open_in_memory().unwrap();
+        let options = IngestOptions {
+            concurrency: 3,
+            ..IngestOptions::default()
+        };
+        let stats = process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        assert_eq!(stats.processed, 6);
+        let count: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM conversations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 6);
+
+        let bad = dir.path().join("rollout-2025-10-01T00-00-09-bad.jsonl");
+        std::fs::write(&bad, "{not json\n").unwrap();
+        let err = process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap_err();
+        assert_eq!(err.path(), Some(bad.as_path()));
+    }
+
+    #[test]
+    fn reports_progress_for_every_file() {
+        let dir = tempdir().unwrap();
+        for idx in 0..2 {
+            let rollout = sample_rollout().replace("urn:uuid:test", &format!("urn:uuid:{idx}"));
+            let name = format!("rollout-2025-10-01T00-00-0{idx}-abc.jsonl");
+            std::fs::write(dir.path().join(name), rollout).unwrap();
+        }
+        let storage = Storage::open_in_memory().unwrap();
+        let events = std::sync::Mutex::new(Vec::new());
+        let sink = |event: ProgressEvent<'_>| {
+            events.lock().unwrap().push(match event {
+                ProgressEvent::Discovered { total } => format!("discovered {total}"),
+                ProgressEvent::FileStarted { .. } => "started".to_string(),
+                ProgressEvent::TurnsEmbedded { turns, .. } => format!("embedded {turns}"),
+                ProgressEvent::FileFinished { outcome, .. } => format!("{outcome:?}"),
+                ProgressEvent::FileFailed { .. } => "failed".to_string(),
+            })
+        };
+        let options = IngestOptions {
+            progress: Some(&sink),
+            ..IngestOptions::default()
+        };
+
+        update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        // Parsing the second file may overlap writing the first.
+        let mut first_run = events.lock().unwrap().drain(..).collect::<Vec<_>>();
+        first_run[1..].sort();
+        assert_eq!(
+            first_run,
+            ["discovered 2", "Ingested", "Ingested", "started", "started"]
+        );
+        update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["discovered 2", "Skipped", "Skipped"]
+        );
+    }
+
+    #[test]
+    fn dry_run_reports_outcomes_without_writing() {
+        let dir = tempdir().unwrap();
+        let kept = dir.path().join("rollout-2025-10-01T00-00-00-kept.jsonl");
+        let moved = dir.path().join("rollout-2025-10-01T00-00-01-moved.jsonl");
+        let rollout = |id: &str| sample_rollout().replace("urn:uuid:test", id);
+        std::fs::write(&kept, rollout("kept")).unwrap();
+        std::fs::write(&moved, rollout("moved")).unwrap();
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir(dir.path(), &storage, None).unwrap();
+
+        let renamed = dir.path().join("rollout-2025-10-01T00-00-01-renamed.jsonl");
+        std::fs::rename(&moved, &renamed).unwrap();
+        std::fs::write(
+            dir.path().join("rollout-2025-10-02T00-00-00-new.jsonl"),
+            rollout("new"),
+        )
+        .unwrap();
+
+        let options = IngestOptions {
+            dry_run: true,
+            ..IngestOptions::default()
+        };
+        let stats = update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        assert_eq!((stats.processed, stats.skipped, stats.moved), (1, 1, 1));
+        assert!(storage.get_rollout_fingerprint(&moved).unwrap().is_some());
+        assert!(storage.get_rollout_fingerprint(&renamed).unwrap().is_none());
+        let conversations: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM conversations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(conversations, 2);
+    }
+
+    #[test]
+    fn imports_claude_code_sessions_next_to_codex_rollouts() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl"),
+            sample_rollout(),
+        )
+        .unwrap();
+        let session = r#"{"type":"user","sessionId":"claude-1","cwd":"/work/repo","gitBranch":"main","timestamp":"2025-06-01T10:00:00.000Z","message":{"role":"user","content":"Run the tests"}}
+{"type":"assistant","sessionId":"claude-1","timestamp":"2025-06-01T10:00:02.000Z","message":{"id":"msg_1","model":"claude-sonnet-4-5","role":"assistant","content":[{"type":"tool_use","id":"toolu_1","name":"Bash","input":{"command":"cargo test"}},{"type":"tool_use","id":"toolu_2","name":"Edit","input":{"file_path":"/work/repo/src/lib.rs"}}]}}
+"#;
+        std::fs::write(
+            dir.path()
+                .join("0f8c2a4e-1b7d-4c11-9e3a-5d6f7a8b9c0d.jsonl"),
+            session,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("history.jsonl"), "not a session\n").unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let stats = process_rollout_dir_with(dir.path(), &storage, None, &IngestOptions::default())
+            .unwrap();
+        assert_eq!(stats.processed, 2);
+        let (model, branch, commands, files): (String, String, String, String) = storage
+            .connection()
+            .query_row(
+                "SELECT model, git_branch, commands_json, files_json FROM conversations \
+                 WHERE id = 'claude-1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(model, "claude-sonnet-4-5");
+        assert_eq!(branch, "main");
+        assert_eq!(commands, r#"["cargo"]"#);
+        assert_eq!(files, r#"["/work/repo/src/lib.rs"]"#);
+        // Only Codex rollouts can be resumed from where the last import stopped.
+        assert!(storage
+            .rollout_resume(
+                dir.path()
+                    .join("0f8c2a4e-1b7d-4c11-9e3a-5d6f7a8b9c0d.jsonl")
+            )
+            .unwrap()
+            .is_none());
+
+        let codex_only = IngestOptions {
+            config: PipelineConfig {
+                format: RolloutFormat::Codex,
+                ..PipelineConfig::default()
+            },
+            ..IngestOptions::default()
+        };
+        assert_eq!(discover_rollouts(dir.path(), &codex_only).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn imports_chatgpt_exports_and_skips_unchanged_conversations() {
+        let conversation = |id: &str, title: &str, answer: &str| {
+            serde_json::json!({
+                "conversation_id": id,
+                "title": title,
+                "create_time": 1_748_772_000.0,
+                "current_node": "a",
+                "mapping": {
+                    "q": {"parent": null, "message": {
+                        "author": {"role": "user"}, "create_time": 1_748_772_000.0,
+                        "content": {"content_type": "text", "parts": ["How do lifetimes work?"]}
+                    }},
+                    "a": {"parent": "q", "message": {
+                        "author": {"role": "assistant"}, "create_time": 1_748_772_005.0,
+                        "metadata": {"model_slug": "gpt-4o"},
+                        "content": {"content_type": "text", "parts": [answer]}
+                    }}
+                }
+            })
+        };
+        let dir = tempdir().unwrap();
+        let export = dir.path().join("conversations.json");
+        let write_export = |second_answer: &str| {
+            let conversations = serde_json::json!([
+                conversation("chat-1", "Rust lifetimes", "They bound borrows."),
+                conversation("chat-2", "More lifetimes", second_answer),
+            ]);
+            std::fs::write(&export, conversations.to_string()).unwrap();
+        };
+        write_export("Scopes.");
+
+        let storage = Storage::open_in_memory().unwrap();
+        let options = IngestOptions::default();
+        let stats = process_chatgpt_export(&export, &storage, None, &options).unwrap();
+        assert_eq!(stats.processed, 2);
+        let (title, model, path): (String, String, String) = storage
+            .connection()
+            .query_row(
+                "SELECT auto_title, model, rollout_path FROM conversations WHERE id = 'chat-1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(title, "Rust lifetimes");
+        assert_eq!(model, "gpt-4o");
+        assert_eq!(path, format!("{}#chat-1", export.display()));
+
+        write_export("Regions of code.");
+        let stats = process_chatgpt_export(&export, &storage, None, &options).unwrap();
+        assert_eq!((stats.processed, stats.skipped), (1, 1));
+
+        // A directory update over the export's folder keeps its conversations.
+        let update = IngestOptions {
+            remove_deleted: true,
+            ..IngestOptions::default()
+        };
+        let stats = update_rollout_dir_with(dir.path(), &storage, None, &update).unwrap();
+        assert_eq!(stats.removed, 0);
+        std::fs::remove_file(&export).unwrap();
+        let stats = update_rollout_dir_with(dir.path(), &storage, None, &update).unwrap();
+        assert_eq!(stats.removed, 2);
+    }
+
+    /// A downstream format: `> question` / `< answer` lines in `*.chat` files.
+    struct ArrowChat;
+
+    impl TranscriptFormat for ArrowChat {
+        fn name(&self) -> &str {
+            "arrow-chat"
+        }
+
+        fn detect(&self, head: &[u8]) -> bool {
+            head.starts_with(b"> ")
+        }
+
+        fn parse(&self, reader: &mut dyn BufRead) -> Result<ConversationRecord, ParseError> {
+            let mut record = ConversationRecord::default();
+            for line in reader.lines() {
+                let line = line.map_err(|source| ParseError::Io {
+                    path: None,
+                    line: record.turns.len() + 1,
+                    source,
+                })?;
+                if let Some(question) = line.strip_prefix("> ") {
+                    record.turns.push(TurnRecord {
+                        index: record.turns.len(),
+                        started_at: None,
+                        ended_at: None,
+                        duration_seconds: None,
+                        context: None,
+                        user_inputs: vec![crate::types::UserInputRecord {
+                            raw: serde_json::Value::Null,
+                            text: Some(question.to_string()),
+                            images: Vec::new(),
+                            kind: crate::types::UserInputKind::Prompt,
+                            image_refs: Vec::new(),
+                            files: Vec::new(),
+                        }],
+                        result: Default::default(),
+                        actions: Vec::new(),
+                        telemetry: Default::default(),
+                        errors: Vec::new(),
+                        source_lines: None,
+                        token_usage: None,
+                        compactions: Vec::new(),
+                    });
+                } else if let (Some(answer), Some(turn)) =
+                    (line.strip_prefix("< "), record.turns.last_mut())
+                {
+                    turn.result.assistant_messages.push(answer.to_string());
+                }
+            }
+            Ok(record)
+        }
+
+        fn matches_file_name(&self, name: &str) -> bool {
+            name.ends_with(".chat")
+        }
+    }
+
+    #[test]
+    fn registered_formats_are_detected_and_discovered() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl"),
+            sample_rollout(),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("notes.chat"),
+            "> Why is the build slow?\n< Too many generics.\n",
+        )
+        .unwrap();
+        let mut formats = FormatRegistry::new();
+        formats.register(ArrowChat);
+        assert_eq!(formats.detect(b"> hi").name(), "arrow-chat");
+        assert_eq!(formats.detect(br#"{"type":"user"}"#).name(), "claude-code");
+        assert!(formats.get("codex").is_some());
+
+        let storage = Storage::open_in_memory().unwrap();
+        let options = IngestOptions {
+            formats: Some(&formats),
+            ..IngestOptions::default()
+        };
+        let stats = process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        assert_eq!(stats.processed, 2);
+        let turns = storage.load_turn_records("notes").unwrap();
+        assert_eq!(turns[0].result.assistant_messages, ["Too many generics."]);
+
+        // Unchanged files, custom ones included, are recognised by their stored hash.
+        let update = IngestOptions {
+            change_detection: ChangeDetection::Hash,
+            ..options
+        };
+        let stats = update_rollout_dir_with(dir.path(), &storage, None, &update).unwrap();
+        assert_eq!((stats.processed, stats.skipped), (0, 2));
+
+        // Without the registry, directory walks pass over `.chat` files.
+        let plain = IngestOptions::default();
+        assert_eq!(discover_rollouts(dir.path(), &plain).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn discovery_honours_excludes_depth_and_symlinks() {
+        let dir = tempdir().unwrap();
+        for relative in [
+            "rollout-top.jsonl",
+            "2025/10/rollout-nested.jsonl",
+            "2025/trash/rollout-deleted.jsonl",
+        ] {
+            let path = dir.path().join(relative);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, sample_rollout()).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("2025/loop")).unwrap();
+        let names = |options: &IngestOptions<'_>| -> Vec<String> {
+            discover_rollouts(dir.path(), options)
+                .unwrap()
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            names(&IngestOptions {
+                exclude: vec!["*/trash/*".into()],
+                follow_symlinks: true,
+                ..IngestOptions::default()
+            }),
+            ["rollout-nested.jsonl", "rollout-top.jsonl"]
+        );
+        assert_eq!(
+            names(&IngestOptions {
+                max_depth: Some(0),
+                ..IngestOptions::default()
+            }),
+            ["rollout-top.jsonl"]
+        );
+        assert!(matches!(
+            discover_rollouts(
+                dir.path(),
+                &IngestOptions {
+                    exclude: vec!["[".into()],
+                    ..IngestOptions::default()
+                }
+            ),
+            Err(PipelineError::Pattern(_))
+        ));
+    }
+
+    #[test]
+    fn discovery_keeps_rollouts_inside_the_date_window() {
+        let dir = tempdir().unwrap();
+        for name in [
+            "rollout-2025-06-30T23-59-59-a.jsonl",
+            "rollout-2025-07-01T00-00-00-b.jsonl",
+            "rollout-2025-10-01T00-00-00-c.jsonl",
+            "rollout-undated.jsonl",
+        ] {
+            std::fs::write(dir.path().join(name), sample_rollout()).unwrap();
+        }
+        let at = |ts: &str| OffsetDateTime::parse(ts, &Rfc3339).unwrap();
+        let options = IngestOptions {
+            since: Some(at("2025-07-01T00:00:00Z")),
+            until: Some(at("2025-10-01T00:00:00Z")),
+            ..IngestOptions::default()
+        };
+        let found = discover_rollouts(dir.path(), &options).unwrap();
+        assert_eq!(
+            found,
+            [dir.path().join("rollout-2025-07-01T00-00-00-b.jsonl")]
+        );
+
+        // Without a timestamp in its name, the undated rollout is judged by its mtime.
+        let options = IngestOptions {
+            since: Some(at("2025-10-01T00:00:00Z")),
+            ..IngestOptions::default()
+        };
+        assert_eq!(discover_rollouts(dir.path(), &options).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn remove_deleted_prunes_conversations_of_vanished_rollouts() {
+        let dir = tempdir().unwrap();
+        let kept = dir.path().join("rollout-2025-10-01T00-00-00-kept.jsonl");
+        let gone = dir.path().join("rollout-2025-10-01T00-00-01-gone.jsonl");
+        let filtered = dir.path().join("rollout-2024-01-01T00-00-00-old.jsonl");
+        for (path, id) in [(&kept, "kept"), (&gone, "gone"), (&filtered, "old")] {
+            std::fs::write(path, sample_rollout().replace("urn:uuid:test", id)).unwrap();
+        }
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir(dir.path(), &storage, None).unwrap();
+        std::fs::remove_file(&gone).unwrap();
+
+        let options = IngestOptions {
+            remove_deleted: true,
+            since: Some(OffsetDateTime::parse("2025-01-01T00:00:00Z", &Rfc3339).unwrap()),
+            dry_run: true,
+            ..IngestOptions::default()
+        };
+        let stats = update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        assert_eq!(stats.removed, 1);
+        assert!(storage.get_rollout_fingerprint(&gone).unwrap().is_some());
+
+        let options = IngestOptions {
+            dry_run: false,
+            ..options
+        };
+        let stats = update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        assert_eq!((stats.removed, stats.skipped), (1, 1));
+        assert!(storage.get_rollout_fingerprint(&gone).unwrap().is_none());
+        assert!(storage
+            .get_rollout_fingerprint(&filtered)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn skip_failures_collects_per_file_errors() {
+        let dir = tempdir().unwrap();
+        let good = dir.path().join("rollout-2025-10-01T00-00-00-good.jsonl");
+        let bad = dir.path().join("rollout-2025-10-01T00-00-01-bad.jsonl");
+        std::fs::write(&good, sample_rollout()).unwrap();
+        std::fs::write(&bad, "{\"timestamp\":\"2025-01-01T00:00:00Z\",\"type\":\n").unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let options = IngestOptions {
+            skip_failures: true,
+            ..IngestOptions::default()
+        };
+        let stats = update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.failed.len(), 1);
+        assert_eq!(stats.failed[0].path, bad);
+        assert_eq!(stats.failed[0].error.path(), Some(bad.as_path()));
+
+        let err = update_rollout_dir(dir.path(), &storage, None).unwrap_err();
+        assert_eq!(err.path(), Some(bad.as_path()));
+    }
+
+    #[test]
+    fn ingests_gzip_and_zstd_rollouts() {
+        let dir = tempdir().unwrap();
+        let plain = dir.path().join("rollout-2025-10-01T00-00-00-plain.jsonl");
+        std::fs::write(&plain, sample_rollout()).unwrap();
+        let zstd_rollout = sample_rollout().replace("urn:uuid:test", "urn:uuid:zstd");
+        std::fs::write(
+            dir.path()
+                .join("rollout-2025-10-01T00-00-01-zstd.jsonl.zst"),
+            zstd::encode_all(zstd_rollout.as_bytes(), 0).unwrap(),
+        )
+        .unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        assert_eq!(process_rollout_dir(dir.path(), &storage, None).unwrap(), 2);
+
+        // Compressing an archived rollout is a move, not a new conversation.
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(sample_rollout().as_bytes()).unwrap();
+        std::fs::write(plain.with_extension("jsonl.gz"), encoder.finish().unwrap()).unwrap();
+        std::fs::remove_file(&plain).unwrap();
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!((stats.moved, stats.skipped, stats.processed), (1, 1, 0));
+
+        let turns: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM turns", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(turns, 2);
+    }
+
+    #[test]
+    fn update_dir_skips_unchanged_and_refreshes_modified_files() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        std::fs::write(&file_path, sample_rollout()).unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let processed = process_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(processed, 1);
+
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(stats.processed, 0);
+        assert_eq!(stats.skipped, 1);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(
+            &file_path,
+            sample_rollout_with_assistant("updated response"),
+        )
+        .unwrap();
+
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(stats.processed, 1);
+        assert_eq!(stats.skipped, 0);
+
+        let assistant: String = storage
+            .connection()
+            .query_row(
+                "SELECT assistant_text FROM turns WHERE conversation_id = (SELECT id FROM conversations LIMIT 1)",
+                [],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .unwrap()
+            .unwrap();
+        assert!(assistant.contains("updated response"));
+    }
+
+    #[test]
+    fn update_dir_follows_moved_rollouts_by_content_hash() {
+        let dir = tempdir().unwrap();
+        let old_path = dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        std::fs::write(&old_path, sample_rollout()).unwrap();
 
-const MAX_STORED_QUESTIONS: usize = 5;
-const EMBED_BATCH_SIZE: usize = 32;
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir(dir.path(), &storage, None).unwrap();
 
-fn compute_conversation_stats(record: &ConversationRecord) -> ConversationStats {
-    let mut commands: HashSet<String> = HashSet::new();
-    let mut files: HashSet<String> = HashSet::new();
-    let mut questions: Vec<String> = Vec::new();
-    let mut search_parts: Vec<String> = Vec::new();
+        let archived = dir.path().join("archive");
+        std::fs::create_dir(&archived).unwrap();
+        let new_path = archived.join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        std::fs::rename(&old_path, &new_path).unwrap();
 
-    let mut cwd = record.session_meta.as_ref().and_then(|meta| {
-        meta.get("cwd")
-            .and_then(Value::as_str)
-            .map(|s| s.to_string())
-            .or_else(|| {
-                meta.get("workspace")
-                    .and_then(|w| w.get("cwd"))
-                    .and_then(Value::as_str)
-                    .map(|s| s.to_string())
-            })
-    });
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(stats.moved, 1);
+        assert_eq!(stats.processed, 0);
 
-    let mut first_question: Option<String> = None;
-    let mut last_question: Option<String> = None;
-    let mut last_user_message: Option<String> = None;
-    let mut model: Option<String> = None;
-    let mut has_live_events = false;
-    let mut turn_count: i64 = 0;
+        let conversations: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM conversations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(conversations, 1);
+        assert!(storage
+            .get_rollout_fingerprint(&old_path)
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .get_rollout_fingerprint(&new_path)
+            .unwrap()
+            .is_some());
 
-    for turn in &record.turns {
-        turn_count += 1;
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(stats.skipped, 1);
+    }
 
-        if let Some(ctx) = &turn.context {
-            if model.is_none() {
-                model = ctx.model.clone();
-            }
-            if ctx
-                .summary_style
-                .as_deref()
-                .map(|s| s.eq_ignore_ascii_case("live"))
-                .unwrap_or(false)
-            {
-                has_live_events = true;
-            }
-            if cwd.is_none() {
-                cwd = ctx.cwd.clone();
-            }
-        }
+    #[test]
+    fn update_dir_parses_only_the_appended_tail_of_grown_rollouts() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        let second_turn = r#"{"timestamp":"2025-01-01T00:00:03.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"again"}]}}
+"#;
+        let appended = r#"{"timestamp":"2025-01-01T00:00:05.000Z","type":"response_item","payload":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"once more"}]}}
+{"timestamp":"2025-01-01T00:00:06.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:07.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"third"}]}}
+"#;
+        let initial = format!("{}{second_turn}", sample_rollout());
+        std::fs::write(&path, &initial).unwrap();
 
-        for input in &turn.user_inputs {
-            if let Some(text) = input.text.as_ref() {
-                let trimmed = text.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
-                last_user_message = Some(trimmed.to_string());
-                if trimmed.contains('?') {
-                    if first_question.is_none() {
-                        first_question = Some(trimmed.to_string());
-                    }
-                    last_question = Some(trimmed.to_string());
-                }
-                questions.push(trimmed.to_string());
-                if questions.len() > MAX_STORED_QUESTIONS {
-                    questions.remove(0);
-                }
-                search_parts.push(trimmed.to_string());
-            }
-        }
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir(dir.path(), &storage, None).unwrap();
 
-        for message in &turn.result.assistant_messages {
-            let trimmed = message.trim();
-            if !trimmed.is_empty() {
-                search_parts.push(trimmed.to_string());
-            }
-        }
-        for summary in &turn.result.reasoning_summaries {
-            let trimmed = summary.trim();
-            if !trimmed.is_empty() {
-                search_parts.push(trimmed.to_string());
-            }
-        }
-        if let Some(fallback) = &turn.result.fallback {
-            let trimmed = fallback.text.trim();
-            if !trimmed.is_empty() {
-                search_parts.push(trimmed.to_string());
-            }
-        }
+        std::fs::write(&path, format!("{initial}{appended}")).unwrap();
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!((stats.appended, stats.processed), (1, 0));
+        let file = &stats.report.files[0];
+        assert_eq!(
+            (file.turns, file.bytes),
+            (2, (second_turn.len() + appended.len()) as u64)
+        );
+
+        let fresh = Storage::open_in_memory().unwrap();
+        process_rollout_dir(dir.path(), &fresh, None).unwrap();
+        let snapshot = |storage: &Storage| -> (i64, String, Option<String>, Vec<String>) {
+            let conn = storage.connection();
+            let (turns, ended_at) = conn
+                .query_row(
+                    "SELECT turn_count, ended_at FROM conversations",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+            let texts = conn
+                .prepare("SELECT assistant_text FROM turns ORDER BY turn_index")
+                .unwrap()
+                .query_map([], |row| row.get::<_, Option<String>>(0))
+                .unwrap()
+                .map(|text| text.unwrap().unwrap_or_default())
+                .collect();
+            let fingerprint = storage.get_rollout_fingerprint(&path).unwrap().unwrap();
+            (turns, ended_at, fingerprint.sha256, texts)
+        };
+        assert_eq!(snapshot(&storage), snapshot(&fresh));
+        assert_eq!(snapshot(&storage).3[1], "once more");
+        let source_lines = |storage: &Storage| -> Vec<Option<(usize, usize)>> {
+            storage
+                .fetch_turn_window("urn:uuid:test", 0, 0, 10)
+                .unwrap()
+                .into_iter()
+                .map(|turn| turn.source_lines)
+                .collect()
+        };
+        assert_eq!(
+            source_lines(&storage),
+            [Some((3, 4)), Some((5, 7)), Some((8, 9))]
+        );
+        assert_eq!(source_lines(&storage), source_lines(&fresh));
+
+        // A rollout rewritten before its resume point is ingested in full.
+        std::fs::write(
+            &path,
+            format!("{initial}{appended}").replace("hello", "hello there"),
+        )
+        .unwrap();
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!((stats.appended, stats.processed), (0, 1));
+    }
 
-        for action in &turn.actions {
-            collect_action_metadata(action, &mut commands, &mut files);
-        }
+    #[test]
+    fn sessions_appended_to_a_rollout_become_conversations_of_their_own() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        let resumed = r#"{"timestamp":"2025-01-02T00:00:00.000Z","type":"session_meta","payload":{"id":"urn:uuid:resumed","cwd":"/tmp"}}
+{"timestamp":"2025-01-02T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"picking up"}]}}
+"#;
+        std::fs::write(&path, sample_rollout()).unwrap();
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir(dir.path(), &storage, None).unwrap();
 
-        if !has_live_events && telemetry_indicates_live(&turn.telemetry) {
-            has_live_events = true;
-        }
-    }
+        std::fs::write(&path, format!("{}{resumed}", sample_rollout())).unwrap();
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!((stats.appended, stats.processed), (0, 1));
+        assert_eq!(stats.report.files[0].turns, 2);
 
-    let preview = last_question.clone().or_else(|| last_user_message.clone());
+        let conversations: Vec<(String, i64, String)> = storage
+            .connection()
+            .prepare("SELECT id, turn_count, ended_at FROM conversations ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].0, "urn:uuid:resumed");
+        assert_eq!(conversations[1].0, "urn:uuid:test");
+        assert_eq!((conversations[0].1, conversations[1].1), (1, 1));
+        assert!(conversations[1].2.starts_with("2025-01-01"));
+        let resumed_turn = storage
+            .fetch_turn_window("urn:uuid:resumed", 0, 0, 0)
+            .unwrap();
+        assert_eq!(resumed_turn[0].user_text.as_deref(), Some("picking up"));
+        assert_eq!(resumed_turn[0].source_lines, Some((6, 6)));
 
-    if let Some(preview_text) = preview.as_ref() {
-        if !preview_text.is_empty() {
-            search_parts.push(preview_text.clone());
-        }
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(stats.skipped, 1);
     }
 
-    for cmd in &commands {
-        search_parts.push(cmd.clone());
+    #[test]
+    fn sessions_of_a_rollout_are_written_together() {
+        let dir = tempdir().unwrap();
+        let resumed = r#"{"timestamp":"2025-01-02T00:00:00.000Z","type":"session_meta","payload":{"id":"urn:uuid:resumed","cwd":"/tmp"}}
+{"timestamp":"2025-01-02T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"picking up"}]}}
+"#;
+        std::fs::write(
+            dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl"),
+            format!("{}{resumed}", sample_rollout()),
+        )
+        .unwrap();
+        let storage = Storage::open_in_memory().unwrap();
+        storage
+            .connection()
+            .execute_batch(
+                "CREATE TEMP TRIGGER fail_resumed BEFORE INSERT ON conversations \
+                 WHEN NEW.id = 'urn:uuid:resumed' BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+            )
+            .unwrap();
+        assert!(process_rollout_dir(dir.path(), &storage, None).is_err());
+        let conversations = |storage: &Storage| -> i64 {
+            storage
+                .connection()
+                .query_row("SELECT COUNT(*) FROM conversations", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(conversations(&storage), 0);
+
+        storage
+            .connection()
+            .execute_batch("DROP TRIGGER fail_resumed")
+            .unwrap();
+        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
+        assert_eq!(stats.processed, 1);
+        assert_eq!(conversations(&storage), 2);
     }
-    for file in &files {
-        search_parts.push(file.clone());
+
+    #[test]
+    fn import_report_lists_every_written_rollout() {
+        let dir = tempdir().unwrap();
+        let small = dir.path().join("rollout-2025-10-01T00-00-00-a.jsonl");
+        let copy = dir.path().join("rollout-2025-10-01T00-00-00-b.jsonl");
+        std::fs::write(&small, sample_rollout()).unwrap();
+        std::fs::write(&copy, sample_rollout()).unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let stats = process_rollout_dir_with(dir.path(), &storage, None, &IngestOptions::default())
+            .unwrap();
+        // The copy is a duplicate and not written, so it has no timings.
+        assert_eq!(stats.report.files.len(), 1);
+        let file = &stats.report.files[0];
+        assert_eq!(file.path, small);
+        assert_eq!((file.turns, file.bytes), (1, sample_rollout().len() as u64));
+        assert_eq!(file.embed_time, Duration::ZERO);
+        assert_eq!(stats.report.slowest(5), vec![file]);
     }
 
-    let search_blob = search_parts
-        .iter()
-        .map(|s| s.to_lowercase())
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    let mut commands_vec: Vec<String> = commands.into_iter().collect();
-    commands_vec.sort();
-    let mut files_vec: Vec<String> = files.into_iter().collect();
-    files_vec.sort();
-
-    ConversationStats {
-        preview,
-        first_question,
-        last_question,
-        last_user_message,
-        model,
-        turn_count,
-        has_live_events,
-        commands: commands_vec,
-        files_touched: files_vec,
-        questions,
-        search_blob,
-        cwd,
-    }
-}
-
-fn collect_action_metadata(
-    action: &ActionRecord,
-    commands: &mut HashSet<String>,
-    files: &mut HashSet<String>,
-) {
-    match &action.kind {
-        ActionKind::FunctionCall { name } => {
-            if let Some(name) = name.as_deref() {
-                match name {
-                    "exec_command" => {
-                        if let Some(args) = action.arguments.as_ref() {
-                            if let Some(cmd) = args.get("cmd").and_then(Value::as_str) {
-                                if let Some(first) = cmd.split_whitespace().next() {
-                                    commands.insert(first.to_string());
-                                }
-                            }
-                            if let Some(command_vec) = args.get("command").and_then(Value::as_array)
-                            {
-                                if let Some(first) =
-                                    command_vec.iter().filter_map(Value::as_str).next()
-                                {
-                                    commands.insert(first.to_string());
-                                }
-                            }
-                        }
-                    }
-                    "apply_patch" => {
-                        if let Some(args) = action.arguments.as_ref() {
-                            if let Some(patch) = args.get("patch").and_then(Value::as_str) {
-                                for path in extract_patch_paths(patch) {
-                                    files.insert(path);
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
-        ActionKind::LocalShellExec { command, .. } => {
-            if let Some(first) = command.first() {
-                if !first.is_empty() {
-                    commands.insert(first.clone());
-                }
-            }
-        }
-        _ => {}
+    #[test]
+    fn post_ingest_hook_sees_every_stored_conversation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        let turn = |at: u8, text: &str| {
+            format!(
+                r#"{{"timestamp":"2025-01-01T00:00:0{at}.000Z","type":"turn_context","payload":{{"cwd":"/tmp"}}}}
+{{"timestamp":"2025-01-01T00:00:0{at}.000Z","type":"response_item","payload":{{"type":"message","role":"user","content":[{{"type":"input_text","text":"{text}"}}]}}}}
+"#
+            )
+        };
+        let initial = format!("{}{}", sample_rollout(), turn(3, "again"));
+        std::fs::write(&path, &initial).unwrap();
+
+        let stored = Mutex::new(Vec::new());
+        let hook = |conversation: StoredConversation<'_>| {
+            stored.lock().unwrap().push((
+                conversation.conversation_id.to_string(),
+                conversation.rollout_path.to_path_buf(),
+                conversation.record.turns.len(),
+                conversation.stats.turn_count,
+                conversation.first_new_turn,
+            ));
+        };
+        let options = IngestOptions {
+            post_ingest: Some(&hook),
+            ..IngestOptions::default()
+        };
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        std::fs::write(&path, format!("{initial}{}", turn(5, "third"))).unwrap();
+        update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        // Unchanged now, so nothing is stored and the hook is not called.
+        update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+
+        let stored = stored.into_inner().unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[0], ("urn:uuid:test".into(), path.clone(), 2, 2, 0));
+        // Only the tail from the last stored turn on was parsed and written.
+        assert_eq!(stored[1], ("urn:uuid:test".into(), path, 3, 3, 1));
     }
-}
 
-fn extract_patch_paths(patch: &str) -> Vec<String> {
-    let mut paths = Vec::new();
-    for line in patch.lines() {
-        if let Some(rest) = line.strip_prefix("*** ") {
-            if let Some(path) = rest.strip_prefix("Update File: ") {
-                paths.push(path.trim().to_string());
-            } else if let Some(path) = rest.strip_prefix("Add File: ") {
-                paths.push(path.trim().to_string());
-            } else if let Some(path) = rest.strip_prefix("Delete File: ") {
-                paths.push(path.trim().to_string());
-            }
-        }
+    #[test]
+    fn summarizer_output_is_stored_and_kept_across_plain_reimports() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl");
+        std::fs::write(&path, sample_rollout()).unwrap();
+
+        let summarizer = |turns: &[String]| -> Result<Option<String>, SummarizeError> {
+            assert!(turns[0].contains("hello"));
+            Ok(Some(format!(" Greeting over {} turn(s).\n", turns.len())))
+        };
+        let options = IngestOptions {
+            summarizer: Some(&summarizer),
+            ..IngestOptions::default()
+        };
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        let summary = || {
+            storage.list_conversations(&ListParams::default()).unwrap()[0]
+                .summary
+                .clone()
+        };
+        assert_eq!(summary().as_deref(), Some("Greeting over 1 turn(s)."));
+
+        process_rollout_file(&path, &storage, None, None).unwrap();
+        assert_eq!(summary().as_deref(), Some("Greeting over 1 turn(s)."));
+
+        let failing = |_: &[String]| -> Result<Option<String>, SummarizeError> {
+            Err("model not loaded".into())
+        };
+        let options = IngestOptions {
+            summarizer: Some(&failing),
+            ..IngestOptions::default()
+        };
+        let err = process_rollout_file_with(&path, &storage, None, None, &options).unwrap_err();
+        assert!(matches!(err, PipelineError::Summarize { .. }));
+        assert_eq!(err.path(), Some(path.as_path()));
     }
-    paths
-}
 
-fn telemetry_indicates_live(telemetry: &TurnTelemetry) -> bool {
-    telemetry.misc_events.iter().any(|event| {
-        let data = &event.data;
-        if data
-            .get("type")
-            .and_then(Value::as_str)
-            .is_some_and(|ty| ty == "listener_event")
-        {
-            return true;
-        }
-        if data
-            .get("kind")
-            .and_then(Value::as_str)
-            .is_some_and(|kind| kind == "live_state")
-        {
-            return data
-                .get("message")
-                .and_then(Value::as_str)
-                .map(|msg| msg.contains("active"))
-                .unwrap_or(true);
-        }
-        false
-    })
-}
+    #[test]
+    fn redactor_scrubs_secrets_before_storage_and_is_recorded() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl"),
+            sample_rollout().replace("hello", "ACME key is sk-abcdefghijklmnopqrstuvwxyz"),
+        )
+        .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::storage::Storage;
-    use std::io::Write;
-    use std::time::Duration;
-    use tempfile::{tempdir, NamedTempFile};
+        let mut redactor = Redactor::secrets();
+        redactor.add_rule("customer", "ACME", "[CUSTOMER]").unwrap();
+        let options = IngestOptions {
+            redactor: Some(&redactor),
+            ..IngestOptions::default()
+        };
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        let (user_text, search_blob): (String, String) = storage
+            .connection()
+            .query_row(
+                "SELECT t.user_text, c.search_blob FROM turns t JOIN conversations c \
+                 ON c.id = t.conversation_id",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(user_text, "[CUSTOMER] key is [REDACTED]");
+        assert!(!search_blob.contains("sk-abc"));
+
+        assert_eq!(storage.redaction_rules().unwrap(), redactor.rules());
+        let mut narrower = Redactor::default();
+        narrower.add_rule("customer", "ACME", "[CUSTOMER]").unwrap();
+        narrower.add_rule("host", r"\bdb\d+\b", "[HOST]").unwrap();
+        storage.record_redaction_rules(&narrower.rules()).unwrap();
+        let recorded = storage.redaction_rules().unwrap();
+        assert_eq!(recorded.len(), redactor.rules().len() + 1);
+        assert_eq!(recorded.last().unwrap().name, "host");
+    }
 
-    fn sample_rollout() -> String {
-        sample_rollout_with_assistant("hi there")
+    #[test]
+    fn inline_images_are_stored_once_by_content_hash() {
+        let dir = tempdir().unwrap();
+        let image = r#"{"type":"input_image","image_url":"data:image/png;base64,aGVsbG8="}"#;
+        let rollout = sample_rollout().replace(
+            r#"{"type":"input_text","text":"hello"}"#,
+            &format!(r#"{{"type":"input_text","text":"hello"}},{image},{image}"#),
+        );
+        std::fs::write(
+            dir.path().join("rollout-2025-10-01T00-00-00-img.jsonl"),
+            rollout,
+        )
+        .unwrap();
+
+        let options = IngestOptions {
+            config: PipelineConfig {
+                store_images: true,
+                ..PipelineConfig::default()
+            },
+            ..IngestOptions::default()
+        };
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        let turn = storage
+            .fetch_turn_window("urn:uuid:test", 0, 0, 0)
+            .unwrap()
+            .remove(0);
+        assert_eq!(turn.images.len(), 2);
+        assert_eq!(turn.images[0], turn.images[1]);
+        assert_eq!(turn.images[0].mime_type.as_deref(), Some("image/png"));
+        let stored = storage.image(&turn.images[0].sha256).unwrap().unwrap();
+        assert_eq!(stored.data, b"hello");
+        let count: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM images", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+
+        assert!(storage.delete_conversation("urn:uuid:test").unwrap());
+        assert_eq!(storage.prune_images().unwrap(), 1);
+        assert_eq!(storage.image(&turn.images[0].sha256).unwrap(), None);
     }
 
-    fn sample_rollout_with_assistant(text: &str) -> String {
-        format!(
-            r#"
-{{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{{"id":"urn:uuid:test","cwd":"/tmp"}}}}
-{{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{{"type":"message","role":"user","content":[{{"type":"input_text","text":"hello"}}]}}}}
-{{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{{"type":"message","role":"assistant","content":[{{"type":"output_text","text":"{text}"}}]}}}}
-"#,
-            text = text
+    #[test]
+    fn pipeline_errors_name_the_failing_rollout() {
+        let dir = tempdir().unwrap();
+        let good = dir.path().join("rollout-2025-10-01T00-00-00-good.jsonl");
+        let bad = dir.path().join("rollout-2025-10-02T00-00-00-bad.jsonl");
+        std::fs::write(&good, sample_rollout()).unwrap();
+        std::fs::write(
+            &bad,
+            format!("{}\n{{\"timestamp\":", sample_rollout().trim()),
         )
+        .unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let err = process_rollout_dir(dir.path(), &storage, None).unwrap_err();
+        assert_eq!(err.path(), Some(bad.as_path()));
+        match err {
+            PipelineError::Parse(ParseError::Json { line, .. }) => assert_eq!(line, 4),
+            other => panic!("unexpected error: {other:?}"),
+        }
     }
 
     #[test]
-    fn pipeline_stores_rollout_without_embeddings() {
+    fn rebuild_derived_restores_stats_from_stored_turns() {
+        let rollout = r#"
+{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"urn:uuid:rebuild","cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"turn_context","payload":{"cwd":"/repo","model":"gpt-5"}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"why does the build fail?"}]}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"response_item","payload":{"type":"reasoning","summary":[{"type":"summary_text","text":"Inspecting Cargo output"}]}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"function_call","name":"shell","call_id":"c1","arguments":"{\"command\":[\"cargo\",\"build\"]}"}}
+"#;
         let mut tmp = NamedTempFile::new().unwrap();
-        let contents = sample_rollout();
-        tmp.write_all(contents.as_bytes()).unwrap();
+        tmp.write_all(rollout.as_bytes()).unwrap();
         tmp.flush().unwrap();
 
         let storage = Storage::open_in_memory().unwrap();
         process_rollout_file(tmp.path(), &storage, None, None).unwrap();
+        let snapshot = |storage: &Storage| -> (Option<String>, String, String, Option<String>) {
+            storage
+                .connection()
+                .query_row(
+                    "SELECT preview, commands_json, search_blob, model FROM conversations",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )
+                .unwrap()
+        };
+        let before = snapshot(&storage);
+
+        storage
+            .connection()
+            .execute(
+                "UPDATE conversations SET preview = NULL, commands_json = '[]', search_blob = ''",
+                [],
+            )
+            .unwrap();
+        assert_eq!(
+            storage
+                .rebuild_derived(&DerivationConfig::default())
+                .unwrap()
+                .rebuilt,
+            1
+        );
+
+        let after = snapshot(&storage);
+        assert_eq!(after, before);
+        assert_eq!(after.1, r#"["cargo"]"#);
+        assert!(after.2.contains("inspecting cargo output"));
+        assert_eq!(after.3.as_deref(), Some("gpt-5"));
+    }
 
-        let count: i64 = storage
+    #[test]
+    fn rebuild_derived_skips_conversations_stored_before_inputs_json() {
+        let rollout = r#"
+{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"urn:uuid:legacy","cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"why does the build fail?"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"reasoning","summary":[{"type":"summary_text","text":"Inspecting Cargo output"}]}}
+"#;
+        let mut tmp = NamedTempFile::new().unwrap();
+        tmp.write_all(rollout.as_bytes()).unwrap();
+        tmp.flush().unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        process_rollout_file(tmp.path(), &storage, None, None).unwrap();
+        // What a turn imported by an older release looks like.
+        storage
             .connection()
-            .query_row("SELECT COUNT(*) FROM turns", [], |row| row.get(0))
+            .execute(
+                "UPDATE turns SET inputs_json = NULL, reasoning_json = NULL",
+                [],
+            )
             .unwrap();
-        assert_eq!(count, 1);
+        let search_blob = |storage: &Storage| -> String {
+            storage
+                .connection()
+                .query_row("SELECT search_blob FROM conversations", [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        let before = search_blob(&storage);
+        assert!(before.contains("inspecting cargo output"));
+
+        let report = storage
+            .rebuild_derived(&DerivationConfig::default())
+            .unwrap();
+        assert_eq!(report.rebuilt, 0);
+        assert_eq!(report.skipped, ["urn:uuid:legacy"]);
+        assert_eq!(search_blob(&storage), before);
     }
 
     #[test]
-    fn pipeline_processes_directory() {
-        let dir = tempdir().unwrap();
-        let nested = dir.path().join("2025/10/01");
-        std::fs::create_dir_all(&nested).unwrap();
-        let file_path = nested.join("rollout-2025-10-01T00-00-00-abc.jsonl");
-        std::fs::write(&file_path, sample_rollout()).unwrap();
+    fn rebuild_derived_matches_ingest_with_injected_context() {
+        let rollout = r#"
+{"timestamp":"2025-01-01T00:00:00.000Z","type":"session_meta","payload":{"id":"urn:uuid:injected","cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"<user_instructions>\nAlways run the tests.\n</user_instructions>"}]}}
+{"timestamp":"2025-01-01T00:00:01.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"<environment_context>\n  <cwd>/repo</cwd>\n</environment_context>"}]}}
+{"timestamp":"2025-01-01T00:00:02.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"why does the build fail?"}]}}
+{"timestamp":"2025-01-01T00:00:03.000Z","type":"response_item","payload":{"type":"message","role":"assistant","content":[{"type":"output_text","text":"A missing feature flag."}]}}
+"#;
+        let mut tmp = NamedTempFile::new().unwrap();
+        tmp.write_all(rollout.as_bytes()).unwrap();
+        tmp.flush().unwrap();
 
         let storage = Storage::open_in_memory().unwrap();
-        let processed = process_rollout_dir(dir.path(), &storage, None).unwrap();
-        assert_eq!(processed, 1);
+        process_rollout_file(tmp.path(), &storage, None, None).unwrap();
+        type Derived = (
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            String,
+        );
+        let snapshot = |storage: &Storage| -> Derived {
+            storage
+                .connection()
+                .query_row(
+                    "SELECT preview, first_question, last_question, questions_json, search_blob \
+                     FROM conversations",
+                    [],
+                    |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                        ))
+                    },
+                )
+                .unwrap()
+        };
+        let ingested = snapshot(&storage);
+        assert_eq!(ingested.1.as_deref(), Some("why does the build fail?"));
+
+        storage
+            .rebuild_derived(&DerivationConfig::default())
+            .unwrap();
+        assert_eq!(snapshot(&storage), ingested);
+    }
 
-        let count: i64 = storage
+    #[test]
+    fn pipeline_config_limits_stored_questions() {
+        let dir = tempdir().unwrap();
+        let second_turn = r#"{"timestamp":"2025-01-01T00:00:03.000Z","type":"turn_context","payload":{"cwd":"/tmp"}}
+{"timestamp":"2025-01-01T00:00:04.000Z","type":"response_item","payload":{"type":"message","role":"user","content":[{"type":"input_text","text":"again"}]}}"#;
+        std::fs::write(
+            dir.path().join("rollout-2025-01-01T00-00-00-abc.jsonl"),
+            format!("{}{second_turn}\n", sample_rollout()),
+        )
+        .unwrap();
+
+        let storage = Storage::open_in_memory().unwrap();
+        let options = IngestOptions {
+            config: PipelineConfig {
+                max_stored_questions: 1,
+                ..PipelineConfig::default()
+            },
+            ..IngestOptions::default()
+        };
+        process_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+        let questions: String = storage
             .connection()
-            .query_row("SELECT COUNT(*) FROM turns", [], |row| row.get(0))
+            .query_row("SELECT questions_json FROM conversations", [], |row| {
+                row.get(0)
+            })
             .unwrap();
-        assert_eq!(count, 1);
+        assert_eq!(questions, r#"["again"]"#);
     }
 
     #[test]
-    fn update_dir_skips_unchanged_and_refreshes_modified_files() {
+    fn change_detection_falls_back_to_or_requires_content_hashes() {
         let dir = tempdir().unwrap();
-        let file_path = dir.path().join("rollout-2025-10-01T00-00-00-abc.jsonl");
-        std::fs::write(&file_path, sample_rollout()).unwrap();
+        let path = dir.path().join("rollout-2025-01-01T00-00-00-abc.jsonl");
+        std::fs::write(&path, sample_rollout()).unwrap();
+        let storage = Storage::open_in_memory().unwrap();
+        let set_mtime = |secs: u64| {
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        let update = |change_detection| {
+            let options = IngestOptions {
+                change_detection,
+                ..IngestOptions::default()
+            };
+            let stats = update_rollout_dir_with(dir.path(), &storage, None, &options).unwrap();
+            (stats.processed, stats.skipped)
+        };
+        set_mtime(1_000);
+        assert_eq!(update(ChangeDetection::Metadata), (1, 0));
+
+        // Touched: the content hash still matches and the new time is recorded.
+        set_mtime(2_000);
+        assert_eq!(update(ChangeDetection::HashOnMismatch), (0, 1));
+        assert_eq!(update(ChangeDetection::Metadata), (0, 1));
+
+        // Same size and time, different content: only a required hash notices.
+        std::fs::write(&path, sample_rollout_with_assistant("hi where")).unwrap();
+        set_mtime(2_000);
+        assert_eq!(update(ChangeDetection::HashOnMismatch), (0, 1));
+        assert_eq!(update(ChangeDetection::Hash), (1, 0));
+        assert_eq!(update(ChangeDetection::Hash), (0, 1));
+    }
+
+    #[test]
+    fn copies_of_a_rollout_are_imported_once() {
+        let dir = tempdir().unwrap();
+        let original = dir.path().join("rollout-2025-01-01T00-00-00-a.jsonl");
+        let copy = dir.path().join("rollout-2025-01-01T00-00-00-b.jsonl");
+        std::fs::write(&original, sample_rollout()).unwrap();
+        std::fs::write(&copy, sample_rollout()).unwrap();
+        std::fs::write(
+            dir.path().join("rollout-2025-01-01T00-00-00-c.jsonl"),
+            sample_rollout().replace("urn:uuid:test", "urn:uuid:other"),
+        )
+        .unwrap();
 
         let storage = Storage::open_in_memory().unwrap();
-        let processed = process_rollout_dir(dir.path(), &storage, None).unwrap();
-        assert_eq!(processed, 1);
+        let stats = process_rollout_dir_with(dir.path(), &storage, None, &IngestOptions::default())
+            .unwrap();
+        assert_eq!(stats.processed, 2);
+        let duplicate = DuplicateRollout {
+            path: copy.clone(),
+            original: original.clone(),
+        };
+        assert_eq!(stats.duplicates, vec![duplicate.clone()]);
 
         let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
-        assert_eq!(stats.processed, 0);
-        assert_eq!(stats.skipped, 1);
+        assert_eq!((stats.processed, stats.skipped), (0, 2));
+        assert_eq!(stats.duplicates, vec![duplicate]);
+        let conversations: i64 = storage
+            .connection()
+            .query_row("SELECT COUNT(*) FROM conversations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(conversations, 2);
+    }
 
-        std::thread::sleep(Duration::from_millis(1100));
+    #[test]
+    fn ingests_several_roots_with_combined_stats() {
+        let sessions = tempdir().unwrap();
+        let archive = tempdir().unwrap();
+        let nested = sessions.path().join("2025");
+        std::fs::create_dir(&nested).unwrap();
         std::fs::write(
-            &file_path,
-            sample_rollout_with_assistant("updated response"),
+            nested.join("rollout-2025-01-01T00-00-00-a.jsonl"),
+            sample_rollout(),
+        )
+        .unwrap();
+        let archived = archive.path().join("rollout-2025-01-01T00-00-00-b.jsonl");
+        std::fs::write(
+            &archived,
+            sample_rollout().replace("urn:uuid:test", "urn:uuid:other"),
         )
         .unwrap();
 
-        let stats = update_rollout_dir(dir.path(), &storage, None).unwrap();
-        assert_eq!(stats.processed, 1);
-        assert_eq!(stats.skipped, 0);
+        let storage = Storage::open_in_memory().unwrap();
+        let roots = [sessions.path(), nested.as_path(), archive.path()];
+        let stats =
+            process_rollout_dirs_with(&roots, &storage, None, &IngestOptions::default()).unwrap();
+        assert_eq!(stats.processed, 2);
+
+        std::fs::remove_file(&archived).unwrap();
+        let options = IngestOptions {
+            remove_deleted: true,
+            ..IngestOptions::default()
+        };
+        let stats = update_rollout_dirs_with(&roots, &storage, None, &options).unwrap();
+        assert_eq!((stats.skipped, stats.removed), (1, 1));
+    }
 
-        let assistant: String = storage
-            .connection()
-            .query_row(
-                "SELECT assistant_text FROM turns WHERE conversation_id = (SELECT id FROM conversations LIMIT 1)",
-                [],
-                |row| row.get::<_, Option<String>>(0),
-            )
-            .unwrap()
-            .unwrap();
-        assert!(assistant.contains("updated response"));
+    #[test]
+    fn staged_pipeline_keeps_order_and_stops_at_the_first_failure() {
+        let items: Vec<usize> = (0..50).collect();
+        let mut seen = Vec::new();
+        run_stages(
+            &items,
+            1,
+            |n| n * 2,
+            |_, n| n + 1,
+            |_, n| {
+                seen.push(n);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(seen, (0..50).map(|n| n * 2 + 1).collect::<Vec<_>>());
+
+        let mut consumed = 0;
+        let err = run_stages(
+            &items,
+            4,
+            |n| *n,
+            |_, n| n,
+            |item, _| {
+                consumed += 1;
+                match consumed {
+                    3 => Err(PipelineError::io(
+                        Path::new(&item.to_string()),
+                        io::Error::other("stop"),
+                    )),
+                    _ => Ok(()),
+                }
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, PipelineError::Io { .. }));
+        assert_eq!(consumed, 3);
     }
 }
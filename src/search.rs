@@ -1,26 +1,178 @@
-use bytemuck::cast_slice;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::io::Write;
+
+use regex::Regex;
 use rusqlite::types::Value as SqlValue;
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::embedding::{EmbeddingError, EmbeddingModel};
-use crate::storage::Storage;
+use crate::filter::{Column, Filter, IntRange};
+use crate::stats::normalize_git_repo;
+use crate::storage::{
+    decode_embedding, l2_norm, line_range, sign_bits, Annotation, Storage, StorageError,
+    DEFAULT_EMBEDDING_MODEL,
+};
+use crate::types::{ActionRecord, Plan, TurnTelemetry};
+use crate::vector_index::VectorIndex;
+
+/// Error type returned by [`Reranker`] implementations.
+pub type RerankError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Hook for re-ordering the prefetched candidates (e.g. with a cross-encoder or an LLM) before
+/// they are truncated to [`SearchParams::limit`].
+pub trait Reranker {
+    /// Reorder, rescore or drop `candidates` in place. They arrive sorted by cosine score, with
+    /// their user/assistant text attached; `query` is the search text when one is known.
+    fn rerank(
+        &self,
+        query: Option<&str>,
+        candidates: &mut Vec<SearchResult>,
+    ) -> Result<(), RerankError>;
+}
 
 /// Parameters describing the metadata filters and limits applied to a search.
+#[derive(Clone)]
 pub struct SearchParams<'a> {
-    pub meta_equals: Vec<(&'a str, &'a str)>,
+    /// Typed filter over conversation columns and metadata keys.
+    pub filter: Option<Filter<'a>>,
+    /// Only match conversations whose turn count falls in this range (e.g. `(20..).into()`).
+    pub turn_count: Option<IntRange>,
+    /// Only match conversations whose duration in seconds falls in this range.
+    pub duration_seconds: Option<IntRange>,
+    /// Only match conversations with (`true`) or without (`false`) live events.
+    pub has_live_events: Option<bool>,
+    /// Only match conversations that did (`true`) or did not (`false`) request exec /
+    /// apply_patch approval, i.e. sessions that needed escalated permissions.
+    pub has_approvals: Option<bool>,
+    /// Only match conversations with (`true`) or without (`false`) a command that exited with
+    /// a non-zero status.
+    pub has_failed_commands: Option<bool>,
+    /// Only match conversations that did (`true`) or did not (`false`) have a turn fail with
+    /// an error or get aborted.
+    pub had_errors: Option<bool>,
+    /// Only match pinned (`true`) or unpinned (`false`) conversations (see
+    /// [`Storage::set_pinned`]).
+    pub pinned: Option<bool>,
+    /// Added to the score of every candidate from a pinned conversation, so canonical sessions
+    /// rank ahead of similar matches (e.g. `0.1`). Not applied by [`search_iter`].
+    pub pinned_boost: f32,
     pub conversation_ids: Vec<&'a str>,
+    /// Only match conversations whose `model` column equals this value.
+    pub model: Option<&'a str>,
+    /// Only match conversations whose `cwd` starts with this prefix (e.g. a repository root).
+    pub cwd_prefix: Option<&'a str>,
+    /// Only match conversations in this git repository. Any remote URL form works
+    /// (`git@github.com:org/repo.git`, `https://github.com/org/repo`); both sides are compared
+    /// as `github.com/org/repo`.
+    pub git_repo: Option<&'a str>,
+    /// Only match conversations on this git branch.
+    pub git_branch: Option<&'a str>,
+    /// Only match conversations that ran at least one of these commands (e.g. `cargo`, `kubectl`).
+    pub commands_any: Vec<&'a str>,
+    /// Only match conversations that ran every one of these commands.
+    pub commands_all: Vec<&'a str>,
+    /// Only match conversations that edited a file matching this pattern. Patterns containing
+    /// `*`, `?` or `[` are SQLite GLOBs (`*src/*.rs`); anything else is a substring match.
+    pub files_touched_glob: Option<&'a str>,
+    /// Only match conversations that linked or opened a URL containing this text
+    /// (case-insensitive), e.g. `docs.rs/tokio`.
+    pub url_contains: Option<&'a str>,
     pub limit: usize,
+    /// Number of ranked results to skip before returning `limit` of them (for paging).
+    pub offset: usize,
+    /// Candidates scored before ranking and paging; defaults to [`DEFAULT_PREFETCH`] or `limit`,
+    /// whichever is larger. It does not depend on `offset`, so every page ranks the same
+    /// candidates.
     pub prefetch: Option<usize>,
+    /// Bump hit counters / `last_accessed_at` for returned turns (off by default since it writes).
+    pub record_access: bool,
+    /// Optional second-stage ranking applied to all prefetched candidates.
+    pub reranker: Option<&'a dyn Reranker>,
+    /// Populate [`SearchResult::details`] for the returned results.
+    pub include_details: bool,
+    /// In-memory index used to pick the `prefetch` candidates instead of scanning the database.
+    pub vector_index: Option<&'a VectorIndex>,
+    /// Two-stage search: rank every matching turn by Hamming distance over its sign-bit
+    /// quantized embedding, then rescore the closest `prefetch` with full-precision vectors.
+    pub quantized: bool,
+    /// Examples to steer away from ("deployment scripts but not kubernetes"): each candidate's
+    /// score is reduced by `negative_weight` times its highest similarity to any of them.
+    /// Embed negative texts with [`EmbeddingModel::embed`] first.
+    pub negative_vectors: Vec<&'a [f32]>,
+    pub negative_weight: f32,
+    /// Hybrid scoring for text queries ([`search_with_text`]): adds a keyword-match bonus
+    /// weighted separately for the user and assistant text of each candidate.
+    pub field_weights: Option<FieldWeights>,
+    /// Collapse results whose embeddings have at least this cosine similarity to a
+    /// higher-scoring result (e.g. `0.98` for re-runs of the same prompt across sessions).
+    pub dedupe_threshold: Option<f32>,
+    /// Only keep candidates whose user or assistant text contains every one of these exact
+    /// (case-sensitive) phrases, e.g. `PipelineError::WalkDir`.
+    pub must_contain: Vec<&'a str>,
+    /// Only keep candidates whose user or assistant text matches this regular expression.
+    pub must_match_regex: Option<&'a str>,
+    /// Fail with [`SearchError::DimensionMismatch`] when a matching conversation was embedded
+    /// with a different dimension than the query, instead of silently skipping its turns.
+    pub strict_dimension: bool,
+    /// Embedding space to search (see [`Storage::insert_embedding`]); defaults to
+    /// [`DEFAULT_EMBEDDING_MODEL`]. `vector_index` and the `sqlite-vec` index only cover the
+    /// default space and are ignored for others.
+    pub embedding_model: Option<&'a str>,
+}
+
+/// Per-field weights of the keyword bonus enabled by [`SearchParams::field_weights`].
+///
+/// Each field contributes `weight * matched / total` query terms (case-insensitive substring
+/// match) on top of the cosine score, so `FieldWeights { user: 0.3, assistant: 0.1 }` ranks
+/// "what did I ask about tokio channels" prompts above assistant boilerplate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldWeights {
+    pub user: f32,
+    pub assistant: f32,
 }
 
 impl<'a> SearchParams<'a> {
     /// Create a new parameter set with a desired result limit.
     pub fn new(limit: usize) -> Self {
         Self {
-            meta_equals: Vec::new(),
+            filter: None,
+            turn_count: None,
+            duration_seconds: None,
+            has_live_events: None,
+            has_approvals: None,
+            has_failed_commands: None,
+            had_errors: None,
+            pinned: None,
+            pinned_boost: 0.0,
             conversation_ids: Vec::new(),
+            model: None,
+            cwd_prefix: None,
+            git_repo: None,
+            git_branch: None,
+            commands_any: Vec::new(),
+            commands_all: Vec::new(),
+            files_touched_glob: None,
+            url_contains: None,
             limit,
+            offset: 0,
             prefetch: None,
+            record_access: false,
+            reranker: None,
+            include_details: false,
+            vector_index: None,
+            quantized: false,
+            negative_vectors: Vec::new(),
+            negative_weight: 1.0,
+            field_weights: None,
+            dedupe_threshold: None,
+            must_contain: Vec::new(),
+            must_match_regex: None,
+            strict_dimension: false,
+            embedding_model: None,
         }
     }
 }
@@ -32,13 +184,121 @@ impl<'a> Default for SearchParams<'a> {
 }
 
 /// Result row returned by a semantic search.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub conversation_id: String,
     pub turn_index: usize,
     pub score: f32,
     pub user_text: Option<String>,
     pub assistant_text: Option<String>,
+    /// Turn and conversation context, present when [`SearchParams::include_details`] is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<SearchResultDetails>,
+    /// Notes left on this turn or its conversation (see [`Storage::add_annotation`]). Filled by
+    /// ranked searches; [`search_iter`] leaves it empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+}
+
+/// Extra context loaded for each returned hit when [`SearchParams::include_details`] is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResultDetails {
+    /// Turn start timestamp, as stored.
+    pub started_at: Option<String>,
+    /// First and last line of `rollout_path` the turn was parsed from (1-based, inclusive).
+    pub source_lines: Option<(usize, usize)>,
+    pub actions: Vec<ActionRecord>,
+    /// Rollout file, resolved against [`Storage::rollout_root`] when stored relative.
+    pub rollout_path: String,
+    /// Custom title set with [`Storage::set_title`].
+    pub title: Option<String>,
+    /// Title derived from the first question or request at import.
+    pub auto_title: Option<String>,
+    pub preview: Option<String>,
+    /// Abstract written by the [`Summarizer`](crate::Summarizer) at import.
+    pub summary: Option<String>,
+    pub model: Option<String>,
+    pub cwd: Option<String>,
+    pub git_repo: Option<String>,
+    pub git_branch: Option<String>,
+    /// The plan as the turn left it, to set against its `actions`.
+    pub plan: Option<Plan>,
+    /// The conversation's last plan (see
+    /// [`ConversationStats::last_plan`](crate::ConversationStats::last_plan)).
+    pub last_plan: Option<Plan>,
+}
+
+/// Number of distinct candidate conversations sharing one facet value (`None` when unset).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub value: Option<String>,
+    pub count: usize,
+}
+
+/// Facet counts over the scored candidate set, most common values first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchFacets {
+    pub by_model: Vec<FacetCount>,
+    pub by_cwd: Vec<FacetCount>,
+    /// Conversation start month as `YYYY-MM`.
+    pub by_month: Vec<FacetCount>,
+}
+
+/// Output of [`search_with_facets`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FacetedResults {
+    pub results: Vec<SearchResult>,
+    pub facets: SearchFacets,
+}
+
+#[derive(Default)]
+struct FacetCounter {
+    seen: HashSet<String>,
+    model: HashMap<Option<String>, usize>,
+    cwd: HashMap<Option<String>, usize>,
+    month: HashMap<Option<String>, usize>,
+}
+
+impl FacetCounter {
+    fn add(
+        &mut self,
+        conversation_id: &str,
+        model: Option<String>,
+        cwd: Option<String>,
+        month: Option<String>,
+    ) {
+        if self.seen.contains(conversation_id) {
+            return;
+        }
+        self.seen.insert(conversation_id.to_string());
+        *self.model.entry(model).or_default() += 1;
+        *self.cwd.entry(cwd).or_default() += 1;
+        *self.month.entry(month).or_default() += 1;
+    }
+
+    fn finish(self) -> SearchFacets {
+        fn sorted(counts: HashMap<Option<String>, usize>) -> Vec<FacetCount> {
+            let mut counts: Vec<FacetCount> = counts
+                .into_iter()
+                .map(|(value, count)| FacetCount { value, count })
+                .collect();
+            counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+            counts
+        }
+        SearchFacets {
+            by_model: sorted(self.model),
+            by_cwd: sorted(self.cwd),
+            by_month: sorted(self.month),
+        }
+    }
+}
+
+/// Conversation returned by [`find_similar_conversations`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarConversation {
+    pub conversation_id: String,
+    /// Cosine similarity between the two conversations' turn-embedding centroids.
+    pub score: f32,
 }
 
 /// Errors produced while executing a search.
@@ -50,17 +310,57 @@ pub enum SearchError {
     InvalidMetaKey(String),
     #[error("embedding error: {0}")]
     Embedding(EmbeddingError),
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+    #[error("reranker failed: {0}")]
+    Rerank(RerankError),
+    #[error("failed to decode stored turn: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid text filter regex: {0}")]
+    InvalidRegex(#[from] regex::Error),
+    #[error(
+        "query has {query} dimensions but {conversations} matching conversation(s) were \
+         embedded with {stored}; re-embed them or search with the original model"
+    )]
+    DimensionMismatch {
+        query: usize,
+        stored: usize,
+        conversations: usize,
+    },
+    #[error("turn {conversation_id}#{turn_index} has no stored embedding")]
+    MissingEmbedding {
+        conversation_id: String,
+        turn_index: usize,
+    },
+}
+
+/// Write `items` (e.g. [`SearchResult`]s) to `writer` as JSON Lines, one object per line.
+pub fn write_json_lines<T: Serialize>(
+    mut writer: impl Write,
+    items: &[T],
+) -> Result<(), SearchError> {
+    for item in items {
+        serde_json::to_writer(&mut writer, item)?;
+        writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+    }
+    writer.flush().map_err(serde_json::Error::io)?;
+    Ok(())
 }
 
-/// Perform a semantic search by first generating an embedding for `text`.
+/// Perform a semantic search by first generating an embedding for `text`. When searching the
+/// default space, fails with [`StorageError::EmbeddingModelMismatch`] if `embedder` is not the
+/// model recorded when the database was embedded.
 pub fn search_with_text(
     storage: &Storage,
     embedder: &EmbeddingModel,
     text: &str,
     params: &SearchParams<'_>,
 ) -> Result<Vec<SearchResult>, SearchError> {
+    if embedding_space(params) == DEFAULT_EMBEDDING_MODEL {
+        storage.check_embedding_model(embedder.info())?;
+    }
     let query_vector = embedder.embed(text).map_err(SearchError::Embedding)?;
-    search_with_vector(storage, &query_vector, params)
+    run_search(storage, &query_vector, Some(text), None, None, params)
 }
 
 /// Perform a semantic search using a pre-computed query vector.
@@ -69,45 +369,373 @@ pub fn search_with_vector(
     query_vector: &[f32],
     params: &SearchParams<'_>,
 ) -> Result<Vec<SearchResult>, SearchError> {
-    if query_vector.is_empty() || params.limit == 0 {
-        return Ok(Vec::new());
-    }
+    run_search(storage, query_vector, None, None, None, params)
+}
+
+/// Rows fetched per query while a [`SearchIter`] streams candidates.
+const SEARCH_ITER_BATCH: usize = 256;
 
+/// Candidates scored per search when [`SearchParams::prefetch`] is not set.
+pub const DEFAULT_PREFETCH: usize = 512;
+
+/// The candidate window of a search: [`SearchParams::prefetch`], or [`DEFAULT_PREFETCH`] grown
+/// to `limit`. Independent of `offset` so that pages slice one ranking.
+fn prefetch_size(params: &SearchParams<'_>) -> usize {
+    params
+        .prefetch
+        .unwrap_or_else(|| DEFAULT_PREFETCH.max(params.limit))
+}
+
+/// Lazily score candidates for `query_vector`, yielding each match as soon as it is scored.
+///
+/// Unlike [`search_with_vector`] the results are not ranked: they arrive in the same
+/// primary-key order the brute-force scan reads them in, so callers can stop early or render
+/// progressive results and keep their own top-k. The scan covers the same filtered `prefetch`
+/// window; `offset`, `reranker`, `vector_index`, `quantized`, `include_details` and
+/// `record_access` are ignored.
+pub fn search_iter<'a>(
+    storage: &'a Storage,
+    query_vector: &'a [f32],
+    params: &SearchParams<'a>,
+) -> Result<SearchIter<'a>, SearchError> {
     let mut sql = String::from(
-        "SELECT t.conversation_id, t.turn_index, t.user_text, t.assistant_text, t.embedding \
-         FROM turns t \
-         JOIN conversations c ON c.id = t.conversation_id \
-         WHERE t.embedding IS NOT NULL",
+        "SELECT t.conversation_id, t.turn_index, t.user_text, t.assistant_text, e.vector, e.norm",
     );
-    let mut values: Vec<SqlValue> = Vec::new();
+    sql.push_str(CANDIDATES_FROM);
+    let mut values = vec![SqlValue::from(embedding_space(params).to_string())];
+    push_filters(&mut sql, &mut values, params)?;
 
-    if !params.conversation_ids.is_empty() {
-        sql.push_str(" AND t.conversation_id IN (");
-        for (idx, _) in params.conversation_ids.iter().enumerate() {
-            if idx > 0 {
-                sql.push_str(", ");
+    let remaining = prefetch_size(params);
+    let scorer = Scorer::new(query_vector, params);
+    Ok(SearchIter {
+        storage,
+        text_filter: TextFilter::new(params)?,
+        done: scorer.is_none() || remaining == 0,
+        scorer,
+        sql,
+        values,
+        remaining,
+        last_key: None,
+        buffer: VecDeque::new(),
+    })
+}
+
+/// Iterator returned by [`search_iter`].
+pub struct SearchIter<'a> {
+    storage: &'a Storage,
+    scorer: Option<Scorer<'a>>,
+    text_filter: Option<TextFilter<'a>>,
+    sql: String,
+    values: Vec<SqlValue>,
+    remaining: usize,
+    last_key: Option<(String, i64)>,
+    buffer: VecDeque<SearchResult>,
+    done: bool,
+}
+
+impl SearchIter<'_> {
+    /// Read the next batch of candidates (keyset-paginated on the primary key) into `buffer`.
+    fn fetch_batch(&mut self) -> Result<(), SearchError> {
+        let Some(scorer) = &self.scorer else {
+            self.done = true;
+            return Ok(());
+        };
+        let batch = self.remaining.min(SEARCH_ITER_BATCH);
+        let mut sql = self.sql.clone();
+        let mut values = self.values.clone();
+        if let Some((conversation_id, turn_index)) = &self.last_key {
+            sql.push_str(" AND (t.conversation_id, t.turn_index) > (?, ?)");
+            values.push(SqlValue::from(conversation_id.clone()));
+            values.push(SqlValue::from(*turn_index));
+        }
+        sql.push_str(" ORDER BY t.conversation_id, t.turn_index LIMIT ?");
+        values.push(SqlValue::from(batch as i64));
+
+        let mut stmt = self.storage.connection().prepare(&sql)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(values.iter()))?;
+        let mut fetched = 0;
+        while let Some(row) = rows.next()? {
+            fetched += 1;
+            let conversation_id: String = row.get(0)?;
+            let turn_index: i64 = row.get(1)?;
+            self.last_key = Some((conversation_id.clone(), turn_index));
+            if turn_index < 0 {
+                continue;
             }
-            sql.push('?');
+            let embedding_blob: Vec<u8> = row.get(4)?;
+            let candidate_norm = row.get::<_, Option<f64>>(5)?.map(|norm| norm as f32);
+            let Some(score) = scorer.score(&embedding_blob, candidate_norm) else {
+                continue;
+            };
+            let user_text: Option<String> = row.get(2)?;
+            let assistant_text: Option<String> = row.get(3)?;
+            if let Some(filter) = &self.text_filter {
+                if !filter.matches(user_text.as_deref(), assistant_text.as_deref()) {
+                    continue;
+                }
+            }
+            self.buffer.push_back(SearchResult {
+                conversation_id,
+                turn_index: turn_index as usize,
+                score,
+                user_text,
+                assistant_text,
+                details: None,
+                annotations: Vec::new(),
+            });
         }
-        sql.push(')');
-        for id in &params.conversation_ids {
-            values.push(SqlValue::from((*id).to_string()));
+        self.remaining -= fetched;
+        if fetched < batch || self.remaining == 0 {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for SearchIter<'_> {
+    type Item = Result<SearchResult, SearchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.is_empty() && !self.done {
+            if let Err(err) = self.fetch_batch() {
+                self.done = true;
+                return Some(Err(err));
+            }
         }
+        self.buffer.pop_front().map(Ok)
     }
+}
+
+/// Like [`search_with_vector`], additionally counting the distinct conversations behind every
+/// scored candidate by model, cwd and start month so UIs can render filters next to results.
+pub fn search_with_facets(
+    storage: &Storage,
+    query_vector: &[f32],
+    params: &SearchParams<'_>,
+) -> Result<FacetedResults, SearchError> {
+    let mut counter = FacetCounter::default();
+    let results = run_search(
+        storage,
+        query_vector,
+        None,
+        None,
+        Some(&mut counter),
+        params,
+    )?;
+    Ok(FacetedResults {
+        results,
+        facets: counter.finish(),
+    })
+}
+
+/// Search using the stored embedding of an existing turn as the query ("more like this
+/// exchange"), without re-running the embedder. The source turn itself is not returned.
+pub fn search_like_turn(
+    storage: &Storage,
+    conversation_id: &str,
+    turn_index: usize,
+    params: &SearchParams<'_>,
+) -> Result<Vec<SearchResult>, SearchError> {
+    let blob: Option<(Vec<u8>, i64)> = storage
+        .connection()
+        .query_row(
+            "SELECT vector, dim FROM embeddings \
+             WHERE conversation_id = ?1 AND turn_index = ?2 AND model_name = ?3",
+            rusqlite::params![conversation_id, turn_index as i64, embedding_space(params)],
+            |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .optional()?;
+    let query_vector = blob
+        .and_then(|(blob, dim)| decode_embedding(&blob, dim as usize).map(Cow::into_owned))
+        .ok_or_else(|| SearchError::MissingEmbedding {
+            conversation_id: conversation_id.to_string(),
+            turn_index,
+        })?;
+    run_search(
+        storage,
+        &query_vector,
+        None,
+        Some((conversation_id, turn_index)),
+        None,
+        params,
+    )
+}
+
+/// Return the `limit` conversations whose turn-embedding centroid is closest to that of
+/// `conversation_id` ("more like this"). Returns nothing when the conversation has no embedded
+/// turns; conversations embedded with a different dimension are skipped.
+pub fn find_similar_conversations(
+    storage: &Storage,
+    conversation_id: &str,
+    limit: usize,
+) -> Result<Vec<SimilarConversation>, SearchError> {
+    let conn = storage.connection();
+    let mut stmt = conn.prepare(
+        "SELECT conversation_id, vector, dim FROM embeddings \
+         WHERE model_name = ?1 ORDER BY conversation_id, turn_index",
+    )?;
+    let mut rows = stmt.query([DEFAULT_EMBEDDING_MODEL])?;
 
-    for (key, value) in &params.meta_equals {
-        ensure_valid_meta_key(key)?;
-        sql.push_str(" AND json_extract(c.meta_json, '$.");
-        sql.push_str(key);
-        sql.push_str("') = ?");
-        values.push(SqlValue::from((*value).to_string()));
+    let mut target: Option<Vec<f32>> = None;
+    let mut others: Vec<(String, Vec<f32>)> = Vec::new();
+    let mut current: Option<(String, Centroid)> = None;
+    while let Some(row) = rows.next()? {
+        let id: String = row.get(0)?;
+        let blob: Vec<u8> = row.get(1)?;
+        let dim: i64 = row.get(2)?;
+        if current
+            .as_ref()
+            .is_none_or(|(current_id, _)| *current_id != id)
+        {
+            if let Some((done_id, centroid)) = current.take() {
+                finish_centroid(conversation_id, done_id, centroid, &mut target, &mut others);
+            }
+            current = Some((id, Centroid::default()));
+        }
+        if let (Some((_, centroid)), Some(vector)) =
+            (current.as_mut(), decode_embedding(&blob, dim as usize))
+        {
+            centroid.add(&vector);
+        }
+    }
+    if let Some((done_id, centroid)) = current.take() {
+        finish_centroid(conversation_id, done_id, centroid, &mut target, &mut others);
     }
 
-    let prefetch = params
-        .prefetch
-        .unwrap_or_else(|| params.limit.saturating_mul(8).max(params.limit));
-    sql.push_str(" LIMIT ?");
-    values.push(SqlValue::from(prefetch as i64));
+    let Some(target) = target else {
+        return Ok(Vec::new());
+    };
+    let target_norm = l2_norm(&target);
+    let mut similar: Vec<SimilarConversation> = others
+        .into_iter()
+        .filter(|(_, centroid)| centroid.len() == target.len())
+        .map(|(conversation_id, centroid)| SimilarConversation {
+            score: cosine_similarity(&target, target_norm, &centroid, None),
+            conversation_id,
+        })
+        .filter(|similar| similar.score.is_finite())
+        .collect();
+    similar.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.conversation_id.cmp(&b.conversation_id))
+    });
+    similar.truncate(limit);
+    Ok(similar)
+}
+
+/// Running mean of a conversation's unit-length turn embeddings.
+#[derive(Default)]
+struct Centroid {
+    sum: Vec<f32>,
+    count: usize,
+}
+
+impl Centroid {
+    fn add(&mut self, vector: &[f32]) {
+        let norm = l2_norm(vector);
+        if norm == 0.0 {
+            return;
+        }
+        if self.count == 0 {
+            self.sum = vec![0.0; vector.len()];
+        } else if self.sum.len() != vector.len() {
+            return;
+        }
+        for (sum, value) in self.sum.iter_mut().zip(vector) {
+            *sum += value / norm;
+        }
+        self.count += 1;
+    }
+}
+
+fn finish_centroid(
+    target_id: &str,
+    id: String,
+    centroid: Centroid,
+    target: &mut Option<Vec<f32>>,
+    others: &mut Vec<(String, Vec<f32>)>,
+) {
+    if centroid.count == 0 {
+        return;
+    }
+    if id == target_id {
+        *target = Some(centroid.sum);
+    } else {
+        others.push((id, centroid.sum));
+    }
+}
+
+fn run_search(
+    storage: &Storage,
+    query_vector: &[f32],
+    query_text: Option<&str>,
+    exclude_turn: Option<(&str, usize)>,
+    mut facets: Option<&mut FacetCounter>,
+    params: &SearchParams<'_>,
+) -> Result<Vec<SearchResult>, SearchError> {
+    if query_vector.is_empty() || params.limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    if params.strict_dimension {
+        check_dimension(storage, query_vector.len(), params)?;
+    }
+
+    let mut sql = String::from(
+        "SELECT t.conversation_id, t.turn_index, t.user_text, t.assistant_text, e.vector, e.norm, \
+         c.model, c.cwd, substr(c.started_at, 1, 7), c.pinned",
+    );
+    sql.push_str(CANDIDATES_FROM);
+    let space = embedding_space(params);
+    let mut values = vec![SqlValue::from(space.to_string())];
+
+    push_filters(&mut sql, &mut values, params)?;
+    if let Some((conversation_id, turn_index)) = exclude_turn {
+        sql.push_str(" AND NOT (t.conversation_id = ? AND t.turn_index = ?)");
+        values.push(SqlValue::from(conversation_id.to_string()));
+        values.push(SqlValue::from(turn_index as i64));
+    }
+
+    // Candidates are read in primary-key order and their number does not depend on `offset`,
+    // so repeated queries (and therefore pages) see the same prefetch window.
+    let window = params.offset.saturating_add(params.limit);
+    let prefetch = prefetch_size(params);
+    let default_space = space == DEFAULT_EMBEDDING_MODEL;
+    if let Some(index) = params
+        .vector_index
+        .filter(|index| default_space && index.dimension() == query_vector.len())
+    {
+        let candidates = index.search(query_vector, prefetch);
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Bound as one JSON array of `[conversation_id, turn_index]` pairs, like the quantized
+        // candidates below, to stay clear of SQLite's bound parameter limit.
+        let pairs: Vec<(String, usize)> = candidates
+            .into_iter()
+            .map(|hit| (hit.conversation_id, hit.turn_index))
+            .collect();
+        sql.push_str(
+            " AND (t.conversation_id, t.turn_index) IN \
+             (SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]') FROM json_each(?))",
+        );
+        values.push(SqlValue::from(serde_json::to_string(&pairs)?));
+    } else if params.quantized {
+        let rowids = hamming_candidates(storage, query_vector, params, prefetch)?;
+        if rowids.is_empty() {
+            return Ok(Vec::new());
+        }
+        // One JSON array instead of a parameter per rowid, which would run into SQLite's bound
+        // parameter limit for large prefetch windows.
+        sql.push_str(" AND t.rowid IN (SELECT value FROM json_each(?))");
+        values.push(SqlValue::from(serde_json::to_string(&rowids)?));
+    } else if !(default_space
+        && push_knn_filter(storage, &mut sql, &mut values, query_vector, prefetch)?)
+    {
+        sql.push_str(" ORDER BY t.conversation_id, t.turn_index LIMIT ?");
+        values.push(SqlValue::from(prefetch as i64));
+    }
 
     let conn = storage.connection();
     let mut stmt = conn.prepare(&sql)?;
@@ -115,12 +743,23 @@ pub fn search_with_vector(
         values.iter().map(|v| v as &dyn rusqlite::ToSql).collect();
     let mut rows = stmt.query(params_refs.as_slice())?;
 
-    let query_norm = l2_norm(query_vector);
-    if query_norm == 0.0 {
+    let Some(scorer) = Scorer::new(query_vector, params) else {
         return Ok(Vec::new());
-    }
+    };
+    let text_filter = TextFilter::new(params)?;
+    let keywords = params
+        .field_weights
+        .zip(query_text.map(keyword_terms))
+        .filter(|(_, terms)| !terms.is_empty());
 
-    let mut results: Vec<SearchResult> = Vec::new();
+    // Keep only the best `capacity` candidates while streaming; a reranker sees the whole
+    // prefetch window, and so does deduplication, which may collapse part of it.
+    let capacity = if params.reranker.is_some() || params.dedupe_threshold.is_some() {
+        prefetch
+    } else {
+        window
+    };
+    let mut heap: BinaryHeap<Ranked> = BinaryHeap::with_capacity(capacity.min(1024) + 1);
 
     while let Some(row) = rows.next()? {
         let conversation_id: String = row.get(0)?;
@@ -128,172 +767,1846 @@ pub fn search_with_vector(
         if turn_index < 0 {
             continue;
         }
-        let user_text: Option<String> = row.get(2)?;
-        let assistant_text: Option<String> = row.get(3)?;
+        let turn_index = turn_index as usize;
         let embedding_blob: Vec<u8> = row.get(4)?;
-        if embedding_blob.is_empty() || embedding_blob.len() % std::mem::size_of::<f32>() != 0 {
+        let candidate_norm = row.get::<_, Option<f64>>(5)?.map(|norm| norm as f32);
+        let Some(mut score) = scorer.score(&embedding_blob, candidate_norm) else {
             continue;
+        };
+        if params.pinned_boost != 0.0 && row.get::<_, bool>(9)? {
+            score += params.pinned_boost;
         }
-        let embedding: Vec<f32> = cast_slice::<u8, f32>(&embedding_blob).to_vec();
-        if embedding.len() != query_vector.len() {
-            continue;
+        // Texts are only decoded up front when they filter or contribute to the score.
+        let mut texts = None;
+        if keywords.is_some() || text_filter.is_some() {
+            let user_text: Option<String> = row.get(2)?;
+            let assistant_text: Option<String> = row.get(3)?;
+            if let Some(filter) = &text_filter {
+                if !filter.matches(user_text.as_deref(), assistant_text.as_deref()) {
+                    continue;
+                }
+            }
+            if let Some((weights, terms)) = &keywords {
+                score += weights.user * keyword_match(terms, user_text.as_deref())
+                    + weights.assistant * keyword_match(terms, assistant_text.as_deref());
+            }
+            texts = Some((user_text, assistant_text));
         }
-        let score = cosine_similarity(query_vector, query_norm, &embedding);
-        if !score.is_finite() {
-            continue;
+        if let Some(counter) = facets.as_deref_mut() {
+            counter.add(&conversation_id, row.get(6)?, row.get(7)?, row.get(8)?);
         }
-        results.push(SearchResult {
-            conversation_id,
-            turn_index: turn_index as usize,
-            score,
-            user_text,
-            assistant_text,
-        });
+        if heap.len() >= capacity {
+            let worst = heap.peek().expect("capacity is non-zero");
+            if compare_rank((score, &conversation_id, turn_index), worst.0.rank_key()).is_ge() {
+                continue;
+            }
+            heap.pop();
+        }
+        let (user_text, assistant_text) = match texts {
+            Some(texts) => texts,
+            None => (row.get(2)?, row.get(3)?),
+        };
+        let embedding = params
+            .dedupe_threshold
+            .and_then(|_| decode_embedding(&embedding_blob, scorer.query.len()))
+            .map(Cow::into_owned);
+        heap.push(Ranked(
+            SearchResult {
+                conversation_id,
+                turn_index,
+                score,
+                user_text,
+                assistant_text,
+                details: None,
+                annotations: Vec::new(),
+            },
+            embedding,
+        ));
     }
 
-    results.sort_by(|a, b| {
-        b.score
-            .partial_cmp(&a.score)
-            .unwrap_or(std::cmp::Ordering::Equal)
-    });
+    let ranked = heap.into_sorted_vec();
+    let mut results: Vec<SearchResult> = match params.dedupe_threshold {
+        Some(threshold) => dedupe_ranked(ranked, threshold),
+        None => ranked.into_iter().map(|ranked| ranked.0).collect(),
+    };
+    if let Some(reranker) = params.reranker {
+        reranker
+            .rerank(query_text, &mut results)
+            .map_err(SearchError::Rerank)?;
+    }
+    results.drain(..params.offset.min(results.len()));
     if results.len() > params.limit {
         results.truncate(params.limit);
     }
+    for result in results.iter_mut() {
+        result.annotations =
+            storage.turn_annotations(&result.conversation_id, result.turn_index)?;
+    }
+    if params.include_details {
+        load_details(storage, &mut results)?;
+    }
+    if params.record_access {
+        let hits: Vec<(&str, usize)> = results
+            .iter()
+            .map(|r| (r.conversation_id.as_str(), r.turn_index))
+            .collect();
+        storage.record_access(&hits)?;
+    }
     Ok(results)
 }
 
-fn cosine_similarity(query: &[f32], query_norm: f32, candidate: &[f32]) -> f32 {
-    let candidate_norm = l2_norm(candidate);
-    if candidate_norm == 0.0 {
-        return 0.0;
+/// Append the metadata filters in `params` as `AND ...` clauses over `turns t` joined with
+/// `conversations c`.
+fn push_filters(
+    sql: &mut String,
+    values: &mut Vec<SqlValue>,
+    params: &SearchParams<'_>,
+) -> Result<(), SearchError> {
+    if !params.conversation_ids.is_empty() {
+        sql.push_str(" AND c.id IN (");
+        for (idx, _) in params.conversation_ids.iter().enumerate() {
+            if idx > 0 {
+                sql.push_str(", ");
+            }
+            sql.push('?');
+        }
+        sql.push(')');
+        for id in &params.conversation_ids {
+            values.push(SqlValue::from((*id).to_string()));
+        }
     }
-    let dot = query
-        .iter()
-        .zip(candidate.iter())
-        .map(|(a, b)| (*a as f64) * (*b as f64))
-        .sum::<f64>();
-    (dot / ((query_norm as f64) * (candidate_norm as f64))) as f32
-}
-
-fn l2_norm(vector: &[f32]) -> f32 {
-    vector
-        .iter()
-        .map(|v| (*v as f64) * (*v as f64))
-        .sum::<f64>()
-        .sqrt() as f32
-}
 
-fn ensure_valid_meta_key(key: &str) -> Result<(), SearchError> {
-    if key.is_empty() {
-        return Err(SearchError::InvalidMetaKey(key.to_string()));
-    }
-    for segment in key.split('.') {
-        if segment.is_empty()
-            || !segment
-                .chars()
-                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
-        {
-            return Err(SearchError::InvalidMetaKey(key.to_string()));
-        }
+    if let Some(model) = params.model {
+        sql.push_str(" AND c.model = ?");
+        values.push(SqlValue::from(model.to_string()));
     }
-    Ok(())
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::storage::{ConversationStats, RolloutFingerprint, Storage};
-    use crate::types::{ConversationRecord, TurnRecord, TurnResult, TurnTelemetry};
-    use serde_json::json;
+    if let Some(prefix) = params.cwd_prefix {
+        sql.push_str(" AND substr(c.cwd, 1, ?) = ?");
+        values.push(SqlValue::from(prefix.chars().count() as i64));
+        values.push(SqlValue::from(prefix.to_string()));
+    }
 
-    fn insert_turn_with_embedding(
-        storage: &Storage,
-        conversation_id: &str,
-        assistant_text: &str,
-        embedding: &[f32],
-    ) {
-        let turn = TurnRecord {
-            index: 0,
-            started_at: None,
-            context: None,
-            user_inputs: Vec::new(),
-            result: TurnResult {
-                assistant_messages: vec![assistant_text.to_string()],
-                ..TurnResult::default()
-            },
-            actions: Vec::new(),
-            telemetry: TurnTelemetry::default(),
-        };
-        storage
-            .insert_turn(conversation_id, &turn, Some(embedding))
-            .unwrap();
+    if let Some(repo) = params.git_repo {
+        sql.push_str(" AND c.git_repo = ?");
+        values.push(SqlValue::from(normalize_git_repo(repo).unwrap_or_default()));
     }
 
-    #[test]
-    fn filters_and_ranks_results() {
-        let storage = Storage::open_in_memory().unwrap();
+    if let Some(branch) = params.git_branch {
+        sql.push_str(" AND c.git_branch = ?");
+        values.push(SqlValue::from(branch.to_string()));
+    }
 
-        let mut record_alpha = ConversationRecord::default();
-        record_alpha.session_meta = Some(json!({"id":"alpha","project":"alpha"}));
-        let mut stats_alpha = ConversationStats::default();
-        stats_alpha.search_blob = "alpha result".to_string();
-        stats_alpha.turn_count = 1;
-        stats_alpha.cwd = Some("/tmp/alpha".to_string());
-        let alpha_id = storage
-            .upsert_conversation(
-                "alpha.jsonl",
-                &record_alpha,
-                &RolloutFingerprint::default(),
-                &stats_alpha,
-                None,
-            )
-            .unwrap();
-        insert_turn_with_embedding(&storage, &alpha_id, "alpha result", &[1.0, 0.0]);
+    if !params.commands_any.is_empty() {
+        sql.push_str(
+            " AND EXISTS (SELECT 1 FROM conversation_commands cc \
+             WHERE cc.conversation_id = c.id AND cc.command IN (",
+        );
+        for (idx, command) in params.commands_any.iter().enumerate() {
+            if idx > 0 {
+                sql.push_str(", ");
+            }
+            sql.push('?');
+            values.push(SqlValue::from((*command).to_string()));
+        }
+        sql.push_str("))");
+    }
 
-        let mut record_beta = ConversationRecord::default();
-        record_beta.session_meta = Some(json!({"id":"beta","project":"beta"}));
-        let mut stats_beta = ConversationStats::default();
-        stats_beta.search_blob = "beta result".to_string();
-        stats_beta.turn_count = 1;
-        stats_beta.cwd = Some("/tmp/beta".to_string());
-        let beta_id = storage
-            .upsert_conversation(
-                "beta.jsonl",
-                &record_beta,
-                &RolloutFingerprint::default(),
-                &stats_beta,
-                None,
-            )
-            .unwrap();
-        insert_turn_with_embedding(&storage, &beta_id, "beta result", &[0.0, 1.0]);
+    for command in &params.commands_all {
+        sql.push_str(
+            " AND EXISTS (SELECT 1 FROM conversation_commands cc \
+             WHERE cc.conversation_id = c.id AND cc.command = ?)",
+        );
+        values.push(SqlValue::from((*command).to_string()));
+    }
 
-        let mut params = SearchParams::new(5);
-        params.meta_equals.push(("project", "alpha"));
+    if let Some(pattern) = params.files_touched_glob {
+        sql.push_str(
+            " AND EXISTS (SELECT 1 FROM conversation_files cf \
+             WHERE cf.conversation_id = c.id AND cf.path GLOB ?)",
+        );
+        values.push(SqlValue::from(files_glob_pattern(pattern)));
+    }
 
-        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].conversation_id, "alpha");
-        assert!(results[0]
-            .assistant_text
-            .as_ref()
-            .unwrap()
-            .contains("alpha"));
+    if let Some(url) = params.url_contains {
+        sql.push_str(" AND instr(lower(c.urls_json), ?) > 0");
+        values.push(SqlValue::from(url.to_lowercase()));
+    }
 
-        let results = search_with_vector(&storage, &[0.0, 1.0], &SearchParams::new(5)).unwrap();
-        assert_eq!(results.len(), 2);
-        assert_eq!(results[0].conversation_id, "beta");
+    if let Some(filter) = &params.filter {
+        sql.push_str(" AND ");
+        filter.compile(sql, values)?;
+    }
+
+    if let Some(live) = params.has_live_events {
+        sql.push_str(if live {
+            " AND COALESCE(c.has_live_events, 0) != 0"
+        } else {
+            " AND COALESCE(c.has_live_events, 0) = 0"
+        });
+    }
+    if let Some(approvals) = params.has_approvals {
+        sql.push_str(if approvals {
+            " AND COALESCE(c.approval_count, 0) > 0"
+        } else {
+            " AND COALESCE(c.approval_count, 0) = 0"
+        });
+    }
+    if let Some(failed) = params.has_failed_commands {
+        sql.push_str(if failed {
+            " AND COALESCE(c.failed_command_count, 0) > 0"
+        } else {
+            " AND COALESCE(c.failed_command_count, 0) = 0"
+        });
+    }
+    if let Some(errors) = params.had_errors {
+        sql.push_str(if errors {
+            " AND COALESCE(c.had_errors, 0) != 0"
+        } else {
+            " AND COALESCE(c.had_errors, 0) = 0"
+        });
+    }
+    if let Some(pinned) = params.pinned {
+        sql.push_str(if pinned {
+            " AND c.pinned != 0"
+        } else {
+            " AND c.pinned = 0"
+        });
+    }
+
+    let ranges = [
+        (Column::TurnCount, params.turn_count),
+        (Column::DurationSeconds, params.duration_seconds),
+    ];
+    for (column, range) in ranges {
+        if let Some(range) = range {
+            sql.push_str(" AND ");
+            range.to_filter(column).compile(sql, values)?;
+        }
+    }
+    Ok(())
+}
+
+/// First stage of a quantized search: rowids of the `prefetch` turns whose sign bits are closest
+/// to the query's. Rows stored before quantization existed are quantized on the fly.
+fn hamming_candidates(
+    storage: &Storage,
+    query_vector: &[f32],
+    params: &SearchParams<'_>,
+    prefetch: usize,
+) -> Result<Vec<i64>, SearchError> {
+    let mut sql =
+        String::from("SELECT t.rowid, e.bits, CASE WHEN e.bits IS NULL THEN e.vector END, e.dim");
+    sql.push_str(CANDIDATES_FROM);
+    let mut values = vec![SqlValue::from(embedding_space(params).to_string())];
+    push_filters(&mut sql, &mut values, params)?;
+
+    let query_bits = sign_bits(query_vector);
+    let mut stmt = storage.connection().prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(values.iter()))?;
+    // Max-heap on distance so the farthest kept candidate is evicted first.
+    let mut heap: BinaryHeap<(u32, i64)> = BinaryHeap::new();
+    while let Some(row) = rows.next()? {
+        let rowid: i64 = row.get(0)?;
+        let bits = match row.get::<_, Option<Vec<u8>>>(1)? {
+            Some(bits) => bits,
+            None => {
+                let blob: Vec<u8> = row.get(2)?;
+                let dim: i64 = row.get(3)?;
+                let Some(vector) = decode_embedding(&blob, dim as usize) else {
+                    continue;
+                };
+                sign_bits(&vector)
+            }
+        };
+        if bits.len() != query_bits.len() {
+            continue;
+        }
+        let distance = bits
+            .iter()
+            .zip(&query_bits)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum::<u32>();
+        heap.push((distance, rowid));
+        if heap.len() > prefetch {
+            heap.pop();
+        }
+    }
+    Ok(heap.into_iter().map(|(_, rowid)| rowid).collect())
+}
+
+/// Narrow the candidates to the `prefetch` nearest neighbours from the `sqlite-vec` index when
+/// it covers the query's dimension. Filters still apply afterwards, so selective filters may
+/// return fewer than `limit` results.
+#[cfg(feature = "sqlite-vec")]
+fn push_knn_filter(
+    storage: &Storage,
+    sql: &mut String,
+    values: &mut Vec<SqlValue>,
+    query_vector: &[f32],
+    prefetch: usize,
+) -> Result<bool, SearchError> {
+    if storage.knn_dimension()? != Some(query_vector.len()) {
+        return Ok(false);
+    }
+    sql.push_str(crate::ann::KNN_FILTER);
+    values.push(SqlValue::from(
+        bytemuck::cast_slice::<f32, u8>(query_vector).to_vec(),
+    ));
+    values.push(SqlValue::from(prefetch.min(crate::ann::MAX_K) as i64));
+    Ok(true)
+}
+
+#[cfg(not(feature = "sqlite-vec"))]
+fn push_knn_filter(
+    _storage: &Storage,
+    _sql: &mut String,
+    _values: &mut Vec<SqlValue>,
+    _query_vector: &[f32],
+    _prefetch: usize,
+) -> Result<bool, SearchError> {
+    Ok(false)
+}
+
+impl SearchResult {
+    fn rank_key(&self) -> (f32, &str, usize) {
+        (self.score, &self.conversation_id, self.turn_index)
+    }
+}
+
+/// Cosine scoring of candidate embedding BLOBs against a query, including the negative-example
+/// penalty.
+struct Scorer<'q> {
+    query: &'q [f32],
+    query_norm: f32,
+    negatives: Vec<(&'q [f32], f32)>,
+    negative_weight: f32,
+}
+
+impl<'q> Scorer<'q> {
+    /// `None` when the query has no direction to compare against.
+    fn new(query: &'q [f32], params: &SearchParams<'q>) -> Option<Self> {
+        let query_norm = l2_norm(query);
+        if query_norm == 0.0 {
+            return None;
+        }
+        let negatives = params
+            .negative_vectors
+            .iter()
+            .filter(|negative| negative.len() == query.len())
+            .map(|negative| (*negative, l2_norm(negative)))
+            .filter(|(_, norm)| *norm > 0.0)
+            .collect();
+        Some(Scorer {
+            query,
+            query_norm,
+            negatives,
+            negative_weight: params.negative_weight,
+        })
+    }
+
+    /// Score a stored embedding, or `None` if it is malformed or of another dimension.
+    fn score(&self, embedding_blob: &[u8], candidate_norm: Option<f32>) -> Option<f32> {
+        let embedding = decode_embedding(embedding_blob, self.query.len())?;
+        let embedding: &[f32] = &embedding;
+        let mut score = cosine_similarity(self.query, self.query_norm, embedding, candidate_norm);
+        if let Some(penalty) = self
+            .negatives
+            .iter()
+            .map(|(negative, norm)| cosine_similarity(negative, *norm, embedding, candidate_norm))
+            .reduce(f32::max)
+        {
+            score -= self.negative_weight * penalty;
+        }
+        score.is_finite().then_some(score)
+    }
+}
+
+/// Orders by descending score, breaking ties by conversation id and turn index so that results
+/// (and therefore pages) are stable. `Less` means `a` ranks ahead of `b`.
+fn compare_rank(a: (f32, &str, usize), b: (f32, &str, usize)) -> Ordering {
+    b.0.total_cmp(&a.0)
+        .then_with(|| a.1.cmp(b.1))
+        .then_with(|| a.2.cmp(&b.2))
+}
+
+/// Heap entry whose ordering puts the worst-ranked result at the top of a max-heap. Carries the
+/// candidate embedding when results are deduplicated.
+struct Ranked(SearchResult, Option<Vec<f32>>);
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_rank(self.0.rank_key(), other.0.rank_key())
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Ranked {}
+
+/// Greedily keep each result (best first) unless it is at least `threshold` similar to one
+/// already kept.
+fn dedupe_ranked(ranked: Vec<Ranked>, threshold: f32) -> Vec<SearchResult> {
+    let mut kept: Vec<(Vec<f32>, f32)> = Vec::new();
+    let mut results = Vec::new();
+    for Ranked(result, embedding) in ranked {
+        let Some(embedding) = embedding else {
+            results.push(result);
+            continue;
+        };
+        let norm = l2_norm(&embedding);
+        let duplicate = kept.iter().any(|(other, other_norm)| {
+            cosine_similarity(&embedding, norm, other, Some(*other_norm)) >= threshold
+        });
+        if !duplicate {
+            kept.push((embedding, norm));
+            results.push(result);
+        }
+    }
+    results
+}
+
+fn load_details(storage: &Storage, results: &mut [SearchResult]) -> Result<(), SearchError> {
+    let mut stmt = storage.connection().prepare(
+        "SELECT t.started_at, t.actions_json, c.rollout_path, c.preview, c.model, c.cwd, \
+         c.title, c.auto_title, c.git_repo, c.git_branch, c.summary, t.source_line_start, \
+         t.source_line_end, t.telemetry_json, c.last_plan_json \
+         FROM turns t \
+         JOIN conversations c ON c.id = t.conversation_id \
+         WHERE t.conversation_id = ?1 AND t.turn_index = ?2",
+    )?;
+    for result in results.iter_mut() {
+        let mut rows = stmt.query(rusqlite::params![
+            result.conversation_id,
+            result.turn_index as i64
+        ])?;
+        let Some(row) = rows.next()? else {
+            continue;
+        };
+        let actions_json: Option<String> = row.get(1)?;
+        let telemetry: Option<TurnTelemetry> = row
+            .get::<_, Option<String>>(13)?
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()?;
+        let last_plan_json: Option<String> = row.get(14)?;
+        result.details = Some(SearchResultDetails {
+            started_at: row.get(0)?,
+            source_lines: line_range(row.get(11)?, row.get(12)?),
+            actions: actions_json
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?
+                .unwrap_or_default(),
+            rollout_path: storage
+                .resolve_rollout_path(&row.get::<_, String>(2)?)?
+                .to_string_lossy()
+                .into_owned(),
+            preview: row.get(3)?,
+            model: row.get(4)?,
+            cwd: row.get(5)?,
+            title: row.get(6)?,
+            auto_title: row.get(7)?,
+            git_repo: row.get(8)?,
+            git_branch: row.get(9)?,
+            summary: row.get(10)?,
+            plan: telemetry.and_then(|telemetry| telemetry.final_plan()),
+            last_plan: last_plan_json
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+        });
+    }
+    Ok(())
+}
+
+/// Cosine similarity using the stored candidate norm when available, so only the dot product
+/// is computed per row.
+fn cosine_similarity(
+    query: &[f32],
+    query_norm: f32,
+    candidate: &[f32],
+    candidate_norm: Option<f32>,
+) -> f32 {
+    let candidate_norm = candidate_norm.unwrap_or_else(|| l2_norm(candidate));
+    if candidate_norm == 0.0 {
+        return 0.0;
+    }
+    let dot = query
+        .iter()
+        .zip(candidate.iter())
+        .map(|(a, b)| (*a as f64) * (*b as f64))
+        .sum::<f64>();
+    (dot / ((query_norm as f64) * (candidate_norm as f64))) as f32
+}
+
+/// Candidate rows: turns joined with their conversation and their embedding in the searched
+/// space, whose name is bound first.
+const CANDIDATES_FROM: &str = " FROM turns t \
+     JOIN conversations c ON c.id = t.conversation_id \
+     JOIN embeddings e ON e.conversation_id = t.conversation_id AND e.turn_index = t.turn_index \
+     WHERE e.model_name = ?";
+
+fn embedding_space<'p>(params: &SearchParams<'p>) -> &'p str {
+    params.embedding_model.unwrap_or(DEFAULT_EMBEDDING_MODEL)
+}
+
+/// Report the most common embedding dimension in the searched space, among the filtered
+/// conversations, that differs from the query dimension.
+fn check_dimension(
+    storage: &Storage,
+    query_dim: usize,
+    params: &SearchParams<'_>,
+) -> Result<(), SearchError> {
+    let mut sql = String::from("SELECT e.dim, count(DISTINCT c.id)");
+    sql.push_str(CANDIDATES_FROM);
+    sql.push_str(" AND e.dim != ?");
+    let mut values = vec![
+        SqlValue::from(embedding_space(params).to_string()),
+        SqlValue::from(query_dim as i64),
+    ];
+    push_filters(&mut sql, &mut values, params)?;
+    sql.push_str(" GROUP BY e.dim ORDER BY count(DISTINCT c.id) DESC, e.dim LIMIT 1");
+    let mismatch: Option<(i64, i64)> = storage
+        .connection()
+        .query_row(&sql, rusqlite::params_from_iter(values.iter()), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .optional()?;
+    match mismatch {
+        Some((stored, conversations)) => Err(SearchError::DimensionMismatch {
+            query: query_dim,
+            stored: stored as usize,
+            conversations: conversations as usize,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Exact-phrase / regex post-filter over the text of each candidate.
+struct TextFilter<'a> {
+    must_contain: Vec<&'a str>,
+    regex: Option<Regex>,
+}
+
+impl<'a> TextFilter<'a> {
+    /// `None` when the parameters do not constrain the text.
+    fn new(params: &SearchParams<'a>) -> Result<Option<Self>, SearchError> {
+        if params.must_contain.is_empty() && params.must_match_regex.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(TextFilter {
+            must_contain: params.must_contain.clone(),
+            regex: params.must_match_regex.map(Regex::new).transpose()?,
+        }))
+    }
+
+    fn matches(&self, user_text: Option<&str>, assistant_text: Option<&str>) -> bool {
+        let texts = [
+            user_text.unwrap_or_default(),
+            assistant_text.unwrap_or_default(),
+        ];
+        self.must_contain
+            .iter()
+            .all(|phrase| texts.iter().any(|text| text.contains(phrase)))
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|regex| texts.iter().any(|text| regex.is_match(text)))
+    }
+}
+
+/// Lower-cased, de-duplicated alphanumeric terms of a text query.
+fn keyword_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+/// Fraction of `terms` found in `text`.
+fn keyword_match(terms: &[String], text: Option<&str>) -> f32 {
+    let Some(text) = text.filter(|text| !text.is_empty()) else {
+        return 0.0;
+    };
+    let text = text.to_lowercase();
+    let matched = terms
+        .iter()
+        .filter(|term| text.contains(term.as_str()))
+        .count();
+    matched as f32 / terms.len() as f32
+}
+
+fn files_glob_pattern(pattern: &str) -> String {
+    if pattern.contains(['*', '?', '[']) {
+        pattern.to_string()
+    } else {
+        format!("*{pattern}*")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::DerivationConfig;
+    use crate::storage::{AccessOrder, ConversationStats, RolloutFingerprint, Storage};
+    use crate::types::{
+        ConversationRecord, TurnRecord, TurnResult, TurnTelemetry, UserInputKind, UserInputRecord,
+    };
+    use serde_json::json;
+
+    fn insert_turn_with_embedding(
+        storage: &Storage,
+        conversation_id: &str,
+        assistant_text: &str,
+        embedding: &[f32],
+    ) {
+        let turn = TurnRecord {
+            index: 0,
+            started_at: None,
+            ended_at: None,
+            duration_seconds: None,
+            context: None,
+            user_inputs: Vec::new(),
+            result: TurnResult {
+                assistant_messages: vec![assistant_text.to_string()],
+                ..TurnResult::default()
+            },
+            actions: Vec::new(),
+            telemetry: TurnTelemetry::default(),
+            errors: Vec::new(),
+            source_lines: None,
+            token_usage: None,
+            compactions: Vec::new(),
+        };
+        storage
+            .insert_turn(conversation_id, &turn, Some(embedding))
+            .unwrap();
+    }
+
+    fn default_stats(id: &str) -> ConversationStats {
+        ConversationStats {
+            search_blob: format!("{id} result"),
+            turn_count: 1,
+            cwd: Some(format!("/tmp/{id}")),
+            ..ConversationStats::default()
+        }
+    }
+
+    fn seed_conversation(
+        storage: &Storage,
+        id: &str,
+        stats: ConversationStats,
+        embedding: &[f32],
+    ) -> String {
+        let record = ConversationRecord {
+            session_meta: Some(json!({"id": id, "project": id})),
+            ..ConversationRecord::default()
+        };
+        let conversation_id = storage
+            .upsert_conversation(
+                format!("{id}.jsonl"),
+                &record,
+                &RolloutFingerprint::default(),
+                &stats,
+                None,
+            )
+            .unwrap();
+        insert_turn_with_embedding(
+            storage,
+            &conversation_id,
+            &format!("{id} result"),
+            embedding,
+        );
+        conversation_id
+    }
+
+    #[test]
+    #[allow(clippy::field_reassign_with_default)]
+    fn filters_and_ranks_results() {
+        let storage = Storage::open_in_memory().unwrap();
+
+        let mut record_alpha = ConversationRecord::default();
+        record_alpha.session_meta = Some(json!({"id":"alpha","project":"alpha"}));
+        let mut stats_alpha = ConversationStats::default();
+        stats_alpha.search_blob = "alpha result".to_string();
+        stats_alpha.turn_count = 1;
+        stats_alpha.cwd = Some("/tmp/alpha".to_string());
+        let alpha_id = storage
+            .upsert_conversation(
+                "alpha.jsonl",
+                &record_alpha,
+                &RolloutFingerprint::default(),
+                &stats_alpha,
+                None,
+            )
+            .unwrap();
+        insert_turn_with_embedding(&storage, &alpha_id, "alpha result", &[1.0, 0.0]);
+
+        let mut record_beta = ConversationRecord::default();
+        record_beta.session_meta = Some(json!({"id":"beta","project":"beta"}));
+        let mut stats_beta = ConversationStats::default();
+        stats_beta.search_blob = "beta result".to_string();
+        stats_beta.turn_count = 1;
+        stats_beta.cwd = Some("/tmp/beta".to_string());
+        let beta_id = storage
+            .upsert_conversation(
+                "beta.jsonl",
+                &record_beta,
+                &RolloutFingerprint::default(),
+                &stats_beta,
+                None,
+            )
+            .unwrap();
+        insert_turn_with_embedding(&storage, &beta_id, "beta result", &[0.0, 1.0]);
+
+        let params = SearchParams {
+            filter: Some(Filter::meta_eq("project", "alpha")),
+            ..SearchParams::new(5)
+        };
+
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "alpha");
+        assert!(results[0]
+            .assistant_text
+            .as_ref()
+            .unwrap()
+            .contains("alpha"));
+
+        let results = search_with_vector(&storage, &[0.0, 1.0], &SearchParams::new(5)).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].conversation_id, "beta");
     }
 
     #[test]
     fn rejects_bad_meta_keys() {
         let storage = Storage::open_in_memory().unwrap();
         let params = SearchParams {
-            meta_equals: vec![("project'; DROP TABLE --", "alpha")],
-            conversation_ids: Vec::new(),
-            limit: 5,
-            prefetch: None,
+            filter: Some(Filter::meta_eq("project'; DROP TABLE --", "alpha")),
+            ..SearchParams::new(5)
         };
         let err = search_with_vector(&storage, &[1.0], &params).unwrap_err();
         assert!(matches!(err, SearchError::InvalidMetaKey(_)));
     }
+
+    #[test]
+    fn records_access_for_returned_turns() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", default_stats("beta"), &[0.0, 1.0]);
+
+        let mut params = SearchParams::new(1);
+        search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        let stats = storage.access_stats(AccessOrder::MostHits, 10).unwrap();
+        assert!(stats.iter().all(|s| s.hit_count == 0));
+
+        params.record_access = true;
+        search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        let stats = storage.access_stats(AccessOrder::MostHits, 10).unwrap();
+        assert_eq!(stats[0].conversation_id, "alpha");
+        assert_eq!(stats[0].hit_count, 2);
+        assert!(stats[0].last_accessed_at.is_some());
+
+        let stale = storage.access_stats(AccessOrder::LeastRecent, 1).unwrap();
+        assert_eq!(stale[0].conversation_id, "beta");
+        assert!(stale[0].last_accessed_at.is_none());
+
+        let turn_hits: i64 = storage
+            .connection()
+            .query_row(
+                "SELECT hit_count FROM turns WHERE conversation_id = 'alpha'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(turn_hits, 2);
+    }
+
+    #[test]
+    fn filters_by_model_and_cwd_prefix() {
+        let storage = Storage::open_in_memory().unwrap();
+        let alpha = ConversationStats {
+            model: Some("gpt-5".into()),
+            cwd: Some("/work/repo-alpha/src".into()),
+            ..default_stats("alpha")
+        };
+        let beta = ConversationStats {
+            model: Some("o3".into()),
+            cwd: Some("/work/repo-beta".into()),
+            ..default_stats("beta")
+        };
+        seed_conversation(&storage, "alpha", alpha, &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", beta, &[0.9, 0.1]);
+
+        let params = SearchParams {
+            model: Some("o3"),
+            ..SearchParams::new(5)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "beta");
+
+        let params = SearchParams {
+            cwd_prefix: Some("/work/repo-alpha"),
+            ..SearchParams::new(5)
+        };
+        let results = search_with_vector(&storage, &[0.0, 1.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "alpha");
+
+        let params = SearchParams {
+            cwd_prefix: Some("/elsewhere"),
+            ..SearchParams::new(5)
+        };
+        assert!(search_with_vector(&storage, &[1.0, 0.0], &params)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn filters_by_git_repository_and_branch() {
+        let storage = Storage::open_in_memory().unwrap();
+        let alpha = ConversationStats {
+            git_repo: Some("github.com/org/alpha".into()),
+            git_branch: Some("main".into()),
+            ..default_stats("alpha")
+        };
+        let feature = ConversationStats {
+            git_repo: Some("github.com/org/alpha".into()),
+            git_branch: Some("feature/search".into()),
+            ..default_stats("feature")
+        };
+        let beta = ConversationStats {
+            git_repo: Some("github.com/org/beta".into()),
+            git_branch: Some("main".into()),
+            ..default_stats("beta")
+        };
+        seed_conversation(&storage, "alpha", alpha, &[1.0, 0.0]);
+        seed_conversation(&storage, "feature", feature, &[0.9, 0.1]);
+        seed_conversation(&storage, "beta", beta, &[0.8, 0.2]);
+
+        let params = SearchParams {
+            git_repo: Some("git@github.com:org/alpha.git"),
+            include_details: true,
+            ..SearchParams::new(5)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        let ids: Vec<_> = results.iter().map(|r| r.conversation_id.as_str()).collect();
+        assert_eq!(ids, ["alpha", "feature"]);
+        let details = results[0].details.as_ref().unwrap();
+        assert_eq!(details.git_repo.as_deref(), Some("github.com/org/alpha"));
+        assert_eq!(details.git_branch.as_deref(), Some("main"));
+
+        let params = SearchParams {
+            git_repo: Some("https://github.com/org/alpha"),
+            git_branch: Some("feature/search"),
+            ..SearchParams::new(5)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "feature");
+    }
+
+    #[test]
+    fn filters_by_commands_executed() {
+        let storage = Storage::open_in_memory().unwrap();
+        let alpha = ConversationStats {
+            commands: vec!["cargo".into(), "git".into()],
+            ..default_stats("alpha")
+        };
+        let beta = ConversationStats {
+            commands: vec!["kubectl".into()],
+            ..default_stats("beta")
+        };
+        seed_conversation(&storage, "alpha", alpha, &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", beta, &[0.0, 1.0]);
+
+        let params = SearchParams {
+            commands_any: vec!["kubectl", "helm"],
+            ..SearchParams::new(5)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "beta");
+
+        let params = SearchParams {
+            commands_all: vec!["cargo", "git"],
+            ..SearchParams::new(5)
+        };
+        let results = search_with_vector(&storage, &[0.0, 1.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "alpha");
+
+        let params = SearchParams {
+            commands_all: vec!["cargo", "kubectl"],
+            ..SearchParams::new(5)
+        };
+        assert!(search_with_vector(&storage, &[1.0, 0.0], &params)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn filters_by_files_touched() {
+        let storage = Storage::open_in_memory().unwrap();
+        let alpha = ConversationStats {
+            files_touched: vec!["src/pipeline.rs".into(), "README.md".into()],
+            ..default_stats("alpha")
+        };
+        let beta = ConversationStats {
+            files_touched: vec!["/abs/src/storage.rs".into()],
+            ..default_stats("beta")
+        };
+        seed_conversation(&storage, "alpha", alpha, &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", beta, &[0.0, 1.0]);
+
+        let only = |pattern: &str| -> Vec<String> {
+            let params = SearchParams {
+                files_touched_glob: Some(pattern),
+                ..SearchParams::new(5)
+            };
+            search_with_vector(&storage, &[1.0, 1.0], &params)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.conversation_id)
+                .collect()
+        };
+        assert_eq!(only("pipeline.rs"), vec!["alpha"]);
+        assert_eq!(only("*/storage.rs"), vec!["beta"]);
+        assert_eq!(only("src/*.rs"), vec!["alpha"]);
+        assert!(only("Cargo.toml").is_empty());
+    }
+
+    #[test]
+    fn reranker_sees_all_candidates_before_truncation() {
+        struct PreferBeta;
+        impl Reranker for PreferBeta {
+            fn rerank(
+                &self,
+                query: Option<&str>,
+                candidates: &mut Vec<SearchResult>,
+            ) -> Result<(), RerankError> {
+                assert!(query.is_none());
+                assert_eq!(candidates.len(), 2);
+                candidates.sort_by_key(|c| {
+                    !c.assistant_text
+                        .as_deref()
+                        .is_some_and(|text| text.contains("beta"))
+                });
+                Ok(())
+            }
+        }
+
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", default_stats("beta"), &[0.0, 1.0]);
+
+        let params = SearchParams {
+            reranker: Some(&PreferBeta),
+            ..SearchParams::new(1)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "beta");
+    }
+
+    #[test]
+    fn pages_through_results_with_offset() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", default_stats("beta"), &[1.0, 0.0]);
+        seed_conversation(&storage, "gamma", default_stats("gamma"), &[0.0, 1.0]);
+
+        let mut pages = Vec::new();
+        for offset in [0, 1, 2, 3] {
+            let params = SearchParams {
+                offset,
+                ..SearchParams::new(1)
+            };
+            let page = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+            pages.push(
+                page.into_iter()
+                    .map(|r| r.conversation_id)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(
+            pages,
+            vec![
+                vec!["alpha".to_string()],
+                vec!["beta".to_string()],
+                vec!["gamma".to_string()],
+                Vec::new(),
+            ]
+        );
+    }
+
+    #[test]
+    fn concatenated_pages_match_one_large_query() {
+        let storage = Storage::open_in_memory().unwrap();
+        for idx in 0..12 {
+            let id = format!("conv-{idx:02}");
+            // The best matches come last in primary-key order, and pairs of conversations
+            // share a vector, so ties are broken by id.
+            let angle = ((11 - idx) / 2) as f32 * 0.2;
+            seed_conversation(
+                &storage,
+                &id,
+                default_stats(&id),
+                &[angle.cos(), angle.sin()],
+            );
+        }
+        let ids = |results: Vec<SearchResult>| -> Vec<String> {
+            results.into_iter().map(|r| r.conversation_id).collect()
+        };
+
+        let all = ids(search_with_vector(&storage, &[1.0, 0.0], &SearchParams::new(12)).unwrap());
+        let mut paged = Vec::new();
+        for offset in 0..12 {
+            let params = SearchParams {
+                offset,
+                ..SearchParams::new(1)
+            };
+            paged.extend(ids(
+                search_with_vector(&storage, &[1.0, 0.0], &params).unwrap()
+            ));
+        }
+        assert_eq!(all.len(), 12);
+        assert_eq!(paged, all);
+    }
+
+    #[test]
+    fn includes_turn_and_conversation_details_on_request() {
+        let storage = Storage::open_in_memory().unwrap();
+        let stats = ConversationStats {
+            model: Some("gpt-5-codex".into()),
+            preview: Some("alpha preview".into()),
+            ..default_stats("alpha")
+        };
+        seed_conversation(&storage, "alpha", stats, &[1.0, 0.0]);
+
+        let plain = search_with_vector(&storage, &[1.0, 0.0], &SearchParams::new(1)).unwrap();
+        assert!(plain[0].details.is_none());
+
+        let params = SearchParams {
+            include_details: true,
+            ..SearchParams::new(1)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        let details = results[0].details.as_ref().expect("details requested");
+        assert_eq!(details.rollout_path, "alpha.jsonl");
+        assert_eq!(details.model.as_deref(), Some("gpt-5-codex"));
+        assert_eq!(details.preview.as_deref(), Some("alpha preview"));
+        assert_eq!(details.cwd.as_deref(), Some("/tmp/alpha"));
+        assert!(details.actions.is_empty());
+    }
+
+    #[test]
+    fn surfaces_turn_and_conversation_annotations() {
+        let storage = Storage::open_in_memory().unwrap();
+        let alpha = seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        let turn_note = storage
+            .add_annotation(&alpha, Some(0), "this was the fix", Some("me"))
+            .unwrap();
+        storage
+            .add_annotation(&alpha, None, "canonical session", None)
+            .unwrap();
+        storage
+            .add_annotation(&alpha, Some(3), "other turn", None)
+            .unwrap();
+
+        let results = search_with_vector(&storage, &[1.0, 0.0], &SearchParams::new(1)).unwrap();
+        let notes: Vec<&str> = results[0]
+            .annotations
+            .iter()
+            .map(|a| a.note.as_str())
+            .collect();
+        assert_eq!(notes, vec!["this was the fix", "canonical session"]);
+        assert_eq!(results[0].annotations[0].author.as_deref(), Some("me"));
+        assert_eq!(storage.annotations(&alpha).unwrap().len(), 3);
+
+        assert!(storage.delete_annotation(turn_note).unwrap());
+        assert!(!storage.delete_annotation(turn_note).unwrap());
+        storage.delete_conversation(&alpha).unwrap();
+        assert!(storage.annotations(&alpha).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_only_the_best_candidates_while_streaming() {
+        let storage = Storage::open_in_memory().unwrap();
+        let embeddings = [
+            ("a", [0.2, 1.0]),
+            ("b", [1.0, 0.1]),
+            ("c", [0.0, 1.0]),
+            ("d", [1.0, 0.0]),
+            ("e", [1.0, 0.5]),
+        ];
+        for (id, embedding) in embeddings {
+            seed_conversation(&storage, id, default_stats(id), &embedding);
+        }
+
+        let results = search_with_vector(&storage, &[1.0, 0.0], &SearchParams::new(3)).unwrap();
+        let ids: Vec<&str> = results.iter().map(|r| r.conversation_id.as_str()).collect();
+        assert_eq!(ids, vec!["d", "b", "e"]);
+        assert!(results.windows(2).all(|w| w[0].score >= w[1].score));
+    }
+
+    #[cfg(feature = "sqlite-vec")]
+    #[test]
+    fn knn_index_mirrors_embeddings_and_serves_search() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", default_stats("beta"), &[0.0, 1.0]);
+        assert_eq!(storage.knn_dimension().unwrap(), Some(2));
+
+        let results = search_with_vector(&storage, &[0.1, 1.0], &SearchParams::new(1)).unwrap();
+        assert_eq!(results[0].conversation_id, "beta");
+
+        storage
+            .connection()
+            .execute_batch("DROP TABLE turn_vectors")
+            .unwrap();
+        assert_eq!(storage.knn_dimension().unwrap(), None);
+        storage
+            .rebuild_derived(&DerivationConfig::default())
+            .unwrap();
+        let indexed: i64 = storage
+            .connection()
+            .query_row("SELECT count(*) FROM turn_vectors", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(indexed, 2);
+    }
+
+    #[test]
+    fn vector_index_supplies_candidates() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        seed_conversation(&storage, "beta", default_stats("beta"), &[0.0, 1.0]);
+
+        let index = VectorIndex::load(&storage, Default::default()).unwrap();
+        let params = SearchParams {
+            vector_index: Some(&index),
+            ..SearchParams::new(1)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results[0].conversation_id, "alpha");
+        assert_eq!(results[0].assistant_text.as_deref(), Some("alpha result"));
+
+        // Only indexed turns are candidates, even when the database holds closer matches.
+        let mut partial =
+            VectorIndex::load(&Storage::open_in_memory().unwrap(), Default::default()).unwrap();
+        partial.insert("beta", 0, &[0.0, 1.0]);
+        let params = SearchParams {
+            vector_index: Some(&partial),
+            ..SearchParams::new(1)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, "beta");
+    }
+
+    #[test]
+    fn uses_stored_embedding_norms_and_backfills_missing_ones() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "alpha", default_stats("alpha"), &[3.0, 4.0]);
+        let stored_norm = || -> Option<f64> {
+            storage
+                .connection()
+                .query_row("SELECT norm FROM embeddings", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(stored_norm(), Some(5.0));
+
+        storage
+            .connection()
+            .execute("UPDATE embeddings SET norm = NULL", [])
+            .unwrap();
+        let results = search_with_vector(&storage, &[3.0, 4.0], &SearchParams::new(1)).unwrap();
+        assert!((results[0].score - 1.0).abs() < 1e-6);
+
+        storage
+            .rebuild_derived(&DerivationConfig::default())
+            .unwrap();
+        assert_eq!(stored_norm(), Some(5.0));
+    }
+
+    #[test]
+    fn quantized_search_rescores_hamming_candidates() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(
+            &storage,
+            "alpha",
+            default_stats("alpha"),
+            &[0.9, 0.2, -0.5, 0.1],
+        );
+        seed_conversation(
+            &storage,
+            "beta",
+            default_stats("beta"),
+            &[0.5, 0.4, -0.1, 0.3],
+        );
+        seed_conversation(
+            &storage,
+            "gamma",
+            default_stats("gamma"),
+            &[-1.0, -1.0, 1.0, -1.0],
+        );
+        // Rows written before quantization existed are quantized on the fly.
+        storage
+            .connection()
+            .execute(
+                "UPDATE embeddings SET bits = NULL WHERE conversation_id = 'beta'",
+                [],
+            )
+            .unwrap();
+
+        let params = SearchParams {
+            quantized: true,
+            prefetch: Some(2),
+            ..SearchParams::new(3)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.2, -0.4, 0.1], &params).unwrap();
+        let ids: Vec<&str> = results.iter().map(|r| r.conversation_id.as_str()).collect();
+        assert_eq!(ids, vec!["alpha", "beta"]);
+    }
+
+    /// Copy turn 0 of `conversation_id` and its embeddings to turn indexes `1..count`, for
+    /// tests that need more candidates than inserting turns one by one would allow quickly.
+    fn repeat_first_turn(storage: &Storage, conversation_id: &str, count: usize) {
+        for table in ["turns", "embeddings"] {
+            let columns: Vec<String> = storage
+                .connection()
+                .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            let selected: Vec<&str> = columns
+                .iter()
+                .map(|name| if name == "turn_index" { "n.i" } else { name })
+                .collect();
+            storage
+                .connection()
+                .execute(
+                    &format!(
+                        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?2) \
+                         INSERT INTO {table} ({}) SELECT {} FROM {table}, n \
+                         WHERE conversation_id = ?1 AND turn_index = 0",
+                        columns.join(", "),
+                        selected.join(", "),
+                    ),
+                    rusqlite::params![conversation_id, count as i64 - 1],
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn quantized_search_binds_prefetch_windows_above_the_parameter_limit() {
+        let storage = Storage::open_in_memory().unwrap();
+        let id = seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        repeat_first_turn(&storage, &id, 40_000);
+
+        let params = SearchParams {
+            quantized: true,
+            prefetch: Some(40_000),
+            ..SearchParams::new(3)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn vector_index_binds_candidate_lists_above_the_parameter_limit() {
+        let storage = Storage::open_in_memory().unwrap();
+        let id = seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        repeat_first_turn(&storage, &id, 20_000);
+
+        let config = crate::vector_index::VectorIndexConfig {
+            probes: 10_000,
+            ..Default::default()
+        };
+        let index = VectorIndex::load(&storage, config).unwrap();
+        let params = SearchParams {
+            vector_index: Some(&index),
+            prefetch: Some(20_000),
+            ..SearchParams::new(3)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn finds_conversations_with_similar_centroids() {
+        let storage = Storage::open_in_memory().unwrap();
+        let bug = seed_conversation(&storage, "bug", default_stats("bug"), &[1.0, 0.0, 0.0]);
+        seed_conversation(
+            &storage,
+            "bug-again",
+            default_stats("bug-again"),
+            &[0.9, 0.1, 0.0],
+        );
+        seed_conversation(
+            &storage,
+            "feature",
+            default_stats("feature"),
+            &[0.0, 0.0, 1.0],
+        );
+        seed_conversation(
+            &storage,
+            "other-dim",
+            default_stats("other-dim"),
+            &[1.0, 0.0],
+        );
+
+        let similar = find_similar_conversations(&storage, &bug, 5).unwrap();
+        let ids: Vec<&str> = similar.iter().map(|s| s.conversation_id.as_str()).collect();
+        assert_eq!(ids, vec!["bug-again", "feature"]);
+        assert!(similar[0].score > 0.9);
+
+        assert!(find_similar_conversations(&storage, "missing", 5)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn searches_like_an_existing_turn() {
+        let storage = Storage::open_in_memory().unwrap();
+        let alpha = seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.1]);
+        seed_conversation(&storage, "beta", default_stats("beta"), &[0.0, 1.0]);
+        seed_conversation(&storage, "gamma", default_stats("gamma"), &[1.0, 0.0]);
+
+        let results = search_like_turn(&storage, &alpha, 0, &SearchParams::new(5)).unwrap();
+        let ids: Vec<&str> = results.iter().map(|r| r.conversation_id.as_str()).collect();
+        assert_eq!(ids, vec!["gamma", "beta"]);
+
+        let err = search_like_turn(&storage, &alpha, 7, &SearchParams::new(5)).unwrap_err();
+        assert!(matches!(
+            err,
+            SearchError::MissingEmbedding { turn_index: 7, .. }
+        ));
+    }
+
+    #[test]
+    fn negative_vectors_push_matching_results_down() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(
+            &storage,
+            "deploy-k8s",
+            default_stats("deploy-k8s"),
+            &[1.0, 1.0, 0.0],
+        );
+        seed_conversation(
+            &storage,
+            "deploy-sh",
+            default_stats("deploy-sh"),
+            &[1.0, 0.0, 0.6],
+        );
+
+        let query = [1.0, 0.5, 0.2];
+        let plain = search_with_vector(&storage, &query, &SearchParams::new(2)).unwrap();
+        assert_eq!(plain[0].conversation_id, "deploy-k8s");
+
+        let kubernetes = [0.0, 1.0, 0.0];
+        let params = SearchParams {
+            negative_vectors: vec![&kubernetes],
+            ..SearchParams::new(2)
+        };
+        let steered = search_with_vector(&storage, &query, &params).unwrap();
+        assert_eq!(steered[0].conversation_id, "deploy-sh");
+        assert!(steered[1].score < plain[0].score);
+    }
+
+    #[test]
+    fn counts_facets_over_candidates() {
+        let storage = Storage::open_in_memory().unwrap();
+        let seed_with_model = |id: &str, model: Option<&str>, embedding: &[f32]| {
+            let stats = ConversationStats {
+                model: model.map(str::to_string),
+                ..default_stats(id)
+            };
+            seed_conversation(&storage, id, stats, embedding);
+        };
+        seed_with_model("alpha", Some("gpt-5"), &[1.0, 0.0]);
+        seed_with_model("beta", Some("gpt-5"), &[0.8, 0.2]);
+        seed_with_model("gamma", None, &[0.0, 1.0]);
+
+        let faceted = search_with_facets(&storage, &[1.0, 0.0], &SearchParams::new(1)).unwrap();
+        assert_eq!(faceted.results.len(), 1);
+        assert_eq!(
+            faceted.facets.by_model,
+            vec![
+                FacetCount {
+                    value: Some("gpt-5".into()),
+                    count: 2
+                },
+                FacetCount {
+                    value: None,
+                    count: 1
+                },
+            ]
+        );
+        assert_eq!(faceted.facets.by_cwd.len(), 3);
+        assert_eq!(
+            faceted.facets.by_month,
+            vec![FacetCount {
+                value: None,
+                count: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn fetches_turns_around_a_hit() {
+        let storage = Storage::open_in_memory().unwrap();
+        let id = seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        for index in 1..5 {
+            let turn = TurnRecord {
+                index,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult {
+                    assistant_messages: vec![format!("answer {index}")],
+                    ..TurnResult::default()
+                },
+                actions: Vec::new(),
+                telemetry: TurnTelemetry::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage.insert_turn(&id, &turn, None).unwrap();
+        }
+
+        let window = storage.fetch_turn_window(&id, 3, 1, 5).unwrap();
+        let indices: Vec<usize> = window.iter().map(|turn| turn.turn_index).collect();
+        assert_eq!(indices, vec![2, 3, 4]);
+        assert_eq!(window[0].assistant_text.as_deref(), Some("answer 2"));
+
+        let start = storage.fetch_turn_window(&id, 0, 3, 0).unwrap();
+        assert_eq!(start.len(), 1);
+        assert_eq!(start[0].assistant_text.as_deref(), Some("alpha result"));
+    }
+
+    #[test]
+    fn filters_by_turn_count_and_duration() {
+        let storage = Storage::open_in_memory().unwrap();
+        for (id, turns, seconds) in [("short", 2, 60), ("long", 25, 3600), ("epic", 40, 90)] {
+            let stats = ConversationStats {
+                turn_count: turns,
+                ..default_stats(id)
+            };
+            let conversation_id = seed_conversation(&storage, id, stats, &[1.0, 0.0]);
+            storage
+                .connection()
+                .execute(
+                    "UPDATE conversations SET duration_seconds = ?1 WHERE id = ?2",
+                    rusqlite::params![seconds, conversation_id],
+                )
+                .unwrap();
+        }
+
+        let search = |params: SearchParams<'_>| -> Vec<String> {
+            let mut ids: Vec<String> = search_with_vector(&storage, &[1.0, 0.0], &params)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.conversation_id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        let long = search(SearchParams {
+            turn_count: Some((20..).into()),
+            ..SearchParams::new(5)
+        });
+        assert_eq!(long, vec!["epic", "long"]);
+
+        let long_and_slow = search(SearchParams {
+            turn_count: Some((20..).into()),
+            duration_seconds: Some((600..=7200).into()),
+            ..SearchParams::new(5)
+        });
+        assert_eq!(long_and_slow, vec!["long"]);
+    }
+
+    #[test]
+    fn filters_by_live_events_approvals_and_failed_commands() {
+        let storage = Storage::open_in_memory().unwrap();
+        let live = ConversationStats {
+            has_live_events: true,
+            ..default_stats("live")
+        };
+        let escalated = ConversationStats {
+            approval_count: 2,
+            ..default_stats("escalated")
+        };
+        let failing = ConversationStats {
+            failed_command_count: 1,
+            ..default_stats("failing")
+        };
+        seed_conversation(&storage, "live", live, &[1.0, 0.0]);
+        seed_conversation(&storage, "escalated", escalated, &[1.0, 0.0]);
+        seed_conversation(&storage, "failing", failing, &[1.0, 0.0]);
+        seed_conversation(&storage, "plain", default_stats("plain"), &[1.0, 0.0]);
+
+        let ids = |params: SearchParams<'_>| -> Vec<String> {
+            let mut ids: Vec<String> = search_with_vector(&storage, &[1.0, 0.0], &params)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.conversation_id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        let params = SearchParams {
+            has_live_events: Some(true),
+            ..SearchParams::new(5)
+        };
+        assert_eq!(ids(params), vec!["live"]);
+        let params = SearchParams {
+            has_approvals: Some(true),
+            ..SearchParams::new(5)
+        };
+        assert_eq!(ids(params), vec!["escalated"]);
+        let params = SearchParams {
+            has_failed_commands: Some(true),
+            ..SearchParams::new(5)
+        };
+        assert_eq!(ids(params), vec!["failing"]);
+        let params = SearchParams {
+            has_live_events: Some(false),
+            has_approvals: Some(false),
+            has_failed_commands: Some(false),
+            ..SearchParams::new(5)
+        };
+        assert_eq!(ids(params), vec!["plain"]);
+    }
+
+    #[test]
+    fn filters_by_referenced_url() {
+        let storage = Storage::open_in_memory().unwrap();
+        let linked = ConversationStats {
+            urls: vec!["https://docs.rs/tokio/latest/tokio/sync/index.html".into()],
+            ..default_stats("linked")
+        };
+        seed_conversation(&storage, "linked", linked, &[1.0, 0.0]);
+        seed_conversation(&storage, "plain", default_stats("plain"), &[1.0, 0.0]);
+
+        let params = SearchParams {
+            url_contains: Some("Docs.rs/tokio"),
+            ..SearchParams::new(5)
+        };
+        let results = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap();
+        let ids: Vec<_> = results.iter().map(|r| r.conversation_id.as_str()).collect();
+        assert_eq!(ids, ["linked"]);
+    }
+
+    #[test]
+    fn boosts_or_restricts_to_pinned_conversations() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "close", default_stats("close"), &[1.0, 0.1]);
+        let pinned = seed_conversation(
+            &storage,
+            "canonical",
+            default_stats("canonical"),
+            &[1.0, 0.3],
+        );
+        assert!(storage.set_pinned(&pinned, true).unwrap());
+        assert!(!storage.set_pinned("missing", true).unwrap());
+
+        let ids = |params: SearchParams<'_>| -> Vec<String> {
+            search_with_vector(&storage, &[1.0, 0.0], &params)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.conversation_id)
+                .collect()
+        };
+        assert_eq!(ids(SearchParams::new(2)), vec!["close", "canonical"]);
+        let params = SearchParams {
+            pinned_boost: 0.1,
+            ..SearchParams::new(2)
+        };
+        assert_eq!(ids(params), vec!["canonical", "close"]);
+        let params = SearchParams {
+            pinned: Some(true),
+            ..SearchParams::new(2)
+        };
+        assert_eq!(ids(params), vec!["canonical"]);
+
+        storage.set_pinned(&pinned, false).unwrap();
+        assert!(storage.pinned_conversations().unwrap().is_empty());
+    }
+
+    #[test]
+    fn writes_results_as_json_lines() {
+        let results = vec![
+            SearchResult {
+                conversation_id: "a".into(),
+                turn_index: 0,
+                score: 0.5,
+                user_text: Some("hi".into()),
+                assistant_text: None,
+                details: None,
+                annotations: Vec::new(),
+            },
+            SearchResult {
+                conversation_id: "b".into(),
+                turn_index: 3,
+                score: 0.25,
+                user_text: None,
+                assistant_text: Some("done".into()),
+                details: None,
+                annotations: Vec::new(),
+            },
+        ];
+        let mut out = Vec::new();
+        write_json_lines(&mut out, &results).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: SearchResult = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first.conversation_id, "a");
+        assert_eq!(first.user_text.as_deref(), Some("hi"));
+        assert!(!lines[0].contains("details"));
+        let second: SearchResult = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second.turn_index, 3);
+    }
+
+    #[test]
+    fn search_iter_streams_scored_candidates_in_key_order() {
+        let storage = Storage::open_in_memory().unwrap();
+        for (id, embedding) in [("a", [1.0, 0.0]), ("b", [0.0, 1.0]), ("c", [1.0, 1.0])] {
+            seed_conversation(&storage, id, default_stats(id), &embedding);
+        }
+        let query = [1.0, 0.0];
+        let params = SearchParams {
+            prefetch: Some(10),
+            ..SearchParams::new(1)
+        };
+        let results: Vec<SearchResult> = search_iter(&storage, &query, &params)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let ids: Vec<&str> = results.iter().map(|r| r.conversation_id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert!((results[0].score - 1.0).abs() < 1e-6);
+
+        let first = search_iter(&storage, &query, &params)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.conversation_id, "a");
+
+        let capped = SearchParams {
+            prefetch: Some(2),
+            ..SearchParams::new(1)
+        };
+        assert_eq!(search_iter(&storage, &query, &capped).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn field_weights_prefer_matches_in_user_text() {
+        let storage = Storage::open_in_memory().unwrap();
+        for (id, user, assistant) in [
+            ("asked", "how do tokio channels work?", "see the docs"),
+            (
+                "boilerplate",
+                "fix the build",
+                "Tokio channels: mpsc, oneshot",
+            ),
+        ] {
+            let conversation_id = seed_conversation(&storage, id, default_stats(id), &[0.0, 1.0]);
+            let turn = TurnRecord {
+                index: 1,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: vec![UserInputRecord {
+                    raw: json!(user),
+                    text: Some(user.to_string()),
+                    images: Vec::new(),
+                    kind: UserInputKind::Prompt,
+                    image_refs: Vec::new(),
+                    files: Vec::new(),
+                }],
+                result: TurnResult {
+                    assistant_messages: vec![assistant.to_string()],
+                    ..TurnResult::default()
+                },
+                actions: Vec::new(),
+                telemetry: TurnTelemetry::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage
+                .insert_turn(&conversation_id, &turn, Some(&[1.0, 0.0]))
+                .unwrap();
+        }
+
+        let query = "tokio channels";
+        let top = |weights: FieldWeights| {
+            let params = SearchParams {
+                field_weights: Some(weights),
+                ..SearchParams::new(1)
+            };
+            run_search(&storage, &[1.0, 0.0], Some(query), None, None, &params).unwrap()[0]
+                .conversation_id
+                .clone()
+        };
+        assert_eq!(
+            top(FieldWeights {
+                user: 0.5,
+                assistant: 0.1
+            }),
+            "asked"
+        );
+        assert_eq!(
+            top(FieldWeights {
+                user: 0.1,
+                assistant: 0.5
+            }),
+            "boilerplate"
+        );
+    }
+
+    #[test]
+    fn dedupe_threshold_collapses_near_identical_turns() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "first", default_stats("first"), &[1.0, 0.0]);
+        seed_conversation(&storage, "rerun", default_stats("rerun"), &[0.99, 0.01]);
+        seed_conversation(&storage, "other", default_stats("other"), &[0.6, 0.8]);
+
+        let ids = |dedupe_threshold: Option<f32>| -> Vec<String> {
+            let params = SearchParams {
+                dedupe_threshold,
+                ..SearchParams::new(2)
+            };
+            search_with_vector(&storage, &[1.0, 0.0], &params)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.conversation_id)
+                .collect()
+        };
+        assert_eq!(ids(None), vec!["first", "rerun"]);
+        assert_eq!(ids(Some(0.98)), vec!["first", "other"]);
+    }
+
+    #[test]
+    fn must_contain_and_regex_filter_result_text() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "walkdir", default_stats("walkdir"), &[1.0, 0.0]);
+        seed_conversation(&storage, "storage", default_stats("storage"), &[1.0, 0.0]);
+
+        let ids = |params: SearchParams<'_>| -> Vec<String> {
+            search_with_vector(&storage, &[1.0, 0.0], &params)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.conversation_id)
+                .collect()
+        };
+        let params = SearchParams {
+            must_contain: vec!["walkdir result"],
+            ..SearchParams::new(5)
+        };
+        assert_eq!(ids(params), vec!["walkdir"]);
+        let params = SearchParams {
+            must_match_regex: Some(r"^stor\w+ result$"),
+            ..SearchParams::new(5)
+        };
+        assert_eq!(ids(params), vec!["storage"]);
+
+        let params = SearchParams {
+            must_match_regex: Some("("),
+            ..SearchParams::new(5)
+        };
+        let err = search_with_vector(&storage, &[1.0, 0.0], &params).unwrap_err();
+        assert!(matches!(err, SearchError::InvalidRegex(_)));
+    }
+
+    #[test]
+    fn strict_dimension_reports_mismatched_embeddings() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed_conversation(&storage, "old", default_stats("old"), &[1.0, 0.0, 0.0]);
+
+        let lenient = SearchParams::new(5);
+        assert!(search_with_vector(&storage, &[1.0, 0.0], &lenient)
+            .unwrap()
+            .is_empty());
+
+        let strict = SearchParams {
+            strict_dimension: true,
+            ..SearchParams::new(5)
+        };
+        let err = search_with_vector(&storage, &[1.0, 0.0], &strict).unwrap_err();
+        assert!(matches!(
+            err,
+            SearchError::DimensionMismatch {
+                query: 2,
+                stored: 3,
+                conversations: 1
+            }
+        ));
+        assert_eq!(
+            search_with_vector(&storage, &[1.0, 0.0, 0.0], &strict)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn searches_the_selected_embedding_space() {
+        let storage = Storage::open_in_memory().unwrap();
+        let alpha = seed_conversation(&storage, "alpha", default_stats("alpha"), &[1.0, 0.0]);
+        let beta = seed_conversation(&storage, "beta", default_stats("beta"), &[0.0, 1.0]);
+        storage
+            .insert_embedding(&alpha, 0, "small", &[0.0, 0.0, 1.0])
+            .unwrap();
+        storage
+            .insert_embedding(&beta, 0, "small", &[1.0, 0.0, 0.0])
+            .unwrap();
+
+        let default = search_with_vector(&storage, &[1.0, 0.0], &SearchParams::new(1)).unwrap();
+        assert_eq!(default[0].conversation_id, "alpha");
+        let params = SearchParams {
+            embedding_model: Some("small"),
+            ..SearchParams::new(1)
+        };
+        let small = search_with_vector(&storage, &[1.0, 0.0, 0.0], &params).unwrap();
+        assert_eq!(small[0].conversation_id, "beta");
+        assert_eq!(
+            storage
+                .embedding_models()
+                .unwrap()
+                .into_iter()
+                .map(|space| (space.model_name, space.dim, space.turns))
+                .collect::<Vec<_>>(),
+            vec![("default".to_string(), 2, 2), ("small".to_string(), 3, 2)]
+        );
+    }
 }
@@ -1,13 +1,33 @@
-use std::path::Path;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 
 use bytemuck::cast_slice;
-use rusqlite::{params, Connection, OpenFlags};
+use half::f16;
+use rusqlite::backup::Backup;
+use rusqlite::{
+    params, Connection, OpenFlags, OptionalExtension, Row, Transaction, TransactionBehavior,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 use time::format_description::well_known::Rfc3339;
+use time::format_description::BorrowedFormatItem;
 use time::OffsetDateTime;
 
-use crate::types::{ConversationRecord, FallbackSource, TokenUsageBreakdown, TurnRecord};
+use crate::embedding::EmbeddingModelInfo;
+use crate::extractor::ResumeState;
+use crate::filter::{Column, Filter};
+use crate::images::decode_data_url;
+use crate::migrations;
+use crate::redact::RedactionRuleInfo;
+use crate::stats::{compute_conversation_stats, DerivationConfig};
+use crate::types::{
+    ConversationRecord, FallbackSource, FallbackSummary, FileAttachment, FileChange, ImageRef,
+    Plan, RolloutVersion, TokenUsageBreakdown, TurnRecord, TurnResult, UserInputKind,
+    UserInputRecord,
+};
 
 /// Errors surfaced by the storage layer.
 #[derive(Error, Debug)]
@@ -16,11 +36,175 @@ pub enum StorageError {
     Sqlite(#[from] rusqlite::Error),
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error(
+        "embedding model mismatch: the database was embedded with {recorded} but {loaded} is \
+         loaded; use the original model or store the new vectors under another embedding model name"
+    )]
+    EmbeddingModelMismatch {
+        recorded: Box<EmbeddingModelInfo>,
+        loaded: Box<EmbeddingModelInfo>,
+    },
+    #[error("invalid filter: {0}")]
+    InvalidFilter(String),
+    #[error("database schema version {found} is newer than the supported version {supported}")]
+    SchemaTooNew { found: i64, supported: i64 },
+    #[error("export target {} already contains conversations", path.display())]
+    ExportTargetNotEmpty { path: PathBuf },
+    #[error("an in-memory database cannot be opened by another connection")]
+    InMemory,
 }
 
+/// Embedding space written by [`Storage::insert_turn`] and searched unless
+/// [`SearchParams::embedding_model`](crate::SearchParams::embedding_model) selects another.
+pub const DEFAULT_EMBEDDING_MODEL: &str = "default";
+
+/// Prepared statements kept per connection; enough for every statement run per imported file
+/// plus the common search and listing queries.
+const STATEMENT_CACHE_CAPACITY: usize = 32;
+
+/// Insert-or-update of a conversation row, run by [`Storage::upsert_conversation`].
+const UPSERT_CONVERSATION_SQL: &str = r#"
+    INSERT INTO conversations
+    (id, rollout_path, started_at, ended_at, duration_seconds, token_input, token_cached,
+     token_output, token_reasoning, token_total, token_model_context, meta_json,
+     rollout_modified_at, rollout_size_bytes, rollout_hash, preview, first_question,
+     last_question, last_user_message, model, turn_count, has_live_events,
+     commands_json, files_json, questions_json, search_blob, cwd, approval_count, auto_title,
+     git_repo, git_branch, summary, file_changes_json, failed_command_count, urls_json,
+     had_errors, rollout_version, compaction_count, last_plan_json)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+            ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29,
+            ?30, ?31, ?32, ?33, ?34, ?35, ?36, ?37, ?38, ?39)
+    ON CONFLICT(id) DO UPDATE SET
+        rollout_path = excluded.rollout_path,
+        started_at = excluded.started_at,
+        ended_at = excluded.ended_at,
+        duration_seconds = excluded.duration_seconds,
+        token_input = excluded.token_input,
+        token_cached = excluded.token_cached,
+        token_output = excluded.token_output,
+        token_reasoning = excluded.token_reasoning,
+        token_total = excluded.token_total,
+        token_model_context = excluded.token_model_context,
+        meta_json = excluded.meta_json,
+        rollout_modified_at = excluded.rollout_modified_at,
+        rollout_size_bytes = excluded.rollout_size_bytes,
+        rollout_hash = excluded.rollout_hash,
+        preview = excluded.preview,
+        first_question = excluded.first_question,
+        last_question = excluded.last_question,
+        last_user_message = excluded.last_user_message,
+        model = excluded.model,
+        turn_count = excluded.turn_count,
+        has_live_events = excluded.has_live_events,
+        commands_json = excluded.commands_json,
+        files_json = excluded.files_json,
+        questions_json = excluded.questions_json,
+        search_blob = excluded.search_blob,
+        cwd = excluded.cwd,
+        approval_count = excluded.approval_count,
+        auto_title = excluded.auto_title,
+        git_repo = excluded.git_repo,
+        git_branch = excluded.git_branch,
+        summary = COALESCE(excluded.summary, conversations.summary),
+        file_changes_json = excluded.file_changes_json,
+        failed_command_count = excluded.failed_command_count,
+        urls_json = excluded.urls_json,
+        had_errors = excluded.had_errors,
+        rollout_version = excluded.rollout_version,
+        compaction_count = excluded.compaction_count,
+        last_plan_json = excluded.last_plan_json
+"#;
+
+/// Insert-or-update of a turn row, run by [`Storage::insert_turn`].
+const UPSERT_TURN_SQL: &str = r#"
+    INSERT INTO turns
+    (conversation_id, turn_index, started_at, user_text, assistant_text, fallback_text,
+     actions_json, telemetry_json, reasoning_json, context_json, ended_at, duration_seconds,
+     images_json, errors_json, source_line_start, source_line_end, input_tokens,
+     cached_input_tokens, output_tokens, reasoning_output_tokens, total_tokens, compactions_json,
+     inputs_json)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+            ?19, ?20, ?21, ?22, ?23)
+    ON CONFLICT(conversation_id, turn_index) DO UPDATE SET
+        started_at = excluded.started_at,
+        ended_at = excluded.ended_at,
+        duration_seconds = excluded.duration_seconds,
+        images_json = excluded.images_json,
+        errors_json = excluded.errors_json,
+        source_line_start = excluded.source_line_start,
+        source_line_end = excluded.source_line_end,
+        input_tokens = excluded.input_tokens,
+        cached_input_tokens = excluded.cached_input_tokens,
+        output_tokens = excluded.output_tokens,
+        reasoning_output_tokens = excluded.reasoning_output_tokens,
+        total_tokens = excluded.total_tokens,
+        compactions_json = excluded.compactions_json,
+        inputs_json = excluded.inputs_json,
+        user_text = excluded.user_text,
+        assistant_text = excluded.assistant_text,
+        fallback_text = excluded.fallback_text,
+        actions_json = excluded.actions_json,
+        telemetry_json = excluded.telemetry_json,
+        reasoning_json = excluded.reasoning_json,
+        context_json = excluded.context_json
+"#;
+
+/// Insert-or-update of one turn's vector in an embedding space.
+const UPSERT_EMBEDDING_SQL: &str = r#"
+    INSERT INTO embeddings
+    (conversation_id, turn_index, model_name, dim, vector, norm, bits)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+    ON CONFLICT(conversation_id, turn_index, model_name) DO UPDATE SET
+        dim = excluded.dim,
+        vector = excluded.vector,
+        norm = excluded.norm,
+        bits = excluded.bits
+"#;
+
+/// Fingerprint lookup run by [`Storage::get_rollout_fingerprint`] for every scanned file.
+const ROLLOUT_FINGERPRINT_SQL: &str = r#"
+    SELECT rollout_modified_at, rollout_size_bytes, rollout_hash
+    FROM conversations
+    WHERE rollout_path = ?1
+    LIMIT 1
+"#;
+
+/// Tables copied by [`Storage::export_conversations`], parents first, with the column that
+/// links each row to its conversation.
+const EXPORT_TABLES: [(&str, &str); 6] = [
+    ("conversations", "id"),
+    ("turns", "conversation_id"),
+    ("embeddings", "conversation_id"),
+    ("annotations", "conversation_id"),
+    ("conversation_commands", "conversation_id"),
+    ("conversation_files", "conversation_id"),
+];
+
+/// Pages copied per step by [`Storage::backup_to`]; the source is unlocked between steps so
+/// concurrent writers are only briefly blocked.
+const BACKUP_PAGES_PER_STEP: std::ffi::c_int = 256;
+
+/// `meta` key holding the JSON [`EmbeddingModelInfo`] of the default embedding space.
+const EMBEDDING_MODEL_KEY: &str = "embedding_model";
+
+/// `meta` key holding the JSON list of [`RedactionRuleInfo`]s applied to imported rollouts.
+const REDACTION_RULES_KEY: &str = "redaction_rules";
+
+/// `meta` key holding the directory that relative `rollout_path`s are anchored to.
+const ROLLOUT_ROOT_KEY: &str = "rollout_root";
+
 /// Simple SQLite-backed persistence for conversations and turn embeddings.
+///
+/// A `Storage` owns one SQLite connection: it can be moved to another thread but not shared
+/// between threads. Multi-threaded servers give each worker its own handle with
+/// [`Storage::clone_connection`], ideally on a database opened in [`JournalMode::Wal`] so
+/// readers do not block each other or the importer.
 pub struct Storage {
     conn: Connection,
+    /// Database file, or `None` for an in-memory database.
+    path: Option<PathBuf>,
+    options: StorageOptions,
 }
 
 /// Fingerprint describing the rollout file that produced a conversation.
@@ -31,9 +215,31 @@ pub struct RolloutFingerprint {
     pub sha256: Option<String>,
 }
 
+/// How often a conversation has been returned by search or fetched, and when it was last touched.
+#[derive(Debug, Clone)]
+pub struct AccessStats {
+    pub conversation_id: String,
+    pub hit_count: i64,
+    pub last_accessed_at: Option<OffsetDateTime>,
+}
+
+/// Ordering applied by [`Storage::access_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessOrder {
+    /// Most frequently recalled conversations first.
+    MostHits,
+    /// Most recently recalled conversations first.
+    MostRecent,
+    /// Least recently recalled conversations first (never-accessed rows lead), for pruning.
+    LeastRecent,
+}
+
 /// Aggregated conversation attributes persisted alongside the base metadata.
 #[derive(Debug, Clone, Default)]
 pub struct ConversationStats {
+    /// Short label derived from the first question or request, or the title an import carries
+    /// (ChatGPT exports), stored as `auto_title`.
+    pub title: Option<String>,
     pub preview: Option<String>,
     pub first_question: Option<String>,
     pub last_question: Option<String>,
@@ -41,30 +247,668 @@ pub struct ConversationStats {
     pub model: Option<String>,
     pub turn_count: i64,
     pub has_live_events: bool,
+    /// Exec / apply_patch approval requests seen across all turns.
+    pub approval_count: i64,
+    /// Commands that exited with a non-zero status across all turns.
+    pub failed_command_count: i64,
+    /// Whether a turn failed with an error or was aborted (retried stream errors aside).
+    pub had_errors: bool,
+    /// History compactions across all turns.
+    pub compaction_count: i64,
+    /// The plan as the last turn with a `plan_update` left it (see
+    /// [`TurnTelemetry::final_plan`](crate::TurnTelemetry::final_plan)).
+    pub last_plan: Option<Plan>,
     pub commands: Vec<String>,
     pub files_touched: Vec<String>,
+    /// Net `apply_patch` changes per file across the conversation, sorted by path; stored as
+    /// `file_changes_json`.
+    pub file_changes: Vec<FileChange>,
+    /// Distinct URLs from assistant messages and web search actions, sorted; stored as
+    /// `urls_json`.
+    pub urls: Vec<String>,
     pub questions: Vec<String>,
     pub search_blob: String,
     pub cwd: Option<String>,
+    /// Repository remote from the session metadata or environment context, normalised to
+    /// `host/path` (e.g. `github.com/org/repo`).
+    pub git_repo: Option<String>,
+    pub git_branch: Option<String>,
+    /// Abstract from the [`Summarizer`](crate::Summarizer), if one ran. Storing `None` keeps
+    /// an existing summary.
+    pub summary: Option<String>,
+}
+
+/// Selection, ordering and paging for [`Storage::list_conversations`].
+#[derive(Debug, Clone)]
+pub struct ListParams<'a> {
+    pub filter: Option<Filter<'a>>,
+    /// Column to sort by; rows where it is NULL come last. Ties are broken by id.
+    pub order_by: Column,
+    pub descending: bool,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl ListParams<'_> {
+    /// Newest conversations first.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            filter: None,
+            order_by: Column::StartedAt,
+            descending: true,
+            limit,
+            offset: 0,
+        }
+    }
+}
+
+impl Default for ListParams<'_> {
+    fn default() -> Self {
+        ListParams::new(50)
+    }
+}
+
+/// Write transaction opened by [`Storage::begin_ingest`]. Writes made through the `Storage`
+/// while it is alive become visible together on [`IngestTransaction::commit`]; dropping it
+/// without committing rolls them all back.
+pub struct IngestTransaction<'a> {
+    tx: Transaction<'a>,
+}
+
+impl IngestTransaction<'_> {
+    pub fn commit(self) -> Result<(), StorageError> {
+        Ok(self.tx.commit()?)
+    }
+}
+
+/// Row returned by [`Storage::list_conversations`], e.g. for a session browser.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    /// Title set with [`Storage::set_title`]; see [`ConversationSummary::label`].
+    pub title: Option<String>,
+    /// Title derived from the first question or request at import.
+    pub auto_title: Option<String>,
+    pub preview: Option<String>,
+    /// Abstract written by the [`Summarizer`](crate::Summarizer) at import.
+    pub summary: Option<String>,
+    pub model: Option<String>,
+    pub cwd: Option<String>,
+    /// Conversation start timestamp, as stored.
+    pub started_at: Option<String>,
+    pub turn_count: i64,
+    pub token_total: Option<i64>,
+    pub pinned: bool,
+    /// Times the conversation was returned by search or fetched; see [`AccessStats`].
+    #[serde(default)]
+    pub hit_count: i64,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_accessed_at: Option<OffsetDateTime>,
+}
+
+/// Columns read by [`summary_from_row`], selected from `conversations c`.
+const SUMMARY_COLUMNS: &str = "c.id, c.preview, c.model, c.cwd, c.started_at, \
+     COALESCE(c.turn_count, 0), c.token_total, c.pinned, c.title, c.auto_title, c.summary, \
+     COALESCE(c.hit_count, 0), c.last_accessed_at";
+
+fn summary_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ConversationSummary> {
+    Ok(ConversationSummary {
+        id: row.get(0)?,
+        preview: row.get(1)?,
+        model: row.get(2)?,
+        cwd: row.get(3)?,
+        started_at: row.get(4)?,
+        turn_count: row.get(5)?,
+        token_total: row.get(6)?,
+        pinned: row.get(7)?,
+        title: row.get(8)?,
+        auto_title: row.get(9)?,
+        summary: row.get(10)?,
+        hit_count: row.get(11)?,
+        last_accessed_at: row
+            .get::<_, Option<String>>(12)?
+            .and_then(|ts| OffsetDateTime::parse(&ts, &Rfc3339).ok()),
+    })
+}
+
+impl ConversationSummary {
+    /// Best available label: the custom title, else the derived one, else the preview.
+    pub fn label(&self) -> Option<&str> {
+        self.title
+            .as_deref()
+            .or(self.auto_title.as_deref())
+            .or(self.preview.as_deref())
+    }
+}
+
+/// Number of conversations using one command or file, from [`Storage::command_usage`] /
+/// [`Storage::file_usage`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageCount {
+    pub value: String,
+    pub conversations: usize,
+}
+
+/// Token usage of the conversations started on one day with one model, from
+/// [`Storage::daily_usage`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyUsage {
+    /// Start day of the conversations as `YYYY-MM-DD`, in the rollout's timestamp offset.
+    pub date: String,
+    pub model: Option<String>,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub conversations: usize,
+}
+
+/// Free-text note attached to a conversation or to one of its turns.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: i64,
+    pub conversation_id: String,
+    /// Annotated turn, or `None` for a note on the whole conversation.
+    pub turn_index: Option<usize>,
+    pub note: String,
+    pub author: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// One embedding space stored in the `embeddings` table, as listed by
+/// [`Storage::embedding_models`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmbeddingSpace {
+    pub model_name: String,
+    pub dim: usize,
+    /// Number of turns embedded in this space.
+    pub turns: usize,
+}
+
+/// Problem reported by [`Storage::check_integrity`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegrityFinding {
+    /// A line of SQLite's own `PRAGMA integrity_check` output (page or index corruption).
+    Corruption(String),
+    /// Turn whose conversation row no longer exists.
+    OrphanTurn {
+        conversation_id: String,
+        turn_index: i64,
+    },
+    /// Embedding whose turn no longer exists.
+    OrphanEmbedding {
+        conversation_id: String,
+        turn_index: i64,
+        model_name: String,
+    },
+    /// Embedding BLOB whose length matches neither the `f32` nor the `f16` encoding of its
+    /// recorded dimension; search skips it.
+    MalformedEmbedding {
+        conversation_id: String,
+        turn_index: i64,
+        model_name: String,
+        dim: i64,
+        bytes: usize,
+    },
+    /// Embeddings of a conversation whose dimension differs from the most common one in
+    /// their embedding space, so queries of that space never match them.
+    DimensionMismatch {
+        conversation_id: String,
+        model_name: String,
+        expected: usize,
+        found: usize,
+        turns: usize,
+    },
+}
+
+/// Outcome of [`Storage::rebuild_derived`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RebuildReport {
+    /// Conversations whose derived columns were recomputed.
+    pub rebuilt: usize,
+    /// Conversations left as they were because some of their turns were stored before schema
+    /// version 25 and lack the columns derivation reads; re-import their rollouts instead.
+    pub skipped: Vec<String>,
+}
+
+/// Transcript of one stored turn, as returned by [`Storage::fetch_turn_window`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredTurn {
+    pub conversation_id: String,
+    pub turn_index: usize,
+    /// Turn start timestamp, as stored.
+    pub started_at: Option<String>,
+    /// Timestamp of the turn's last record, as stored.
+    pub ended_at: Option<String>,
+    pub duration_seconds: Option<i64>,
+    pub user_text: Option<String>,
+    pub assistant_text: Option<String>,
+    pub fallback_text: Option<String>,
+    /// Image attachments of the turn's user messages, when they were stored (see
+    /// [`PipelineConfig::store_images`](crate::PipelineConfig::store_images)).
+    pub images: Vec<ImageRef>,
+    /// Lines of the rollout the turn was parsed from, see [`TurnRecord::source_lines`].
+    pub source_lines: Option<(usize, usize)>,
+    /// Tokens spent during the turn, see [`TurnRecord::token_usage`].
+    pub token_usage: Option<TokenUsageBreakdown>,
+}
+
+/// An image attachment loaded from the image store by [`Storage::image`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredImage {
+    pub mime_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// SQLite `journal_mode` values accepted by [`StorageOptions::journal_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    /// Write-ahead logging: readers do not block the writer, so an import and a search can run
+    /// from separate processes.
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+}
+
+/// SQLite `synchronous` levels accepted by [`StorageOptions::synchronous`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_str(self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+/// How embedding vectors are encoded when written. Reads accept either encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmbeddingPrecision {
+    /// Little-endian `f32` components.
+    #[default]
+    F32,
+    /// Little-endian IEEE half-precision components: half the size, widened to `f32` on read.
+    F16,
+}
+
+/// Connection settings applied by [`Storage::open_with_options`]. Unset values keep SQLite's
+/// defaults.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use conv_memory::{JournalMode, Storage, StorageOptions};
+/// let options = StorageOptions::new()
+///     .journal_mode(JournalMode::Wal)
+///     .busy_timeout(Duration::from_secs(5));
+/// let storage = Storage::open_with_options("conv-memory.sqlite", &options)?;
+/// # Ok::<(), conv_memory::StorageError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StorageOptions {
+    pub journal_mode: Option<JournalMode>,
+    /// How long a statement waits on a locked database before failing with `database is locked`.
+    pub busy_timeout: Option<Duration>,
+    pub synchronous: Option<Synchronous>,
+    /// Page cache size in KiB.
+    pub cache_size_kib: Option<u64>,
+    /// Maximum number of bytes of the database file to memory-map.
+    pub mmap_size: Option<u64>,
+    /// Encoding of embeddings written through this handle.
+    pub embedding_precision: EmbeddingPrecision,
+    /// Count turns loaded with [`Storage::fetch_turn_window`] as accesses, like search hits
+    /// with [`SearchParams::record_access`](crate::SearchParams::record_access). Off by default
+    /// since it makes reads write.
+    pub record_fetch_access: bool,
+    /// SQLCipher key the database is encrypted with; applied before anything else is read.
+    #[cfg(feature = "encryption")]
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+/// Passphrase of a SQLCipher-encrypted database. `Debug` output redacts it.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct EncryptionKey(String);
+
+#[cfg(feature = "encryption")]
+impl EncryptionKey {
+    pub fn new(passphrase: impl Into<String>) -> Self {
+        Self(passphrase.into())
+    }
+
+    fn apply(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.pragma_update(None, "key", &self.0)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl StorageOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn journal_mode(mut self, mode: JournalMode) -> Self {
+        self.journal_mode = Some(mode);
+        self
+    }
+
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = Some(timeout);
+        self
+    }
+
+    pub fn synchronous(mut self, level: Synchronous) -> Self {
+        self.synchronous = Some(level);
+        self
+    }
+
+    pub fn cache_size_kib(mut self, kib: u64) -> Self {
+        self.cache_size_kib = Some(kib);
+        self
+    }
+
+    pub fn mmap_size(mut self, bytes: u64) -> Self {
+        self.mmap_size = Some(bytes);
+        self
+    }
+
+    pub fn embedding_precision(mut self, precision: EmbeddingPrecision) -> Self {
+        self.embedding_precision = precision;
+        self
+    }
+
+    pub fn record_fetch_access(mut self, enabled: bool) -> Self {
+        self.record_fetch_access = enabled;
+        self
+    }
+
+    /// Encrypt the database with SQLCipher under `passphrase` (or open one encrypted with it).
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, passphrase: impl Into<String>) -> Self {
+        self.encryption_key = Some(EncryptionKey::new(passphrase));
+        self
+    }
+
+    fn apply(&self, conn: &Connection) -> Result<(), StorageError> {
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.encryption_key {
+            key.apply(conn)?;
+        }
+        if let Some(timeout) = self.busy_timeout {
+            conn.busy_timeout(timeout)?;
+        }
+        if let Some(mode) = self.journal_mode {
+            // `journal_mode` reports the resulting mode as a row.
+            conn.pragma_update_and_check(None, "journal_mode", mode.as_str(), |_| Ok(()))?;
+        }
+        if let Some(level) = self.synchronous {
+            conn.pragma_update(None, "synchronous", level.as_str())?;
+        }
+        if let Some(kib) = self.cache_size_kib {
+            // Negative values are interpreted by SQLite as KiB rather than pages.
+            conn.pragma_update(None, "cache_size", -(kib.min(i64::MAX as u64) as i64))?;
+        }
+        if let Some(bytes) = self.mmap_size {
+            conn.pragma_update_and_check(
+                None,
+                "mmap_size",
+                bytes.min(i64::MAX as u64) as i64,
+                |_| Ok(()),
+            )?;
+        }
+        Ok(())
+    }
 }
 
 impl Storage {
     /// Open (or create) the database at `path`.
     pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::open_with_options(path, &StorageOptions::default())
+    }
+
+    /// Open (or create) a SQLCipher-encrypted database at `path`. Opening an existing database
+    /// with the wrong key fails with a `file is not a database` SQLite error.
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted(
+        path: impl AsRef<Path>,
+        passphrase: impl Into<String>,
+    ) -> Result<Self, StorageError> {
+        Self::open_with_options(path, &StorageOptions::new().encryption_key(passphrase))
+    }
+
+    /// Open (or create) the database at `path`, applying the given connection settings first.
+    pub fn open_with_options(
+        path: impl AsRef<Path>,
+        options: &StorageOptions,
+    ) -> Result<Self, StorageError> {
+        #[cfg(feature = "sqlite-vec")]
+        crate::ann::register();
+        let path = path.as_ref();
         let conn = Connection::open_with_flags(
             path,
             OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
         )?;
+        options.apply(&conn)?;
         setup_schema(&conn)?;
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            path: Some(path.to_path_buf()),
+            options: options.clone(),
+        })
     }
 
     /// Create an in-memory database. Handy for tests.
     #[cfg(test)]
     pub fn open_in_memory() -> Result<Self, StorageError> {
+        #[cfg(feature = "sqlite-vec")]
+        crate::ann::register();
         let conn = Connection::open_in_memory()?;
         setup_schema(&conn)?;
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            path: None,
+            options: StorageOptions::default(),
+        })
+    }
+
+    /// Open another connection to the same database file with the same [`StorageOptions`],
+    /// e.g. one per worker thread. Fails with [`StorageError::InMemory`] for an in-memory
+    /// database, which cannot be shared between connections.
+    pub fn clone_connection(&self) -> Result<Self, StorageError> {
+        let path = self.path.as_ref().ok_or(StorageError::InMemory)?;
+        Self::open_with_options(path, &self.options)
+    }
+
+    /// Apply any pending schema migrations and return how many ran. [`Storage::open`] already
+    /// does this; fails with [`StorageError::SchemaTooNew`] when the database was written by a
+    /// newer library.
+    pub fn migrate(&self) -> Result<usize, StorageError> {
+        migrations::migrate(&self.conn)
+    }
+
+    /// Schema version recorded in the database (see [`SCHEMA_VERSION`](crate::SCHEMA_VERSION)).
+    pub fn schema_version(&self) -> Result<i64, StorageError> {
+        Ok(migrations::current_version(&self.conn)?)
+    }
+
+    /// Embedding model recorded for the default embedding space, if any.
+    pub fn embedding_model(&self) -> Result<Option<EmbeddingModelInfo>, StorageError> {
+        self.get_meta(EMBEDDING_MODEL_KEY)?
+            .map(|json| serde_json::from_str(&json))
+            .transpose()
+            .map_err(StorageError::from)
+    }
+
+    /// Fail with [`StorageError::EmbeddingModelMismatch`] if a different model was recorded.
+    pub fn check_embedding_model(&self, loaded: &EmbeddingModelInfo) -> Result<(), StorageError> {
+        match self.embedding_model()? {
+            Some(recorded) if recorded != *loaded => Err(StorageError::EmbeddingModelMismatch {
+                recorded: Box::new(recorded),
+                loaded: Box::new(loaded.clone()),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record `info` as the model behind the default embedding space, failing if another model
+    /// was recorded before. The pipeline calls this before storing embeddings.
+    pub fn record_embedding_model(&self, info: &EmbeddingModelInfo) -> Result<(), StorageError> {
+        self.check_embedding_model(info)?;
+        self.set_meta(EMBEDDING_MODEL_KEY, &serde_json::to_string(info)?)
+    }
+
+    /// Every redaction rule that has been applied to rollouts imported into this database, in
+    /// the order first used; empty when nothing was redacted.
+    pub fn redaction_rules(&self) -> Result<Vec<RedactionRuleInfo>, StorageError> {
+        Ok(self
+            .get_meta(REDACTION_RULES_KEY)?
+            .map(|json| serde_json::from_str(&json))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    /// Add the `rules` not recorded yet to [`Storage::redaction_rules`]. The pipeline calls this
+    /// before storing rollouts scrubbed by a [`Redactor`](crate::Redactor).
+    pub fn record_redaction_rules(&self, rules: &[RedactionRuleInfo]) -> Result<(), StorageError> {
+        let mut recorded = self.redaction_rules()?;
+        let before = recorded.len();
+        for rule in rules {
+            if !recorded.contains(rule) {
+                recorded.push(rule.clone());
+            }
+        }
+        if recorded.len() == before {
+            return Ok(());
+        }
+        self.set_meta(REDACTION_RULES_KEY, &serde_json::to_string(&recorded)?)
+    }
+
+    /// Directory that stored rollout paths are relative to, if one was set with
+    /// [`Storage::set_rollout_root`].
+    pub fn rollout_root(&self) -> Result<Option<PathBuf>, StorageError> {
+        Ok(self.get_meta(ROLLOUT_ROOT_KEY)?.map(PathBuf::from))
+    }
+
+    /// Record rollout paths relative to `root` (e.g. `~/.codex/sessions`) so the database keeps
+    /// matching its rollouts after being copied to another machine or a renamed home directory:
+    /// call this again with the new location and fingerprints, updates and deletes by path keep
+    /// working. Stored absolute paths under `root` become relative, paths already relative are
+    /// re-anchored to `root`, and `None` turns relative paths back into absolute ones under the
+    /// previous root. A relative `root` is resolved against the current directory, as are
+    /// relative paths stored while no root was set.
+    pub fn set_rollout_root(&self, root: Option<&Path>) -> Result<(), StorageError> {
+        let previous = self.rollout_root()?;
+        let root = root.map(absolute_path);
+        let tx = self.conn.unchecked_transaction()?;
+        let paths: Vec<(String, String)> = {
+            let mut stmt = tx.prepare("SELECT id, rollout_path FROM conversations")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let mut update = tx.prepare("UPDATE conversations SET rollout_path = ?1 WHERE id = ?2")?;
+        for (id, stored) in paths {
+            let stored_path = Path::new(&stored);
+            let rebased = match root.as_deref() {
+                Some(_) if stored_path.is_relative() && previous.is_some() => None,
+                Some(root) => {
+                    let absolute = absolute_path(stored_path);
+                    match absolute.strip_prefix(root) {
+                        Ok(relative) => Some(relative.to_string_lossy().into_owned()),
+                        Err(_) => Some(absolute.to_string_lossy().into_owned()),
+                    }
+                }
+                None if stored_path.is_relative() => previous
+                    .as_ref()
+                    .map(|previous| previous.join(stored_path).to_string_lossy().into_owned()),
+                None => None,
+            };
+            if let Some(rebased) = rebased.filter(|rebased| *rebased != stored) {
+                update.execute(params![rebased, id])?;
+            }
+        }
+        drop(update);
+        match root.as_deref() {
+            Some(root) => {
+                tx.execute(
+                    "INSERT INTO meta (key, value) VALUES (?1, ?2) \
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                    params![ROLLOUT_ROOT_KEY, root.to_string_lossy()],
+                )?;
+            }
+            None => {
+                tx.execute("DELETE FROM meta WHERE key = ?1", [ROLLOUT_ROOT_KEY])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Absolute location of a stored `rollout_path`, joining relative paths onto the rollout
+    /// root.
+    pub fn resolve_rollout_path(&self, stored: &str) -> Result<PathBuf, StorageError> {
+        Ok(match self.rollout_root()? {
+            Some(root) if Path::new(stored).is_relative() => root.join(stored),
+            _ => PathBuf::from(stored),
+        })
+    }
+
+    /// Form of `path` stored in `conversations.rollout_path`. With a rollout root set it is
+    /// relative to the root when it lies under it and absolute otherwise, so a relative stored
+    /// path always means one under the root; without a root it is unchanged.
+    fn rollout_key(&self, path: &Path) -> Result<String, StorageError> {
+        let Some(root) = self.rollout_root()? else {
+            return Ok(path.to_string_lossy().into_owned());
+        };
+        let path = absolute_path(path);
+        let relative = path.strip_prefix(&root).unwrap_or(&path);
+        Ok(relative.to_string_lossy().into_owned())
+    }
+
+    fn get_meta(&self, key: &str) -> Result<Option<String>, StorageError> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT value FROM meta WHERE key = ?1")?
+            .query_row([key], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn set_meta(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO meta (key, value) VALUES (?1, ?2) \
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            )?
+            .execute(params![key, value])?;
+        Ok(())
     }
 
     /// Insert or update conversation metadata and return the conversation id we stored under.
@@ -84,7 +928,7 @@ impl Storage {
         let meta_json = record
             .session_meta
             .as_ref()
-            .map(|v| serde_json::to_string(v))
+            .map(serde_json::to_string)
             .transpose()?;
 
         let started_at = record.started_at.map(|ts| ts.to_string());
@@ -131,6 +975,13 @@ impl Storage {
         let has_live_events = if stats.has_live_events { 1 } else { 0 };
         let commands_json = serde_json::to_string(&stats.commands)?;
         let files_json = serde_json::to_string(&stats.files_touched)?;
+        let file_changes_json = serde_json::to_string(&stats.file_changes)?;
+        let urls_json = serde_json::to_string(&stats.urls)?;
+        let last_plan_json = stats
+            .last_plan
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
         let questions_json = serde_json::to_string(&stats.questions)?;
         let search_blob = if stats.search_blob.is_empty() {
             None
@@ -139,47 +990,11 @@ impl Storage {
         };
         let cwd = stats.cwd.clone();
 
-        self.conn.execute(
-            r#"
-            INSERT INTO conversations
-            (id, rollout_path, started_at, ended_at, duration_seconds, token_input, token_cached,
-             token_output, token_reasoning, token_total, token_model_context, meta_json,
-             rollout_modified_at, rollout_size_bytes, rollout_hash, preview, first_question,
-             last_question, last_user_message, model, turn_count, has_live_events,
-             commands_json, files_json, questions_json, search_blob, cwd)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
-                    ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27)
-            ON CONFLICT(id) DO UPDATE SET
-                rollout_path = excluded.rollout_path,
-                started_at = excluded.started_at,
-                ended_at = excluded.ended_at,
-                duration_seconds = excluded.duration_seconds,
-                token_input = excluded.token_input,
-                token_cached = excluded.token_cached,
-                token_output = excluded.token_output,
-                token_reasoning = excluded.token_reasoning,
-                token_total = excluded.token_total,
-                token_model_context = excluded.token_model_context,
-                meta_json = excluded.meta_json,
-                rollout_modified_at = excluded.rollout_modified_at,
-                rollout_size_bytes = excluded.rollout_size_bytes,
-                rollout_hash = excluded.rollout_hash,
-                preview = excluded.preview,
-                first_question = excluded.first_question,
-                last_question = excluded.last_question,
-                last_user_message = excluded.last_user_message,
-                model = excluded.model,
-                turn_count = excluded.turn_count,
-                has_live_events = excluded.has_live_events,
-                commands_json = excluded.commands_json,
-                files_json = excluded.files_json,
-                questions_json = excluded.questions_json,
-                search_blob = excluded.search_blob,
-                cwd = excluded.cwd
-            "#,
-            params![
+        self.conn
+            .prepare_cached(UPSERT_CONVERSATION_SQL)?
+            .execute(params![
                 conversation_id,
-                rollout_path.to_string_lossy(),
+                self.rollout_key(rollout_path)?,
                 started_at,
                 ended_at,
                 duration_seconds,
@@ -205,13 +1020,26 @@ impl Storage {
                 questions_json,
                 search_blob,
                 cwd,
-            ],
-        )?;
+                stats.approval_count,
+                stats.title,
+                stats.git_repo,
+                stats.git_branch,
+                stats.summary,
+                file_changes_json,
+                stats.failed_command_count,
+                urls_json,
+                stats.had_errors,
+                record.rollout_version.map(RolloutVersion::name),
+                stats.compaction_count,
+                last_plan_json,
+            ])?;
+        replace_commands_and_files(&self.conn, &conversation_id, stats)?;
 
         Ok(conversation_id)
     }
 
-    /// Persist a turn and its embedding.
+    /// Persist a turn and its embedding in the [`DEFAULT_EMBEDDING_MODEL`] space (`None` removes
+    /// a previously stored one).
     pub fn insert_turn(
         &self,
         conversation_id: &str,
@@ -219,110 +1047,1297 @@ impl Storage {
         embedding: Option<&[f32]>,
     ) -> Result<(), StorageError> {
         let started_at = turn.started_at.map(|ts| ts.to_string());
+        let ended_at = turn.ended_at.map(|ts| ts.to_string());
         let user_text = join_user_inputs(turn);
+        let inputs_json = serde_json::to_string(&stored_inputs(turn))?;
         let assistant_text = join_assistant_messages(turn);
-        let fallback_text = turn.result.fallback.as_ref().map(|f| format_fallback(f));
+        let fallback_text = turn.result.fallback.as_ref().map(format_fallback);
         let actions_json = serde_json::to_string(&turn.actions)?;
         let telemetry_json = serde_json::to_string(&turn.telemetry)?;
+        let reasoning_json = if turn.result.reasoning_summaries.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&turn.result.reasoning_summaries)?)
+        };
+        let context_json = turn
+            .context
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let image_refs: Vec<&ImageRef> = turn
+            .user_inputs
+            .iter()
+            .flat_map(|input| &input.image_refs)
+            .collect();
+        let images_json = if image_refs.is_empty() {
+            None
+        } else {
+            self.store_images(turn)?;
+            Some(serde_json::to_string(&image_refs)?)
+        };
+        let errors_json = if turn.errors.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&turn.errors)?)
+        };
+        let compactions_json = if turn.compactions.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&turn.compactions)?)
+        };
 
-        let embedding_blob = embedding.map(|vec| cast_slice::<f32, u8>(vec).to_vec());
+        let usage = turn.token_usage.as_ref();
+        self.conn.prepare_cached(UPSERT_TURN_SQL)?.execute(params![
+            conversation_id,
+            turn.index as i64,
+            started_at,
+            user_text,
+            assistant_text,
+            fallback_text,
+            actions_json,
+            telemetry_json,
+            reasoning_json,
+            context_json,
+            ended_at,
+            turn.duration_seconds.map(|secs| secs as i64),
+            images_json,
+            errors_json,
+            turn.source_lines.map(|(start, _)| start as i64),
+            turn.source_lines.map(|(_, end)| end as i64),
+            usage.and_then(|usage| usage.input_tokens).map(|n| n as i64),
+            usage
+                .and_then(|usage| usage.cached_input_tokens)
+                .map(|n| n as i64),
+            usage
+                .and_then(|usage| usage.output_tokens)
+                .map(|n| n as i64),
+            usage
+                .and_then(|usage| usage.reasoning_output_tokens)
+                .map(|n| n as i64),
+            usage.and_then(|usage| usage.total_tokens).map(|n| n as i64),
+            compactions_json,
+            inputs_json,
+        ])?;
 
-        self.conn.execute(
-            r#"
-            INSERT INTO turns
-            (conversation_id, turn_index, started_at, user_text, assistant_text, fallback_text,
-             actions_json, telemetry_json, embedding)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-            ON CONFLICT(conversation_id, turn_index) DO UPDATE SET
-                started_at = excluded.started_at,
-                user_text = excluded.user_text,
-                assistant_text = excluded.assistant_text,
-                fallback_text = excluded.fallback_text,
-                actions_json = excluded.actions_json,
-                telemetry_json = excluded.telemetry_json,
-                embedding = excluded.embedding
-            "#,
-            params![
-                conversation_id,
-                turn.index as i64,
-                started_at,
-                user_text,
-                assistant_text,
-                fallback_text,
-                actions_json,
-                telemetry_json,
-                embedding_blob,
-            ],
-        )?;
+        self.store_embedding(
+            conversation_id,
+            turn.index,
+            DEFAULT_EMBEDDING_MODEL,
+            embedding,
+        )
+    }
 
-        if let Some(embedding) = embedding {
-            let dim = embedding.len() as i64;
-            self.conn.execute(
-                "UPDATE conversations SET embedding_dim = ?1 WHERE id = ?2 AND (embedding_dim IS NULL OR embedding_dim = ?1)",
-                params![dim, conversation_id],
-            )?;
+    /// Keep one copy of each referenced inline image of `turn` in the image store.
+    fn store_images(&self, turn: &TurnRecord) -> Result<(), StorageError> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR IGNORE INTO images (sha256, mime_type, size_bytes, data) \
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for input in &turn.user_inputs {
+            let inline = input.images.iter().filter_map(|url| decode_data_url(url));
+            for (image, (_, data)) in input.image_refs.iter().zip(inline) {
+                stmt.execute(params![
+                    image.sha256,
+                    image.mime_type,
+                    image.size_bytes as i64,
+                    data
+                ])?;
+            }
         }
-
         Ok(())
     }
 
-    /// Expose raw connection for advanced queries.
-    pub fn connection(&self) -> &Connection {
-        &self.conn
+    /// An image attachment by the SHA-256 in its [`ImageRef`], or `None` when it is not stored.
+    pub fn image(&self, sha256: &str) -> Result<Option<StoredImage>, StorageError> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT mime_type, data FROM images WHERE sha256 = ?1")?
+            .query_row(params![sha256], |row| {
+                Ok(StoredImage {
+                    mime_type: row.get(0)?,
+                    data: row.get(1)?,
+                })
+            })
+            .optional()?)
     }
 
-    /// Fetch stored fingerprint information for a rollout path, if present.
-    pub fn get_rollout_fingerprint(
+    /// Remove stored images no turn refers to any more, e.g. after conversations were deleted.
+    /// Returns the number removed.
+    pub fn prune_images(&self) -> Result<usize, StorageError> {
+        Ok(self.conn.execute(
+            "DELETE FROM images WHERE sha256 NOT IN \
+             (SELECT json_extract(j.value, '$.sha256') FROM turns t, json_each(t.images_json) j \
+              WHERE t.images_json IS NOT NULL)",
+            [],
+        )?)
+    }
+
+    /// Store an additional embedding of an existing turn under `model_name`, so vectors from
+    /// several embedding models can live side by side and be searched separately.
+    pub fn insert_embedding(
         &self,
-        rollout_path: impl AsRef<Path>,
-    ) -> Result<Option<RolloutFingerprint>, StorageError> {
+        conversation_id: &str,
+        turn_index: usize,
+        model_name: &str,
+        embedding: &[f32],
+    ) -> Result<(), StorageError> {
+        self.store_embedding(conversation_id, turn_index, model_name, Some(embedding))
+    }
+
+    /// List the embedding spaces present in the database with their dimension and size.
+    pub fn embedding_models(&self) -> Result<Vec<EmbeddingSpace>, StorageError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT model_name, dim, count(*) FROM embeddings \
+             GROUP BY model_name, dim ORDER BY model_name, dim",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(EmbeddingSpace {
+                model_name: row.get(0)?,
+                dim: row.get::<_, i64>(1)? as usize,
+                turns: row.get::<_, i64>(2)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Delete every vector stored under `model_name` and return how many were removed.
+    pub fn delete_embedding_space(&self, model_name: &str) -> Result<usize, StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let removed = tx.execute(
+            "DELETE FROM embeddings WHERE model_name = ?1",
+            params![model_name],
+        )?;
+        if model_name == DEFAULT_EMBEDDING_MODEL {
+            reset_default_embedding_space(&tx)?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Make the vectors stored under `model_name` the default embedding space, replacing the
+    /// current one in a single transaction, and record `info` as the model that produced them.
+    /// Used to switch embedding models without re-importing: embed into a side space first,
+    /// then promote it. Returns the number of vectors promoted. In-memory
+    /// [`VectorIndex`](crate::VectorIndex)es built from the old space must be rebuilt.
+    pub fn promote_embedding_space(
+        &self,
+        model_name: &str,
+        info: &EmbeddingModelInfo,
+    ) -> Result<usize, StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM embeddings WHERE model_name = ?1",
+            params![DEFAULT_EMBEDDING_MODEL],
+        )?;
+        let promoted = tx.execute(
+            "UPDATE embeddings SET model_name = ?1 WHERE model_name = ?2",
+            params![DEFAULT_EMBEDDING_MODEL, model_name],
+        )?;
+        reset_default_embedding_space(&tx)?;
+        self.set_meta(EMBEDDING_MODEL_KEY, &serde_json::to_string(info)?)?;
+        tx.commit()?;
+        Ok(promoted)
+    }
+
+    /// Conversations with turns that have no vector in the default embedding space, ordered by
+    /// id, with their resolved rollout path and the indices of those turns.
+    pub(crate) fn turns_missing_embeddings(
+        &self,
+    ) -> Result<Vec<(String, PathBuf, Vec<usize>)>, StorageError> {
         let mut stmt = self.conn.prepare(
             r#"
-            SELECT rollout_modified_at, rollout_size_bytes, rollout_hash
-            FROM conversations
-            WHERE rollout_path = ?1
-            LIMIT 1
+            SELECT t.conversation_id, c.rollout_path, t.turn_index
+            FROM turns t
+            JOIN conversations c ON c.id = t.conversation_id
+            WHERE NOT EXISTS (
+                SELECT 1 FROM embeddings e
+                WHERE e.conversation_id = t.conversation_id
+                  AND e.turn_index = t.turn_index
+                  AND e.model_name = ?1
+            )
+            ORDER BY t.conversation_id, t.turn_index
             "#,
         )?;
-        let mut rows = stmt.query(params![rollout_path.as_ref().to_string_lossy()])?;
-        if let Some(row) = rows.next()? {
-            let modified_at: Option<String> = row.get(0)?;
-            let size_bytes: Option<i64> = row.get(1)?;
-            let sha256: Option<String> = row.get(2)?;
-            let parsed_modified =
-                modified_at.and_then(|ts| OffsetDateTime::parse(&ts, &Rfc3339).ok());
-            Ok(Some(RolloutFingerprint {
-                modified_at: parsed_modified,
-                size_bytes: size_bytes.map(|v| v as u64),
-                sha256,
-            }))
-        } else {
-            Ok(None)
+        let mut rows = stmt.query(params![DEFAULT_EMBEDDING_MODEL])?;
+        let mut missing: Vec<(String, PathBuf, Vec<usize>)> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let conversation_id: String = row.get(0)?;
+            let turn_index = row.get::<_, i64>(2)?.max(0) as usize;
+            match missing.last_mut() {
+                Some((id, _, turns)) if *id == conversation_id => turns.push(turn_index),
+                _ => {
+                    let path = self.resolve_rollout_path(&row.get::<_, String>(1)?)?;
+                    missing.push((conversation_id, path, vec![turn_index]));
+                }
+            }
         }
+        Ok(missing)
     }
-}
 
-fn approximate_input_tokens(record: &ConversationRecord) -> Option<i64> {
-    let total: i64 = record
-        .turns
-        .iter()
-        .flat_map(|turn| turn.user_inputs.iter())
-        .filter_map(|input| input.text.as_ref())
-        .map(|text| estimate_token_count(text))
-        .sum();
-    if total > 0 {
-        Some(total)
-    } else {
-        None
+    /// Id and resolved rollout path of every stored conversation, ordered by id.
+    pub(crate) fn conversation_rollouts(&self) -> Result<Vec<(String, PathBuf)>, StorageError> {
+        let stored: Vec<(String, String)> = {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT id, rollout_path FROM conversations ORDER BY id")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        stored
+            .into_iter()
+            .map(|(id, path)| Ok((id, self.resolve_rollout_path(&path)?)))
+            .collect()
     }
-}
 
-fn approximate_output_tokens(record: &ConversationRecord) -> Option<i64> {
-    let total: i64 = record
-        .turns
-        .iter()
-        .map(|turn| {
-            let assistant: i64 = turn
+    /// Upsert (or, for `None`, remove) the embedding of a turn in one space. The default space
+    /// also maintains `conversations.embedding_dim` and the `sqlite-vec` index.
+    fn store_embedding(
+        &self,
+        conversation_id: &str,
+        turn_index: usize,
+        model_name: &str,
+        embedding: Option<&[f32]>,
+    ) -> Result<(), StorageError> {
+        let embedding = embedding.filter(|vec| !vec.is_empty());
+        match embedding {
+            Some(vector) => {
+                let (blob, stored) = encode_embedding(vector, self.options.embedding_precision);
+                self.conn
+                    .prepare_cached(UPSERT_EMBEDDING_SQL)?
+                    .execute(params![
+                        conversation_id,
+                        turn_index as i64,
+                        model_name,
+                        vector.len() as i64,
+                        blob,
+                        l2_norm(&stored) as f64,
+                        sign_bits(&stored),
+                    ])?;
+            }
+            None => {
+                self.conn
+                    .prepare_cached(
+                        "DELETE FROM embeddings \
+                         WHERE conversation_id = ?1 AND turn_index = ?2 AND model_name = ?3",
+                    )?
+                    .execute(params![conversation_id, turn_index as i64, model_name])?;
+            }
+        }
+        if model_name != DEFAULT_EMBEDDING_MODEL {
+            return Ok(());
+        }
+
+        #[cfg(feature = "sqlite-vec")]
+        {
+            let rowid: i64 = self
+                .conn
+                .prepare_cached(
+                    "SELECT rowid FROM turns WHERE conversation_id = ?1 AND turn_index = ?2",
+                )?
+                .query_row(params![conversation_id, turn_index as i64], |row| {
+                    row.get(0)
+                })?;
+            crate::ann::sync(&self.conn, rowid, embedding)?;
+        }
+
+        if let Some(embedding) = embedding {
+            let dim = embedding.len() as i64;
+            self.conn
+                .prepare_cached(
+                    "UPDATE conversations SET embedding_dim = ?1 \
+                     WHERE id = ?2 AND (embedding_dim IS NULL OR embedding_dim = ?1)",
+                )?
+                .execute(params![dim, conversation_id])?;
+        }
+
+        Ok(())
+    }
+
+    /// Start a write transaction covering a whole rollout: call
+    /// [`Storage::upsert_conversation`] and [`Storage::insert_turn`] as usual, then commit the
+    /// returned guard. Batching the writes is much faster than committing each turn, and a
+    /// process killed mid-file leaves no half-written conversation behind. The write lock is
+    /// taken immediately, so embed turns before starting. Methods that open their own
+    /// transaction (deletes, `rebuild_derived`, ...) cannot be called until it ends.
+    pub fn begin_ingest(&self) -> Result<IngestTransaction<'_>, StorageError> {
+        Ok(IngestTransaction {
+            tx: Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)?,
+        })
+    }
+
+    /// Remove the turns of `conversation_id` whose index is `turn_count` or higher, with their
+    /// embeddings and turn annotations, so a re-imported rollout that shrank (truncated or
+    /// compacted) leaves no stale turns behind. Returns the number of turns removed.
+    pub fn truncate_turns(
+        &self,
+        conversation_id: &str,
+        turn_count: usize,
+    ) -> Result<usize, StorageError> {
+        delete_turn_rows(&self.conn, conversation_id, turn_count)
+    }
+
+    /// Bump hit counters and `last_accessed_at` for the given `(conversation_id, turn_index)` pairs,
+    /// e.g. for turns an application showed without going through search.
+    pub fn record_access(&self, hits: &[(&str, usize)]) -> Result<(), StorageError> {
+        if hits.is_empty() {
+            return Ok(());
+        }
+        let now = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        let tx = self.conn.unchecked_transaction()?;
+        let mut seen: Vec<&str> = Vec::new();
+        for (conversation_id, turn_index) in hits {
+            tx.execute(
+                "UPDATE turns SET hit_count = COALESCE(hit_count, 0) + 1, last_accessed_at = ?1 \
+                 WHERE conversation_id = ?2 AND turn_index = ?3",
+                params![now, conversation_id, *turn_index as i64],
+            )?;
+            if !seen.contains(conversation_id) {
+                seen.push(conversation_id);
+                tx.execute(
+                    "UPDATE conversations SET hit_count = COALESCE(hit_count, 0) + 1, \
+                     last_accessed_at = ?1 WHERE id = ?2",
+                    params![now, conversation_id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// List per-conversation access statistics, ordered for "frequently recalled" views or
+    /// usage-aware pruning.
+    pub fn access_stats(
+        &self,
+        order: AccessOrder,
+        limit: usize,
+    ) -> Result<Vec<AccessStats>, StorageError> {
+        let order_by = match order {
+            AccessOrder::MostHits => "COALESCE(hit_count, 0) DESC, last_accessed_at DESC",
+            AccessOrder::MostRecent => "last_accessed_at IS NULL, last_accessed_at DESC",
+            AccessOrder::LeastRecent => "last_accessed_at IS NOT NULL, last_accessed_at ASC",
+        };
+        let sql = format!(
+            "SELECT id, COALESCE(hit_count, 0), last_accessed_at FROM conversations \
+             ORDER BY {order_by}, id LIMIT ?1"
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            let last_accessed_at: Option<String> = row.get(2)?;
+            Ok(AccessStats {
+                conversation_id: row.get(0)?,
+                hit_count: row.get(1)?,
+                last_accessed_at: last_accessed_at
+                    .and_then(|ts| OffsetDateTime::parse(&ts, &Rfc3339).ok()),
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// List conversation summaries matching `params.filter`, sorted and paged.
+    pub fn list_conversations(
+        &self,
+        params: &ListParams<'_>,
+    ) -> Result<Vec<ConversationSummary>, StorageError> {
+        let mut sql = format!("SELECT {SUMMARY_COLUMNS} FROM conversations c");
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(filter) = &params.filter {
+            sql.push_str(" WHERE ");
+            filter
+                .compile(&mut sql, &mut values)
+                .map_err(|err| StorageError::InvalidFilter(err.to_string()))?;
+        }
+        let column = params.order_by.sql();
+        let direction = if params.descending { "DESC" } else { "ASC" };
+        sql.push_str(&format!(
+            " ORDER BY {column} IS NULL, {column} {direction}, c.id LIMIT ? OFFSET ?"
+        ));
+        values.push((params.limit.min(i64::MAX as usize) as i64).into());
+        values.push((params.offset.min(i64::MAX as usize) as i64).into());
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values.iter()), summary_from_row)?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Conversations that touched a file matching the GLOB `pattern` (e.g. `*src/storage.rs`;
+    /// a plain path matches exactly), most recently started first.
+    pub fn conversations_touching(
+        &self,
+        pattern: &str,
+    ) -> Result<Vec<ConversationSummary>, StorageError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {SUMMARY_COLUMNS} FROM conversations c WHERE c.id IN \
+             (SELECT conversation_id FROM conversation_files WHERE path GLOB ?1) \
+             ORDER BY c.started_at IS NULL, c.started_at DESC, c.id"
+        ))?;
+        let rows = stmt.query_map([pattern], summary_from_row)?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Conversations that ran `command` (the program name, e.g. `cargo`), most recently
+    /// started first.
+    pub fn conversations_running(
+        &self,
+        command: &str,
+    ) -> Result<Vec<ConversationSummary>, StorageError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {SUMMARY_COLUMNS} FROM conversations c WHERE c.id IN \
+             (SELECT conversation_id FROM conversation_commands WHERE command = ?1) \
+             ORDER BY c.started_at IS NULL, c.started_at DESC, c.id"
+        ))?;
+        let rows = stmt.query_map([command], summary_from_row)?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Number of conversations that ran each command, most used first (ties by name).
+    pub fn command_usage(&self, limit: usize) -> Result<Vec<UsageCount>, StorageError> {
+        self.usage_counts("conversation_commands", "command", limit)
+    }
+
+    /// Number of conversations that touched each file, most touched first (ties by path).
+    pub fn file_usage(&self, limit: usize) -> Result<Vec<UsageCount>, StorageError> {
+        self.usage_counts("conversation_files", "path", limit)
+    }
+
+    /// Token usage per day and model from the `usage_daily` rollup, oldest day first, limited
+    /// to days between `since` and `until` (inclusive `YYYY-MM-DD` dates) when given. A
+    /// conversation counts towards the day it started, whole.
+    pub fn daily_usage(
+        &self,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Vec<DailyUsage>, StorageError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT date, model, input_tokens, output_tokens, conversations FROM usage_daily \
+             WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2) \
+             ORDER BY date, model",
+        )?;
+        let rows = stmt.query_map(params![since, until], |row| {
+            let model: String = row.get(1)?;
+            Ok(DailyUsage {
+                date: row.get(0)?,
+                model: (!model.is_empty()).then_some(model),
+                input_tokens: row.get(2)?,
+                output_tokens: row.get(3)?,
+                conversations: row.get::<_, i64>(4)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn usage_counts(
+        &self,
+        table: &str,
+        column: &str,
+        limit: usize,
+    ) -> Result<Vec<UsageCount>, StorageError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {column}, COUNT(*) AS uses FROM {table} GROUP BY {column} \
+             ORDER BY uses DESC, {column} LIMIT ?1"
+        ))?;
+        let rows = stmt.query_map([limit.min(i64::MAX as usize) as i64], |row| {
+            Ok(UsageCount {
+                value: row.get(0)?,
+                conversations: row.get::<_, i64>(1)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Load the turns from `turn_index - before` to `turn_index + after` (clamped to the
+    /// conversation) in order, e.g. to give a search hit its surrounding context. With
+    /// [`StorageOptions::record_fetch_access`] the loaded turns are counted as accessed.
+    pub fn fetch_turn_window(
+        &self,
+        conversation_id: &str,
+        turn_index: usize,
+        before: usize,
+        after: usize,
+    ) -> Result<Vec<StoredTurn>, StorageError> {
+        let first = turn_index.saturating_sub(before) as i64;
+        let last = turn_index.saturating_add(after).min(i64::MAX as usize) as i64;
+        let mut stmt = self.conn.prepare(
+            "SELECT turn_index, started_at, user_text, assistant_text, fallback_text, ended_at, \
+             duration_seconds, images_json, source_line_start, source_line_end, input_tokens, \
+             cached_input_tokens, output_tokens, reasoning_output_tokens, total_tokens FROM turns \
+             WHERE conversation_id = ?1 AND turn_index BETWEEN ?2 AND ?3 ORDER BY turn_index",
+        )?;
+        let rows = stmt.query_map(params![conversation_id, first, last], |row| {
+            let turn_index: i64 = row.get(0)?;
+            let images_json: Option<String> = row.get(7)?;
+            Ok(StoredTurn {
+                conversation_id: conversation_id.to_string(),
+                turn_index: turn_index.max(0) as usize,
+                started_at: row.get(1)?,
+                ended_at: row.get(5)?,
+                duration_seconds: row.get(6)?,
+                user_text: row.get(2)?,
+                assistant_text: row.get(3)?,
+                fallback_text: row.get(4)?,
+                images: images_json
+                    .and_then(|json| serde_json::from_str(&json).ok())
+                    .unwrap_or_default(),
+                source_lines: line_range(row.get(8)?, row.get(9)?),
+                token_usage: token_usage(row, 10)?,
+            })
+        })?;
+        let turns = rows.collect::<Result<Vec<_>, _>>()?;
+        if self.options.record_fetch_access {
+            let hits: Vec<(&str, usize)> = turns
+                .iter()
+                .map(|turn| (conversation_id, turn.turn_index))
+                .collect();
+            self.record_access(&hits)?;
+        }
+        Ok(turns)
+    }
+
+    /// Pin or unpin a conversation, e.g. a session kept as a canonical reference. Pins survive
+    /// re-imports. Returns `false` when no such conversation exists.
+    pub fn set_pinned(&self, conversation_id: &str, pinned: bool) -> Result<bool, StorageError> {
+        let updated = self.conn.execute(
+            "UPDATE conversations SET pinned = ?1 WHERE id = ?2",
+            params![pinned, conversation_id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Give a conversation a custom title, e.g. when its auto-derived preview is a poor label
+    /// for a long session, or clear it with `None`. Titles survive re-imports. Returns `false`
+    /// when no such conversation exists.
+    pub fn set_title(
+        &self,
+        conversation_id: &str,
+        title: Option<&str>,
+    ) -> Result<bool, StorageError> {
+        let updated = self.conn.execute(
+            "UPDATE conversations SET title = ?1 WHERE id = ?2",
+            params![title, conversation_id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Summaries of every pinned conversation, most recently started first.
+    pub fn pinned_conversations(&self) -> Result<Vec<ConversationSummary>, StorageError> {
+        self.list_conversations(&ListParams {
+            filter: Some(Filter::eq(Column::Pinned, true)),
+            ..ListParams::new(usize::MAX)
+        })
+    }
+
+    /// Attach a note to `conversation_id`, or to one of its turns when `turn_index` is set.
+    /// Returns the new annotation's id.
+    pub fn add_annotation(
+        &self,
+        conversation_id: &str,
+        turn_index: Option<usize>,
+        note: &str,
+        author: Option<&str>,
+    ) -> Result<i64, StorageError> {
+        let now = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        self.conn.execute(
+            "INSERT INTO annotations (conversation_id, turn_index, note, author, created_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                conversation_id,
+                turn_index.map(|idx| idx as i64),
+                note,
+                author,
+                now
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Every annotation of a conversation (on the conversation itself and on its turns), oldest
+    /// first.
+    pub fn annotations(&self, conversation_id: &str) -> Result<Vec<Annotation>, StorageError> {
+        self.query_annotations("WHERE conversation_id = ?1", params![conversation_id])
+    }
+
+    /// Annotations that apply to one turn: notes on the turn plus notes on its conversation.
+    pub fn turn_annotations(
+        &self,
+        conversation_id: &str,
+        turn_index: usize,
+    ) -> Result<Vec<Annotation>, StorageError> {
+        self.query_annotations(
+            "WHERE conversation_id = ?1 AND (turn_index IS NULL OR turn_index = ?2)",
+            params![conversation_id, turn_index as i64],
+        )
+    }
+
+    /// Remove one annotation. Returns `false` when no annotation has that id.
+    pub fn delete_annotation(&self, id: i64) -> Result<bool, StorageError> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM annotations WHERE id = ?1", params![id])?;
+        Ok(deleted > 0)
+    }
+
+    fn query_annotations(
+        &self,
+        condition: &str,
+        values: impl rusqlite::Params,
+    ) -> Result<Vec<Annotation>, StorageError> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT id, conversation_id, turn_index, note, author, created_at FROM annotations \
+             {condition} ORDER BY created_at, id"
+        ))?;
+        let rows = stmt.query_map(values, |row| {
+            let turn_index: Option<i64> = row.get(2)?;
+            let created_at: String = row.get(5)?;
+            Ok(Annotation {
+                id: row.get(0)?,
+                conversation_id: row.get(1)?,
+                turn_index: turn_index.map(|idx| idx as usize),
+                note: row.get(3)?,
+                author: row.get(4)?,
+                created_at: OffsetDateTime::parse(&created_at, &Rfc3339)
+                    .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Remove a conversation together with its turns, embeddings and annotations (including the
+    /// `sqlite-vec` index) in one transaction. Returns `false` when no such conversation exists.
+    pub fn delete_conversation(&self, conversation_id: &str) -> Result<bool, StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let deleted = delete_conversation_rows(&tx, conversation_id)?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Remove every conversation imported from `rollout_path`, as [`Storage::delete_conversation`]
+    /// does. Returns the number of conversations removed.
+    pub fn delete_by_rollout_path(
+        &self,
+        rollout_path: impl AsRef<Path>,
+    ) -> Result<usize, StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let ids: Vec<String> = {
+            let mut stmt = tx.prepare("SELECT id FROM conversations WHERE rollout_path = ?1")?;
+            let rows = stmt
+                .query_map(params![self.rollout_key(rollout_path.as_ref())?], |row| {
+                    row.get(0)
+                })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        for id in &ids {
+            delete_conversation_rows(&tx, id)?;
+        }
+        tx.commit()?;
+        Ok(ids.len())
+    }
+
+    /// Resolved rollout paths of conversations whose rollout content hashed to `sha256`.
+    pub fn rollout_paths_with_hash(&self, sha256: &str) -> Result<Vec<PathBuf>, StorageError> {
+        let stored: Vec<String> = {
+            let mut stmt = self.conn.prepare(
+                "SELECT DISTINCT rollout_path FROM conversations WHERE rollout_hash = ?1",
+            )?;
+            let rows = stmt.query_map([sha256], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        stored
+            .iter()
+            .map(|path| self.resolve_rollout_path(path))
+            .collect()
+    }
+
+    /// Point every conversation imported from `from` at `to` after the rollout was moved or
+    /// renamed, recording `fingerprint` for the file at its new location. Turns, embeddings,
+    /// annotations and pins are kept. Returns the number of conversations updated.
+    pub fn move_rollout(
+        &self,
+        from: impl AsRef<Path>,
+        to: impl AsRef<Path>,
+        fingerprint: &RolloutFingerprint,
+    ) -> Result<usize, StorageError> {
+        let modified_at = fingerprint
+            .modified_at
+            .and_then(|ts| ts.format(&Rfc3339).ok());
+        let size_bytes = fingerprint.size_bytes.map(|v| v as i64);
+        Ok(self.conn.execute(
+            "UPDATE conversations SET rollout_path = ?1, rollout_modified_at = ?2, \
+             rollout_size_bytes = ?3, rollout_hash = ?4 WHERE rollout_path = ?5",
+            params![
+                self.rollout_key(to.as_ref())?,
+                modified_at,
+                size_bytes,
+                fingerprint.sha256,
+                self.rollout_key(from.as_ref())?,
+            ],
+        )?)
+    }
+
+    /// Recompute the derived data of every conversation from the stored turns, without
+    /// re-reading rollouts or re-embedding. Pass the
+    /// [`PipelineConfig::derivation`](crate::PipelineConfig::derivation) of the import so stored
+    /// question counts and context handling match.
+    ///
+    /// Rebuilt: the derived conversation columns (title, preview, questions, commands, files,
+    /// file changes, URLs, error and compaction counts, last plan, search blob, live-event
+    /// flag, ...) and the command and file lookup tables; missing embedding norms and quantized
+    /// copies are backfilled, and with the `sqlite-vec` feature the KNN index is repopulated.
+    /// Not rebuilt: turns, embeddings, summaries and the columns copied from the rollout
+    /// (timestamps, token totals, rollout metadata). There is no full-text or entity index to
+    /// rebuild, and conversation centroids are computed at query time by
+    /// [`find_similar_conversations`](crate::find_similar_conversations).
+    ///
+    /// Conversations with turns stored before schema version 25 are skipped and keep their
+    /// derived values: their rows may lack the reasoning summaries, turn context and per-input
+    /// kinds derivation reads, so recomputing would lose information. Re-import those.
+    pub fn rebuild_derived(
+        &self,
+        config: &DerivationConfig,
+    ) -> Result<RebuildReport, StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let conversations: Vec<(String, Option<String>, bool)> = {
+            let mut stmt = tx.prepare(
+                "SELECT c.id, c.meta_json, EXISTS (SELECT 1 FROM turns t \
+                 WHERE t.conversation_id = c.id AND t.inputs_json IS NULL) \
+                 FROM conversations c ORDER BY c.id",
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+
+        let mut report = RebuildReport::default();
+        for (conversation_id, meta_json, incomplete) in conversations {
+            if incomplete {
+                report.skipped.push(conversation_id);
+                continue;
+            }
+            let record = ConversationRecord {
+                session_meta: meta_json.as_deref().map(serde_json::from_str).transpose()?,
+                turns: self.load_turn_records(&conversation_id)?,
+                ..ConversationRecord::default()
+            };
+            let stats = compute_conversation_stats(&record, config);
+            self.update_conversation_stats(&conversation_id, &stats)?;
+            report.rebuilt += 1;
+        }
+        backfill_embedding_columns(&tx)?;
+        #[cfg(feature = "sqlite-vec")]
+        crate::ann::rebuild(&tx)?;
+
+        tx.commit()?;
+        Ok(report)
+    }
+
+    /// Rebuild `TurnRecord`s from the stored turn rows. Assistant messages come back joined and
+    /// raw payloads and inline image data are not retained, so this is only suitable for
+    /// re-deriving aggregates. User inputs come from `inputs_json`; turns stored before that
+    /// column existed have their `user_text` split back into inputs at the injected-context
+    /// blocks.
+    pub(crate) fn load_turn_records(
+        &self,
+        conversation_id: &str,
+    ) -> Result<Vec<TurnRecord>, StorageError> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT turn_index, user_text, assistant_text, fallback_text, actions_json,
+                   telemetry_json, reasoning_json, context_json, duration_seconds, errors_json,
+                   source_line_start, source_line_end, input_tokens, cached_input_tokens,
+                   output_tokens, reasoning_output_tokens, total_tokens, compactions_json,
+                   inputs_json, started_at, ended_at
+            FROM turns
+            WHERE conversation_id = ?1
+            ORDER BY turn_index
+            "#,
+        )?;
+        let mut rows = stmt.query(params![conversation_id])?;
+        let mut turns = Vec::new();
+        while let Some(row) = rows.next()? {
+            let turn_index: i64 = row.get(0)?;
+            let user_text: Option<String> = row.get(1)?;
+            let assistant_text: Option<String> = row.get(2)?;
+            let fallback_text: Option<String> = row.get(3)?;
+            let actions_json: Option<String> = row.get(4)?;
+            let telemetry_json: Option<String> = row.get(5)?;
+            let reasoning_json: Option<String> = row.get(6)?;
+            let context_json: Option<String> = row.get(7)?;
+            let duration_seconds: Option<i64> = row.get(8)?;
+            let errors_json: Option<String> = row.get(9)?;
+            let source_lines = line_range(row.get(10)?, row.get(11)?);
+            let token_usage = token_usage(row, 12)?;
+            let compactions_json: Option<String> = row.get(17)?;
+            let inputs_json: Option<String> = row.get(18)?;
+            let started_at: Option<String> = row.get(19)?;
+            let ended_at: Option<String> = row.get(20)?;
+            let user_inputs = match inputs_json {
+                Some(json) => serde_json::from_str(&json)?,
+                None => user_text
+                    .as_deref()
+                    .map(split_joined_inputs)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|text| UserInputRecord {
+                        raw: Value::Null,
+                        kind: UserInputKind::classify(text),
+                        text: Some(text.to_string()),
+                        images: Vec::new(),
+                        image_refs: Vec::new(),
+                        files: Vec::new(),
+                    })
+                    .collect(),
+            };
+
+            turns.push(TurnRecord {
+                index: turn_index.max(0) as usize,
+                started_at: started_at.as_deref().and_then(parse_stored_timestamp),
+                ended_at: ended_at.as_deref().and_then(parse_stored_timestamp),
+                duration_seconds: duration_seconds.map(|secs| secs.max(0) as u64),
+                context: context_json
+                    .as_deref()
+                    .map(serde_json::from_str)
+                    .transpose()?,
+                user_inputs,
+                result: TurnResult {
+                    assistant_messages: assistant_text.into_iter().collect(),
+                    fallback: fallback_text.as_deref().map(parse_fallback),
+                    reasoning_summaries: reasoning_json
+                        .as_deref()
+                        .map(serde_json::from_str)
+                        .transpose()?
+                        .unwrap_or_default(),
+                    reasoning_encrypted: false,
+                },
+                actions: actions_json
+                    .as_deref()
+                    .map(serde_json::from_str)
+                    .transpose()?
+                    .unwrap_or_default(),
+                telemetry: telemetry_json
+                    .as_deref()
+                    .map(serde_json::from_str)
+                    .transpose()?
+                    .unwrap_or_default(),
+                errors: errors_json
+                    .as_deref()
+                    .map(serde_json::from_str)
+                    .transpose()?
+                    .unwrap_or_default(),
+                source_lines,
+                token_usage,
+                compactions: compactions_json
+                    .as_deref()
+                    .map(serde_json::from_str)
+                    .transpose()?
+                    .unwrap_or_default(),
+            });
+        }
+        Ok(turns)
+    }
+
+    /// Overwrite the derived stats columns of an existing conversation. `model`, `cwd`, the git
+    /// columns and `summary` keep their stored values when the recomputed stats lack them.
+    fn update_conversation_stats(
+        &self,
+        conversation_id: &str,
+        stats: &ConversationStats,
+    ) -> Result<(), StorageError> {
+        let search_blob = if stats.search_blob.is_empty() {
+            None
+        } else {
+            Some(stats.search_blob.as_str())
+        };
+        self.conn.execute(
+            r#"
+            UPDATE conversations SET
+                preview = ?2,
+                first_question = ?3,
+                last_question = ?4,
+                last_user_message = ?5,
+                model = COALESCE(?6, model),
+                turn_count = ?7,
+                has_live_events = ?8,
+                commands_json = ?9,
+                files_json = ?10,
+                questions_json = ?11,
+                search_blob = ?12,
+                cwd = COALESCE(?13, cwd),
+                approval_count = ?14,
+                auto_title = ?15,
+                git_repo = COALESCE(?16, git_repo),
+                git_branch = COALESCE(?17, git_branch),
+                summary = COALESCE(?18, summary),
+                file_changes_json = ?19,
+                failed_command_count = ?20,
+                urls_json = ?21,
+                had_errors = ?22,
+                compaction_count = ?23,
+                last_plan_json = ?24
+            WHERE id = ?1
+            "#,
+            params![
+                conversation_id,
+                stats.preview,
+                stats.first_question,
+                stats.last_question,
+                stats.last_user_message,
+                stats.model,
+                stats.turn_count,
+                if stats.has_live_events { 1 } else { 0 },
+                serde_json::to_string(&stats.commands)?,
+                serde_json::to_string(&stats.files_touched)?,
+                serde_json::to_string(&stats.questions)?,
+                search_blob,
+                stats.cwd,
+                stats.approval_count,
+                stats.title,
+                stats.git_repo,
+                stats.git_branch,
+                stats.summary,
+                serde_json::to_string(&stats.file_changes)?,
+                stats.failed_command_count,
+                serde_json::to_string(&stats.urls)?,
+                stats.had_errors,
+                stats.compaction_count,
+                stats
+                    .last_plan
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+            ],
+        )?;
+        replace_commands_and_files(&self.conn, conversation_id, stats)?;
+        Ok(())
+    }
+
+    /// Write a consistent snapshot of the database to `path` with SQLite's online backup API,
+    /// so a live database can be copied while another connection keeps writing to it. The copy
+    /// proceeds in small steps and restarts if the source changes underneath it; an existing
+    /// file at `path` is overwritten.
+    pub fn backup_to(&self, path: impl AsRef<Path>) -> Result<(), StorageError> {
+        let mut destination = Connection::open(path)?;
+        // SQLCipher only copies between databases encrypted with the same key.
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.options.encryption_key {
+            key.apply(&destination)?;
+        }
+        let backup = Backup::new(&self.conn, &mut destination)?;
+        backup.run_to_completion(BACKUP_PAGES_PER_STEP, Duration::from_millis(10), None)?;
+        Ok(())
+    }
+
+    /// Copy the conversations matching `filter` (e.g. an id list, a `started_at` range or a
+    /// metadata key) with their turns, embeddings, annotations and the stored images their turns
+    /// refer to into a new database at `path`, along with the database metadata such as the
+    /// recorded embedding model. Returns
+    /// the number of conversations exported; fails with [`StorageError::ExportTargetNotEmpty`]
+    /// rather than merging into a database that already holds conversations.
+    pub fn export_conversations(
+        &self,
+        path: impl AsRef<Path>,
+        filter: &Filter<'_>,
+    ) -> Result<usize, StorageError> {
+        let path = path.as_ref();
+        // The target shares this handle's options, including its encryption key, which SQLCipher
+        // also uses for the attached database below.
+        let target = Storage::open_with_options(path, &self.options)?;
+        let existing: i64 =
+            target
+                .conn
+                .query_row("SELECT count(*) FROM conversations", [], |row| row.get(0))?;
+        if existing > 0 {
+            return Err(StorageError::ExportTargetNotEmpty {
+                path: path.to_path_buf(),
+            });
+        }
+        let columns: Vec<(&str, Vec<String>)> = EXPORT_TABLES
+            .iter()
+            .map(|(table, _)| {
+                let mut stmt = target
+                    .conn
+                    .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))?;
+                let names = stmt
+                    .query_map([], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<String>>>()?;
+                Ok((*table, names))
+            })
+            .collect::<rusqlite::Result<_>>()?;
+        drop(target);
+
+        let mut sql = String::from("SELECT c.id FROM conversations c WHERE ");
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        filter
+            .compile(&mut sql, &mut values)
+            .map_err(|err| StorageError::InvalidFilter(err.to_string()))?;
+
+        self.conn.execute(
+            "ATTACH DATABASE ?1 AS export",
+            params![path.to_string_lossy()],
+        )?;
+        let copied = self.copy_into_export(&sql, &values, &columns);
+        let detached = self.conn.execute_batch("DETACH DATABASE export");
+        let copied = copied?;
+        detached?;
+
+        #[cfg(feature = "sqlite-vec")]
+        crate::ann::rebuild(&Storage::open_with_options(path, &self.options)?.conn)?;
+        Ok(copied)
+    }
+
+    fn copy_into_export(
+        &self,
+        select_ids: &str,
+        values: &[rusqlite::types::Value],
+        columns: &[(&str, Vec<String>)],
+    ) -> Result<usize, StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute_batch("CREATE TEMP TABLE export_ids (id TEXT PRIMARY KEY)")?;
+        let copied = tx.execute(
+            &format!("INSERT INTO temp.export_ids {select_ids}"),
+            rusqlite::params_from_iter(values.iter()),
+        )?;
+        for ((table, link), (_, names)) in EXPORT_TABLES.iter().zip(columns) {
+            let names = names.join(", ");
+            tx.execute(
+                &format!(
+                    "INSERT INTO export.{table} ({names}) SELECT {names} FROM main.{table} \
+                     WHERE {link} IN (SELECT id FROM temp.export_ids)"
+                ),
+                [],
+            )?;
+        }
+        tx.execute_batch(
+            "INSERT OR IGNORE INTO export.images (sha256, mime_type, size_bytes, data)
+             SELECT sha256, mime_type, size_bytes, data FROM main.images WHERE sha256 IN
+             (SELECT json_extract(j.value, '$.sha256') FROM export.turns t,
+              json_each(t.images_json) j WHERE t.images_json IS NOT NULL);
+             INSERT INTO export.meta (key, value) SELECT key, value FROM main.meta;
+             DROP TABLE temp.export_ids;",
+        )?;
+        tx.commit()?;
+        Ok(copied)
+    }
+
+    /// Drop and recreate the secondary indexes on `conversations` (start time, model, cwd,
+    /// rollout path) and `turns` (start time per conversation), e.g. after bulk edits through
+    /// [`Storage::connection`] or to restore an index dropped by hand.
+    pub fn rebuild_indexes(&self) -> Result<(), StorageError> {
+        Ok(migrations::rebuild_indexes(&self.conn)?)
+    }
+
+    /// Let SQLite refresh its planner statistics (`ANALYZE`, `PRAGMA optimize`) and rebuild the
+    /// file to reclaim space left by deleted rows (`VACUUM`). Must not be called inside a
+    /// transaction; `VACUUM` needs up to twice the database size in free disk space.
+    pub fn optimize(&self) -> Result<(), StorageError> {
+        self.conn
+            .execute_batch("ANALYZE; PRAGMA optimize; VACUUM;")?;
+        Ok(())
+    }
+
+    /// Scan the database for corruption and for rows the library cannot use: orphaned turns
+    /// and embeddings, malformed embedding BLOBs and embeddings whose dimension disagrees with
+    /// the rest of their space. Returns an empty list for a healthy database; nothing is
+    /// modified.
+    pub fn check_integrity(&self) -> Result<Vec<IntegrityFinding>, StorageError> {
+        let mut findings = Vec::new();
+
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        for message in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let message = message?;
+            if message != "ok" {
+                findings.push(IntegrityFinding::Corruption(message));
+            }
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT t.conversation_id, t.turn_index FROM turns t \
+             WHERE NOT EXISTS (SELECT 1 FROM conversations c WHERE c.id = t.conversation_id) \
+             ORDER BY t.conversation_id, t.turn_index",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(IntegrityFinding::OrphanTurn {
+                conversation_id: row.get(0)?,
+                turn_index: row.get(1)?,
+            })
+        })?;
+        for finding in rows {
+            findings.push(finding?);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT e.conversation_id, e.turn_index, e.model_name FROM embeddings e \
+             WHERE NOT EXISTS (SELECT 1 FROM turns t \
+                 WHERE t.conversation_id = e.conversation_id AND t.turn_index = e.turn_index) \
+             ORDER BY e.conversation_id, e.turn_index, e.model_name",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(IntegrityFinding::OrphanEmbedding {
+                conversation_id: row.get(0)?,
+                turn_index: row.get(1)?,
+                model_name: row.get(2)?,
+            })
+        })?;
+        for finding in rows {
+            findings.push(finding?);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT conversation_id, turn_index, model_name, dim, length(vector) FROM embeddings \
+             WHERE dim <= 0 OR length(vector) NOT IN (dim * 4, dim * 2) \
+             ORDER BY conversation_id, turn_index, model_name",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(IntegrityFinding::MalformedEmbedding {
+                conversation_id: row.get(0)?,
+                turn_index: row.get(1)?,
+                model_name: row.get(2)?,
+                dim: row.get(3)?,
+                bytes: row.get::<_, i64>(4)? as usize,
+            })
+        })?;
+        for finding in rows {
+            findings.push(finding?);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "WITH dims AS (
+                 SELECT model_name, dim, count(*) AS n FROM embeddings GROUP BY model_name, dim
+             ),
+             expected AS (
+                 SELECT model_name, dim FROM dims d
+                 WHERE dim = (SELECT dim FROM dims WHERE model_name = d.model_name
+                              ORDER BY n DESC, dim LIMIT 1)
+             )
+             SELECT e.conversation_id, e.model_name, x.dim, e.dim, count(*) FROM embeddings e
+             JOIN expected x ON x.model_name = e.model_name
+             WHERE e.dim != x.dim
+             GROUP BY e.conversation_id, e.model_name, e.dim
+             ORDER BY e.conversation_id, e.model_name, e.dim",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(IntegrityFinding::DimensionMismatch {
+                conversation_id: row.get(0)?,
+                model_name: row.get(1)?,
+                expected: row.get::<_, i64>(2)? as usize,
+                found: row.get::<_, i64>(3)? as usize,
+                turns: row.get::<_, i64>(4)? as usize,
+            })
+        })?;
+        for finding in rows {
+            findings.push(finding?);
+        }
+
+        Ok(findings)
+    }
+
+    /// Expose raw connection for advanced queries.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Dimension of the `sqlite-vec` KNN index, if it has been populated.
+    #[cfg(feature = "sqlite-vec")]
+    pub(crate) fn knn_dimension(&self) -> Result<Option<usize>, StorageError> {
+        Ok(crate::ann::dimension(&self.conn)?)
+    }
+
+    /// Record where parsing of a conversation's rollout can resume after it grows, or forget it
+    /// with `None`.
+    pub(crate) fn set_rollout_resume(
+        &self,
+        conversation_id: &str,
+        resume: Option<&ResumeState>,
+    ) -> Result<(), StorageError> {
+        let json = resume.map(serde_json::to_string).transpose()?;
+        self.conn
+            .prepare_cached("UPDATE conversations SET rollout_resume_json = ?1 WHERE id = ?2")?
+            .execute(params![json, conversation_id])?;
+        Ok(())
+    }
+
+    /// Conversation stored for a rollout path together with its resume point, if one was
+    /// recorded.
+    pub(crate) fn rollout_resume(
+        &self,
+        rollout_path: impl AsRef<Path>,
+    ) -> Result<Option<(String, ResumeState)>, StorageError> {
+        let row: Option<(String, Option<String>)> = self
+            .conn
+            .prepare_cached(
+                "SELECT id, rollout_resume_json FROM conversations WHERE rollout_path = ?1 LIMIT 1",
+            )?
+            .query_row(params![self.rollout_key(rollout_path.as_ref())?], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+        match row {
+            Some((id, Some(json))) => Ok(Some((id, serde_json::from_str(&json)?))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Net files changed by `apply_patch` over a conversation, with their line counts, sorted
+    /// by path. Empty for unknown conversations and for ones imported before file changes were
+    /// recorded, until they are rebuilt or re-imported.
+    pub fn file_changes(&self, conversation_id: &str) -> Result<Vec<FileChange>, StorageError> {
+        let json: Option<String> = self
+            .conn
+            .prepare_cached("SELECT file_changes_json FROM conversations WHERE id = ?1")?
+            .query_row(params![conversation_id], |row| row.get(0))
+            .optional()?
+            .flatten();
+        match json {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Fetch stored fingerprint information for a rollout path, if present.
+    pub fn get_rollout_fingerprint(
+        &self,
+        rollout_path: impl AsRef<Path>,
+    ) -> Result<Option<RolloutFingerprint>, StorageError> {
+        let mut stmt = self.conn.prepare_cached(ROLLOUT_FINGERPRINT_SQL)?;
+        let mut rows = stmt.query(params![self.rollout_key(rollout_path.as_ref())?])?;
+        if let Some(row) = rows.next()? {
+            let modified_at: Option<String> = row.get(0)?;
+            let size_bytes: Option<i64> = row.get(1)?;
+            let sha256: Option<String> = row.get(2)?;
+            let parsed_modified =
+                modified_at.and_then(|ts| OffsetDateTime::parse(&ts, &Rfc3339).ok());
+            Ok(Some(RolloutFingerprint {
+                modified_at: parsed_modified,
+                size_bytes: size_bytes.map(|v| v as u64),
+                sha256,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn approximate_input_tokens(record: &ConversationRecord) -> Option<i64> {
+    let total: i64 = record
+        .turns
+        .iter()
+        .flat_map(|turn| turn.user_inputs.iter())
+        .filter_map(|input| input.text.as_ref())
+        .map(|text| estimate_token_count(text))
+        .sum();
+    if total > 0 {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+fn approximate_output_tokens(record: &ConversationRecord) -> Option<i64> {
+    let total: i64 = record
+        .turns
+        .iter()
+        .map(|turn| {
+            let assistant: i64 = turn
                 .result
                 .assistant_messages
                 .iter()
@@ -359,12 +2374,147 @@ fn estimate_token_count(text: &str) -> i64 {
     }
 }
 
+/// Euclidean length of an embedding, accumulated in f64.
+pub(crate) fn l2_norm(vector: &[f32]) -> f32 {
+    vector
+        .iter()
+        .map(|v| (*v as f64) * (*v as f64))
+        .sum::<f64>()
+        .sqrt() as f32
+}
+
+/// Binary quantization of an embedding: bit `i` is set when component `i` is positive.
+pub(crate) fn sign_bits(vector: &[f32]) -> Vec<u8> {
+    let mut bits = vec![0u8; vector.len().div_ceil(8)];
+    for (idx, value) in vector.iter().enumerate() {
+        if *value > 0.0 {
+            bits[idx / 8] |= 1 << (idx % 8);
+        }
+    }
+    bits
+}
+
+/// Encode `vector` for storage, also returning the values as they will read back.
+fn encode_embedding(vector: &[f32], precision: EmbeddingPrecision) -> (Vec<u8>, Cow<'_, [f32]>) {
+    match precision {
+        EmbeddingPrecision::F32 => (
+            cast_slice::<f32, u8>(vector).to_vec(),
+            Cow::Borrowed(vector),
+        ),
+        EmbeddingPrecision::F16 => {
+            let halves: Vec<f16> = vector.iter().map(|value| f16::from_f32(*value)).collect();
+            let blob = halves.iter().flat_map(|half| half.to_le_bytes()).collect();
+            (
+                blob,
+                Cow::Owned(halves.iter().map(|half| half.to_f32()).collect()),
+            )
+        }
+    }
+}
+
+/// Decode a stored embedding with `dim` components, widening half-precision BLOBs. Returns
+/// `None` for BLOBs matching neither encoding.
+pub(crate) fn decode_embedding(blob: &[u8], dim: usize) -> Option<Cow<'_, [f32]>> {
+    if dim == 0 {
+        return None;
+    }
+    if blob.len() == dim * std::mem::size_of::<f32>() {
+        Some(match bytemuck::try_cast_slice::<u8, f32>(blob) {
+            Ok(vector) => Cow::Borrowed(vector),
+            Err(_) => Cow::Owned(bytemuck::pod_collect_to_vec::<u8, f32>(blob)),
+        })
+    } else if blob.len() == dim * std::mem::size_of::<f16>() {
+        Some(Cow::Owned(
+            blob.chunks_exact(2)
+                .map(|bytes| f16::from_le_bytes([bytes[0], bytes[1]]).to_f32())
+                .collect(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Recompute `conversations.embedding_dim` (and the `sqlite-vec` index) after the default
+/// embedding space was replaced wholesale.
+fn reset_default_embedding_space(conn: &Connection) -> Result<(), StorageError> {
+    conn.execute(
+        "UPDATE conversations SET embedding_dim = \
+         (SELECT MIN(dim) FROM embeddings e WHERE e.conversation_id = conversations.id \
+          AND e.model_name = ?1)",
+        params![DEFAULT_EMBEDDING_MODEL],
+    )?;
+    #[cfg(feature = "sqlite-vec")]
+    crate::ann::rebuild(conn)?;
+    Ok(())
+}
+
+fn backfill_embedding_columns(conn: &Connection) -> Result<(), StorageError> {
+    let missing: Vec<(i64, Vec<u8>, i64)> = {
+        let mut stmt = conn.prepare(
+            "SELECT rowid, vector, dim FROM embeddings WHERE norm IS NULL OR bits IS NULL",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let mut update = conn.prepare("UPDATE embeddings SET norm = ?1, bits = ?2 WHERE rowid = ?3")?;
+    for (rowid, blob, dim) in missing {
+        let Some(vector) = decode_embedding(&blob, dim as usize) else {
+            continue;
+        };
+        update.execute(params![l2_norm(&vector) as f64, sign_bits(&vector), rowid])?;
+    }
+    Ok(())
+}
+
+/// Stored `source_line_start` / `source_line_end` columns as a line range.
+/// `path` made absolute against the current directory, or unchanged when that fails (an empty
+/// path or an unreadable current directory).
+fn absolute_path(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+pub(crate) fn line_range(start: Option<i64>, end: Option<i64>) -> Option<(usize, usize)> {
+    Some((start?.max(0) as usize, end?.max(0) as usize))
+}
+
+/// Inverse of the `OffsetDateTime` display format turn timestamps are stored in, e.g.
+/// `2025-01-01 0:00:01.5 +00:00:00`.
+fn parse_stored_timestamp(text: &str) -> Option<OffsetDateTime> {
+    static FORMAT: OnceLock<Vec<BorrowedFormatItem<'static>>> = OnceLock::new();
+    let format = FORMAT.get_or_init(|| {
+        time::format_description::parse_borrowed::<1>(
+            "[year]-[month]-[day] [hour padding:none]:[minute]:[second].[subsecond] \
+             [offset_hour sign:mandatory]:[offset_minute]:[offset_second]",
+        )
+        .expect("valid format description")
+    });
+    OffsetDateTime::parse(text, format).ok()
+}
+
+/// The five token columns of a turn starting at `first`; `None` when none is set.
+fn token_usage(row: &Row<'_>, first: usize) -> rusqlite::Result<Option<TokenUsageBreakdown>> {
+    let count = |offset: usize| -> rusqlite::Result<Option<u64>> {
+        Ok(row
+            .get::<_, Option<i64>>(first + offset)?
+            .map(|n| n.max(0) as u64))
+    };
+    let usage = TokenUsageBreakdown {
+        input_tokens: count(0)?,
+        cached_input_tokens: count(1)?,
+        output_tokens: count(2)?,
+        reasoning_output_tokens: count(3)?,
+        total_tokens: count(4)?,
+    };
+    Ok((usage != TokenUsageBreakdown::default()).then_some(usage))
+}
+
 fn join_user_inputs(turn: &TurnRecord) -> Option<String> {
     let mut texts: Vec<String> = Vec::new();
     for input in &turn.user_inputs {
         if let Some(text) = &input.text {
             texts.push(text.clone());
         }
+        texts.extend(input.files.iter().map(render_attachment));
     }
     if texts.is_empty() {
         None
@@ -373,6 +2523,63 @@ fn join_user_inputs(turn: &TurnRecord) -> Option<String> {
     }
 }
 
+/// A turn's user inputs as kept in `inputs_json`: raw payloads are dropped and inline images
+/// keep only their `data:` URL header.
+fn stored_inputs(turn: &TurnRecord) -> Vec<UserInputRecord> {
+    turn.user_inputs
+        .iter()
+        .map(|input| UserInputRecord {
+            raw: Value::Null,
+            images: input
+                .images
+                .iter()
+                .map(|url| match url.split_once(',') {
+                    Some((header, _)) if url.starts_with("data:") => header.to_string(),
+                    _ => url.clone(),
+                })
+                .collect(),
+            ..input.clone()
+        })
+        .collect()
+}
+
+/// Opening and closing markers of the context blocks Codex injects ahead of a prompt.
+const INJECTED_CONTEXT_BLOCKS: [(&str, &str); 3] = [
+    ("<user_instructions>", "</user_instructions>"),
+    ("<environment_context>", "</environment_context>"),
+    ("# AGENTS.md instructions", "</INSTRUCTIONS>"),
+];
+
+/// Split a `user_text` joined by [`join_user_inputs`] back into its inputs, cutting off each
+/// leading injected-context block so the prompt after them is classified on its own.
+fn split_joined_inputs(text: &str) -> Vec<&str> {
+    let mut inputs = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(end) = INJECTED_CONTEXT_BLOCKS
+        .iter()
+        .filter(|(open, _)| rest.starts_with(open))
+        .find_map(|(_, close)| rest.find(close).map(|at| at + close.len()))
+    {
+        inputs.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        inputs.push(rest);
+    }
+    inputs
+}
+
+/// An attached file as it appears in a turn's user text and summary: a `[file: name]` header
+/// followed by its text.
+pub(crate) fn render_attachment(file: &FileAttachment) -> String {
+    let mut rendered = format!("[file: {}]", file.filename.as_deref().unwrap_or("unnamed"));
+    if let Some(text) = &file.text {
+        rendered.push('\n');
+        rendered.push_str(text);
+    }
+    rendered
+}
+
 fn join_assistant_messages(turn: &TurnRecord) -> Option<String> {
     if turn.result.assistant_messages.is_empty() {
         None
@@ -381,7 +2588,7 @@ fn join_assistant_messages(turn: &TurnRecord) -> Option<String> {
     }
 }
 
-fn format_fallback(fallback: &crate::types::FallbackSummary) -> String {
+fn format_fallback(fallback: &FallbackSummary) -> String {
     match fallback.source {
         FallbackSource::AssistantReasoning => format!("[reasoning] {}", fallback.text),
         FallbackSource::ToolOutput => format!("[tool] {}", fallback.text),
@@ -389,6 +2596,24 @@ fn format_fallback(fallback: &crate::types::FallbackSummary) -> String {
     }
 }
 
+/// Inverse of [`format_fallback`].
+fn parse_fallback(text: &str) -> FallbackSummary {
+    let (source, text) = if let Some(rest) = text.strip_prefix("[reasoning] ") {
+        (FallbackSource::AssistantReasoning, rest)
+    } else if let Some(rest) = text.strip_prefix("[tool] ") {
+        (FallbackSource::ToolOutput, rest)
+    } else {
+        (
+            FallbackSource::EventStream,
+            text.strip_prefix("[event] ").unwrap_or(text),
+        )
+    };
+    FallbackSummary {
+        source,
+        text: text.to_string(),
+    }
+}
+
 fn best_breakdown(record: &ConversationRecord) -> Option<&TokenUsageBreakdown> {
     record
         .token_usage
@@ -397,7 +2622,9 @@ fn best_breakdown(record: &ConversationRecord) -> Option<&TokenUsageBreakdown> {
         .or(record.token_usage.last.as_ref())
 }
 
-fn extract_conversation_id(record: &ConversationRecord, fallback_path: &Path) -> String {
+/// Id a conversation is stored under: the session id from its metadata, else the rollout's file
+/// name.
+pub(crate) fn extract_conversation_id(record: &ConversationRecord, fallback_path: &Path) -> String {
     let from_meta = record
         .session_meta
         .as_ref()
@@ -412,98 +2639,1121 @@ fn extract_conversation_id(record: &ConversationRecord, fallback_path: &Path) ->
     if let Some(id) = from_meta {
         id.to_string()
     } else {
-        // Fall back to the rollout filename to keep results deterministic.
+        // Fall back to the rollout filename to keep results deterministic; a compressed
+        // `rollout-*.jsonl.gz` keeps the id of the plain file it was made from.
         fallback_path
             .file_stem()
-            .map(|stem| stem.to_string_lossy().to_string())
+            .map(|stem| {
+                let stem = stem.to_string_lossy();
+                stem.strip_suffix(".jsonl").unwrap_or(&stem).to_string()
+            })
             .unwrap_or_else(|| fallback_path.to_string_lossy().to_string())
     }
 }
 
-fn setup_schema(conn: &Connection) -> Result<(), StorageError> {
-    conn.execute_batch(
-        r#"
-        PRAGMA foreign_keys = ON;
-        CREATE TABLE IF NOT EXISTS conversations (
-            id TEXT PRIMARY KEY,
-            rollout_path TEXT NOT NULL,
-            started_at TEXT,
-            ended_at TEXT,
-            duration_seconds INTEGER,
-            token_input INTEGER,
-            token_cached INTEGER,
-            token_output INTEGER,
-            token_reasoning INTEGER,
-            token_total INTEGER,
-            token_model_context INTEGER,
-            embedding_dim INTEGER,
-            meta_json TEXT,
-            rollout_modified_at TEXT,
-            rollout_size_bytes INTEGER,
-            rollout_hash TEXT,
-            preview TEXT,
-            first_question TEXT,
-            last_question TEXT,
-            last_user_message TEXT,
-            model TEXT,
-            turn_count INTEGER,
-            has_live_events INTEGER,
-            commands_json TEXT,
-            files_json TEXT,
-            questions_json TEXT,
-            search_blob TEXT,
-            cwd TEXT
-        );
-
-        CREATE TABLE IF NOT EXISTS turns (
-            conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
-            turn_index INTEGER NOT NULL,
-            started_at TEXT,
-            user_text TEXT,
-            assistant_text TEXT,
-            fallback_text TEXT,
-            actions_json TEXT,
-            telemetry_json TEXT,
-            embedding BLOB,
-            PRIMARY KEY (conversation_id, turn_index)
-        );
+fn delete_conversation_rows(
+    conn: &Connection,
+    conversation_id: &str,
+) -> Result<bool, StorageError> {
+    delete_turn_rows(conn, conversation_id, 0)?;
+    for table in ["annotations", "conversation_commands", "conversation_files"] {
+        conn.execute(
+            &format!("DELETE FROM {table} WHERE conversation_id = ?1"),
+            params![conversation_id],
+        )?;
+    }
+    let deleted = conn.execute(
+        "DELETE FROM conversations WHERE id = ?1",
+        params![conversation_id],
+    )?;
+    Ok(deleted > 0)
+}
 
-        CREATE INDEX IF NOT EXISTS idx_turns_conversation ON turns(conversation_id);
-        "#,
+/// Replace the `conversation_commands` / `conversation_files` rows of `conversation_id` with
+/// the commands and files in `stats`.
+fn replace_commands_and_files(
+    conn: &Connection,
+    conversation_id: &str,
+    stats: &ConversationStats,
+) -> Result<(), StorageError> {
+    conn.prepare_cached("DELETE FROM conversation_commands WHERE conversation_id = ?1")?
+        .execute([conversation_id])?;
+    conn.prepare_cached("DELETE FROM conversation_files WHERE conversation_id = ?1")?
+        .execute([conversation_id])?;
+    let mut insert = conn.prepare_cached(
+        "INSERT OR IGNORE INTO conversation_commands (conversation_id, command) VALUES (?1, ?2)",
     )?;
-    ensure_column(conn, "conversations", "rollout_modified_at", "TEXT")?;
-    ensure_column(conn, "conversations", "rollout_size_bytes", "INTEGER")?;
-    ensure_column(conn, "conversations", "rollout_hash", "TEXT")?;
-    ensure_column(conn, "conversations", "preview", "TEXT")?;
-    ensure_column(conn, "conversations", "first_question", "TEXT")?;
-    ensure_column(conn, "conversations", "last_question", "TEXT")?;
-    ensure_column(conn, "conversations", "last_user_message", "TEXT")?;
-    ensure_column(conn, "conversations", "model", "TEXT")?;
-    ensure_column(conn, "conversations", "turn_count", "INTEGER")?;
-    ensure_column(conn, "conversations", "has_live_events", "INTEGER")?;
-    ensure_column(conn, "conversations", "commands_json", "TEXT")?;
-    ensure_column(conn, "conversations", "files_json", "TEXT")?;
-    ensure_column(conn, "conversations", "questions_json", "TEXT")?;
-    ensure_column(conn, "conversations", "search_blob", "TEXT")?;
-    ensure_column(conn, "conversations", "cwd", "TEXT")?;
+    for command in &stats.commands {
+        insert.execute(params![conversation_id, command])?;
+    }
+    let mut insert = conn.prepare_cached(
+        "INSERT OR IGNORE INTO conversation_files (conversation_id, path) VALUES (?1, ?2)",
+    )?;
+    for path in &stats.files_touched {
+        insert.execute(params![conversation_id, path])?;
+    }
     Ok(())
 }
 
-fn ensure_column(
+/// Delete the turns of `conversation_id` with index `from_index` or higher, together with their
+/// embeddings and annotations. Returns the number of turns removed.
+fn delete_turn_rows(
     conn: &Connection,
-    table: &str,
-    column: &str,
-    ty: &str,
-) -> Result<(), StorageError> {
-    let mut stmt = conn.prepare(format!("PRAGMA table_info({table})").as_str())?;
-    let mut rows = stmt.query([])?;
-    while let Some(row) = rows.next()? {
-        let name: String = row.get(1)?;
-        if name == column {
-            return Ok(());
+    conversation_id: &str,
+    from_index: usize,
+) -> Result<usize, StorageError> {
+    #[cfg(feature = "sqlite-vec")]
+    crate::ann::remove_turns(conn, conversation_id, from_index)?;
+    let from_index = from_index as i64;
+    // Rows are removed explicitly rather than relying on `ON DELETE CASCADE`, which only
+    // applies to tables created with the foreign key.
+    conn.execute(
+        "DELETE FROM annotations WHERE conversation_id = ?1 AND turn_index >= ?2",
+        params![conversation_id, from_index],
+    )?;
+    conn.execute(
+        "DELETE FROM embeddings WHERE conversation_id = ?1 AND turn_index >= ?2",
+        params![conversation_id, from_index],
+    )?;
+    Ok(conn.execute(
+        "DELETE FROM turns WHERE conversation_id = ?1 AND turn_index >= ?2",
+        params![conversation_id, from_index],
+    )?)
+}
+
+fn setup_schema(conn: &Connection) -> Result<(), StorageError> {
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    migrations::migrate(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TokenUsageSummary;
+
+    #[test]
+    fn open_with_options_applies_pragmas() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = StorageOptions::new()
+            .journal_mode(JournalMode::Wal)
+            .busy_timeout(Duration::from_millis(250))
+            .synchronous(Synchronous::Normal)
+            .cache_size_kib(4096);
+        let storage = Storage::open_with_options(dir.path().join("db.sqlite"), &options).unwrap();
+        let conn = storage.connection();
+        let journal: String = conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal, "wal");
+        let synchronous: i64 = conn
+            .query_row("PRAGMA synchronous", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(synchronous, 1);
+        let cache_size: i64 = conn
+            .query_row("PRAGMA cache_size", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(cache_size, -4096);
+    }
+
+    #[test]
+    fn backs_up_a_live_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::open(dir.path().join("live.sqlite")).unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        storage
+            .upsert_conversation(
+                "a.jsonl",
+                &record,
+                &RolloutFingerprint::default(),
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+
+        let snapshot = dir.path().join("snapshot.sqlite");
+        storage.backup_to(&snapshot).unwrap();
+        let copy = Storage::open(&snapshot).unwrap();
+        assert_eq!(copy.schema_version().unwrap(), crate::SCHEMA_VERSION);
+        let ids: Vec<String> = copy
+            .list_conversations(&ListParams::default())
+            .unwrap()
+            .into_iter()
+            .map(|summary| summary.id)
+            .collect();
+        assert_eq!(ids, vec!["a"]);
+    }
+
+    #[test]
+    fn exports_selected_conversations_to_a_new_database() {
+        let storage = Storage::open_in_memory().unwrap();
+        for id in ["a", "b", "c"] {
+            let record = ConversationRecord {
+                session_meta: Some(serde_json::json!({ "id": id })),
+                ..ConversationRecord::default()
+            };
+            storage
+                .upsert_conversation(
+                    format!("{id}.jsonl"),
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &ConversationStats::default(),
+                    None,
+                )
+                .unwrap();
+            let turn = TurnRecord {
+                index: 0,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: Default::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage.insert_turn(id, &turn, Some(&[1.0, 0.0])).unwrap();
         }
+        storage.add_annotation("b", Some(0), "keep", None).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("subset.sqlite");
+        let filter = Filter::In(Column::Id.into(), vec!["a".into(), "b".into()]);
+        assert_eq!(storage.export_conversations(&path, &filter).unwrap(), 2);
+
+        let subset = Storage::open(&path).unwrap();
+        let count = |table: &str| -> i64 {
+            subset
+                .connection()
+                .query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(count("conversations"), 2);
+        assert_eq!(count("turns"), 2);
+        assert_eq!(count("embeddings"), 2);
+        assert_eq!(subset.annotations("b").unwrap()[0].note, "keep");
+        assert!(matches!(
+            storage.export_conversations(&path, &filter),
+            Err(StorageError::ExportTargetNotEmpty { .. })
+        ));
+    }
+
+    #[test]
+    fn exports_the_images_of_exported_turns() {
+        let storage = Storage::open_in_memory().unwrap();
+        for (id, data) in [("a", "aGVsbG8="), ("b", "d29ybGQ=")] {
+            let record = ConversationRecord {
+                session_meta: Some(serde_json::json!({ "id": id })),
+                ..ConversationRecord::default()
+            };
+            storage
+                .upsert_conversation(
+                    format!("{id}.jsonl"),
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &ConversationStats::default(),
+                    None,
+                )
+                .unwrap();
+            let turn = TurnRecord {
+                index: 0,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: vec![UserInputRecord {
+                    raw: Value::Null,
+                    kind: UserInputKind::Prompt,
+                    text: Some("see screenshot".into()),
+                    images: vec![format!("data:image/png;base64,{data}")],
+                    image_refs: vec![ImageRef {
+                        sha256: format!("sha-{id}"),
+                        mime_type: Some("image/png".into()),
+                        size_bytes: 5,
+                    }],
+                    files: Vec::new(),
+                }],
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: Default::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage.insert_turn(id, &turn, None).unwrap();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("subset.sqlite");
+        let filter = Filter::In(Column::Id.into(), vec!["a".into()]);
+        assert_eq!(storage.export_conversations(&path, &filter).unwrap(), 1);
+
+        let subset = Storage::open(&path).unwrap();
+        assert_eq!(subset.image("sha-a").unwrap().unwrap().data, b"hello");
+        assert_eq!(subset.image("sha-b").unwrap(), None);
+    }
+
+    #[test]
+    fn clones_connections_for_worker_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = StorageOptions::new().journal_mode(JournalMode::Wal);
+        let storage = Storage::open_with_options(dir.path().join("db.sqlite"), &options).unwrap();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = storage.clone_connection().unwrap();
+                std::thread::spawn(move || handle.schema_version().unwrap())
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), crate::SCHEMA_VERSION);
+        }
+        assert!(matches!(
+            Storage::open_in_memory().unwrap().clone_connection(),
+            Err(StorageError::InMemory)
+        ));
+    }
+
+    #[test]
+    fn splits_joined_user_text_at_injected_context() {
+        let joined = "<user_instructions>\nBe brief.\n</user_instructions>\n\n\
+                      <environment_context>\n</environment_context>\n\nfix the build";
+        assert_eq!(
+            split_joined_inputs(joined),
+            [
+                "<user_instructions>\nBe brief.\n</user_instructions>",
+                "<environment_context>\n</environment_context>",
+                "fix the build",
+            ]
+        );
+        assert_eq!(
+            split_joined_inputs("<user_instructions> unclosed"),
+            ["<user_instructions> unclosed"]
+        );
+    }
+
+    #[test]
+    fn stores_turn_end_and_duration() {
+        let storage = Storage::open_in_memory().unwrap();
+        let started_at = OffsetDateTime::parse("2025-01-01T00:00:00Z", &Rfc3339).unwrap();
+        let turn = TurnRecord {
+            index: 0,
+            started_at: Some(started_at),
+            ended_at: Some(started_at + time::Duration::seconds(90)),
+            duration_seconds: Some(90),
+            context: None,
+            user_inputs: Vec::new(),
+            result: TurnResult::default(),
+            actions: Vec::new(),
+            telemetry: Default::default(),
+            errors: Vec::new(),
+            source_lines: None,
+            token_usage: None,
+            compactions: Vec::new(),
+        };
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        storage
+            .upsert_conversation(
+                "a.jsonl",
+                &record,
+                &RolloutFingerprint::default(),
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+        storage.insert_turn("a", &turn, None).unwrap();
+        let stored = &storage.fetch_turn_window("a", 0, 0, 0).unwrap()[0];
+        assert_eq!(stored.duration_seconds, Some(90));
+        assert_eq!(stored.ended_at, turn.ended_at.map(|ts| ts.to_string()),);
+        let loaded = &storage.load_turn_records("a").unwrap()[0];
+        assert_eq!(loaded.duration_seconds, Some(90));
+        assert_eq!(loaded.started_at, turn.started_at);
+        assert_eq!(loaded.ended_at, turn.ended_at);
+    }
+
+    #[test]
+    fn ingest_transactions_commit_or_roll_back_together() {
+        let storage = Storage::open_in_memory().unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        let turn = TurnRecord {
+            index: 0,
+            started_at: None,
+            ended_at: None,
+            duration_seconds: None,
+            context: None,
+            user_inputs: Vec::new(),
+            result: TurnResult::default(),
+            actions: Vec::new(),
+            telemetry: Default::default(),
+            errors: Vec::new(),
+            source_lines: None,
+            token_usage: None,
+            compactions: Vec::new(),
+        };
+        let ingest = |commit: bool| {
+            let tx = storage.begin_ingest().unwrap();
+            storage
+                .upsert_conversation(
+                    "a.jsonl",
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &ConversationStats::default(),
+                    None,
+                )
+                .unwrap();
+            storage.insert_turn("a", &turn, Some(&[1.0, 0.0])).unwrap();
+            if commit {
+                tx.commit().unwrap();
+            }
+        };
+        let turns = || -> i64 {
+            storage
+                .connection()
+                .query_row("SELECT count(*) FROM turns", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        ingest(false);
+        assert_eq!(turns(), 0);
+        assert!(storage
+            .get_rollout_fingerprint("a.jsonl")
+            .unwrap()
+            .is_none());
+        ingest(true);
+        assert_eq!(turns(), 1);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_databases_need_their_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.sqlite");
+        let storage = Storage::open_encrypted(&path, "hunter2").unwrap();
+        let snapshot = dir.path().join("snapshot.sqlite");
+        storage.backup_to(&snapshot).unwrap();
+        drop(storage);
+        assert!(Storage::open_encrypted(&snapshot, "hunter2").is_ok());
+
+        let header = std::fs::read(&path).unwrap();
+        assert!(!header.starts_with(b"SQLite format 3"));
+        assert!(Storage::open(&path).is_err());
+        assert!(Storage::open_encrypted(&path, "wrong").is_err());
+        let reopened = Storage::open_encrypted(&path, "hunter2").unwrap();
+        assert_eq!(reopened.schema_version().unwrap(), crate::SCHEMA_VERSION);
+        assert_eq!(
+            format!(
+                "{:?}",
+                StorageOptions::new()
+                    .encryption_key("hunter2")
+                    .encryption_key
+            ),
+            "Some(EncryptionKey(..))"
+        );
+    }
+
+    #[test]
+    fn rollout_paths_follow_the_rollout_root() {
+        let storage = Storage::open_in_memory().unwrap();
+        let old_root = Path::new("/home/old/.codex/sessions");
+        let rollout = old_root.join("2025/rollout-a.jsonl");
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        let fingerprint = RolloutFingerprint {
+            size_bytes: Some(10),
+            ..RolloutFingerprint::default()
+        };
+        storage
+            .upsert_conversation(
+                &rollout,
+                &record,
+                &fingerprint,
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+        let stored = || -> String {
+            storage
+                .connection()
+                .query_row("SELECT rollout_path FROM conversations", [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+
+        storage.set_rollout_root(Some(old_root)).unwrap();
+        assert_eq!(stored(), "2025/rollout-a.jsonl");
+
+        let new_root = Path::new("/Users/new/.codex/sessions");
+        storage.set_rollout_root(Some(new_root)).unwrap();
+        let moved = new_root.join("2025/rollout-a.jsonl");
+        let found = storage.get_rollout_fingerprint(&moved).unwrap().unwrap();
+        assert_eq!(found.size_bytes, Some(10));
+        assert_eq!(storage.resolve_rollout_path(&stored()).unwrap(), moved);
+
+        storage.set_rollout_root(None).unwrap();
+        assert_eq!(Path::new(&stored()), moved);
+        assert_eq!(storage.delete_by_rollout_path(&moved).unwrap(), 1);
+    }
+
+    #[test]
+    fn stores_relative_paths_outside_the_rollout_root_as_absolute() {
+        let storage = Storage::open_in_memory().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        storage.set_rollout_root(Some(root.path())).unwrap();
+        let record = |id: &str| ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": id })),
+            ..ConversationRecord::default()
+        };
+        // A walk dir given relative to the current directory, as the CLI default is.
+        let walked = Path::new("codex/sessions/rollout-a.jsonl");
+        let under_root = root.path().join("2025/rollout-b.jsonl");
+        for (id, path) in [("a", walked), ("b", under_root.as_path())] {
+            storage
+                .upsert_conversation(
+                    path,
+                    &record(id),
+                    &RolloutFingerprint::default(),
+                    &ConversationStats::default(),
+                    None,
+                )
+                .unwrap();
+        }
+
+        let absolute = std::env::current_dir().unwrap().join(walked);
+        let mut rollouts = storage.conversation_rollouts().unwrap();
+        rollouts.sort();
+        assert_eq!(
+            rollouts,
+            [
+                ("a".to_string(), absolute.clone()),
+                ("b".to_string(), under_root.clone()),
+            ]
+        );
+        assert!(storage.get_rollout_fingerprint(walked).unwrap().is_some());
+
+        storage.set_rollout_root(None).unwrap();
+        let mut rollouts = storage.conversation_rollouts().unwrap();
+        rollouts.sort();
+        assert_eq!(rollouts[0].1, absolute);
+        assert_eq!(rollouts[1].1, under_root);
+    }
+
+    #[test]
+    fn deletes_conversations_and_their_turns() {
+        let storage = Storage::open_in_memory().unwrap();
+        for id in ["keep", "drop"] {
+            let record = ConversationRecord {
+                session_meta: Some(serde_json::json!({ "id": id })),
+                ..ConversationRecord::default()
+            };
+            storage
+                .upsert_conversation(
+                    format!("{id}.jsonl"),
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &ConversationStats::default(),
+                    None,
+                )
+                .unwrap();
+            let turn = TurnRecord {
+                index: 0,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: Default::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage.insert_turn(id, &turn, Some(&[1.0, 0.0])).unwrap();
+        }
+        let turns = |id: &str| -> i64 {
+            storage
+                .connection()
+                .query_row(
+                    "SELECT count(*) FROM turns WHERE conversation_id = ?1",
+                    [id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+
+        assert!(storage.delete_conversation("drop").unwrap());
+        assert!(!storage.delete_conversation("drop").unwrap());
+        assert_eq!(turns("drop"), 0);
+        assert_eq!(turns("keep"), 1);
+        assert_eq!(storage.delete_by_rollout_path("keep.jsonl").unwrap(), 1);
+        assert_eq!(turns("keep"), 0);
+    }
+
+    #[test]
+    fn lists_conversations_sorted_and_paged() {
+        let storage = Storage::open_in_memory().unwrap();
+        for (id, turns, model) in [("a", 3, "gpt-5"), ("b", 10, "o3"), ("c", 7, "gpt-5")] {
+            let record = ConversationRecord {
+                session_meta: Some(serde_json::json!({ "id": id })),
+                ..ConversationRecord::default()
+            };
+            let stats = ConversationStats {
+                turn_count: turns,
+                model: Some(model.to_string()),
+                ..ConversationStats::default()
+            };
+            storage
+                .upsert_conversation(
+                    format!("{id}.jsonl"),
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &stats,
+                    None,
+                )
+                .unwrap();
+        }
+        let ids = |params: ListParams<'_>| -> Vec<String> {
+            storage
+                .list_conversations(&params)
+                .unwrap()
+                .into_iter()
+                .map(|summary| summary.id)
+                .collect()
+        };
+
+        let params = ListParams {
+            order_by: Column::TurnCount,
+            ..ListParams::new(2)
+        };
+        assert_eq!(ids(params.clone()), vec!["b", "c"]);
+        assert_eq!(
+            ids(ListParams {
+                offset: 2,
+                ..params
+            }),
+            vec!["a"]
+        );
+        let params = ListParams {
+            filter: Some(Filter::eq(Column::Model, "gpt-5")),
+            order_by: Column::TurnCount,
+            descending: false,
+            ..ListParams::default()
+        };
+        assert_eq!(ids(params), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn fetching_turns_records_access_when_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.sqlite");
+        let storage = Storage::open(&path).unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        storage
+            .upsert_conversation(
+                "a.jsonl",
+                &record,
+                &RolloutFingerprint::default(),
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+        for index in 0..2 {
+            let turn = TurnRecord {
+                index,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: Default::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage.insert_turn("a", &turn, None).unwrap();
+        }
+        let summary = |storage: &Storage| -> ConversationSummary {
+            storage
+                .list_conversations(&ListParams::default())
+                .unwrap()
+                .remove(0)
+        };
+
+        storage.fetch_turn_window("a", 0, 0, 1).unwrap();
+        assert_eq!(summary(&storage).hit_count, 0);
+
+        let recording =
+            Storage::open_with_options(&path, &StorageOptions::new().record_fetch_access(true))
+                .unwrap();
+        assert_eq!(recording.fetch_turn_window("a", 0, 0, 1).unwrap().len(), 2);
+        let listed = summary(&recording);
+        assert_eq!(listed.hit_count, 1);
+        assert!(listed.last_accessed_at.is_some());
+        let turn_hits: i64 = recording
+            .connection()
+            .query_row("SELECT SUM(hit_count) FROM turns", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(turn_hits, 2);
+    }
+
+    #[test]
+    fn custom_titles_survive_reimports() {
+        let storage = Storage::open_in_memory().unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "titled" })),
+            ..ConversationRecord::default()
+        };
+        let stats = ConversationStats {
+            title: Some("Add a migration for titles".into()),
+            ..ConversationStats::default()
+        };
+        let upsert = || {
+            storage
+                .upsert_conversation(
+                    "titled.jsonl",
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &stats,
+                    None,
+                )
+                .unwrap()
+        };
+        upsert();
+        assert!(storage
+            .set_title("titled", Some("Schema migrations"))
+            .unwrap());
+        assert!(!storage.set_title("missing", Some("nope")).unwrap());
+        upsert();
+
+        let titles = |filter: Option<Filter<'static>>| -> Vec<Option<String>> {
+            storage
+                .list_conversations(&ListParams {
+                    filter,
+                    ..ListParams::default()
+                })
+                .unwrap()
+                .into_iter()
+                .map(|summary| summary.title)
+                .collect()
+        };
+        assert_eq!(
+            titles(Some(Filter::eq(Column::Title, "Schema migrations"))),
+            vec![Some("Schema migrations".to_string())]
+        );
+
+        storage.set_title("titled", None).unwrap();
+        assert_eq!(titles(None), vec![None]);
+        let summaries = storage.list_conversations(&ListParams::default()).unwrap();
+        assert_eq!(summaries[0].label(), Some("Add a migration for titles"));
+    }
+
+    #[test]
+    fn indexes_commands_and_files_per_conversation() {
+        let storage = Storage::open_in_memory().unwrap();
+        for (id, commands, files) in [
+            ("a", vec!["cargo", "git"], vec!["src/storage.rs"]),
+            ("b", vec!["cargo"], vec!["/abs/src/storage.rs", "README.md"]),
+        ] {
+            let record = ConversationRecord {
+                session_meta: Some(serde_json::json!({ "id": id })),
+                ..ConversationRecord::default()
+            };
+            let stats = ConversationStats {
+                commands: commands.into_iter().map(String::from).collect(),
+                files_touched: files.into_iter().map(String::from).collect(),
+                ..ConversationStats::default()
+            };
+            storage
+                .upsert_conversation(
+                    format!("{id}.jsonl"),
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &stats,
+                    None,
+                )
+                .unwrap();
+        }
+        let ids = |summaries: Vec<ConversationSummary>| -> Vec<String> {
+            summaries.into_iter().map(|summary| summary.id).collect()
+        };
+
+        assert_eq!(
+            ids(storage.conversations_touching("src/storage.rs").unwrap()),
+            ["a"]
+        );
+        assert_eq!(
+            ids(storage.conversations_touching("*src/storage.rs").unwrap()),
+            ["a", "b"]
+        );
+        assert_eq!(ids(storage.conversations_running("git").unwrap()), ["a"]);
+        assert_eq!(
+            storage.command_usage(10).unwrap(),
+            [
+                UsageCount {
+                    value: "cargo".into(),
+                    conversations: 2
+                },
+                UsageCount {
+                    value: "git".into(),
+                    conversations: 1
+                },
+            ]
+        );
+
+        storage.delete_conversation("a").unwrap();
+        assert_eq!(storage.command_usage(10).unwrap().len(), 1);
+        assert_eq!(storage.file_usage(1).unwrap()[0].conversations, 1);
+    }
+
+    #[test]
+    fn rolls_up_token_usage_per_day_and_model() {
+        let storage = Storage::open_in_memory().unwrap();
+        let upsert = |id: &str, started_at: &str, model: Option<&str>, input: u64| {
+            let record = ConversationRecord {
+                session_meta: Some(serde_json::json!({ "id": id })),
+                started_at: Some(OffsetDateTime::parse(started_at, &Rfc3339).unwrap()),
+                token_usage: TokenUsageSummary {
+                    total: Some(TokenUsageBreakdown {
+                        input_tokens: Some(input),
+                        output_tokens: Some(10),
+                        ..TokenUsageBreakdown::default()
+                    }),
+                    ..TokenUsageSummary::default()
+                },
+                ..ConversationRecord::default()
+            };
+            let stats = ConversationStats {
+                model: model.map(String::from),
+                ..ConversationStats::default()
+            };
+            storage
+                .upsert_conversation(
+                    format!("{id}.jsonl"),
+                    &record,
+                    &RolloutFingerprint::default(),
+                    &stats,
+                    None,
+                )
+                .unwrap();
+        };
+        upsert("a", "2025-03-01T09:00:00Z", Some("gpt-5"), 100);
+        upsert("b", "2025-03-01T23:00:00Z", Some("gpt-5"), 50);
+        upsert("c", "2025-03-02T08:00:00Z", None, 7);
+        let usage = |day: DailyUsage| (day.date, day.model, day.input_tokens, day.conversations);
+        assert_eq!(
+            storage
+                .daily_usage(None, None)
+                .unwrap()
+                .into_iter()
+                .map(usage)
+                .collect::<Vec<_>>(),
+            [
+                ("2025-03-01".into(), Some("gpt-5".into()), 150, 2),
+                ("2025-03-02".into(), None, 7, 1),
+            ]
+        );
+
+        // A re-import replaces the conversation's contribution and a delete removes it.
+        upsert("b", "2025-03-02T10:00:00Z", Some("gpt-5"), 60);
+        storage.delete_conversation("c").unwrap();
+        let days = storage.daily_usage(Some("2025-03-02"), None).unwrap();
+        assert_eq!(
+            days.into_iter().map(usage).collect::<Vec<_>>(),
+            [("2025-03-02".into(), Some("gpt-5".into()), 60, 1)]
+        );
+        let first = storage.daily_usage(None, Some("2025-03-01")).unwrap();
+        assert_eq!((first[0].input_tokens, first[0].output_tokens), (100, 10));
+    }
+
+    #[test]
+    fn records_and_enforces_the_embedding_model() {
+        let storage = Storage::open_in_memory().unwrap();
+        let nomic = EmbeddingModelInfo {
+            name: "nomic".into(),
+            dim: 768,
+            file_hash: "aa".into(),
+        };
+        assert_eq!(storage.embedding_model().unwrap(), None);
+        storage.record_embedding_model(&nomic).unwrap();
+        storage.record_embedding_model(&nomic).unwrap();
+        assert_eq!(storage.embedding_model().unwrap(), Some(nomic.clone()));
+
+        let other = EmbeddingModelInfo {
+            file_hash: "bb".into(),
+            ..nomic
+        };
+        assert!(matches!(
+            storage.check_embedding_model(&other),
+            Err(StorageError::EmbeddingModelMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn promoting_an_embedding_space_replaces_the_default_one() {
+        let storage = Storage::open_in_memory().unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        storage
+            .upsert_conversation(
+                "a.jsonl",
+                &record,
+                &RolloutFingerprint::default(),
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+        let turn = TurnRecord {
+            index: 0,
+            started_at: None,
+            ended_at: None,
+            duration_seconds: None,
+            context: None,
+            user_inputs: Vec::new(),
+            result: TurnResult::default(),
+            actions: Vec::new(),
+            telemetry: Default::default(),
+            errors: Vec::new(),
+            source_lines: None,
+            token_usage: None,
+            compactions: Vec::new(),
+        };
+        let old = EmbeddingModelInfo {
+            name: "old".into(),
+            dim: 3,
+            file_hash: "aa".into(),
+        };
+        storage.record_embedding_model(&old).unwrap();
+        storage
+            .insert_turn("a", &turn, Some(&[1.0, 0.0, 0.0]))
+            .unwrap();
+        storage
+            .insert_embedding("a", 0, "staging", &[0.0, 1.0])
+            .unwrap();
+
+        let new = EmbeddingModelInfo {
+            name: "new".into(),
+            dim: 2,
+            file_hash: "bb".into(),
+        };
+        assert_eq!(storage.promote_embedding_space("staging", &new).unwrap(), 1);
+        assert_eq!(storage.embedding_model().unwrap(), Some(new));
+        let spaces = storage.embedding_models().unwrap();
+        assert_eq!(spaces.len(), 1);
+        assert_eq!(
+            (spaces[0].model_name.as_str(), spaces[0].dim),
+            (DEFAULT_EMBEDDING_MODEL, 2)
+        );
+        let dim: i64 = storage
+            .connection()
+            .query_row("SELECT embedding_dim FROM conversations", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(dim, 2);
+
+        assert_eq!(
+            storage
+                .delete_embedding_space(DEFAULT_EMBEDDING_MODEL)
+                .unwrap(),
+            1
+        );
+        assert!(storage.embedding_models().unwrap().is_empty());
+    }
+
+    #[test]
+    fn lists_turns_missing_default_embeddings() {
+        let storage = Storage::open_in_memory().unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        storage
+            .upsert_conversation(
+                "a.jsonl",
+                &record,
+                &RolloutFingerprint::default(),
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+        for (index, embedding) in [(0, Some([1.0, 0.0])), (1, None), (2, None)] {
+            let turn = TurnRecord {
+                index,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: Default::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage
+                .insert_turn("a", &turn, embedding.as_ref().map(|e| e.as_slice()))
+                .unwrap();
+        }
+        storage.insert_embedding("a", 2, "other", &[1.0]).unwrap();
+
+        assert_eq!(
+            storage.turns_missing_embeddings().unwrap(),
+            [("a".to_string(), PathBuf::from("a.jsonl"), vec![1, 2])]
+        );
+    }
+
+    #[test]
+    fn stores_half_precision_embeddings() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = StorageOptions::new().embedding_precision(EmbeddingPrecision::F16);
+        let storage = Storage::open_with_options(dir.path().join("db.sqlite"), &options).unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "half" })),
+            ..ConversationRecord::default()
+        };
+        storage
+            .upsert_conversation(
+                "half.jsonl",
+                &record,
+                &RolloutFingerprint::default(),
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+        let turn = TurnRecord {
+            index: 0,
+            started_at: None,
+            ended_at: None,
+            duration_seconds: None,
+            context: None,
+            user_inputs: Vec::new(),
+            result: TurnResult::default(),
+            actions: Vec::new(),
+            telemetry: Default::default(),
+            errors: Vec::new(),
+            source_lines: None,
+            token_usage: None,
+            compactions: Vec::new(),
+        };
+        storage
+            .insert_turn("half", &turn, Some(&[0.5, -1.25, 3.0]))
+            .unwrap();
+        let blob: Vec<u8> = storage
+            .connection()
+            .query_row("SELECT vector FROM embeddings", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(blob.len(), 6);
+        assert_eq!(
+            decode_embedding(&blob, 3).unwrap().as_ref(),
+            &[0.5, -1.25, 3.0]
+        );
+        assert!(decode_embedding(&blob, 4).is_none());
+    }
+
+    #[test]
+    fn reports_orphans_malformed_blobs_and_mixed_dimensions() {
+        let storage = Storage::open_in_memory().unwrap();
+        let record = ConversationRecord {
+            session_meta: Some(serde_json::json!({ "id": "a" })),
+            ..ConversationRecord::default()
+        };
+        storage
+            .upsert_conversation(
+                "a.jsonl",
+                &record,
+                &RolloutFingerprint::default(),
+                &ConversationStats::default(),
+                None,
+            )
+            .unwrap();
+        for (index, embedding) in [(0, vec![1.0, 0.0]), (1, vec![0.0, 1.0]), (2, vec![1.0; 3])] {
+            let turn = TurnRecord {
+                index,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: Default::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage.insert_turn("a", &turn, Some(&embedding)).unwrap();
+        }
+        assert_eq!(storage.check_integrity().unwrap().len(), 1);
+
+        let conn = storage.connection();
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO turns (conversation_id, turn_index) VALUES ('ghost', 0);
+             INSERT INTO embeddings (conversation_id, turn_index, model_name, dim, vector)
+                 VALUES ('a', 9, 'default', 2, zeroblob(8));
+             UPDATE embeddings SET vector = zeroblob(5) WHERE turn_index = 1;",
+        )
+        .unwrap();
+        let findings = storage.check_integrity().unwrap();
+        assert_eq!(
+            findings,
+            vec![
+                IntegrityFinding::OrphanTurn {
+                    conversation_id: "ghost".into(),
+                    turn_index: 0,
+                },
+                IntegrityFinding::OrphanEmbedding {
+                    conversation_id: "a".into(),
+                    turn_index: 9,
+                    model_name: DEFAULT_EMBEDDING_MODEL.into(),
+                },
+                IntegrityFinding::MalformedEmbedding {
+                    conversation_id: "a".into(),
+                    turn_index: 1,
+                    model_name: DEFAULT_EMBEDDING_MODEL.into(),
+                    dim: 2,
+                    bytes: 5,
+                },
+                IntegrityFinding::DimensionMismatch {
+                    conversation_id: "a".into(),
+                    model_name: DEFAULT_EMBEDDING_MODEL.into(),
+                    expected: 2,
+                    found: 3,
+                    turns: 1,
+                },
+            ]
+        );
+        storage.optimize().unwrap();
     }
-    let sql = format!("ALTER TABLE {table} ADD COLUMN {column} {ty}");
-    let _ = conn.execute(sql.as_str(), []);
-    Ok(())
 }
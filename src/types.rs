@@ -13,6 +13,61 @@ pub struct ConversationRecord {
     pub duration_seconds: Option<u64>,
     pub token_usage: TokenUsageSummary,
     pub turns: Vec<TurnRecord>,
+    /// Lines skipped by a lenient parse; always empty otherwise.
+    #[serde(default)]
+    pub warnings: Vec<ParseWarning>,
+    /// Record layout of a Codex rollout, detected from its first record. `None` for other
+    /// transcript formats.
+    #[serde(default)]
+    pub rollout_version: Option<RolloutVersion>,
+}
+
+/// Record layouts Codex has written rollouts in. Records of older layouts are adapted to the
+/// current one before they are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RolloutVersion {
+    /// Early Codex CLI releases: bare session metadata (`{id, timestamp, instructions}`) on the
+    /// first line, then bare response items (`{"type": "message", ...}`) without timestamps,
+    /// interleaved with `{"record_type": "state"}` lines.
+    Legacy,
+    /// `{timestamp, type, payload}` records of type `session_meta`, `turn_context`,
+    /// `response_item`, `event_msg` or `compacted`.
+    Envelope,
+}
+
+impl ConversationRecord {
+    /// History compactions across all turns, in order.
+    pub fn compactions(&self) -> impl Iterator<Item = &CompactionEvent> {
+        self.turns.iter().flat_map(|turn| &turn.compactions)
+    }
+}
+
+impl RolloutVersion {
+    /// Name stored in `conversations.rollout_version`.
+    pub fn name(self) -> &'static str {
+        match self {
+            RolloutVersion::Legacy => "legacy",
+            RolloutVersion::Envelope => "envelope",
+        }
+    }
+}
+
+/// A line a lenient parse skipped instead of failing on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseWarning {
+    /// 1-based line number of the skipped record.
+    pub line: usize,
+    pub kind: ParseWarningKind,
+    /// What was wrong with it, including a snippet of the record where useful.
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParseWarningKind {
+    MalformedJson,
+    MissingField,
+    InvalidTimestamp,
+    UnknownType,
 }
 
 /// Normalised view of a single turn.
@@ -20,11 +75,75 @@ pub struct ConversationRecord {
 pub struct TurnRecord {
     pub index: usize,
     pub started_at: Option<OffsetDateTime>,
+    /// Timestamp of the turn's last record.
+    #[serde(default)]
+    pub ended_at: Option<OffsetDateTime>,
+    /// Whole seconds from `started_at` to `ended_at`.
+    #[serde(default)]
+    pub duration_seconds: Option<u64>,
     pub context: Option<TurnContextInfo>,
     pub user_inputs: Vec<UserInputRecord>,
     pub result: TurnResult,
     pub actions: Vec<ActionRecord>,
     pub telemetry: TurnTelemetry,
+    /// Errors and aborts reported during the turn, in order.
+    #[serde(default)]
+    pub errors: Vec<TurnError>,
+    /// First and last line (1-based, inclusive) of the transcript records the turn was built
+    /// from; `None` for sources not read line by line.
+    #[serde(default)]
+    pub source_lines: Option<(usize, usize)>,
+    /// Tokens spent during the turn: the difference between the cumulative `token_count`
+    /// snapshot at its end and the one in effect when it started.
+    #[serde(default)]
+    pub token_usage: Option<TokenUsageBreakdown>,
+    /// History compactions that happened during the turn.
+    #[serde(default)]
+    pub compactions: Vec<CompactionEvent>,
+}
+
+/// A `compacted` record: Codex replaced the conversation history so far with a summary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactionEvent {
+    pub timestamp: OffsetDateTime,
+    /// The summary the history was replaced with, when the record carries one.
+    pub summary: Option<String>,
+    /// First and last index (inclusive) of the turns the summary stands in for: those since the
+    /// turn of the previous compaction, up to and including the turn this one happened in.
+    pub replaced_turns: (usize, usize),
+}
+
+impl CompactionEvent {
+    /// Number of turns the summary replaced, wholly or in part.
+    pub fn replaced_turn_count(&self) -> usize {
+        self.replaced_turns.1 - self.replaced_turns.0 + 1
+    }
+}
+
+/// An `error`, `stream_error` or `turn_aborted` event of a turn.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TurnError {
+    pub timestamp: OffsetDateTime,
+    pub kind: TurnErrorKind,
+    /// The error message, or the abort reason (e.g. `interrupted`).
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TurnErrorKind {
+    /// The turn failed.
+    Error,
+    /// The response stream broke off; Codex retries, so the turn may still have completed.
+    StreamError,
+    /// The turn was interrupted by the user or replaced by a new one.
+    Aborted,
+}
+
+impl TurnErrorKind {
+    /// Whether the turn ended because of it, as opposed to a retried stream failure.
+    pub fn is_fatal(self) -> bool {
+        self != TurnErrorKind::StreamError
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +163,66 @@ pub struct UserInputRecord {
     pub raw: Value,
     pub text: Option<String>,
     pub images: Vec<String>,
+    #[serde(default)]
+    pub kind: UserInputKind,
+    /// Content hashes of the inline (`data:` URL) images, in order, filled in when
+    /// [`PipelineConfig::store_images`](crate::PipelineConfig::store_images) is set.
+    #[serde(default)]
+    pub image_refs: Vec<ImageRef>,
+    /// Files attached to the message (`input_file` and similar content items).
+    #[serde(default)]
+    pub files: Vec<FileAttachment>,
+}
+
+/// A file attached to a user message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileAttachment {
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    /// Text of the file, when it was sent inline and is valid UTF-8; capped at 20,000
+    /// characters.
+    pub text: Option<String>,
+    /// Whether `text` was cut at the cap.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// Stable reference to an image attachment kept in the database's content-addressed image
+/// store; load it with [`Storage::image`](crate::Storage::image).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageRef {
+    /// Lowercase hex SHA-256 of the decoded image bytes.
+    pub sha256: String,
+    /// Media type from the data URL, e.g. `image/png`.
+    pub mime_type: Option<String>,
+    pub size_bytes: u64,
+}
+
+/// Whether a user message was typed by the user or is context the agent sent in their name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UserInputKind {
+    #[default]
+    Prompt,
+    /// Codex `<environment_context>` block: cwd, sandbox and network policy, shell.
+    EnvironmentContext,
+    /// `AGENTS.md` / user instructions Codex sends ahead of the first prompt.
+    UserInstructions,
+}
+
+impl UserInputKind {
+    /// Classify a user message by the markers Codex wraps injected context in.
+    pub fn classify(text: &str) -> Self {
+        let text = text.trim_start();
+        if text.starts_with("<environment_context>") {
+            UserInputKind::EnvironmentContext
+        } else if text.starts_with("<user_instructions>")
+            || text.starts_with("# AGENTS.md instructions")
+        {
+            UserInputKind::UserInstructions
+        } else {
+            UserInputKind::Prompt
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -75,6 +254,31 @@ pub struct ActionRecord {
     pub output: Option<ActionOutput>,
     pub status: ActionStatus,
     pub events: Vec<ActionEvent>,
+    /// Files the action changed through `apply_patch`.
+    #[serde(default)]
+    pub file_changes: Vec<FileChange>,
+    /// First and last transcript line (1-based, inclusive) of the records describing the
+    /// action: its call, output and events.
+    #[serde(default)]
+    pub source_lines: Option<(usize, usize)>,
+}
+
+/// One file of an `apply_patch` patch, with its diffstat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChange {
+    pub path: String,
+    pub kind: FileChangeKind,
+    /// `+` lines in the file's hunks (every line of an added file).
+    pub hunks_added: usize,
+    /// `-` lines in the file's hunks.
+    pub hunks_removed: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileChangeKind {
+    Add,
+    Update,
+    Delete,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,6 +298,11 @@ pub enum ActionKind {
     WebSearch {
         query: Option<String>,
     },
+    /// A tool served by an MCP server.
+    McpToolCall {
+        server: Option<String>,
+        tool: Option<String>,
+    },
     Other {
         kind: Option<String>,
     },
@@ -116,6 +325,15 @@ pub struct ActionOutput {
 pub struct ActionStatus {
     pub status_text: Option<String>,
     pub local_status: Option<String>,
+    /// Exit code of a command run by the action.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// How long the command took, in milliseconds.
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// Whether the command exited with status 0; `None` when no exit code was recorded.
+    #[serde(default)]
+    pub success: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -133,6 +351,73 @@ pub struct TurnTelemetry {
     pub misc_events: Vec<Timed<Value>>,
 }
 
+impl TurnTelemetry {
+    /// The plan as the turn's last readable `plan_update` event left it.
+    pub fn final_plan(&self) -> Option<Plan> {
+        self.plan_updates
+            .iter()
+            .rev()
+            .find_map(|update| Plan::from_value(update.timestamp, &update.data))
+    }
+}
+
+/// An agent's plan, from a `plan_update` event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub timestamp: OffsetDateTime,
+    /// Why the plan was made or changed, when the agent said.
+    pub explanation: Option<String>,
+    pub steps: Vec<PlanStep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub step: String,
+    pub status: PlanStepStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanStepStatus {
+    Pending,
+    InProgress,
+    Completed,
+}
+
+impl Plan {
+    /// Parse a `plan_update` payload (`{explanation, plan: [{step, status}]}`). Steps without
+    /// text are dropped, and a status other than `in_progress` or `completed` reads as pending.
+    pub fn from_value(timestamp: OffsetDateTime, value: &Value) -> Option<Self> {
+        let steps = value
+            .get("plan")?
+            .as_array()?
+            .iter()
+            .filter_map(|item| {
+                let step = item.get("step").and_then(Value::as_str)?.trim();
+                let status = match item.get("status").and_then(Value::as_str) {
+                    Some("in_progress") => PlanStepStatus::InProgress,
+                    Some("completed") => PlanStepStatus::Completed,
+                    _ => PlanStepStatus::Pending,
+                };
+                (!step.is_empty()).then(|| PlanStep {
+                    step: step.to_string(),
+                    status,
+                })
+            })
+            .collect();
+        Some(Plan {
+            timestamp,
+            explanation: value
+                .get("explanation")
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_string),
+            steps,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Timed<T> {
     pub timestamp: OffsetDateTime,
@@ -146,7 +431,7 @@ pub struct TokenUsageSummary {
     pub model_context_window: Option<u64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct TokenUsageBreakdown {
     pub input_tokens: Option<u64>,
     pub cached_input_tokens: Option<u64>,
@@ -165,12 +450,19 @@ pub(crate) struct ConversationBuilder {
     pub first_timestamp: Option<OffsetDateTime>,
     pub last_timestamp: Option<OffsetDateTime>,
     pub token_usage: TokenUsageSummary,
+    pub warnings: Vec<ParseWarning>,
+    pub rollout_version: Option<RolloutVersion>,
+    /// Turn of the most recent history compaction.
+    pub last_compaction_turn: Option<usize>,
+    /// Line of the record being parsed (1-based); 0 when the source is not read line by line.
+    pub line: usize,
 }
 
 #[derive(Default)]
 pub(crate) struct TurnBuilder {
     pub index: usize,
     pub started_at: Option<OffsetDateTime>,
+    pub ended_at: Option<OffsetDateTime>,
     pub context: Option<TurnContextInfo>,
     pub user_inputs: Vec<UserInputRecord>,
     pub assistant_messages: Vec<String>,
@@ -179,11 +471,28 @@ pub(crate) struct TurnBuilder {
     pub fallback_reasoning: Option<String>,
     pub fallback_tool_output: Option<String>,
     pub fallback_event: Option<String>,
+    /// Text of `*_delta` events not yet superseded by the complete item, in arrival order.
+    pub streamed: Vec<StreamedText>,
+    pub errors: Vec<TurnError>,
+    pub compactions: Vec<CompactionEvent>,
+    /// Line of the record being parsed, see [`ConversationBuilder::line`].
+    pub line: usize,
+    pub source_lines: Option<(usize, usize)>,
+    /// Cumulative token usage when the turn started, and the latest snapshot seen during it.
+    pub token_baseline: Option<TokenUsageBreakdown>,
+    pub token_latest: Option<TokenUsageBreakdown>,
     pub actions: HashMap<String, ActionRecordBuilder>,
     pub anonymous_actions: Vec<ActionRecordBuilder>,
     pub telemetry: TurnTelemetry,
 }
 
+/// Deltas of one streamed item, keyed by the event type without its `_delta` suffix.
+pub(crate) struct StreamedText {
+    pub kind: String,
+    pub item_id: Option<String>,
+    pub text: String,
+}
+
 impl ConversationBuilder {
     pub fn observe_timestamp(&mut self, timestamp: OffsetDateTime) {
         if self.first_timestamp.is_none() {
@@ -194,7 +503,11 @@ impl ConversationBuilder {
 
     pub fn update_token_usage(&mut self, info: &Value) {
         if let Some(total) = info.get("total_token_usage") {
-            self.token_usage.total = Some(TokenUsageBreakdown::from_value(total));
+            let total = TokenUsageBreakdown::from_value(total);
+            if let Some(turn) = self.current_turn.as_mut() {
+                turn.token_latest = Some(total.clone());
+            }
+            self.token_usage.total = Some(total);
         }
         if let Some(last) = info.get("last_token_usage") {
             self.token_usage.last = Some(TokenUsageBreakdown::from_value(last));
@@ -215,10 +528,15 @@ impl ConversationBuilder {
             self.current_turn = Some(TurnBuilder {
                 index,
                 started_at: Some(timestamp),
+                token_baseline: self.token_usage.total.clone(),
                 ..TurnBuilder::default()
             });
         }
-        self.current_turn.as_mut().unwrap()
+        let line = self.line;
+        let turn = self.current_turn.as_mut().unwrap();
+        turn.observe_timestamp(timestamp);
+        turn.observe_line(line);
+        turn
     }
 
     pub fn start_new_turn(
@@ -236,10 +554,55 @@ impl ConversationBuilder {
         self.current_turn = Some(TurnBuilder {
             index,
             started_at: Some(timestamp),
+            ended_at: Some(timestamp),
             context: Some(context),
+            token_baseline: self.token_usage.total.clone(),
             ..TurnBuilder::default()
         });
-        self.current_turn.as_mut().unwrap()
+        let line = self.line;
+        let turn = self.current_turn.as_mut().unwrap();
+        turn.observe_line(line);
+        turn
+    }
+
+    /// Whether the current turn already holds a user prompt.
+    pub fn has_prompt(&self) -> bool {
+        self.current_turn.as_ref().is_some_and(|turn| {
+            turn.user_inputs
+                .iter()
+                .any(|input| input.kind == UserInputKind::Prompt)
+        })
+    }
+
+    /// Close the current turn and open the next one with the same context, for rollouts
+    /// segmented by user message.
+    pub fn split_turn(&mut self, timestamp: OffsetDateTime) -> &mut TurnBuilder {
+        let context = self
+            .current_turn
+            .as_ref()
+            .and_then(|turn| turn.context.clone());
+        if let Some(builder) = self.current_turn.take() {
+            if !builder.is_empty() {
+                self.turns.push(builder.finish());
+            }
+        }
+        let turn = self.ensure_turn(timestamp);
+        turn.context = context;
+        turn
+    }
+
+    /// Record a `compacted` record in the current turn.
+    pub fn push_compaction(&mut self, timestamp: OffsetDateTime, summary: Option<String>) {
+        let first = self.last_compaction_turn.unwrap_or(0);
+        let turn = self.ensure_turn(timestamp);
+        turn.ensure_started_at(timestamp);
+        let index = turn.index;
+        turn.compactions.push(CompactionEvent {
+            timestamp,
+            summary,
+            replaced_turns: (first.min(index), index),
+        });
+        self.last_compaction_turn = Some(index);
     }
 
     pub fn finalize(mut self) -> ConversationRecord {
@@ -260,6 +623,8 @@ impl ConversationBuilder {
             duration_seconds,
             token_usage: self.token_usage,
             turns: self.turns,
+            warnings: self.warnings,
+            rollout_version: self.rollout_version,
         }
     }
 }
@@ -271,6 +636,13 @@ impl TurnBuilder {
         }
     }
 
+    /// Extend the turn to a record at `timestamp`.
+    pub fn observe_timestamp(&mut self, timestamp: OffsetDateTime) {
+        if self.ended_at.is_none_or(|ended| timestamp > ended) {
+            self.ended_at = Some(timestamp);
+        }
+    }
+
     pub fn push_user_input(&mut self, input: UserInputRecord) {
         self.user_inputs.push(input);
     }
@@ -296,8 +668,48 @@ impl TurnBuilder {
         self.fallback_event = Some(text);
     }
 
+    pub fn push_delta(&mut self, kind: &str, item_id: Option<&str>, delta: &str) {
+        let open = self
+            .streamed
+            .iter_mut()
+            .rev()
+            .find(|streamed| streamed.kind == kind && streamed.item_id.as_deref() == item_id);
+        match open {
+            Some(streamed) => streamed.text.push_str(delta),
+            None => self.streamed.push(StreamedText {
+                kind: kind.to_string(),
+                item_id: item_id.map(String::from),
+                text: delta.to_string(),
+            }),
+        }
+    }
+
+    /// Drops the deltas a complete item of `kind` replaces: those with its id, or all of the
+    /// kind when either side has none.
+    pub fn discard_deltas(&mut self, kind: &str, item_id: Option<&str>) {
+        self.streamed.retain(|streamed| {
+            streamed.kind != kind
+                || matches!((streamed.item_id.as_deref(), item_id), (Some(a), Some(b)) if a != b)
+        });
+    }
+
+    /// Emits streams that never completed as the items they would have become.
+    fn flush_deltas(&mut self) {
+        for streamed in std::mem::take(&mut self.streamed) {
+            if streamed.text.trim().is_empty() {
+                continue;
+            }
+            match streamed.kind.as_str() {
+                "agent_message" => self.push_assistant_message(streamed.text),
+                "agent_reasoning" => self.push_reasoning_summary(streamed.text),
+                _ => self.record_event_agent_message(streamed.text),
+            }
+        }
+    }
+
     pub fn action_builder_mut(&mut self, call_id: Option<&str>) -> &mut ActionRecordBuilder {
-        if let Some(id) = call_id {
+        let line = self.line;
+        let builder = if let Some(id) = call_id {
             self.actions
                 .entry(id.to_string())
                 .or_insert_with(|| ActionRecordBuilder::new(Some(id.to_string())))
@@ -306,7 +718,15 @@ impl TurnBuilder {
             self.anonymous_actions
                 .last_mut()
                 .expect("anonymous action builder present")
-        }
+        };
+        extend_line_range(&mut builder.source_lines, line);
+        builder
+    }
+
+    /// Track the record at `line` as part of the turn.
+    pub fn observe_line(&mut self, line: usize) {
+        self.line = line;
+        extend_line_range(&mut self.source_lines, line);
     }
 
     pub fn is_empty(&self) -> bool {
@@ -315,14 +735,18 @@ impl TurnBuilder {
             && self.actions.is_empty()
             && self.anonymous_actions.is_empty()
             && self.reasoning_summaries.is_empty()
+            && self.streamed.is_empty()
             && self.telemetry.token_counts.is_empty()
+            && self.errors.is_empty()
+            && self.compactions.is_empty()
     }
 
     pub fn finish(mut self) -> TurnRecord {
+        self.flush_deltas();
         let mut actions: Vec<ActionRecord> = self
             .actions
-            .into_iter()
-            .map(|(_, builder)| builder.finish())
+            .into_values()
+            .map(|builder| builder.finish())
             .collect();
         actions.extend(self.anonymous_actions.into_iter().map(|b| b.finish()));
         actions.sort_by(|a, b| a.call_id.cmp(&b.call_id));
@@ -339,18 +763,23 @@ impl TurnBuilder {
                 source: FallbackSource::ToolOutput,
                 text,
             })
-        } else if let Some(text) = self.fallback_event.take() {
-            Some(FallbackSummary {
+        } else {
+            self.fallback_event.take().map(|text| FallbackSummary {
                 source: FallbackSource::EventStream,
                 text,
             })
-        } else {
-            None
+        };
+
+        let duration_seconds = match (self.started_at, self.ended_at) {
+            (Some(start), Some(end)) => Some((end - start).whole_seconds().max(0) as u64),
+            _ => None,
         };
 
         TurnRecord {
             index: self.index,
             started_at: self.started_at,
+            ended_at: self.ended_at,
+            duration_seconds,
             context: self.context,
             user_inputs: self.user_inputs,
             result: TurnResult {
@@ -361,10 +790,27 @@ impl TurnBuilder {
             },
             actions,
             telemetry: self.telemetry,
+            errors: self.errors,
+            source_lines: self.source_lines,
+            token_usage: self
+                .token_latest
+                .map(|latest| latest.since(self.token_baseline.as_ref())),
+            compactions: self.compactions,
         }
     }
 }
 
+/// Widen `range` to cover `line`; line 0 (unknown) leaves it alone.
+fn extend_line_range(range: &mut Option<(usize, usize)>, line: usize) {
+    if line == 0 {
+        return;
+    }
+    *range = Some(match *range {
+        Some((start, end)) => (start.min(line), end.max(line)),
+        None => (line, line),
+    });
+}
+
 #[derive(Debug, Clone, Default)]
 pub(crate) struct ActionRecordBuilder {
     call_id: Option<String>,
@@ -373,6 +819,8 @@ pub(crate) struct ActionRecordBuilder {
     output: Option<ActionOutput>,
     status: ActionStatus,
     events: Vec<ActionEvent>,
+    file_changes: Vec<FileChange>,
+    source_lines: Option<(usize, usize)>,
 }
 
 impl ActionRecordBuilder {
@@ -395,6 +843,14 @@ impl ActionRecordBuilder {
         self.output = Some(output);
     }
 
+    pub fn set_file_changes(&mut self, changes: Vec<FileChange>) {
+        self.file_changes = changes;
+    }
+
+    pub fn has_output(&self) -> bool {
+        self.output.is_some()
+    }
+
     pub fn update_status_text(&mut self, status: Option<String>) {
         self.status.status_text = status;
     }
@@ -403,6 +859,17 @@ impl ActionRecordBuilder {
         self.status.local_status = status;
     }
 
+    /// Record how a command ended; values already known are kept when one is missing.
+    pub fn record_exit(&mut self, exit_code: Option<i32>, duration_ms: Option<u64>) {
+        if let Some(code) = exit_code {
+            self.status.exit_code = Some(code);
+            self.status.success = Some(code == 0);
+        }
+        if duration_ms.is_some() {
+            self.status.duration_ms = duration_ms;
+        }
+    }
+
     pub fn push_event(&mut self, timestamp: OffsetDateTime, kind: String, data: Value) {
         self.events.push(ActionEvent {
             timestamp,
@@ -419,6 +886,8 @@ impl ActionRecordBuilder {
             output: self.output,
             status: self.status,
             events: self.events,
+            file_changes: self.file_changes,
+            source_lines: self.source_lines,
         }
     }
 }
@@ -436,4 +905,22 @@ impl TokenUsageBreakdown {
             total_tokens: value.get("total_tokens").and_then(Value::as_u64),
         }
     }
+
+    /// Fieldwise difference to an earlier cumulative snapshot; missing baseline counts are zero.
+    pub fn since(&self, baseline: Option<&TokenUsageBreakdown>) -> TokenUsageBreakdown {
+        let delta = |latest: Option<u64>, base: Option<u64>| {
+            latest.map(|latest| latest.saturating_sub(base.unwrap_or(0)))
+        };
+        let base = baseline.cloned().unwrap_or_default();
+        TokenUsageBreakdown {
+            input_tokens: delta(self.input_tokens, base.input_tokens),
+            cached_input_tokens: delta(self.cached_input_tokens, base.cached_input_tokens),
+            output_tokens: delta(self.output_tokens, base.output_tokens),
+            reasoning_output_tokens: delta(
+                self.reasoning_output_tokens,
+                base.reasoning_output_tokens,
+            ),
+            total_tokens: delta(self.total_tokens, base.total_tokens),
+        }
+    }
 }
@@ -0,0 +1,407 @@
+//! In-memory IVF (inverted file) index over turn embeddings for fast approximate top-k queries.
+//!
+//! Vectors are normalised on load and clustered with spherical k-means; a query only scans the
+//! `probes` lists whose centroids are closest to it. Pass the index to
+//! [`SearchParams::vector_index`](crate::SearchParams) to have it pick the search candidates.
+//! The index covers the default embedding space.
+
+use std::collections::HashMap;
+
+use rusqlite::params;
+
+use crate::storage::{decode_embedding, Storage, StorageError, DEFAULT_EMBEDDING_MODEL};
+
+/// Tuning knobs for [`VectorIndex`].
+#[derive(Debug, Clone)]
+pub struct VectorIndexConfig {
+    /// Number of inverted lists (k-means clusters). Defaults to `sqrt(n)`.
+    pub lists: Option<usize>,
+    /// Lists scanned per query; higher values trade latency for recall.
+    pub probes: usize,
+    /// k-means refinement rounds run while training.
+    pub iterations: usize,
+}
+
+impl Default for VectorIndexConfig {
+    fn default() -> Self {
+        Self {
+            lists: None,
+            probes: 8,
+            iterations: 8,
+        }
+    }
+}
+
+/// Turn returned by [`VectorIndex::search`], scored by cosine similarity.
+#[derive(Debug, Clone)]
+pub struct VectorHit {
+    pub conversation_id: String,
+    pub turn_index: usize,
+    pub score: f32,
+}
+
+struct Entry {
+    conversation_id: String,
+    turn_index: usize,
+    vector: Vec<f32>,
+}
+
+/// Approximate nearest-neighbour index held in memory.
+pub struct VectorIndex {
+    config: VectorIndexConfig,
+    dim: usize,
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<Vec<Entry>>,
+    /// List holding each indexed turn, so re-inserts only scan one list.
+    locations: HashMap<(String, usize), usize>,
+    /// `rollout_hash` per conversation at the time it was indexed, used by `refresh`.
+    versions: HashMap<String, Option<String>>,
+}
+
+impl VectorIndex {
+    /// Load every stored embedding and train the index. When the database mixes embedding
+    /// dimensions, the most common one is indexed and the rest are ignored.
+    pub fn load(storage: &Storage, config: VectorIndexConfig) -> Result<Self, StorageError> {
+        let mut entries = read_entries(storage, None)?;
+        let mut dim_counts: HashMap<usize, usize> = HashMap::new();
+        for entry in &entries {
+            *dim_counts.entry(entry.vector.len()).or_default() += 1;
+        }
+        let dim = dim_counts
+            .into_iter()
+            .max_by_key(|(dim, count)| (*count, std::cmp::Reverse(*dim)))
+            .map(|(dim, _)| dim)
+            .unwrap_or(0);
+        entries.retain(|entry| entry.vector.len() == dim);
+
+        let vectors: Vec<&[f32]> = entries.iter().map(|e| e.vector.as_slice()).collect();
+        let lists = config
+            .lists
+            .unwrap_or_else(|| (entries.len() as f64).sqrt().ceil() as usize);
+        let centroids = train(&vectors, lists, config.iterations);
+
+        let mut index = Self {
+            config,
+            dim,
+            lists: centroids.iter().map(|_| Vec::new()).collect(),
+            centroids,
+            locations: HashMap::new(),
+            versions: read_versions(storage)?,
+        };
+        for entry in entries {
+            index.push(entry);
+        }
+        Ok(index)
+    }
+
+    /// Dimension of the indexed vectors (0 while empty).
+    pub fn dimension(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Add or replace one turn's embedding without retraining. Returns `false` when the vector
+    /// does not match the index dimension or has zero length.
+    pub fn insert(&mut self, conversation_id: &str, turn_index: usize, embedding: &[f32]) -> bool {
+        if self.dim == 0 {
+            self.dim = embedding.len();
+        }
+        if embedding.is_empty() || embedding.len() != self.dim {
+            return false;
+        }
+        let Some(vector) = normalized(embedding) else {
+            return false;
+        };
+        self.remove(conversation_id, turn_index);
+        self.push(Entry {
+            conversation_id: conversation_id.to_string(),
+            turn_index,
+            vector,
+        });
+        true
+    }
+
+    /// Pick up conversations imported, updated or removed since the index was loaded (or last
+    /// refreshed), e.g. after a pipeline run. Conversations without a stored rollout hash are
+    /// always reloaded. Returns the number of conversations re-read.
+    pub fn refresh(&mut self, storage: &Storage) -> Result<usize, StorageError> {
+        let current = read_versions(storage)?;
+        let stale: Vec<String> = self
+            .versions
+            .iter()
+            .filter(|(id, hash)| hash.is_none() || current.get(*id) != Some(*hash))
+            .map(|(id, _)| id.clone())
+            .chain(
+                current
+                    .keys()
+                    .filter(|id| !self.versions.contains_key(*id))
+                    .cloned(),
+            )
+            .collect();
+
+        for id in &stale {
+            for list in &mut self.lists {
+                list.retain(|entry| &entry.conversation_id != id);
+            }
+        }
+        self.rebuild_locations();
+
+        let mut reloaded = 0;
+        for id in &stale {
+            if !current.contains_key(id) {
+                continue;
+            }
+            for entry in read_entries(storage, Some(id))? {
+                self.insert(&entry.conversation_id, entry.turn_index, &entry.vector);
+            }
+            reloaded += 1;
+        }
+        self.versions = current;
+        Ok(reloaded)
+    }
+
+    /// Return up to `k` turns most similar to `query`, best first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<VectorHit> {
+        if k == 0 || query.len() != self.dim || self.centroids.is_empty() {
+            return Vec::new();
+        }
+        let Some(query) = normalized(query) else {
+            return Vec::new();
+        };
+
+        let mut ranked_lists: Vec<(usize, f32)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(idx, centroid)| (idx, dot(&query, centroid)))
+            .collect();
+        ranked_lists.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut hits: Vec<VectorHit> = ranked_lists
+            .iter()
+            .take(self.config.probes.max(1))
+            .flat_map(|(list, _)| &self.lists[*list])
+            .map(|entry| VectorHit {
+                conversation_id: entry.conversation_id.clone(),
+                turn_index: entry.turn_index,
+                score: dot(&query, &entry.vector),
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.conversation_id.cmp(&b.conversation_id))
+                .then_with(|| a.turn_index.cmp(&b.turn_index))
+        });
+        hits.truncate(k);
+        hits
+    }
+
+    fn push(&mut self, entry: Entry) {
+        if self.centroids.is_empty() {
+            self.centroids.push(entry.vector.clone());
+            self.lists.push(Vec::new());
+        }
+        let list = nearest(&self.centroids, &entry.vector);
+        self.locations
+            .insert((entry.conversation_id.clone(), entry.turn_index), list);
+        self.lists[list].push(entry);
+    }
+
+    fn remove(&mut self, conversation_id: &str, turn_index: usize) {
+        if let Some(list) = self
+            .locations
+            .remove(&(conversation_id.to_string(), turn_index))
+        {
+            self.lists[list].retain(|entry| {
+                entry.conversation_id != conversation_id || entry.turn_index != turn_index
+            });
+        }
+    }
+
+    fn rebuild_locations(&mut self) {
+        self.locations = self
+            .lists
+            .iter()
+            .enumerate()
+            .flat_map(|(list, entries)| {
+                entries
+                    .iter()
+                    .map(move |entry| ((entry.conversation_id.clone(), entry.turn_index), list))
+            })
+            .collect();
+    }
+}
+
+fn read_entries(
+    storage: &Storage,
+    conversation_id: Option<&str>,
+) -> Result<Vec<Entry>, StorageError> {
+    let mut stmt = storage.connection().prepare(
+        "SELECT conversation_id, turn_index, vector, dim FROM embeddings \
+         WHERE model_name = ?1 AND (?2 IS NULL OR conversation_id = ?2)",
+    )?;
+    let mut rows = stmt.query(params![DEFAULT_EMBEDDING_MODEL, conversation_id])?;
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next()? {
+        let turn_index: i64 = row.get(1)?;
+        let blob: Vec<u8> = row.get(2)?;
+        let dim: i64 = row.get(3)?;
+        if turn_index < 0 {
+            continue;
+        }
+        let Some(vector) = decode_embedding(&blob, dim as usize).and_then(|v| normalized(&v))
+        else {
+            continue;
+        };
+        entries.push(Entry {
+            conversation_id: row.get(0)?,
+            turn_index: turn_index as usize,
+            vector,
+        });
+    }
+    Ok(entries)
+}
+
+fn read_versions(storage: &Storage) -> Result<HashMap<String, Option<String>>, StorageError> {
+    let mut stmt = storage
+        .connection()
+        .prepare("SELECT id, rollout_hash FROM conversations")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Spherical k-means over a strided sample of `vectors` (already unit length).
+fn train(vectors: &[&[f32]], lists: usize, iterations: usize) -> Vec<Vec<f32>> {
+    let n = vectors.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let k = lists.clamp(1, n);
+    let mut centroids: Vec<Vec<f32>> = (0..k).map(|i| vectors[i * n / k].to_vec()).collect();
+    let step = (n / (k * 64)).max(1);
+    let dim = vectors[0].len();
+
+    for _ in 0..iterations {
+        let mut sums = vec![vec![0.0f32; dim]; k];
+        let mut counts = vec![0usize; k];
+        for vector in vectors.iter().step_by(step) {
+            let cluster = nearest(&centroids, vector);
+            counts[cluster] += 1;
+            for (sum, value) in sums[cluster].iter_mut().zip(vector.iter()) {
+                *sum += value;
+            }
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                if let Some(updated) = normalized(&sum) {
+                    *centroid = updated;
+                }
+            }
+        }
+    }
+    centroids
+}
+
+fn nearest(centroids: &[Vec<f32>], vector: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .max_by(|a, b| dot(a.1, vector).total_cmp(&dot(b.1, vector)))
+        .map(|(idx, _)| idx)
+        .unwrap_or(0)
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+fn normalized(vector: &[f32]) -> Option<Vec<f32>> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+    Some(vector.iter().map(|v| v / norm).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ConversationStats, RolloutFingerprint};
+    use crate::types::{ConversationRecord, TurnRecord, TurnResult, TurnTelemetry};
+
+    fn seed(storage: &Storage, id: &str, hash: &str, embeddings: &[[f32; 2]]) {
+        let fingerprint = RolloutFingerprint {
+            sha256: Some(hash.to_string()),
+            ..RolloutFingerprint::default()
+        };
+        let conversation_id = storage
+            .upsert_conversation(
+                format!("{id}.jsonl"),
+                &ConversationRecord::default(),
+                &fingerprint,
+                &ConversationStats::default(),
+                Some(id),
+            )
+            .unwrap();
+        for (index, embedding) in embeddings.iter().enumerate() {
+            let turn = TurnRecord {
+                index,
+                started_at: None,
+                ended_at: None,
+                duration_seconds: None,
+                context: None,
+                user_inputs: Vec::new(),
+                result: TurnResult::default(),
+                actions: Vec::new(),
+                telemetry: TurnTelemetry::default(),
+                errors: Vec::new(),
+                source_lines: None,
+                token_usage: None,
+                compactions: Vec::new(),
+            };
+            storage
+                .insert_turn(&conversation_id, &turn, Some(embedding))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn answers_top_k_and_tracks_storage_changes() {
+        let storage = Storage::open_in_memory().unwrap();
+        seed(&storage, "east", "h1", &[[1.0, 0.0], [0.9, 0.1]]);
+        seed(&storage, "north", "h1", &[[0.0, 1.0], [0.1, 0.9]]);
+
+        let config = VectorIndexConfig {
+            lists: Some(2),
+            probes: 1,
+            ..VectorIndexConfig::default()
+        };
+        let mut index = VectorIndex::load(&storage, config).unwrap();
+        assert_eq!((index.len(), index.dimension()), (4, 2));
+
+        let hits = index.search(&[1.0, 0.05], 2);
+        let keys: Vec<(&str, usize)> = hits
+            .iter()
+            .map(|h| (h.conversation_id.as_str(), h.turn_index))
+            .collect();
+        assert_eq!(keys, vec![("east", 0), ("east", 1)]);
+
+        seed(&storage, "north", "h2", &[[0.0, 1.0]]);
+        seed(&storage, "west", "h1", &[[-1.0, 0.0]]);
+        assert_eq!(index.refresh(&storage).unwrap(), 2);
+        assert_eq!(index.refresh(&storage).unwrap(), 0);
+        assert_eq!(index.search(&[-1.0, 0.0], 1)[0].conversation_id, "west");
+
+        assert!(index.insert("manual", 0, &[0.0, -1.0]));
+        assert!(!index.insert("manual", 1, &[1.0, 0.0, 0.0]));
+        assert_eq!(index.search(&[0.0, -1.0], 1)[0].conversation_id, "manual");
+    }
+}